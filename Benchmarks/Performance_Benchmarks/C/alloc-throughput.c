@@ -0,0 +1,93 @@
+// Allocation throughput benchmark
+//
+// Measures how fast malloc/free serve three patterns: many small allocations
+// of 16-256 bytes, a few large allocations of 1-16 MB, and a mix of mostly
+// small and medium allocations with the occasional large one. Allocations are
+// made in batches that are all live at once, written to, and then freed, and
+// the sizes come from the same generator as in the Rust version in
+// ../Rust/alloc-throughput.rs. Reports millions of allocation/free pairs per
+// second for each pattern.
+//
+// Run through run.py with --allocator jemalloc or --allocator mimalloc to
+// compare allocators; the library is then preloaded in place of malloc.
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <time.h>
+
+#define MB (1024*1024)
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// xorshift64, so the sizes match the Rust version exactly.
+static uint64_t next_Random(uint64_t * const state){
+  *state^=*state<<13;
+  *state^=*state>>7;
+  *state^=*state<<17;
+  return *state;
+}
+
+
+static size_t between(uint64_t * const state, const size_t low,
+  const size_t high){
+  return low+next_Random(state)%(high-low+1);
+}
+
+
+static size_t small(uint64_t * const state){
+  return between(state, 16, 256);
+}
+
+
+static size_t large(uint64_t * const state){
+  return between(state, MB, 16*MB);
+}
+
+
+// One in a thousand allocations is large, a tenth of the rest are up to 64 KB.
+static size_t mixed(uint64_t * const state){
+  const uint64_t r=next_Random(state)%1000;
+  if(r==0)
+    return large(state);
+  if(r<100)
+    return between(state, 257, 64*1024);
+  return small(state);
+}
+
+
+// Make count allocations with sizes from size, batch at a time, and return the
+// millions of allocations per second.
+static double measure(const size_t count, const size_t batch,
+  size_t (*size)(uint64_t *)){
+  uint64_t state=0x2545f4914f6cdd1dULL;
+  char ** const live=malloc(batch*sizeof(char *));
+  const double start=now();
+  for(size_t n=0; n<count/batch; n++){
+    for(size_t i=0; i<batch; i++){
+      // Written through a volatile pointer so the pair isn't optimized away.
+      volatile char * const p=malloc(size(&state));
+      *p=1;
+      live[i]=(char *)p;
+    }
+    for(size_t i=0; i<batch; i++)
+      free(live[i]);
+  }
+  const double elapsed=now()-start;
+  free(live);
+  return count/batch*batch/elapsed/1e6;
+}
+
+
+int main(){
+  printf("%-6s: %8.2f M allocations/s\n", "small", measure(10000000, 1000, small));
+  printf("%-6s: %8.2f M allocations/s\n", "large", measure(2000, 4, large));
+  printf("%-6s: %8.2f M allocations/s\n", "mixed", measure(1000000, 1000, mixed));
+  return 0;
+}
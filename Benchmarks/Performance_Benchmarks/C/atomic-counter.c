@@ -0,0 +1,115 @@
+// Atomic fetch-add benchmark
+//
+// N threads each add 1 to a shared _Atomic uint64_t with
+// atomic_fetch_add_explicit M times (M defaults to 10 000 000 and can be given
+// as the first argument), for N = 1, 2, 4, 8 and 16, first with
+// memory_order_relaxed and then with memory_order_seq_cst. Thread i is pinned
+// to the i-th CPU the process may run on, wrapping around when there are more
+// threads than CPUs, so runs are comparable with the Rust version in
+// ../Rust/atomic-counter, which does the same with an AtomicU64. Reports the
+// wall time and the operations per second for each N and ordering.
+
+#define _GNU_SOURCE
+#include <pthread.h>
+#include <sched.h>
+#include <stdatomic.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <time.h>
+
+static const int thread_Counts[]={1, 2, 4, 8, 16};
+
+static _Atomic uint64_t counter;
+
+typedef struct {
+  int cpu;
+  uint64_t operations;
+  memory_order order;
+} worker_Args;
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+static void *worker(void *arg){
+  const worker_Args * const args=arg;
+  cpu_set_t set;
+  CPU_ZERO(&set);
+  CPU_SET(args->cpu, &set);
+  sched_setaffinity(0, sizeof(set), &set);
+
+  // Branch outside the loops, which then only do the atomic operation.
+  if(args->order==memory_order_relaxed){
+    for(uint64_t i=0; i<args->operations; i++)
+      atomic_fetch_add_explicit(&counter, 1, memory_order_relaxed);
+  }else{
+    for(uint64_t i=0; i<args->operations; i++)
+      atomic_fetch_add_explicit(&counter, 1, memory_order_seq_cst);
+  }
+  return NULL;
+}
+
+
+// Run threads threads adding to the counter operations times each with order
+// and return the elapsed time in seconds.
+static double run(const int threads, const uint64_t operations,
+  const memory_order order, const int * const cpus, const int cpu_Count){
+  pthread_t * const handles=malloc(threads*sizeof(pthread_t));
+  worker_Args * const args=malloc(threads*sizeof(worker_Args));
+  atomic_store(&counter, 0);
+
+  const double start=now();
+  for(int i=0; i<threads; i++){
+    args[i].cpu=cpus[i%cpu_Count];
+    args[i].operations=operations;
+    args[i].order=order;
+    pthread_create(&handles[i], NULL, worker, &args[i]);
+  }
+  for(int i=0; i<threads; i++)
+    pthread_join(handles[i], NULL);
+  const double elapsed=now()-start;
+
+  const uint64_t total=atomic_load(&counter);
+  if(total!=threads*operations){
+    fprintf(stderr, "counter is %llu, expected %llu\n",
+      (unsigned long long)total, (unsigned long long)(threads*operations));
+    exit(1);
+  }
+  free(handles);
+  free(args);
+  return elapsed;
+}
+
+
+int main(int argc, char *argv[]){
+  const uint64_t operations=argc>1 ? strtoull(argv[1], NULL, 10) : 10000000;
+
+  // The CPUs this process may run on.
+  cpu_set_t allowed;
+  int cpus[CPU_SETSIZE], cpu_Count=0;
+  if(sched_getaffinity(0, sizeof(allowed), &allowed)==0){
+    for(int cpu=0; cpu<CPU_SETSIZE; cpu++)
+      if(CPU_ISSET(cpu, &allowed))
+        cpus[cpu_Count++]=cpu;
+  }else
+    cpus[cpu_Count++]=0;
+
+  const struct {
+    const char *name;
+    memory_order order;
+  } orders[]={{"relaxed", memory_order_relaxed}, {"seq_cst", memory_order_seq_cst}};
+  for(size_t o=0; o<sizeof(orders)/sizeof(orders[0]); o++){
+    for(size_t t=0; t<sizeof(thread_Counts)/sizeof(thread_Counts[0]); t++){
+      const int threads=thread_Counts[t];
+      const double elapsed=run(threads, operations, orders[o].order, cpus, cpu_Count);
+      printf("%s threads %3d: %10.3f ms, %8.2f Mops/s\n", orders[o].name, threads,
+        elapsed*1e3, threads*operations/elapsed/1e6);
+    }
+  }
+  return 0;
+}
@@ -0,0 +1,137 @@
+// Base64 encoding and decoding benchmark
+//
+// Encodes 100 MB of pseudo-random bytes (the size can be changed with the
+// first argument, in MB) with OpenSSL's EVP_EncodeBlock, decodes the result
+// with EVP_DecodeBlock, and then decodes it again with a newline after every
+// 64 characters, as in PEM files. EVP_DecodeBlock only skips whitespace at the
+// ends of its input, so the last pass uses the streaming EVP_DecodeUpdate,
+// which skips it anywhere. Each pass runs 5 times and reports MB/s of raw
+// data. The data is the same in the Rust version in ../Rust/base64, which uses
+// the base64 crate, so the hashes of the encoded text printed on the "verify: "
+// lines must match.
+
+#include <openssl/evp.h>
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#define REPEATS 5
+
+// Characters per line in the text with embedded whitespace.
+#define LINE_LENGTH 64
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// xorshift64, so the data matches the Rust version exactly.
+static uint64_t next_Random(uint64_t * const state){
+  *state^=*state<<13;
+  *state^=*state>>7;
+  *state^=*state<<17;
+  return *state;
+}
+
+
+// FNV-1a, to summarize the encoded text.
+static uint64_t fnv1a(const unsigned char * const bytes, const size_t size){
+  uint64_t hash=0xcbf29ce484222325ULL;
+  for(size_t i=0; i<size; i++)
+    hash=(hash^bytes[i])*0x100000001b3ULL;
+  return hash;
+}
+
+
+static void report(const char * const name, const size_t size, const double elapsed){
+  printf("%-17s: %8.1f MB/s\n", name, (double)size*REPEATS/(1024*1024)/elapsed);
+}
+
+
+static void check_Decoded(const char * const name, const unsigned char * const decoded,
+  const size_t length, const unsigned char * const data, const size_t size){
+  if(length!=size || memcmp(decoded, data, size)!=0){
+    fprintf(stderr, "%s doesn't give back the data\n", name);
+    exit(1);
+  }
+  printf("verify: %s %zu bytes\n", name, length);
+}
+
+
+int main(int argc, char *argv[]){
+  const size_t megabytes=argc>1 ? atoi(argv[1]) : 100;
+  const size_t size=megabytes*1024*1024;
+  unsigned char * const data=malloc(size);
+  uint64_t state=0x2545f4914f6cdd1dULL;
+  for(size_t i=0; i<size; i+=8){
+    const uint64_t r=next_Random(&state);
+    for(int b=0; b<8; b++)
+      data[i+b]=(unsigned char)(r>>(8*b));
+  }
+
+  // EVP_EncodeBlock adds a terminating NUL.
+  const size_t encoded_Size=(size+2)/3*4;
+  unsigned char * const encoded=malloc(encoded_Size+1);
+  double start=now();
+  for(int r=0; r<REPEATS; r++)
+    EVP_EncodeBlock(encoded, data, size);
+  report("encode", size, now()-start);
+  printf("verify: encoded %zu bytes, hash %016" PRIx64 "\n", encoded_Size,
+    fnv1a(encoded, encoded_Size));
+
+  // EVP_DecodeBlock decodes the padding too, as zero bytes, so the length it
+  // returns is a multiple of 3 and has to be corrected.
+  unsigned char * const decoded=malloc(size+3);
+  size_t length=0;
+  start=now();
+  for(int r=0; r<REPEATS; r++){
+    const int n=EVP_DecodeBlock(decoded, encoded, encoded_Size);
+    if(n<0){
+      fprintf(stderr, "EVP_DecodeBlock failed\n");
+      return 1;
+    }
+    length=n;
+    for(size_t i=encoded_Size; i>0 && encoded[i-1]=='='; i--)
+      length--;
+  }
+  report("decode", size, now()-start);
+  check_Decoded("decoded", decoded, length, data, size);
+
+  const size_t lines=(encoded_Size+LINE_LENGTH-1)/LINE_LENGTH;
+  unsigned char * const wrapped=malloc(encoded_Size+lines);
+  size_t wrapped_Size=0;
+  for(size_t i=0; i<encoded_Size; i+=LINE_LENGTH){
+    const size_t n=encoded_Size-i<LINE_LENGTH ? encoded_Size-i : LINE_LENGTH;
+    memcpy(wrapped+wrapped_Size, encoded+i, n);
+    wrapped_Size+=n;
+    wrapped[wrapped_Size++]='\n';
+  }
+  memset(decoded, 0, size);
+  EVP_ENCODE_CTX * const context=EVP_ENCODE_CTX_new();
+  start=now();
+  for(int r=0; r<REPEATS; r++){
+    int n, final;
+    EVP_DecodeInit(context);
+    if(EVP_DecodeUpdate(context, decoded, &n, wrapped, wrapped_Size)<0 ||
+        EVP_DecodeFinal(context, decoded+n, &final)<0){
+      fprintf(stderr, "EVP_DecodeUpdate failed\n");
+      return 1;
+    }
+    length=(size_t)n+final;
+  }
+  report("decode with lines", size, now()-start);
+  check_Decoded("decoded with lines", decoded, length, data, size);
+
+  EVP_ENCODE_CTX_free(context);
+  free(wrapped);
+  free(decoded);
+  free(encoded);
+  free(data);
+  return 0;
+}
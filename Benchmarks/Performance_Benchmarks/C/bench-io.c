@@ -0,0 +1,112 @@
+// Sequential I/O throughput benchmark
+//
+// Writes a temporary file of the requested size (in MB, default 64) through
+// stdio with several buffer sizes, reads it back, and reports the throughput
+// of each pass in MB/s. The Rust version in ../Rust/bench-io.rs performs the
+// same passes with BufWriter/BufReader.
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <unistd.h>
+
+// Size of each individual fwrite()/fread() call.
+#define CHUNK_SIZE 4096
+
+static const size_t buffer_Sizes[]={8*1024, 64*1024, 1024*1024};
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// Write file_Size bytes to path using a stdio buffer of buffer_Size bytes and
+// return the elapsed time in seconds.
+static double write_File(const char * const path, const size_t file_Size,
+  const size_t buffer_Size){
+  char chunk[CHUNK_SIZE];
+  for(size_t i=0; i<CHUNK_SIZE; i++)
+    chunk[i]=(char)i;
+
+  char * const buffer=malloc(buffer_Size);
+  const double start=now();
+
+  FILE * const file=fopen(path, "wb");
+  if(!file){
+    perror(path);
+    exit(1);
+  }
+  setvbuf(file, buffer, _IOFBF, buffer_Size);
+  for(size_t written=0; written<file_Size; written+=CHUNK_SIZE)
+    fwrite(chunk, 1, CHUNK_SIZE, file);
+  fflush(file);
+  fsync(fileno(file));
+  fclose(file);
+
+  const double elapsed=now()-start;
+  free(buffer);
+  return elapsed;
+}
+
+
+// Read path back using a stdio buffer of buffer_Size bytes and return the
+// elapsed time in seconds. The checksum keeps the reads from being optimized
+// away.
+static double read_File(const char * const path, const size_t file_Size,
+  const size_t buffer_Size){
+  char chunk[CHUNK_SIZE];
+  char * const buffer=malloc(buffer_Size);
+  unsigned long checksum=0;
+  size_t total=0, count;
+  const double start=now();
+
+  FILE * const file=fopen(path, "rb");
+  if(!file){
+    perror(path);
+    exit(1);
+  }
+  setvbuf(file, buffer, _IOFBF, buffer_Size);
+  while((count=fread(chunk, 1, CHUNK_SIZE, file))>0){
+    checksum+=(unsigned char)chunk[count-1];
+    total+=count;
+  }
+  fclose(file);
+
+  const double elapsed=now()-start;
+  free(buffer);
+  if(total!=file_Size){
+    fprintf(stderr, "short read: %zu of %zu bytes\n", total, file_Size);
+    exit(1);
+  }
+  if(checksum==0)
+    puts("");
+  return elapsed;
+}
+
+
+int main(int argc, char *argv[]){
+  const size_t megabytes=argc>1 ? atoi(argv[1]) : 64;
+  const size_t file_Size=megabytes*1024*1024;
+
+  char path[]="/tmp/bench-io-XXXXXX";
+  const int fd=mkstemp(path);
+  if(fd<0){
+    perror("mkstemp");
+    return 1;
+  }
+  close(fd);
+
+  for(size_t i=0; i<sizeof(buffer_Sizes)/sizeof(buffer_Sizes[0]); i++){
+    const double write_Time=write_File(path, file_Size, buffer_Sizes[i]);
+    const double read_Time=read_File(path, file_Size, buffer_Sizes[i]);
+    printf("buffer %7zu: write %.1f MB/s, read %.1f MB/s\n", buffer_Sizes[i],
+      megabytes/write_Time, megabytes/read_Time);
+  }
+
+  unlink(path);
+  return 0;
+}
@@ -0,0 +1,133 @@
+// Math library benchmark
+//
+// Computes sin, sqrt, pow and exp of 1M pseudo-random doubles in [0.5, 2),
+// 50 times over, and reports millions of operations per second. pow raises
+// the i-th value to the power i % 16. The exact variant calls sin(), sqrt(),
+// pow() and exp() from <math.h> as compiled, like the Rust version in
+// ../Rust/bench-math calls the f64 methods. The fast variant runs the same
+// loops in functions built with the optimize("fast-math") attribute, which
+// applies -ffast-math to them alone, so both variants are in one program. The
+// sum of the values the exact variant computes is printed on "verify: " lines,
+// rounded so that the last-bit differences between pow() and Rust's powi don't
+// show.
+
+#include <math.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <time.h>
+
+#define VALUES 1000000
+
+#define REPEATS 50
+
+#define FAST __attribute__((optimize("fast-math")))
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// xorshift64, so the data matches the Rust version exactly.
+static uint64_t next_Random(uint64_t * const state){
+  *state^=*state<<13;
+  *state^=*state>>7;
+  *state^=*state<<17;
+  return *state;
+}
+
+
+// Like Rust's black_box: the compiler must assume p's memory is read and
+// written.
+static inline void escape(void * const p){
+  __asm__ volatile("" : : "r"(p) : "memory");
+}
+
+
+static void exact_sin(const double * const in, double * const out){
+  for(size_t i=0; i<VALUES; i++)
+    out[i]=sin(in[i]);
+}
+
+static void exact_sqrt(const double * const in, double * const out){
+  for(size_t i=0; i<VALUES; i++)
+    out[i]=sqrt(in[i]);
+}
+
+static void exact_pow(const double * const in, double * const out){
+  for(size_t i=0; i<VALUES; i++)
+    out[i]=pow(in[i], (double)(i%16));
+}
+
+static void exact_exp(const double * const in, double * const out){
+  for(size_t i=0; i<VALUES; i++)
+    out[i]=exp(in[i]);
+}
+
+FAST static void fast_sin(const double * const in, double * const out){
+  for(size_t i=0; i<VALUES; i++)
+    out[i]=sin(in[i]);
+}
+
+FAST static void fast_sqrt(const double * const in, double * const out){
+  for(size_t i=0; i<VALUES; i++)
+    out[i]=sqrt(in[i]);
+}
+
+FAST static void fast_pow(const double * const in, double * const out){
+  for(size_t i=0; i<VALUES; i++)
+    out[i]=pow(in[i], (double)(i%16));
+}
+
+FAST static void fast_exp(const double * const in, double * const out){
+  for(size_t i=0; i<VALUES; i++)
+    out[i]=exp(in[i]);
+}
+
+
+static const struct {
+  const char *name;
+  void (*exact)(const double *, double *);
+  void (*fast)(const double *, double *);
+} functions[]={{"sin", exact_sin, fast_sin}, {"sqrt", exact_sqrt, fast_sqrt},
+  {"pow", exact_pow, fast_pow}, {"exp", exact_exp, fast_exp}};
+
+
+// Apply f to in REPEATS times and return the millions of operations per
+// second.
+static double measure(const double * const in, double * const out,
+  void (*f)(const double *, double *)){
+  const double start=now();
+  for(int r=0; r<REPEATS; r++){
+    f(in, out);
+    escape(out);
+  }
+  const double elapsed=now()-start;
+  return (double)VALUES*REPEATS/1e6/elapsed;
+}
+
+
+int main(){
+  double * const in=malloc(VALUES*sizeof(double));
+  double * const out=malloc(VALUES*sizeof(double));
+  uint64_t state=0x2545f4914f6cdd1dULL;
+  for(size_t i=0; i<VALUES; i++)
+    in[i]=0.5+1.5*((next_Random(&state)>>11)/9007199254740992.0);
+
+  for(size_t i=0; i<sizeof(functions)/sizeof(functions[0]); i++){
+    const double exact=measure(in, out, functions[i].exact);
+    double sum=0;
+    for(size_t j=0; j<VALUES; j++)
+      sum+=out[j];
+    const double fast=measure(in, out, functions[i].fast);
+    printf("%-4s: exact %8.2f Mops/s, fast %8.2f Mops/s\n", functions[i].name, exact,
+      fast);
+    printf("verify: %s of %d values, sum %.3f\n", functions[i].name, VALUES, sum);
+  }
+  free(in);
+  free(out);
+  return 0;
+}
@@ -0,0 +1,128 @@
+// Hash map benchmark
+//
+// Performs N inserts of random uint64_t -> uint64_t entries (N defaults to
+// 1 000 000 and can be given as the first argument), then N successful
+// lookups, N failed lookups and N removals, on a uthash table. Reports the
+// mean time per operation, and how much the resident set size grew while
+// inserting. The Rust version in ../Rust/hash-map performs the same
+// operations with HashMap and FxHashMap.
+//
+// Needs uthash.h, packaged as uthash-dev on Debian and Ubuntu.
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include "uthash.h"
+
+typedef struct {
+  uint64_t key;
+  uint64_t value;
+  UT_hash_handle hh;
+} entry;
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// xorshift64, so the keys match the Rust version exactly.
+static uint64_t next_Random(uint64_t * const state){
+  *state^=*state<<13;
+  *state^=*state>>7;
+  *state^=*state<<17;
+  return *state;
+}
+
+
+// The current resident set size in KB, from /proc/self/status.
+static long rss_KB(){
+  char line[256];
+  long kb=0;
+  FILE * const status=fopen("/proc/self/status", "r");
+  if(!status)
+    return 0;
+  while(fgets(line, sizeof(line), status))
+    if(!strncmp(line, "VmRSS:", 6)){
+      kb=atol(line+6);
+      break;
+    }
+  fclose(status);
+  return kb;
+}
+
+
+int main(int argc, char *argv[]){
+  const size_t n=argc>1 ? (size_t)atol(argv[1]) : 1000000;
+
+  // Inserted keys are odd and missing keys even, so lookups of the latter
+  // always fail.
+  uint64_t * const present=malloc(n*sizeof(uint64_t));
+  uint64_t * const missing=malloc(n*sizeof(uint64_t));
+  uint64_t state=0x2545f4914f6cdd1dULL;
+  for(size_t i=0; i<n; i++)
+    present[i]=next_Random(&state)|1;
+  for(size_t i=0; i<n; i++)
+    missing[i]=next_Random(&state)&~(uint64_t)1;
+
+  entry *map=NULL, *found;
+  const long rss_Before=rss_KB();
+
+  double start=now();
+  for(size_t i=0; i<n; i++){
+    entry * const e=malloc(sizeof(entry));
+    e->key=present[i];
+    e->value=present[i]*3;
+    HASH_ADD(hh, map, key, sizeof(uint64_t), e);
+  }
+  const double insert=(now()-start)*1e9/n;
+  const long rss=rss_KB()-rss_Before;
+
+  start=now();
+  for(size_t i=0; i<n; i++){
+    HASH_FIND(hh, map, &present[i], sizeof(uint64_t), found);
+    if(!found){
+      fprintf(stderr, "key %llu is missing\n", (unsigned long long)present[i]);
+      return 1;
+    }
+  }
+  const double hit=(now()-start)*1e9/n;
+
+  start=now();
+  for(size_t i=0; i<n; i++){
+    HASH_FIND(hh, map, &missing[i], sizeof(uint64_t), found);
+    if(found){
+      fprintf(stderr, "key %llu is present\n", (unsigned long long)missing[i]);
+      return 1;
+    }
+  }
+  const double miss=(now()-start)*1e9/n;
+
+  start=now();
+  for(size_t i=0; i<n; i++){
+    HASH_FIND(hh, map, &present[i], sizeof(uint64_t), found);
+    if(found){
+      HASH_DEL(map, found);
+      free(found);
+    }
+  }
+  const double remove=(now()-start)*1e9/n;
+  if(HASH_COUNT(map)!=0){
+    fprintf(stderr, "%u entries left after removing\n", HASH_COUNT(map));
+    return 1;
+  }
+
+  printf("%-10s %-12s: %8.1f ns/op\n", "uthash", "insert", insert);
+  printf("%-10s %-12s: %8.1f ns/op\n", "uthash", "lookup-hit", hit);
+  printf("%-10s %-12s: %8.1f ns/op\n", "uthash", "lookup-miss", miss);
+  printf("%-10s %-12s: %8.1f ns/op\n", "uthash", "remove", remove);
+  printf("%-10s %-12s: %8ld KB\n", "uthash", "rss-growth", rss);
+
+  free(present);
+  free(missing);
+  return 0;
+}
@@ -0,0 +1,52 @@
+// Integer formatting benchmark
+//
+// Formats 10 million random uint64_t values (the count can be changed with the
+// first argument) as decimal strings with snprintf, appending the digits to a
+// preallocated output buffer, and reports millions of integers formatted per
+// second. The Rust version in ../Rust/int-format formats the same values the
+// same way with the itoa crate.
+
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <time.h>
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// xorshift64, so the values match the Rust version exactly.
+static uint64_t next_Random(uint64_t * const state){
+  *state^=*state<<13;
+  *state^=*state>>7;
+  *state^=*state<<17;
+  return *state;
+}
+
+
+int main(int argc, char *argv[]){
+  const size_t count=argc>1 ? strtoull(argv[1], NULL, 10) : 10000000;
+  uint64_t * const values=malloc(count*sizeof(uint64_t));
+  uint64_t state=0x2545f4914f6cdd1dULL;
+  for(size_t i=0; i<count; i++)
+    values[i]=next_Random(&state);
+
+  // Room for every value at its longest, 20 digits, and the last NUL.
+  char * const output=malloc(count*20+1);
+  size_t total=0;
+  const double start=now();
+  for(size_t i=0; i<count; i++)
+    total+=snprintf(output+total, 21, "%" PRIu64, values[i]);
+  const double elapsed=now()-start;
+
+  printf("%zu values: snprintf %.1f M/s (%zu bytes)\n", count,
+    count/elapsed/1e6, total);
+  free(output);
+  free(values);
+  return 0;
+}
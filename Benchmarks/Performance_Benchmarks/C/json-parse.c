@@ -0,0 +1,202 @@
+// JSON parsing latency benchmark
+//
+// Parses the JSON document in the file given as the first argument (1 MB as
+// generated by Random_Input_Generator/random_gen_json.py), or a similar one
+// generated in memory without an argument, with cJSON_Parse over and over, and
+// reports the mean and fastest time per parse along with the throughput. The
+// Rust version in ../Rust/json-parse parses the same document into a
+// serde_json::Value.
+
+#include <cjson/cJSON.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#define ITERATIONS 200
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// Read the whole file at path into a NUL-terminated buffer.
+static char *read_File(const char * const path, size_t * const size){
+  FILE * const file=fopen(path, "rb");
+  if(!file){
+    perror(path);
+    exit(1);
+  }
+  fseek(file, 0, SEEK_END);
+  *size=ftell(file);
+  fseek(file, 0, SEEK_SET);
+  char * const contents=malloc(*size+1);
+  if(fread(contents, 1, *size, file)!=*size){
+    perror(path);
+    exit(1);
+  }
+  contents[*size]='\0';
+  fclose(file);
+  return contents;
+}
+
+
+// Without a file, the document is generated: an array of randomly nested
+// objects like random_gen_json.py writes, of at least this many bytes. The
+// Rust version generates the same one.
+#define GENERATED_SIZE (1024*1024)
+
+#define MAX_DEPTH 5
+
+// A growable string the generated document is written to.
+typedef struct {
+  char *data;
+  size_t length;
+  size_t capacity;
+} Buffer;
+
+
+static void append(Buffer * const buffer, const char * const s){
+  const size_t n=strlen(s);
+  if(buffer->length+n+1>buffer->capacity){
+    buffer->capacity=(buffer->length+n+1)*2;
+    buffer->data=realloc(buffer->data, buffer->capacity);
+  }
+  memcpy(buffer->data+buffer->length, s, n+1);
+  buffer->length+=n;
+}
+
+
+// xorshift64, so the document matches the Rust version exactly.
+static uint64_t next_Random(uint64_t * const state){
+  *state^=*state<<13;
+  *state^=*state>>7;
+  *state^=*state<<17;
+  return *state;
+}
+
+static uint64_t below(uint64_t * const state, const uint64_t n){
+  return next_Random(state)%n;
+}
+
+
+static void generate_Object(Buffer *buffer, uint64_t *state, int depth);
+
+static void generate_Value(Buffer * const buffer, uint64_t * const state,
+  const int depth){
+  // Mostly plain text, with the odd character that has to be escaped or
+  // encoded as more than one byte.
+  static const char plain[]=
+    "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789        ";
+  static const char * const special[]={"\\\"", "\\\\", "\\n", "\\t", "\xc3\xa9"};
+  char text[32];
+  const uint64_t kind=below(state, 100);
+  if(depth<MAX_DEPTH && kind<15)
+    generate_Object(buffer, state, depth+1);
+  else if(depth<MAX_DEPTH && kind<25){
+    append(buffer, "[");
+    const uint64_t n=below(state, 9);
+    for(uint64_t i=0; i<n; i++){
+      if(i>0)
+        append(buffer, ",");
+      generate_Value(buffer, state, depth+1);
+    }
+    append(buffer, "]");
+  }else if(kind<45){
+    append(buffer, "\"");
+    const uint64_t n=below(state, 41);
+    for(uint64_t i=0; i<n; i++){
+      const uint64_t c=below(state, sizeof(plain)-1+5);
+      if(c<sizeof(plain)-1){
+        text[0]=plain[c];
+        text[1]='\0';
+        append(buffer, text);
+      }else
+        append(buffer, special[c-(sizeof(plain)-1)]);
+    }
+    append(buffer, "\"");
+  }else if(kind<65){
+    sprintf(text, "%lld", (long long)below(state, (1ULL<<32)+1)-(1LL<<31));
+    append(buffer, text);
+  }else if(kind<80){
+    // Thousandths, so both versions print exactly the same digits.
+    const long long value=(long long)below(state, 2000000001)-1000000000;
+    const long long magnitude=value<0 ? -value : value;
+    sprintf(text, "%s%lld.%03lld", value<0 ? "-" : "", magnitude/1000,
+      magnitude%1000);
+    append(buffer, text);
+  }else if(kind<90)
+    append(buffer, below(state, 2) ? "true" : "false");
+  else
+    append(buffer, "null");
+}
+
+static void generate_Object(Buffer * const buffer, uint64_t * const state,
+  const int depth){
+  char key[32];
+  append(buffer, "{");
+  const uint64_t n=1+below(state, 8);
+  for(uint64_t i=0; i<n; i++){
+    if(i>0)
+      append(buffer, ",");
+    // The entry's index keeps the keys of an object distinct.
+    const uint64_t length=3+below(state, 8);
+    for(uint64_t j=0; j<length; j++)
+      key[j]='a'+below(state, 26);
+    sprintf(key+length, "%llu", (unsigned long long)i);
+    append(buffer, "\"");
+    append(buffer, key);
+    append(buffer, "\":");
+    generate_Value(buffer, state, depth);
+  }
+  append(buffer, "}");
+}
+
+static char *generate_Document(size_t * const size){
+  Buffer buffer={NULL, 0, 0};
+  uint64_t state=0x2545f4914f6cdd1dULL;
+  append(&buffer, "[");
+  while(buffer.length<GENERATED_SIZE){
+    if(buffer.length>1)
+      append(&buffer, ",");
+    generate_Object(&buffer, &state, 0);
+  }
+  append(&buffer, "]");
+  *size=buffer.length;
+  return buffer.data;
+}
+
+
+int main(int argc, char *argv[]){
+  const char * const name=argc>1 ? argv[1] : "generated document";
+  size_t size;
+  char * const document=argc>1 ? read_File(argv[1], &size)
+    : generate_Document(&size);
+
+  double fastest=1e300;
+  const double start=now();
+  for(int i=0; i<ITERATIONS; i++){
+    const double parse_Start=now();
+    cJSON * const value=cJSON_Parse(document);
+    if(!value){
+      fprintf(stderr, "%s: parse error before: %.20s\n", name,
+        cJSON_GetErrorPtr());
+      return 1;
+    }
+    const double elapsed=now()-parse_Start;
+    if(elapsed<fastest)
+      fastest=elapsed;
+    // Freeing the tree is part of the cost of a parse, as in the Rust version.
+    cJSON_Delete(value);
+  }
+  const double mean=(now()-start)/ITERATIONS;
+  printf("%zu bytes: mean %.3f ms, fastest %.3f ms, %.1f MB/s\n", size,
+    mean*1e3, fastest*1e3, size/(1024.0*1024.0)/mean);
+
+  free(document);
+  return 0;
+}
@@ -0,0 +1,125 @@
+// Line reading throughput benchmark
+//
+// Writes files of 10 MB, 100 MB and 1 GB of text (the largest size can be
+// changed with the first argument, in MB) and counts their lines with getline,
+// reporting millions of lines per second. The files go to /dev/shm, or the
+// temporary directory where there is none, so they are read from memory rather
+// than the disk; each is written before its reading is timed and removed
+// afterwards. The Rust version in ../Rust/line-count.rs writes the same text
+// and reads it with BufReader::lines().
+
+#define _GNU_SOURCE
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <sys/stat.h>
+#include <time.h>
+#include <unistd.h>
+
+static const char alphabet[]="abcdefghijklmnopqrstuvwxyz0123456789 ";
+#define ALPHABET_LEN (sizeof(alphabet)-1)
+
+// Lines are up to this many bytes long, not counting the newline.
+#define MAX_LINE 120
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// xorshift64, so the text matches the Rust version exactly.
+static uint64_t next_Random(uint64_t * const state){
+  *state^=*state<<13;
+  *state^=*state>>7;
+  *state^=*state<<17;
+  return *state;
+}
+
+
+// Store in path the file to write the text to, in /dev/shm if there is one.
+static void text_Path(char * const path, const size_t size){
+  struct stat st;
+  const char *dir="/dev/shm";
+  if(stat(dir, &st)!=0 || !S_ISDIR(st.st_mode)){
+    dir=getenv("TMPDIR");
+    if(!dir || !*dir)
+      dir="/tmp";
+  }
+  snprintf(path, size, "%s/line-count-%ld.txt", dir, (long)getpid());
+}
+
+
+// Write lines of random length to path until there are at least size bytes,
+// and return the number of lines written.
+static size_t write_Text(const char * const path, const size_t size){
+  FILE * const file=fopen(path, "wb");
+  if(!file){
+    perror(path);
+    exit(1);
+  }
+  char line[MAX_LINE+1];
+  uint64_t state=0x2545f4914f6cdd1dULL;
+  size_t written=0, lines=0;
+  while(written<size){
+    const uint64_t r=next_Random(&state);
+    const size_t start=(r>>8)%ALPHABET_LEN;
+    const size_t length=r%MAX_LINE;
+    for(size_t i=0; i<length; i++)
+      line[i]=alphabet[(start+i)%ALPHABET_LEN];
+    line[length]='\n';
+    fwrite(line, 1, length+1, file);
+    written+=length+1;
+    lines++;
+  }
+  if(fclose(file)!=0){
+    perror(path);
+    exit(1);
+  }
+  return lines;
+}
+
+
+// Count the lines of the file at path, storing the elapsed time in seconds in
+// elapsed.
+static size_t count_Lines(const char * const path, double * const elapsed){
+  const double start=now();
+  FILE * const file=fopen(path, "rb");
+  if(!file){
+    perror(path);
+    exit(1);
+  }
+  char *line=NULL;
+  size_t capacity=0, lines=0;
+  while(getline(&line, &capacity, file)!=-1)
+    lines++;
+  free(line);
+  fclose(file);
+  *elapsed=now()-start;
+  return lines;
+}
+
+
+int main(int argc, char *argv[]){
+  const size_t megabytes=argc>1 ? atoi(argv[1]) : 1024;
+  const size_t sizes[]={10, 100, megabytes};
+  char path[4096];
+  text_Path(path, sizeof(path));
+
+  for(size_t i=0; i<sizeof(sizes)/sizeof(sizes[0]); i++){
+    const size_t expected=write_Text(path, sizes[i]*1024*1024);
+    double elapsed;
+    const size_t lines=count_Lines(path, &elapsed);
+    remove(path);
+    if(lines!=expected){
+      fprintf(stderr, "counted %zu lines, wrote %zu\n", lines, expected);
+      return 1;
+    }
+    printf("%5zu MB: %zu lines, %.1f M lines/s\n", sizes[i], lines,
+      lines/elapsed/1e6);
+  }
+
+  return 0;
+}
@@ -0,0 +1,300 @@
+// Lock-free queue benchmark
+//
+// P producer threads each enqueue M items onto a shared Michael-Scott queue
+// while C consumer threads dequeue from it until the producers are done and
+// the queue is empty (P and C default to 4 and can be given with --producers
+// and --consumers, M defaults to 1 000 000 and can be given with --items).
+// The queue's nodes are allocated up front, one per item, and never reused,
+// which rules out the ABA problem and the need for safe memory reclamation.
+// Threads are pinned to the CPUs the process may run on, producers first, as
+// in the Rust version in ../Rust/lockfree-queue, which uses a
+// crossbeam_queue::ArrayQueue. Every 16th enqueue and successful dequeue is
+// timed, and the 50th, 99th and 99.9th percentile and maximum latencies are
+// reported along with the total throughput. The sum of the items dequeued is
+// printed on a "verify: " line.
+
+#define _GNU_SOURCE
+#include <inttypes.h>
+#include <pthread.h>
+#include <sched.h>
+#include <stdatomic.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+// One in this many operations is timed.
+#define SAMPLE_EVERY 16
+
+typedef struct node {
+  _Atomic(struct node *) next;
+  uint64_t value;
+} node;
+
+// The head and tail are on separate cache lines, so producers and consumers
+// don't contend for one.
+typedef struct {
+  _Alignas(64) _Atomic(node *) head;
+  _Alignas(64) _Atomic(node *) tail;
+} queue;
+
+typedef struct {
+  int cpu;
+  queue *q;
+  node *nodes;
+  uint64_t first;
+  size_t items;
+  atomic_bool *done;
+  uint64_t count;
+  uint64_t sum;
+  uint64_t *latencies;
+  size_t samples;
+} worker_Args;
+
+
+static uint64_t now_Ns(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return (uint64_t)ts.tv_sec*1000000000+ts.tv_nsec;
+}
+
+
+static void pin(const int cpu){
+  cpu_set_t set;
+  CPU_ZERO(&set);
+  CPU_SET(cpu, &set);
+  sched_setaffinity(0, sizeof(set), &set);
+}
+
+
+// The value of the command line option name, or default_Value without it.
+static size_t option(const int argc, char *argv[], const char * const name,
+  const size_t default_Value){
+  for(int i=1; i<argc; i++){
+    if(strcmp(argv[i], name)==0){
+      char *end;
+      const size_t n=i+1<argc ? strtoull(argv[i+1], &end, 10) : 0;
+      if(n==0 || *end){
+        fprintf(stderr, "%s takes a positive integer\n", name);
+        exit(1);
+      }
+      return n;
+    }
+  }
+  return default_Value;
+}
+
+
+static void enqueue(queue * const q, node * const n){
+  atomic_store_explicit(&n->next, NULL, memory_order_relaxed);
+  for(;;){
+    node *tail=atomic_load(&q->tail);
+    node *next=atomic_load(&tail->next);
+    if(tail!=atomic_load(&q->tail))
+      continue;
+    if(next==NULL){
+      if(atomic_compare_exchange_weak(&tail->next, &next, n)){
+        // Swing the tail to the new node; if this fails, another thread
+        // already has.
+        atomic_compare_exchange_strong(&q->tail, &tail, n);
+        return;
+      }
+    }else
+      // The tail is lagging behind: help move it along.
+      atomic_compare_exchange_strong(&q->tail, &tail, next);
+  }
+}
+
+
+static bool dequeue(queue * const q, uint64_t * const value){
+  for(;;){
+    node *head=atomic_load(&q->head);
+    node *tail=atomic_load(&q->tail);
+    node * const next=atomic_load(&head->next);
+    if(head!=atomic_load(&q->head))
+      continue;
+    if(head==tail){
+      if(next==NULL)
+        return false;
+      atomic_compare_exchange_strong(&q->tail, &tail, next);
+    }else{
+      // Read before the exchange, after which another dequeue may take it.
+      const uint64_t v=next->value;
+      if(atomic_compare_exchange_weak(&q->head, &head, next)){
+        *value=v;
+        return true;
+      }
+    }
+  }
+}
+
+
+// Enqueue items values starting at first, sampling the latencies.
+static void *produce(void *arg){
+  worker_Args * const args=arg;
+  pin(args->cpu);
+  args->latencies=malloc((args->items/SAMPLE_EVERY+1)*sizeof(uint64_t));
+  for(size_t i=0; i<args->items; i++){
+    node * const n=&args->nodes[i];
+    n->value=args->first+i;
+    if(i%SAMPLE_EVERY==0){
+      const uint64_t start=now_Ns();
+      enqueue(args->q, n);
+      args->latencies[args->samples++]=now_Ns()-start;
+    }else
+      enqueue(args->q, n);
+  }
+  return NULL;
+}
+
+
+// Dequeue until the producers are done and the queue is empty, counting and
+// summing the items and sampling the latencies.
+static void *consume(void *arg){
+  worker_Args * const args=arg;
+  pin(args->cpu);
+  size_t capacity=1024;
+  args->latencies=malloc(capacity*sizeof(uint64_t));
+  for(;;){
+    // Read before dequeueing: if the producers were done then, an empty queue
+    // stays empty.
+    const bool finished=atomic_load_explicit(args->done, memory_order_acquire);
+    const bool sampled=args->count%SAMPLE_EVERY==0;
+    uint64_t value;
+    const uint64_t start=now_Ns();
+    if(dequeue(args->q, &value)){
+      if(sampled){
+        if(args->samples==capacity){
+          capacity*=2;
+          args->latencies=realloc(args->latencies, capacity*sizeof(uint64_t));
+        }
+        args->latencies[args->samples++]=now_Ns()-start;
+      }
+      args->count++;
+      args->sum+=value;
+    }else if(finished)
+      return NULL;
+    else
+      sched_yield();
+  }
+}
+
+
+static int compare_U64(const void *a, const void *b){
+  const uint64_t x=*(const uint64_t *)a, y=*(const uint64_t *)b;
+  return (x>y)-(x<y);
+}
+
+
+// The pth percentile of sorted.
+static uint64_t percentile(const uint64_t * const sorted, const size_t n, const double p){
+  const double exact=p/100*n;
+  size_t rank=(size_t)exact;
+  if(rank<exact)
+    rank++;
+  if(rank<1)
+    rank=1;
+  if(rank>n)
+    rank=n;
+  return sorted[rank-1];
+}
+
+
+// Gather the latencies sampled by the threads in workers and report them.
+static void report(const char * const name, const worker_Args * const workers,
+  const size_t count){
+  size_t n=0;
+  for(size_t i=0; i<count; i++)
+    n+=workers[i].samples;
+  uint64_t * const latencies=malloc(n*sizeof(uint64_t));
+  n=0;
+  for(size_t i=0; i<count; i++){
+    memcpy(latencies+n, workers[i].latencies, workers[i].samples*sizeof(uint64_t));
+    n+=workers[i].samples;
+  }
+  qsort(latencies, n, sizeof(uint64_t), compare_U64);
+  printf("%s latency: p50 %" PRIu64 " ns, p99 %" PRIu64 " ns, p99.9 %" PRIu64
+    " ns, max %" PRIu64 " ns\n", name, percentile(latencies, n, 50),
+    percentile(latencies, n, 99), percentile(latencies, n, 99.9), latencies[n-1]);
+  free(latencies);
+}
+
+
+int main(int argc, char *argv[]){
+  const size_t producers=option(argc, argv, "--producers", 4);
+  const size_t consumers=option(argc, argv, "--consumers", 4);
+  const size_t items=option(argc, argv, "--items", 1000000);
+  const size_t total=producers*items;
+
+  // The CPUs this process may run on.
+  cpu_set_t allowed;
+  int cpus[CPU_SETSIZE], cpu_Count=0;
+  if(sched_getaffinity(0, sizeof(allowed), &allowed)==0){
+    for(int cpu=0; cpu<CPU_SETSIZE; cpu++)
+      if(CPU_ISSET(cpu, &allowed))
+        cpus[cpu_Count++]=cpu;
+  }else
+    cpus[cpu_Count++]=0;
+
+  // Node 0 is the initial dummy node; producer i uses nodes 1+i*items onwards.
+  node * const nodes=malloc((total+1)*sizeof(node));
+  atomic_init(&nodes[0].next, NULL);
+  queue q;
+  atomic_init(&q.head, &nodes[0]);
+  atomic_init(&q.tail, &nodes[0]);
+  atomic_bool done=false;
+
+  worker_Args * const producer_Args=calloc(producers, sizeof(worker_Args));
+  worker_Args * const consumer_Args=calloc(consumers, sizeof(worker_Args));
+  pthread_t * const producer_Handles=malloc(producers*sizeof(pthread_t));
+  pthread_t * const consumer_Handles=malloc(consumers*sizeof(pthread_t));
+  const uint64_t start=now_Ns();
+  for(size_t i=0; i<consumers; i++){
+    consumer_Args[i].cpu=cpus[(producers+i)%cpu_Count];
+    consumer_Args[i].q=&q;
+    consumer_Args[i].done=&done;
+    pthread_create(&consumer_Handles[i], NULL, consume, &consumer_Args[i]);
+  }
+  for(size_t i=0; i<producers; i++){
+    producer_Args[i].cpu=cpus[i%cpu_Count];
+    producer_Args[i].q=&q;
+    producer_Args[i].nodes=&nodes[1+i*items];
+    producer_Args[i].first=i*items;
+    producer_Args[i].items=items;
+    pthread_create(&producer_Handles[i], NULL, produce, &producer_Args[i]);
+  }
+  for(size_t i=0; i<producers; i++)
+    pthread_join(producer_Handles[i], NULL);
+  atomic_store_explicit(&done, true, memory_order_release);
+  for(size_t i=0; i<consumers; i++)
+    pthread_join(consumer_Handles[i], NULL);
+  const double elapsed=(now_Ns()-start)/1e9;
+
+  uint64_t count=0, sum=0;
+  for(size_t i=0; i<consumers; i++){
+    count+=consumer_Args[i].count;
+    sum+=consumer_Args[i].sum;
+  }
+  if(count!=total){
+    fprintf(stderr, "dequeued %" PRIu64 " items, enqueued %zu\n", count, total);
+    return 1;
+  }
+  printf("%zu producers, %zu consumers, %zu items: %.3f ms, %.2f M items/s\n",
+    producers, consumers, total, elapsed*1e3, total/elapsed/1e6);
+  report("enqueue", producer_Args, producers);
+  report("dequeue", consumer_Args, consumers);
+  printf("verify: %" PRIu64 " items, sum %" PRIu64 "\n", count, sum);
+
+  for(size_t i=0; i<producers; i++)
+    free(producer_Args[i].latencies);
+  for(size_t i=0; i<consumers; i++)
+    free(consumer_Args[i].latencies);
+  free(producer_Handles);
+  free(consumer_Handles);
+  free(producer_Args);
+  free(consumer_Args);
+  free(nodes);
+  return 0;
+}
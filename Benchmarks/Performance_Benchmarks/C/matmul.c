@@ -0,0 +1,76 @@
+// Matrix multiplication benchmark
+//
+// Multiplies square matrices of doubles of size 64, 512 and 2048 with BLAS's
+// cblas_dgemm and reports GFLOPS, counting 2n^3 operations per product. The
+// smaller sizes are repeated so that each one does as much work as a single
+// 2048x2048 product. Link against OpenBLAS or another CBLAS, found with
+// `pkg-config blas`. The Rust version in ../Rust/matmul.rs multiplies the
+// same matrices with hand-written loops.
+//
+// The matrix entries are small integers, so every product is exact whatever
+// the order of the additions, and the checksums printed on the "verify: "
+// lines must match the Rust version's.
+
+#include <cblas.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <time.h>
+
+static const int sizes[]={64, 512, 2048};
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// xorshift64, so the matrices match the Rust version exactly.
+static uint64_t next_Random(uint64_t * const state){
+  *state^=*state<<13;
+  *state^=*state>>7;
+  *state^=*state<<17;
+  return *state;
+}
+
+
+// An n x n row-major matrix of integers in [-8, 8].
+static double * random_Matrix(const int n, uint64_t * const state){
+  double * const m=malloc((size_t)n*n*sizeof(double));
+  for(size_t i=0; i<(size_t)n*n; i++)
+    m[i]=(double)(next_Random(state)%17)-8.0;
+  return m;
+}
+
+
+int main(){
+  const int sizes_Count=sizeof(sizes)/sizeof(sizes[0]);
+  const int largest=sizes[sizes_Count-1];
+  for(int s=0; s<sizes_Count; s++){
+    const int n=sizes[s];
+    uint64_t state=0x2545f4914f6cdd1d;
+    double * const a=random_Matrix(n, &state);
+    double * const b=random_Matrix(n, &state);
+    double * const c=calloc((size_t)n*n, sizeof(double));
+    const long repeats=(long)(largest/n)*(largest/n)*(largest/n);
+
+    const double start=now();
+    for(long r=0; r<repeats; r++)
+      cblas_dgemm(CblasRowMajor, CblasNoTrans, CblasNoTrans, n, n, n, 1.0, a,
+        n, b, n, 0.0, c, n);
+    const double elapsed=now()-start;
+
+    const double flops=2.0*n*n*(double)n*repeats;
+    double checksum=0;
+    for(size_t i=0; i<(size_t)n*n; i++)
+      checksum+=c[i];
+    printf("n %4d: %7.2f GFLOPS (cblas_dgemm)\n", n, flops/elapsed/1e9);
+    printf("verify: n %d checksum %lld\n", n, (long long)checksum);
+    free(a);
+    free(b);
+    free(c);
+  }
+  return 0;
+}
@@ -0,0 +1,101 @@
+// Mutex contention benchmark
+//
+// N threads each increment a shared counter protected by a pthread_mutex_t M
+// times (M defaults to 1 000 000 and can be given as the first argument), for
+// N = 1, 2, 4, ... up to the number of CPUs the process may run on. Thread i
+// is pinned to the i-th of those CPUs, so runs are comparable with the Rust
+// version in ../Rust/mutex-counter, which does the same with an
+// Arc<Mutex<u64>>. Reports the wall time and the increments per second for
+// each N.
+
+#define _GNU_SOURCE
+#include <pthread.h>
+#include <sched.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <time.h>
+
+static pthread_mutex_t lock=PTHREAD_MUTEX_INITIALIZER;
+static uint64_t counter;
+
+typedef struct {
+  int cpu;
+  uint64_t increments;
+} worker_Args;
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+static void *worker(void *arg){
+  const worker_Args * const args=arg;
+  cpu_set_t set;
+  CPU_ZERO(&set);
+  CPU_SET(args->cpu, &set);
+  sched_setaffinity(0, sizeof(set), &set);
+
+  for(uint64_t i=0; i<args->increments; i++){
+    pthread_mutex_lock(&lock);
+    counter++;
+    pthread_mutex_unlock(&lock);
+  }
+  return NULL;
+}
+
+
+// Run threads threads incrementing the counter increments times each and
+// return the elapsed time in seconds.
+static double run(const int threads, const uint64_t increments,
+  const int * const cpus, const int cpu_Count){
+  pthread_t * const handles=malloc(threads*sizeof(pthread_t));
+  worker_Args * const args=malloc(threads*sizeof(worker_Args));
+  counter=0;
+
+  const double start=now();
+  for(int i=0; i<threads; i++){
+    args[i].cpu=cpus[i%cpu_Count];
+    args[i].increments=increments;
+    pthread_create(&handles[i], NULL, worker, &args[i]);
+  }
+  for(int i=0; i<threads; i++)
+    pthread_join(handles[i], NULL);
+  const double elapsed=now()-start;
+
+  if(counter!=threads*increments){
+    fprintf(stderr, "counter is %llu, expected %llu\n",
+      (unsigned long long)counter, (unsigned long long)(threads*increments));
+    exit(1);
+  }
+  free(handles);
+  free(args);
+  return elapsed;
+}
+
+
+int main(int argc, char *argv[]){
+  const uint64_t increments=argc>1 ? strtoull(argv[1], NULL, 10) : 1000000;
+
+  // The CPUs this process may run on.
+  cpu_set_t allowed;
+  int cpus[CPU_SETSIZE], cpu_Count=0;
+  if(sched_getaffinity(0, sizeof(allowed), &allowed)==0){
+    for(int cpu=0; cpu<CPU_SETSIZE; cpu++)
+      if(CPU_ISSET(cpu, &allowed))
+        cpus[cpu_Count++]=cpu;
+  }else
+    cpus[cpu_Count++]=0;
+
+  for(int threads=1;; threads=threads*2<cpu_Count ? threads*2 : cpu_Count){
+    const double elapsed=run(threads, increments, cpus, cpu_Count);
+    printf("threads %3d: %10.3f ms, %8.2f Mops/s\n", threads, elapsed*1e3,
+      threads*increments/elapsed/1e6);
+    if(threads==cpu_Count)
+      break;
+  }
+  return 0;
+}
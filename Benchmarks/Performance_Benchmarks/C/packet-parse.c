@@ -0,0 +1,234 @@
+// Packet header parsing benchmark
+//
+// Generates 1 million synthetic Ethernet/IPv4/TCP packets from a seed (--seed,
+// which the Rust version takes too) and parses their headers by casting the
+// bytes to packed structs, 20 times over, reporting millions of packets per
+// second. The IPv4 and TCP headers carry a few bytes of options, so their
+// lengths vary, and each packet has a payload of up to 63 bytes that is
+// skipped using the IPv4 total length. The fields extracted are hashed, and the
+// hash is printed on a "verify: " line, which must match the one from the Rust
+// version in ../Rust/packet-parse, which parses the packets with nom.
+
+#include <arpa/inet.h>
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#define PACKETS 1000000
+#define ITERATIONS 20
+
+// The largest packet: Ethernet, IPv4 and TCP headers with the most options,
+// and the largest payload.
+#define MAX_PACKET (14+28+32+63)
+
+#define ETHERTYPE_IPV4 0x0800
+#define PROTOCOL_TCP 6
+
+struct ethernet_Header {
+  uint8_t dst[6];
+  uint8_t src[6];
+  uint16_t ethertype;
+} __attribute__((packed));
+
+struct ipv4_Header {
+  uint8_t version_ihl;
+  uint8_t tos;
+  uint16_t total_length;
+  uint16_t id;
+  uint16_t fragment;
+  uint8_t ttl;
+  uint8_t protocol;
+  uint16_t checksum;
+  uint32_t src;
+  uint32_t dst;
+} __attribute__((packed));
+
+struct tcp_Header {
+  uint16_t src_port;
+  uint16_t dst_port;
+  uint32_t seq;
+  uint32_t ack;
+  uint8_t data_offset;
+  uint8_t flags;
+  uint16_t window;
+  uint16_t checksum;
+  uint16_t urgent;
+} __attribute__((packed));
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// xorshift64, so the packets match the Rust version exactly.
+static uint64_t next_Random(uint64_t * const state){
+  *state^=*state<<13;
+  *state^=*state>>7;
+  *state^=*state<<17;
+  return *state;
+}
+
+
+// Write a packet at out, drawing everything from state, and return its size.
+static size_t generate_Packet(uint8_t * const out, uint64_t * const state){
+  const uint64_t dst_Mac=next_Random(state);
+  const uint64_t src_Mac=next_Random(state);
+  const uint64_t r=next_Random(state);
+  const size_t ihl=5+r%3;
+  const size_t data_Offset=5+(r>>2)%4;
+  const size_t payload=(r>>4)%64;
+  const uint64_t ips=next_Random(state);
+  const uint64_t ports_Seq=next_Random(state);
+  const uint32_t ack=(uint32_t)next_Random(state);
+
+  struct ethernet_Header * const eth=(struct ethernet_Header *)out;
+  for(int i=0; i<6; i++){
+    eth->dst[i]=dst_Mac>>(40-8*i);
+    eth->src[i]=src_Mac>>(40-8*i);
+  }
+  eth->ethertype=htons(ETHERTYPE_IPV4);
+
+  struct ipv4_Header * const ip=(struct ipv4_Header *)(out+sizeof(*eth));
+  ip->version_ihl=0x40|ihl;
+  ip->tos=0;
+  ip->total_length=htons(ihl*4+data_Offset*4+payload);
+  ip->id=htons((uint16_t)(r>>18));
+  ip->fragment=htons(0x4000);
+  ip->ttl=(uint8_t)(r>>10);
+  ip->protocol=PROTOCOL_TCP;
+  ip->checksum=0;
+  ip->src=htonl((uint32_t)ips);
+  ip->dst=htonl((uint32_t)(ips>>32));
+  // Options, as no-ops.
+  memset(ip+1, 1, (ihl-5)*4);
+
+  struct tcp_Header * const tcp=(struct tcp_Header *)((uint8_t *)ip+ihl*4);
+  tcp->src_port=htons((uint16_t)ports_Seq);
+  tcp->dst_port=htons((uint16_t)(ports_Seq>>16));
+  tcp->seq=htonl((uint32_t)(ports_Seq>>32));
+  tcp->ack=htonl(ack);
+  tcp->data_offset=data_Offset<<4;
+  tcp->flags=(uint8_t)(r>>34);
+  tcp->window=htons((uint16_t)(r>>42));
+  tcp->checksum=0;
+  tcp->urgent=0;
+  memset(tcp+1, 1, (data_Offset-5)*4);
+
+  memset((uint8_t *)tcp+data_Offset*4, 0, payload);
+  return sizeof(*eth)+ihl*4+data_Offset*4+payload;
+}
+
+
+static uint64_t mac(const uint8_t * const bytes){
+  uint64_t mac=0;
+  for(int i=0; i<6; i++)
+    mac=mac<<8|bytes[i];
+  return mac;
+}
+
+
+// FNV-1a over whole fields rather than bytes.
+static inline uint64_t hash_Field(const uint64_t hash, const uint64_t field){
+  return (hash^field)*0x100000001b3ULL;
+}
+
+
+static void malformed(const size_t packet){
+  fprintf(stderr, "packet %zu is malformed\n", packet);
+  exit(1);
+}
+
+
+// Parse every packet in buffer, returning how many there were and storing the
+// hash of their fields in hash.
+static size_t parse_All(const uint8_t *buffer, size_t size, uint64_t * const hash){
+  size_t packets=0;
+  uint64_t h=0xcbf29ce484222325ULL;
+  while(size>0){
+    if(size<sizeof(struct ethernet_Header)+sizeof(struct ipv4_Header))
+      malformed(packets);
+    const struct ethernet_Header * const eth=(const struct ethernet_Header *)buffer;
+    const struct ipv4_Header * const ip=(const struct ipv4_Header *)(eth+1);
+    const uint16_t ethertype=ntohs(eth->ethertype);
+    const size_t ihl=ip->version_ihl&0xf;
+    if(ethertype!=ETHERTYPE_IPV4 || ip->version_ihl>>4!=4 || ihl<5 ||
+        ip->protocol!=PROTOCOL_TCP)
+      malformed(packets);
+    const size_t total_Length=ntohs(ip->total_length);
+    if(size<sizeof(*eth)+ihl*4+sizeof(struct tcp_Header))
+      malformed(packets);
+    const struct tcp_Header * const tcp=(const struct tcp_Header *)((const uint8_t *)ip+ihl*4);
+    const size_t data_Offset=tcp->data_offset>>4;
+    const size_t headers=ihl*4+data_Offset*4;
+    const size_t length=sizeof(*eth)+(total_Length>headers ? total_Length : headers);
+    if(data_Offset<5 || size<length)
+      malformed(packets);
+
+    h=hash_Field(h, mac(eth->dst));
+    h=hash_Field(h, mac(eth->src));
+    h=hash_Field(h, ethertype);
+    h=hash_Field(h, ihl);
+    h=hash_Field(h, total_Length);
+    h=hash_Field(h, ip->ttl);
+    h=hash_Field(h, ip->protocol);
+    h=hash_Field(h, ntohl(ip->src));
+    h=hash_Field(h, ntohl(ip->dst));
+    h=hash_Field(h, ntohs(tcp->src_port));
+    h=hash_Field(h, ntohs(tcp->dst_port));
+    h=hash_Field(h, ntohl(tcp->seq));
+    h=hash_Field(h, ntohl(tcp->ack));
+    h=hash_Field(h, data_Offset);
+    h=hash_Field(h, tcp->flags);
+    h=hash_Field(h, ntohs(tcp->window));
+    packets++;
+    buffer+=length;
+    size-=length;
+  }
+  *hash=h;
+  return packets;
+}
+
+
+int main(int argc, char *argv[]){
+  uint64_t seed=0x2545f4914f6cdd1dULL;
+  for(int i=1; i<argc; i++){
+    if(strcmp(argv[i], "--seed")==0){
+      char *end;
+      seed=i+1<argc ? strtoull(argv[++i], &end, 10) : 0;
+      if(seed==0 || *end){
+        fprintf(stderr, "--seed takes a non-zero integer\n");
+        return 1;
+      }
+    }
+  }
+
+  uint64_t state=seed;
+  uint8_t * const buffer=malloc((size_t)PACKETS*MAX_PACKET);
+  size_t size=0;
+  for(int i=0; i<PACKETS; i++)
+    size+=generate_Packet(buffer+size, &state);
+
+  const double start=now();
+  size_t packets=0;
+  uint64_t hash=0;
+  for(int i=0; i<ITERATIONS; i++)
+    packets=parse_All(buffer, size, &hash);
+  const double elapsed=now()-start;
+  if(packets!=PACKETS){
+    fprintf(stderr, "parsed %zu packets, generated %d\n", packets, PACKETS);
+    return 1;
+  }
+
+  printf("%zu packets, %zu MB: %.1f M packets/s\n", packets, size/(1024*1024),
+    (double)packets*ITERATIONS/elapsed/1e6);
+  printf("verify: seed %" PRIu64 " %zu packets, fields hash %016" PRIx64 "\n", seed,
+    packets, hash);
+  free(buffer);
+  return 0;
+}
@@ -0,0 +1,75 @@
+// SHA-256 hashing throughput benchmark
+//
+// Hashes 1 GB of pseudo-random data (the size can be changed with the first
+// argument, in MB) in 64 KB chunks with OpenSSL's SHA256_Update, and reports
+// the throughput in MB/s along with the digest. The data is the same in the
+// Rust version in ../Rust/sha256, which hashes it with RustCrypto's
+// sha2::Sha256, so the two digests must match; run.py verify checks they do.
+
+// SHA256_Update is deprecated in OpenSSL 3 in favour of the EVP interface, but
+// it is the function being measured.
+#define OPENSSL_SUPPRESS_DEPRECATED
+#include <openssl/sha.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <time.h>
+
+#define CHUNK_SIZE (64*1024)
+
+// The data is this buffer over and over, so generating it doesn't take longer
+// than hashing it.
+#define BUFFER_SIZE (64*1024*1024)
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// xorshift64, so the data matches the Rust version exactly.
+static uint64_t next_Random(uint64_t * const state){
+  *state^=*state<<13;
+  *state^=*state>>7;
+  *state^=*state<<17;
+  return *state;
+}
+
+
+// Fill buffer with the little-endian bytes of successive random numbers.
+static unsigned char *fill_Buffer(){
+  unsigned char * const buffer=malloc(BUFFER_SIZE);
+  uint64_t state=0x2545f4914f6cdd1dULL;
+  for(size_t i=0; i<BUFFER_SIZE; i+=8){
+    const uint64_t r=next_Random(&state);
+    for(int b=0; b<8; b++)
+      buffer[i+b]=(unsigned char)(r>>(8*b));
+  }
+  return buffer;
+}
+
+
+int main(int argc, char *argv[]){
+  const size_t megabytes=argc>1 ? atoi(argv[1]) : 1024;
+  const size_t chunks=megabytes*1024*1024/CHUNK_SIZE;
+  unsigned char * const buffer=fill_Buffer();
+
+  const double start=now();
+  SHA256_CTX context;
+  SHA256_Init(&context);
+  for(size_t i=0; i<chunks; i++)
+    SHA256_Update(&context, buffer+i*CHUNK_SIZE%BUFFER_SIZE, CHUNK_SIZE);
+  unsigned char digest[SHA256_DIGEST_LENGTH];
+  SHA256_Final(digest, &context);
+  const double elapsed=now()-start;
+
+  printf("%zu MB: %.1f MB/s\n", megabytes, megabytes/elapsed);
+  printf("verify: sha256 ");
+  for(int i=0; i<SHA256_DIGEST_LENGTH; i++)
+    printf("%02x", digest[i]);
+  printf("\n");
+  free(buffer);
+  return 0;
+}
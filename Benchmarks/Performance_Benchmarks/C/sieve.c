@@ -0,0 +1,65 @@
+// Prime sieve benchmark
+//
+// Finds the primes up to 1e6, 1e7 and 1e8 with a single-threaded Sieve of
+// Eratosthenes, storing one bit per number in a hand-managed array of 64-bit
+// words. Each line gives the number of primes found, which is checked against
+// the known count and by run.py verify, and the time per candidate in
+// nanoseconds. The Rust version in ../Rust/sieve runs the same sieve over a
+// bitvec::BitVec.
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <time.h>
+
+// N, and the number of primes up to N.
+static const size_t limits[]={1000000, 10000000, 100000000};
+static const size_t expected_Primes[]={78498, 664579, 5761455};
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// Count the primes up to n.
+static size_t sieve(const size_t n){
+  const size_t words=(n+1+63)/64;
+  uint64_t * const composite=calloc(words, sizeof(uint64_t));
+  for(size_t i=2; i*i<=n; i++){
+    if(!(composite[i/64]>>(i%64)&1)){
+      for(size_t j=i*i; j<=n; j+=i)
+        composite[j/64]|=(uint64_t)1<<(j%64);
+    }
+  }
+
+  // 0 and 1 aren't primes, and neither are the bits past n in the last word.
+  composite[0]|=3;
+  if((n+1)%64)
+    composite[words-1]|=~(uint64_t)0<<((n+1)%64);
+  size_t primes=0;
+  for(size_t i=0; i<words; i++)
+    primes+=64-__builtin_popcountll(composite[i]);
+  free(composite);
+  return primes;
+}
+
+
+int main(){
+  for(size_t i=0; i<sizeof(limits)/sizeof(limits[0]); i++){
+    const double start=now();
+    const size_t primes=sieve(limits[i]);
+    const double elapsed=now()-start;
+    if(primes!=expected_Primes[i]){
+      fprintf(stderr, "found %zu primes up to %zu, expected %zu\n", primes,
+        limits[i], expected_Primes[i]);
+      return 1;
+    }
+    printf("n %9zu: %7zu primes, %.3f ns/candidate\n", limits[i], primes,
+      elapsed*1e9/(limits[i]-1));
+    printf("verify: %zu primes up to %zu\n", primes, limits[i]);
+  }
+  return 0;
+}
@@ -0,0 +1,148 @@
+// Slice copying benchmark
+//
+// Copies buffers of 1 KB, 64 KB, 1 MB and 256 MB of pseudo-random bytes over
+// and over, 2 GB in all for each size, and reports GB/s. The first variant
+// copies into a buffer allocated once with memcpy, as the Rust version in
+// ../Rust/slice-copy.rs does with extend_from_slice on a Vec made with
+// Vec::with_capacity. The second starts from an empty buffer each time and
+// appends to it 4 KB at a time with realloc, doubling its capacity whenever
+// it is full like a Rust Vec, so that it goes through the allocator's
+// reallocation path. Every copy is checked against the source, and the FNV-1a
+// hashes of the last copies of each size are printed on "verify: " lines.
+
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+// The bytes copied for each size and variant.
+#define TOTAL (2UL*1024*1024*1024)
+
+// The growing variant appends this many bytes at a time.
+#define CHUNK 4096
+
+static const struct {
+  const char *name;
+  size_t size;
+} sizes[]={{"1 KB", 1024}, {"64 KB", 64*1024}, {"1 MB", 1024*1024},
+  {"256 MB", 256*1024*1024}};
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// xorshift64, so the data matches the Rust version exactly.
+static uint64_t next_Random(uint64_t * const state){
+  *state^=*state<<13;
+  *state^=*state>>7;
+  *state^=*state<<17;
+  return *state;
+}
+
+
+// Like Rust's black_box: the compiler must assume p's memory is read and
+// written.
+static inline void escape(void * const p){
+  __asm__ volatile("" : : "r"(p) : "memory");
+}
+
+
+// FNV-1a, to summarize a copy.
+static uint64_t fnv1a(const uint8_t * const bytes, const size_t size){
+  uint64_t hash=0xcbf29ce484222325ULL;
+  for(size_t i=0; i<size; i++)
+    hash=(hash^bytes[i])*0x100000001b3ULL;
+  return hash;
+}
+
+
+static void check(const char * const name, const char * const variant,
+  const uint8_t * const dst, const uint8_t * const src, const size_t size){
+  if(memcmp(dst, src, size)!=0){
+    fprintf(stderr, "%s %s copy differs from the source\n", name, variant);
+    exit(1);
+  }
+}
+
+
+static uint8_t *preallocated(const uint8_t * const src, const size_t size,
+  const size_t repeats){
+  uint8_t * const dst=malloc(size);
+  for(size_t r=0; r<repeats; r++){
+    memcpy(dst, src, size);
+    escape(dst);
+  }
+  return dst;
+}
+
+
+static uint8_t *growing(const uint8_t * const src, const size_t size,
+  const size_t repeats){
+  uint8_t *dst=NULL;
+  for(size_t r=0; r<repeats; r++){
+    free(dst);
+    dst=NULL;
+    size_t length=0, capacity=0;
+    for(size_t offset=0; offset<size; offset+=CHUNK){
+      const size_t n=size-offset<CHUNK ? size-offset : CHUNK;
+      if(length+n>capacity){
+        capacity=capacity*2>length+n ? capacity*2 : length+n;
+        if(capacity<8)
+          capacity=8;
+        dst=realloc(dst, capacity);
+      }
+      memcpy(dst+length, src+offset, n);
+      length+=n;
+    }
+    escape(dst);
+  }
+  return dst;
+}
+
+
+// Copy src with copy and return the GB/s, storing the hash of the copy in hash.
+static double measure(const char * const name, const char * const variant,
+  const uint8_t * const src, const size_t size,
+  uint8_t *(*copy)(const uint8_t *, size_t, size_t), uint64_t * const hash){
+  const size_t repeats=TOTAL/size;
+  const double start=now();
+  uint8_t * const dst=copy(src, size, repeats);
+  const double elapsed=now()-start;
+  check(name, variant, dst, src, size);
+  *hash=fnv1a(dst, size);
+  free(dst);
+  return (double)size*repeats/(1024.0*1024*1024)/elapsed;
+}
+
+
+int main(){
+  const size_t count=sizeof(sizes)/sizeof(sizes[0]);
+  const size_t largest=sizes[count-1].size;
+  uint8_t * const data=malloc(largest);
+  uint64_t state=0x2545f4914f6cdd1dULL;
+  for(size_t i=0; i<largest; i+=8){
+    const uint64_t r=next_Random(&state);
+    for(int b=0; b<8; b++)
+      data[i+b]=(uint8_t)(r>>(8*b));
+  }
+
+  for(size_t i=0; i<count; i++){
+    uint64_t pre_Hash, grow_Hash;
+    const double pre=measure(sizes[i].name, "preallocated", data, sizes[i].size,
+      preallocated, &pre_Hash);
+    const double grow=measure(sizes[i].name, "growing", data, sizes[i].size, growing,
+      &grow_Hash);
+    printf("%-6s: preallocated %6.2f GB/s, growing %6.2f GB/s\n", sizes[i].name, pre,
+      grow);
+    printf("verify: %s copied, hashes %016" PRIx64 " %016" PRIx64 "\n", sizes[i].name,
+      pre_Hash, grow_Hash);
+  }
+  free(data);
+  return 0;
+}
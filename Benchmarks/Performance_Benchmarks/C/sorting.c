@@ -0,0 +1,111 @@
+// Sorting benchmark
+//
+// Sorts uint32_t arrays of 1 000, 100 000 and 10 000 000 elements (the largest
+// size can be changed with the first argument) drawn from four distributions:
+// random, sorted, reverse-sorted, and nearly sorted, where 1% of the elements
+// of a sorted array were swapped at random. Each input is sorted with qsort,
+// and the mean time per sort is reported for each (algorithm, distribution,
+// size). The Rust version in ../Rust/sorting.rs sorts the same inputs with
+// slice::sort and slice::sort_unstable.
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+// Small inputs are sorted repeatedly until this many elements were sorted.
+#define ELEMENTS_PER_MEASUREMENT 10000000
+
+static const char * const distributions[]={"random", "sorted", "reversed",
+  "nearly-sorted"};
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// xorshift64, so the inputs match the Rust version exactly.
+static uint64_t next_Random(uint64_t * const state){
+  *state^=*state<<13;
+  *state^=*state>>7;
+  *state^=*state<<17;
+  return *state;
+}
+
+
+static void fill_Input(uint32_t * const input, const char * const distribution,
+  const size_t size){
+  uint64_t state=0x2545f4914f6cdd1dULL;
+  if(!strcmp(distribution, "random")){
+    for(size_t i=0; i<size; i++)
+      input[i]=(uint32_t)next_Random(&state);
+  }else if(!strcmp(distribution, "sorted")){
+    for(size_t i=0; i<size; i++)
+      input[i]=i;
+  }else if(!strcmp(distribution, "reversed")){
+    for(size_t i=0; i<size; i++)
+      input[i]=size-1-i;
+  }else{
+    for(size_t i=0; i<size; i++)
+      input[i]=i;
+    for(size_t n=0; n<size/100; n++){
+      const size_t i=next_Random(&state)%size;
+      const size_t j=next_Random(&state)%size;
+      const uint32_t tmp=input[i];
+      input[i]=input[j];
+      input[j]=tmp;
+    }
+  }
+}
+
+
+static int compare_U32(const void * const a, const void * const b){
+  const uint32_t x=*(const uint32_t *)a, y=*(const uint32_t *)b;
+  return (x>y)-(x<y);
+}
+
+
+// Sort copies of input with qsort and return the mean time per sort in
+// milliseconds.
+static double measure(const uint32_t * const input, const size_t size){
+  size_t iterations=ELEMENTS_PER_MEASUREMENT/size;
+  if(iterations==0)
+    iterations=1;
+  uint32_t * const v=malloc(size*sizeof(uint32_t));
+  double total=0;
+  for(size_t n=0; n<iterations; n++){
+    memcpy(v, input, size*sizeof(uint32_t));
+    const double start=now();
+    qsort(v, size, sizeof(uint32_t), compare_U32);
+    total+=now()-start;
+    for(size_t i=1; i<size; i++){
+      if(v[i-1]>v[i]){
+        fprintf(stderr, "output is not sorted\n");
+        exit(1);
+      }
+    }
+  }
+  free(v);
+  return total/iterations*1e3;
+}
+
+
+int main(int argc, char *argv[]){
+  const size_t largest=argc>1 ? (size_t)atol(argv[1]) : 10000000;
+  const size_t sizes[]={1000, 100000, largest};
+
+  for(size_t i=0; i<sizeof(sizes)/sizeof(sizes[0]); i++){
+    uint32_t * const input=malloc(sizes[i]*sizeof(uint32_t));
+    for(size_t j=0; j<sizeof(distributions)/sizeof(distributions[0]); j++){
+      fill_Input(input, distributions[j], sizes[i]);
+      printf("%-14s %-14s %9zu: %10.3f ms\n", "qsort", distributions[j],
+        sizes[i], measure(input, sizes[i]));
+    }
+    free(input);
+  }
+  return 0;
+}
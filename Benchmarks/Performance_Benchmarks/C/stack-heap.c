@@ -0,0 +1,102 @@
+// Stack vs heap allocation benchmark
+//
+// Runs the same loop body twice, once on a 4096-byte local array on the stack
+// and once on a buffer from malloc, so that the difference between the two is
+// the cost of the allocation and free alone. Each iteration zeroes the buffer,
+// writes two bytes that depend on the iteration and reads them back into a
+// checksum, with the buffer escaping through an empty asm statement so that it
+// isn't optimized away. The number of iterations defaults to 10 000 000 and
+// can be given with --iterations, which the Rust version in
+// ../Rust/stack-heap.rs takes too; both print the checksums on "verify: "
+// lines. Reports nanoseconds per iteration for each variant and the difference
+// between them.
+//
+// Run through run.py with --allocator jemalloc or --allocator mimalloc to
+// compare the heap variant across allocators.
+
+#include <inttypes.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#define SIZE 4096
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// Write to buf for iteration i and return what to add to the checksum.
+static inline uint64_t work(uint8_t * const buf, const uint64_t i){
+  const size_t a=i%SIZE, b=(i*7+1)%SIZE;
+  buf[a]=(uint8_t)i;
+  buf[b]+=(uint8_t)(i>>8);
+  // Like Rust's black_box: the compiler must assume buf is read and written.
+  __asm__ volatile("" : : "r"(buf) : "memory");
+  return (uint64_t)buf[a]+buf[b];
+}
+
+
+static uint64_t stack(const uint64_t iterations){
+  uint64_t checksum=0;
+  for(uint64_t i=0; i<iterations; i++){
+    uint8_t buf[SIZE];
+    memset(buf, 0, SIZE);
+    checksum+=work(buf, i);
+  }
+  return checksum;
+}
+
+
+static uint64_t heap(const uint64_t iterations){
+  uint64_t checksum=0;
+  for(uint64_t i=0; i<iterations; i++){
+    uint8_t * const buf=malloc(SIZE);
+    memset(buf, 0, SIZE);
+    checksum+=work(buf, i);
+    free(buf);
+  }
+  return checksum;
+}
+
+
+// Run variant and return the nanoseconds per iteration, storing the checksum
+// in checksum.
+static double measure(const uint64_t iterations, uint64_t (*variant)(uint64_t),
+  uint64_t * const checksum){
+  const double start=now();
+  *checksum=variant(iterations);
+  return (now()-start)*1e9/iterations;
+}
+
+
+int main(int argc, char *argv[]){
+  uint64_t iterations=10000000;
+  for(int i=1; i<argc; i++){
+    if(strcmp(argv[i], "--iterations")==0){
+      char *end;
+      iterations=i+1<argc ? strtoull(argv[++i], &end, 10) : 0;
+      if(iterations==0 || *end){
+        fprintf(stderr, "--iterations takes a positive integer\n");
+        return 1;
+      }
+    }
+  }
+
+  uint64_t stack_Checksum, heap_Checksum;
+  const double stack_Ns=measure(iterations, stack, &stack_Checksum);
+  const double heap_Ns=measure(iterations, heap, &heap_Checksum);
+  printf("stack: %8.2f ns/iteration\n", stack_Ns);
+  printf("heap : %8.2f ns/iteration\n", heap_Ns);
+  printf("allocation overhead: %8.2f ns\n", heap_Ns-stack_Ns);
+  printf("verify: stack %" PRIu64 " iterations, checksum %" PRIu64 "\n", iterations,
+    stack_Checksum);
+  printf("verify: heap %" PRIu64 " iterations, checksum %" PRIu64 "\n", iterations,
+    heap_Checksum);
+  return 0;
+}
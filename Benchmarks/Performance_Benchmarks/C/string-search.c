@@ -0,0 +1,103 @@
+// Substring search throughput benchmark
+//
+// Searches for a needle in haystacks of 1 KB, 1 MB and 256 MB, with the needle
+// placed at the start, the middle and the end, using strstr and memmem. The
+// largest haystack can be changed with the first argument (in MB). Throughput
+// is the number of haystack bytes up to the end of the match, in GB/s. The
+// Rust version in ../Rust/string-search performs the same searches with the
+// memchr crate.
+
+#define _GNU_SOURCE
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+static const char needle[]="needle-in-a-haystack";
+#define NEEDLE_LEN (sizeof(needle)-1)
+
+// Every search is repeated until it has scanned at least this many bytes.
+#define BYTES_PER_MEASUREMENT ((size_t)1<<30)
+
+static const size_t positions[]={0, 50, 100};
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// Fill haystack with size bytes of text that never contains the needle, except
+// at percent% of the way through, and NUL-terminate it for strstr. Returns the
+// offset of the needle.
+static size_t fill_Haystack(char * const haystack, const size_t size,
+  const size_t percent){
+  static const char filler[]="the quick brown fox jumps over the lazy dog ";
+  for(size_t i=0; i<size; i++)
+    haystack[i]=filler[i%(sizeof(filler)-1)];
+  haystack[size]='\0';
+  const size_t offset=(size-NEEDLE_LEN)*percent/100;
+  memcpy(haystack+offset, needle, NEEDLE_LEN);
+  return offset;
+}
+
+
+static const char *search_Strstr(const char * const haystack,
+  const size_t size){
+  (void)size;
+  return strstr(haystack, needle);
+}
+
+
+static const char *search_Memmem(const char * const haystack,
+  const size_t size){
+  return memmem(haystack, size, needle, NEEDLE_LEN);
+}
+
+
+// Run search until it has scanned BYTES_PER_MEASUREMENT bytes, checking it
+// finds the needle at offset, and return the throughput in GB/s.
+static double measure(const char * const haystack, const size_t size,
+  const size_t offset,
+  const char *(*search)(const char * const, const size_t)){
+  // Calling through a volatile pointer keeps the searches from being hoisted
+  // out of the loop.
+  const char *(* volatile search_Fn)(const char * const, const size_t)=search;
+  const size_t scanned=offset+NEEDLE_LEN;
+  size_t iterations=BYTES_PER_MEASUREMENT/scanned;
+  if(iterations==0)
+    iterations=1;
+
+  const double start=now();
+  for(size_t i=0; i<iterations; i++){
+    if(search_Fn(haystack, size)!=haystack+offset){
+      fprintf(stderr, "needle not found at offset %zu\n", offset);
+      exit(1);
+    }
+  }
+  const double elapsed=now()-start;
+  return (double)scanned*iterations/elapsed/1e9;
+}
+
+
+int main(int argc, char *argv[]){
+  const size_t megabytes=argc>1 ? atoi(argv[1]) : 256;
+  const size_t sizes[]={1024, 1024*1024, megabytes*1024*1024};
+
+  for(size_t i=0; i<sizeof(sizes)/sizeof(sizes[0]); i++){
+    char * const haystack=malloc(sizes[i]+1);
+    for(size_t j=0; j<sizeof(positions)/sizeof(positions[0]); j++){
+      const size_t offset=fill_Haystack(haystack, sizes[i], positions[j]);
+      const double strstr_Rate=measure(haystack, sizes[i], offset,
+        search_Strstr);
+      const double memmem_Rate=measure(haystack, sizes[i], offset,
+        search_Memmem);
+      printf("haystack %10zu needle at %3zu%%: strstr %.2f GB/s, memmem %.2f GB/s\n",
+        sizes[i], positions[j], strstr_Rate, memmem_Rate);
+    }
+    free(haystack);
+  }
+  return 0;
+}
@@ -0,0 +1,108 @@
+// UTF-8 validation benchmark
+//
+// Generates 100 MB of UTF-8 text from random code points (the size can be
+// changed with the first argument, in MB) and validates it by calling
+// utf8proc_iterate in a loop, reporting MB/s. A second pass validates a copy in
+// which 1% of the bytes were replaced with 0xff, which is never valid UTF-8, to
+// measure the error path, counting the invalid bytes. The Rust version in
+// ../Rust/utf8-validate.rs validates the same text with std::str::from_utf8,
+// and both print the number of invalid bytes on their "verify: " lines.
+
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+#include <utf8proc.h>
+
+// Each pass validates the text this many times.
+#define REPEATS 10
+
+
+static double now(){
+  struct timespec ts;
+  clock_gettime(CLOCK_MONOTONIC, &ts);
+  return ts.tv_sec+ts.tv_nsec/1e9;
+}
+
+
+// xorshift64, so the text matches the Rust version exactly.
+static uint64_t next_Random(uint64_t * const state){
+  *state^=*state<<13;
+  *state^=*state>>7;
+  *state^=*state<<17;
+  return *state;
+}
+
+
+// Fill size bytes with random code points, a quarter each of the one, two,
+// three and four byte ones, padding the end with spaces.
+static uint8_t * random_Text(const size_t size, uint64_t * const state){
+  static const uint32_t ranges[4][2]={
+    {0, 0x80}, {0x80, 0x800}, {0x800, 0x10000}, {0x10000, 0x110000}
+  };
+  uint8_t * const text=malloc(size);
+  size_t length=0;
+  while(length+4<=size){
+    const uint64_t r=next_Random(state);
+    const uint32_t low=ranges[r%4][0], high=ranges[r%4][1];
+    uint32_t c=low+(uint32_t)((r>>2)%(high-low));
+    // Move surrogates, which can't be encoded, down into valid code points.
+    if(c>=0xd800 && c<0xe000)
+      c-=0x800;
+    length+=utf8proc_encode_char((utf8proc_int32_t)c, text+length);
+  }
+  memset(text+length, ' ', size-length);
+  return text;
+}
+
+
+// Replace about 1% of the bytes of text with 0xff.
+static void corrupt(uint8_t * const text, const size_t size, uint64_t * const state){
+  for(size_t i=0; i<size; i++){
+    if(next_Random(state)%100==0)
+      text[i]=0xff;
+  }
+}
+
+
+// Count the bytes of text that aren't part of a valid UTF-8 sequence.
+static size_t count_Invalid(const uint8_t * const text, const size_t size){
+  size_t invalid=0, i=0;
+  utf8proc_int32_t c;
+  while(i<size){
+    const utf8proc_ssize_t length=utf8proc_iterate(text+i, (utf8proc_ssize_t)(size-i), &c);
+    if(length<0){
+      invalid++;
+      i++;
+    } else {
+      i+=length;
+    }
+  }
+  return invalid;
+}
+
+
+static void validate(const char * const name, const uint8_t * const text, const size_t size){
+  const double start=now();
+  size_t invalid=0;
+  for(int i=0; i<REPEATS; i++)
+    invalid=count_Invalid(text, size);
+  const double elapsed=now()-start;
+  const double megabytes=(double)size*REPEATS/(1024*1024);
+  printf("%-10s: %8.1f MB/s, %zu invalid bytes\n", name, megabytes/elapsed, invalid);
+  printf("verify: %s %zu invalid bytes\n", name, invalid);
+}
+
+
+int main(int argc, char *argv[]){
+  const size_t megabytes=argc>1 ? atoi(argv[1]) : 100;
+  const size_t size=megabytes*1024*1024;
+  uint64_t state=0x2545f4914f6cdd1dULL;
+  uint8_t * const text=random_Text(size, &state);
+  validate("valid", text, size);
+  corrupt(text, size, &state);
+  validate("1% invalid", text, size);
+  free(text);
+  return 0;
+}
@@ -0,0 +1,80 @@
+/// Allocation throughput benchmark
+///
+/// Measures how fast the global allocator serves three patterns: many small
+/// allocations of 16-256 bytes, a few large allocations of 1-16 MB, and a mix
+/// of mostly small and medium allocations with the occasional large one.
+/// Allocations are made in batches that are all live at once, written to, and
+/// then freed, and the sizes come from the same generator as in the C version
+/// in ../C/alloc-throughput.c, which uses malloc/free. Each allocation is a
+/// `Vec::with_capacity`, a single uninitialized allocation like a boxed slice.
+/// Reports millions of allocation/free pairs per second for each pattern.
+///
+/// Run through run.py with `--allocator jemalloc` or `--allocator mimalloc` to
+/// compare allocators; both versions then use the same one.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+// xorshift64, so the sizes match the C version exactly.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn between(&mut self, low: usize, high: usize) -> usize {
+        low + (self.next() % (high - low + 1) as u64) as usize
+    }
+}
+
+const MB: usize = 1024 * 1024;
+
+fn small(rng: &mut Rng) -> usize {
+    rng.between(16, 256)
+}
+
+fn large(rng: &mut Rng) -> usize {
+    rng.between(MB, 16 * MB)
+}
+
+// One in a thousand allocations is large, a tenth of the rest are up to 64 KB.
+fn mixed(rng: &mut Rng) -> usize {
+    match rng.next() % 1000 {
+        0 => large(rng),
+        1..=99 => rng.between(257, 64 * 1024),
+        _ => small(rng),
+    }
+}
+
+// Make `count` allocations with sizes from `size`, `batch` at a time, and
+// return the millions of allocations per second.
+fn measure(count: usize, batch: usize, size: fn(&mut Rng) -> usize) -> f64 {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let mut live: Vec<Vec<u8>> = Vec::with_capacity(batch);
+    let start = Instant::now();
+    for _ in 0..count / batch {
+        for _ in 0..batch {
+            let mut v = Vec::with_capacity(size(&mut rng));
+            v.push(1u8);
+            live.push(black_box(v));
+        }
+        live.clear();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    (count / batch * batch) as f64 / elapsed / 1e6
+}
+
+fn main() {
+    let patterns: [(&str, usize, usize, fn(&mut Rng) -> usize); 3] = [
+        ("small", 10_000_000, 1000, small),
+        ("large", 2_000, 4, large),
+        ("mixed", 1_000_000, 1000, mixed),
+    ];
+    for &(name, count, batch, size) in patterns.iter() {
+        println!("{:6}: {:8.2} M allocations/s", name, measure(count, batch, size));
+    }
+}
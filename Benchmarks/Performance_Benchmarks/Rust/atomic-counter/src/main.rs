@@ -0,0 +1,96 @@
+/// Atomic fetch-add benchmark
+///
+/// N threads each add 1 to a shared `AtomicU64` with `fetch_add` M times (M
+/// defaults to 10 000 000 and can be given as the first argument), for N = 1,
+/// 2, 4, 8 and 16, first with `Ordering::Relaxed` and then with
+/// `Ordering::SeqCst`. Thread i is pinned to the i-th CPU the process may run
+/// on, wrapping around when there are more threads than CPUs, so runs are
+/// comparable with the C version in ../../C/atomic-counter.c, which does the
+/// same with an `_Atomic uint64_t` and atomic_fetch_add_explicit. Reports the
+/// wall time and the operations per second for each N and ordering.
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+const THREADS: [usize; 5] = [1, 2, 4, 8, 16];
+
+// The CPUs this process may run on.
+fn allowed_cpus() -> Vec<usize> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set) != 0 {
+            return vec![0];
+        }
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .collect()
+    }
+}
+
+// Pin the calling thread to `cpu`.
+fn pin(cpu: usize) {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set);
+    }
+}
+
+// Run `threads` threads adding to a shared counter `operations` times each
+// with `ordering` and return the elapsed time in seconds.
+fn run(threads: usize, operations: u64, ordering: Ordering, cpus: &[usize]) -> f64 {
+    let counter = Arc::new(AtomicU64::new(0));
+    let start = Instant::now();
+    let handles: Vec<_> = (0..threads)
+        .map(|i| {
+            let counter = Arc::clone(&counter);
+            let cpu = cpus[i % cpus.len()];
+            thread::spawn(move || {
+                pin(cpu);
+                for _ in 0..operations {
+                    counter.fetch_add(1, ordering);
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let total = counter.load(Ordering::SeqCst);
+    if total != threads as u64 * operations {
+        eprintln!(
+            "counter is {}, expected {}",
+            total,
+            threads as u64 * operations
+        );
+        std::process::exit(1);
+    }
+    elapsed
+}
+
+fn main() {
+    let operations: u64 = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(10_000_000);
+    let cpus = allowed_cpus();
+
+    for (name, ordering) in [
+        ("relaxed", Ordering::Relaxed),
+        ("seq_cst", Ordering::SeqCst),
+    ] {
+        for threads in THREADS {
+            let elapsed = run(threads, operations, ordering, &cpus);
+            println!(
+                "{} threads {:3}: {:10.3} ms, {:8.2} Mops/s",
+                name,
+                threads,
+                elapsed * 1e3,
+                (threads as u64 * operations) as f64 / elapsed / 1e6
+            );
+        }
+    }
+}
@@ -0,0 +1,96 @@
+/// Base64 encoding and decoding benchmark
+///
+/// Encodes 100 MB of pseudo-random bytes (the size can be changed with the
+/// first argument, in MB) with the `base64` crate's standard engine, decodes
+/// the result, and then decodes it again with a newline after every 64
+/// characters, as in PEM files. The engine rejects whitespace, so the last
+/// pass filters it out before decoding, and the time includes doing so. Each
+/// pass runs 5 times and reports MB/s of raw data. The data is the same in
+/// the C version in ../../C/base64.c, which uses OpenSSL's EVP_EncodeBlock,
+/// EVP_DecodeBlock and EVP_DecodeUpdate, so the hashes of the encoded text
+/// printed on the `verify: ` lines must match.
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::time::Instant;
+
+const REPEATS: usize = 5;
+
+// Characters per line in the text with embedded whitespace.
+const LINE_LENGTH: usize = 64;
+
+// xorshift64, so the data matches the C version exactly.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+// FNV-1a, to summarize the encoded text.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Run `pass` REPEATS times and print the MB/s of `size` raw bytes.
+fn measure(name: &str, size: usize, mut pass: impl FnMut()) {
+    let start = Instant::now();
+    for _ in 0..REPEATS {
+        pass();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let megabytes = (size * REPEATS) as f64 / (1024.0 * 1024.0);
+    println!("{:17}: {:8.1} MB/s", name, megabytes / elapsed);
+}
+
+fn check_decoded(name: &str, decoded: &[u8], data: &[u8]) {
+    if decoded != data {
+        eprintln!("{} doesn't give back the data", name);
+        std::process::exit(1);
+    }
+    println!("verify: {} {} bytes", name, decoded.len());
+}
+
+fn main() {
+    let megabytes: usize = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(100);
+    let size = megabytes * 1024 * 1024;
+    let mut state = 0x2545f4914f6cdd1d;
+    let data: Vec<u8> = (0..size / 8)
+        .flat_map(|_| next_random(&mut state).to_le_bytes())
+        .collect();
+
+    let mut encoded = vec![0; base64::encoded_len(size, true).unwrap()];
+    measure("encode", size, || {
+        STANDARD.encode_slice(&data, &mut encoded).unwrap();
+    });
+    println!(
+        "verify: encoded {} bytes, hash {:016x}",
+        encoded.len(),
+        fnv1a(&encoded)
+    );
+
+    let mut decoded = vec![0; size];
+    let mut length = 0;
+    measure("decode", size, || {
+        length = STANDARD.decode_slice(&encoded, &mut decoded).unwrap();
+    });
+    check_decoded("decoded", &decoded[..length], &data);
+
+    let mut wrapped = Vec::with_capacity(encoded.len() + encoded.len() / LINE_LENGTH + 1);
+    for line in encoded.chunks(LINE_LENGTH) {
+        wrapped.extend_from_slice(line);
+        wrapped.push(b'\n');
+    }
+    let mut decoded = vec![0; size];
+    let mut stripped = Vec::with_capacity(encoded.len());
+    measure("decode with lines", size, || {
+        stripped.clear();
+        stripped.extend(wrapped.iter().filter(|b| !b.is_ascii_whitespace()));
+        length = STANDARD.decode_slice(&stripped, &mut decoded).unwrap();
+    });
+    check_decoded("decoded with lines", &decoded[..length], &data);
+}
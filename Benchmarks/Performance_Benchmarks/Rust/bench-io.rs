@@ -0,0 +1,91 @@
+/// Sequential I/O throughput benchmark
+///
+/// Writes a temporary file of the requested size (in MB, default 64) through
+/// `BufWriter` with several buffer sizes, reads it back through `BufReader`,
+/// and reports the throughput of each pass in MB/s. The C version in
+/// ../C/bench-io.c performs the same passes with fwrite/fread.
+
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::Instant;
+
+// Size of each individual write()/read() call.
+const CHUNK_SIZE: usize = 4096;
+
+const BUFFER_SIZES: [usize; 3] = [8 * 1024, 64 * 1024, 1024 * 1024];
+
+// Write `file_size` bytes to `path` through a `BufWriter` of `buffer_size`
+// bytes and return the elapsed time in seconds.
+fn write_file(path: &Path, file_size: usize, buffer_size: usize) -> f64 {
+    let mut chunk = [0u8; CHUNK_SIZE];
+    for (i, b) in chunk.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+
+    let start = Instant::now();
+    let file = File::create(path).unwrap();
+    let mut writer = BufWriter::with_capacity(buffer_size, file);
+    let mut written = 0;
+    while written < file_size {
+        writer.write_all(&chunk).unwrap();
+        written += CHUNK_SIZE;
+    }
+    let file = writer.into_inner().unwrap();
+    file.sync_all().unwrap();
+    start.elapsed().as_secs_f64()
+}
+
+// Read `path` back through a `BufReader` of `buffer_size` bytes and return the
+// elapsed time in seconds. The checksum keeps the reads from being optimized
+// away.
+fn read_file(path: &Path, file_size: usize, buffer_size: usize) -> f64 {
+    let mut chunk = [0u8; CHUNK_SIZE];
+    let mut checksum: u64 = 0;
+    let mut total = 0;
+
+    let start = Instant::now();
+    let file = File::open(path).unwrap();
+    let mut reader = BufReader::with_capacity(buffer_size, file);
+    loop {
+        let count = reader.read(&mut chunk).unwrap();
+        if count == 0 {
+            break;
+        }
+        checksum += chunk[count - 1] as u64;
+        total += count;
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    if total != file_size {
+        eprintln!("short read: {} of {} bytes", total, file_size);
+        std::process::exit(1);
+    }
+    if checksum == 0 {
+        println!();
+    }
+    elapsed
+}
+
+fn main() {
+    let megabytes = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(64);
+    let file_size = megabytes * 1024 * 1024;
+
+    let path = std::env::temp_dir().join(format!("bench-io-{}", std::process::id()));
+
+    for &buffer_size in BUFFER_SIZES.iter() {
+        let write_time = write_file(&path, file_size, buffer_size);
+        let read_time = read_file(&path, file_size, buffer_size);
+        println!(
+            "buffer {:7}: write {:.1} MB/s, read {:.1} MB/s",
+            buffer_size,
+            megabytes as f64 / write_time,
+            megabytes as f64 / read_time
+        );
+    }
+
+    fs::remove_file(&path).unwrap();
+}
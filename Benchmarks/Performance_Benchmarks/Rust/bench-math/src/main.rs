@@ -0,0 +1,97 @@
+/// Math library benchmark
+///
+/// Computes `sin`, `sqrt`, `powi` and `exp` of 1M pseudo-random `f64`s in
+/// [0.5, 2), 50 times over, and reports millions of operations per second.
+/// `powi` raises the i-th value to the power i % 16. The exact variant calls
+/// the `f64` methods, like the C version in ../../C/bench-math.c calls sin(),
+/// sqrt(), pow() and exp() from <math.h>. The fast variant uses the
+/// `fast-math` crate, whose only counterpart of these is an `f32`
+/// approximation of `exp`, so it computes `exp` in `f32` and falls back to
+/// the `f64` methods for the others; the C version builds its fast variant
+/// with -ffast-math instead. The sum of the values the exact variant computes
+/// is printed on `verify: ` lines, rounded so that the last-bit differences
+/// between `powi` and pow() don't show.
+use std::hint::black_box;
+use std::time::Instant;
+
+const VALUES: usize = 1_000_000;
+
+const REPEATS: usize = 50;
+
+type Function = fn(&[f64], &mut [f64]);
+
+const FUNCTIONS: [(&str, Function, Function); 4] = [
+    ("sin", exact_sin, exact_sin),
+    ("sqrt", exact_sqrt, exact_sqrt),
+    ("pow", exact_powi, exact_powi),
+    ("exp", exact_exp, fast_exp),
+];
+
+// xorshift64, so the data matches the C version exactly.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+fn exact_sin(input: &[f64], output: &mut [f64]) {
+    for (out, x) in output.iter_mut().zip(input) {
+        *out = x.sin();
+    }
+}
+
+fn exact_sqrt(input: &[f64], output: &mut [f64]) {
+    for (out, x) in output.iter_mut().zip(input) {
+        *out = x.sqrt();
+    }
+}
+
+fn exact_powi(input: &[f64], output: &mut [f64]) {
+    for (i, (out, x)) in output.iter_mut().zip(input).enumerate() {
+        *out = x.powi((i % 16) as i32);
+    }
+}
+
+fn exact_exp(input: &[f64], output: &mut [f64]) {
+    for (out, x) in output.iter_mut().zip(input) {
+        *out = x.exp();
+    }
+}
+
+fn fast_exp(input: &[f64], output: &mut [f64]) {
+    for (out, &x) in output.iter_mut().zip(input) {
+        *out = fast_math::exp(x as f32) as f64;
+    }
+}
+
+// Apply `f` to `input` REPEATS times and return the millions of operations per
+// second.
+fn measure(input: &[f64], output: &mut [f64], f: Function) -> f64 {
+    let start = Instant::now();
+    for _ in 0..REPEATS {
+        f(black_box(input), output);
+        black_box(&mut *output);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    (VALUES * REPEATS) as f64 / 1e6 / elapsed
+}
+
+fn main() {
+    let mut state = 0x2545f4914f6cdd1d;
+    let input: Vec<f64> = (0..VALUES)
+        .map(|_| 0.5 + 1.5 * ((next_random(&mut state) >> 11) as f64 / 9007199254740992.0))
+        .collect();
+    let mut output = vec![0.0; VALUES];
+
+    for &(name, exact, fast) in FUNCTIONS.iter() {
+        let exact = measure(&input, &mut output, exact);
+        let sum: f64 = output.iter().sum();
+        let fast = measure(&input, &mut output, fast);
+        println!(
+            "{:4}: exact {:8.2} Mops/s, fast {:8.2} Mops/s",
+            name, exact, fast
+        );
+        println!("verify: {} of {} values, sum {:.3}", name, VALUES, sum);
+    }
+}
@@ -0,0 +1,49 @@
+/// BLAKE3 hashing throughput benchmark
+///
+/// Hashes 1 GB of pseudo-random data (the size can be changed with the first
+/// argument, in MB) in 64 KB chunks with the `blake3` crate, and reports the
+/// throughput in MB/s along with the digest. The data is the same in the C
+/// version in ../../C/blake3.c, which hashes it with the BLAKE3 C reference
+/// implementation, so the two digests must match; `run.py verify` checks
+/// they do.
+use std::time::Instant;
+
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// The data is this buffer over and over, so generating it doesn't take longer
+// than hashing it.
+const BUFFER_SIZE: usize = 64 * 1024 * 1024;
+
+// xorshift64, so the data matches the C version exactly.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+fn main() {
+    let megabytes: usize = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1024);
+    let mut state = 0x2545f4914f6cdd1d;
+    let buffer: Vec<u8> = (0..BUFFER_SIZE / 8)
+        .flat_map(|_| next_random(&mut state).to_le_bytes())
+        .collect();
+
+    let start = Instant::now();
+    let mut hasher = blake3::Hasher::new();
+    for chunk in buffer
+        .chunks(CHUNK_SIZE)
+        .cycle()
+        .take(megabytes * 1024 * 1024 / CHUNK_SIZE)
+    {
+        hasher.update(chunk);
+    }
+    let digest = hasher.finalize();
+    let elapsed = start.elapsed().as_secs_f64();
+
+    println!("{} MB: {:.1} MB/s", megabytes, megabytes as f64 / elapsed);
+    println!("verify: blake3 {}", digest.to_hex());
+}
@@ -0,0 +1,114 @@
+/// Hash map benchmark
+///
+/// Performs N inserts of random `u64 -> u64` entries (N defaults to 1 000 000
+/// and can be given as the first argument), then N successful lookups, N
+/// failed lookups and N removals, on a `HashMap` with the default SipHash
+/// hasher and on an `FxHashMap`. Reports the mean time per operation for each
+/// (map, operation), and how much the resident set size grew while inserting.
+/// Setting `HASH_MAP` to `std` or `fx` runs only that map, which is how
+/// run.py times each of them on its own. The C version in ../../C/hash-map.c
+/// performs the same operations on a single uthash table.
+use rustc_hash::FxHashMap;
+use std::collections::HashMap;
+use std::fs;
+use std::hash::BuildHasher;
+use std::hint::black_box;
+use std::time::Instant;
+
+// xorshift64, so the keys match the C version exactly.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+// Inserted keys are odd and missing keys even, so lookups of the latter
+// always fail.
+fn keys(n: usize) -> (Vec<u64>, Vec<u64>) {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    let present = (0..n).map(|_| rng.next() | 1).collect();
+    let missing = (0..n).map(|_| rng.next() & !1).collect();
+    (present, missing)
+}
+
+// The current resident set size in KB, from /proc/self/status.
+fn rss_kb() -> u64 {
+    let status = fs::read_to_string("/proc/self/status").unwrap_or_default();
+    status
+        .lines()
+        .find(|line| line.starts_with("VmRSS:"))
+        .and_then(|line| line.split_whitespace().nth(1))
+        .and_then(|kb| kb.parse().ok())
+        .unwrap_or(0)
+}
+
+// Run `op` on every key and return the mean time per call in nanoseconds.
+fn measure(keys: &[u64], mut op: impl FnMut(u64)) -> f64 {
+    let start = Instant::now();
+    for &key in keys {
+        op(key);
+    }
+    start.elapsed().as_secs_f64() * 1e9 / keys.len() as f64
+}
+
+fn bench<S: BuildHasher>(
+    name: &str,
+    mut map: HashMap<u64, u64, S>,
+    present: &[u64],
+    missing: &[u64],
+) {
+    let rss_before = rss_kb();
+
+    let insert = measure(present, |key| {
+        map.insert(key, key.wrapping_mul(3));
+    });
+    let rss = rss_kb().saturating_sub(rss_before);
+    let hit = measure(present, |key| {
+        if black_box(map.get(&key)).is_none() {
+            panic!("key {} is missing", key);
+        }
+    });
+    let miss = measure(missing, |key| {
+        if black_box(map.get(&key)).is_some() {
+            panic!("key {} is present", key);
+        }
+    });
+    let remove = measure(present, |key| {
+        black_box(map.remove(&key));
+    });
+    assert!(map.is_empty());
+
+    for (op, ns) in [
+        ("insert", insert),
+        ("lookup-hit", hit),
+        ("lookup-miss", miss),
+        ("remove", remove),
+    ] {
+        println!("{:10} {:12}: {:8.1} ns/op", name, op, ns);
+    }
+    println!("{:10} {:12}: {:8} KB", name, "rss-growth", rss);
+}
+
+fn main() {
+    let n = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1_000_000);
+    let (present, missing) = keys(n);
+    let maps = std::env::var("HASH_MAP").unwrap_or_default();
+    if !matches!(maps.as_str(), "" | "std" | "fx") {
+        eprintln!("HASH_MAP is `{}`, expected std or fx", maps);
+        std::process::exit(2);
+    }
+    if maps != "fx" {
+        bench("HashMap", HashMap::new(), &present, &missing);
+    }
+    if maps != "std" {
+        bench("FxHashMap", FxHashMap::default(), &present, &missing);
+    }
+}
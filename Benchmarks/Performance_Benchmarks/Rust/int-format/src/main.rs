@@ -0,0 +1,40 @@
+/// Integer formatting benchmark
+///
+/// Formats 10 million random u64 values as decimal strings with an
+/// `itoa::Buffer`, appending the digits to a preallocated output buffer.
+/// `#[bench_main]` runs it `--warmup` times and then `--iter` times timed and
+/// prints the JSON line run.py reads the time from. The values are generated
+/// from `--seed` on the first run, so only formatting is timed after the
+/// warm-up; seed 0, the default, gives the values the C version in
+/// ../../C/int-format.c formats the same way with snprintf.
+use bench_main::bench_main;
+use std::sync::OnceLock;
+
+const COUNT: usize = 10_000_000;
+
+static VALUES: OnceLock<Vec<u64>> = OnceLock::new();
+
+// xorshift64, so the values match the C version exactly.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+#[bench_main]
+fn int_format(seed: u64) -> Vec<u8> {
+    let values = VALUES.get_or_init(|| {
+        // xorshift64 never leaves a zero state.
+        let mut state = (0x2545f4914f6cdd1d ^ seed).max(1);
+        (0..COUNT).map(|_| next_random(&mut state)).collect()
+    });
+
+    // Room for every value at its longest, 20 digits.
+    let mut output = Vec::with_capacity(COUNT * 20);
+    let mut buffer = itoa::Buffer::new();
+    for &value in values {
+        output.extend_from_slice(buffer.format(value).as_bytes());
+    }
+    output
+}
@@ -0,0 +1,150 @@
+/// JSON serialization latency benchmark
+///
+/// Parses the JSON document in the file given as the first argument (1 MB as
+/// generated by Random_Input_Generator/random_gen_json.py), or a similar one
+/// generated in memory without an argument, into a `serde_json::Value` once,
+/// then serializes it back to a compact string over and over, and reports the
+/// mean and fastest time per serialization along with the throughput. The C
+/// version in ../../C/json-serialize.c does the same with cJSON.
+use serde_json::Value;
+use std::fs;
+use std::hint::black_box;
+use std::time::Instant;
+
+const ITERATIONS: usize = 200;
+
+// Without a file, the document is generated: an array of randomly nested
+// objects like random_gen_json.py writes, of at least this many bytes. The C
+// version generates the same one.
+const GENERATED_SIZE: usize = 1024 * 1024;
+
+const MAX_DEPTH: u32 = 5;
+
+// xorshift64, so the document matches the C version exactly.
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: u64) -> u64 {
+        self.next() % n
+    }
+}
+
+fn generate_value(out: &mut String, random: &mut Random, depth: u32) {
+    // Mostly plain text, with the odd character that has to be escaped or
+    // encoded as more than one byte.
+    const PLAIN: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ0123456789        ";
+    const SPECIAL: [&str; 5] = ["\\\"", "\\\\", "\\n", "\\t", "é"];
+    let kind = random.below(100);
+    if depth < MAX_DEPTH && kind < 15 {
+        generate_object(out, random, depth + 1);
+    } else if depth < MAX_DEPTH && kind < 25 {
+        out.push('[');
+        for i in 0..random.below(9) {
+            if i > 0 {
+                out.push(',');
+            }
+            generate_value(out, random, depth + 1);
+        }
+        out.push(']');
+    } else if kind < 45 {
+        out.push('"');
+        for _ in 0..random.below(41) {
+            let c = random.below(PLAIN.len() as u64 + 5) as usize;
+            match PLAIN.get(c) {
+                Some(&c) => out.push(c as char),
+                None => out.push_str(SPECIAL[c - PLAIN.len()]),
+            }
+        }
+        out.push('"');
+    } else if kind < 65 {
+        let value = random.below((1 << 32) + 1) as i64 - (1 << 31);
+        out.push_str(&value.to_string());
+    } else if kind < 80 {
+        // Thousandths, so both versions print exactly the same digits.
+        let value = random.below(2_000_000_001) as i64 - 1_000_000_000;
+        let sign = if value < 0 { "-" } else { "" };
+        let magnitude = value.abs();
+        out.push_str(&format!(
+            "{}{}.{:03}",
+            sign,
+            magnitude / 1000,
+            magnitude % 1000
+        ));
+    } else if kind < 90 {
+        out.push_str(if random.below(2) == 1 {
+            "true"
+        } else {
+            "false"
+        });
+    } else {
+        out.push_str("null");
+    }
+}
+
+fn generate_object(out: &mut String, random: &mut Random, depth: u32) {
+    out.push('{');
+    for i in 0..1 + random.below(8) {
+        if i > 0 {
+            out.push(',');
+        }
+        // The entry's index keeps the keys of an object distinct.
+        out.push('"');
+        for _ in 0..3 + random.below(8) {
+            out.push((b'a' + random.below(26) as u8) as char);
+        }
+        out.push_str(&i.to_string());
+        out.push_str("\":");
+        generate_value(out, random, depth);
+    }
+    out.push('}');
+}
+
+fn generate_document() -> String {
+    let mut out = String::from("[");
+    let mut random = Random(0x2545f4914f6cdd1d);
+    while out.len() < GENERATED_SIZE {
+        if out.len() > 1 {
+            out.push(',');
+        }
+        generate_object(&mut out, &mut random, 0);
+    }
+    out.push(']');
+    out
+}
+
+fn main() {
+    let document = match std::env::args().nth(1) {
+        Some(path) => fs::read_to_string(&path).unwrap_or_else(|e| {
+            eprintln!("{}: {}", path, e);
+            std::process::exit(1);
+        }),
+        None => generate_document(),
+    };
+    let value: Value = serde_json::from_str(&document).unwrap();
+
+    let mut size = 0;
+    let mut fastest = f64::INFINITY;
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        let serialize_start = Instant::now();
+        let output = serde_json::to_string(black_box(&value)).unwrap();
+        fastest = fastest.min(serialize_start.elapsed().as_secs_f64());
+        size = output.len();
+        drop(black_box(output));
+    }
+    let mean = start.elapsed().as_secs_f64() / ITERATIONS as f64;
+    println!(
+        "{} bytes: mean {:.3} ms, fastest {:.3} ms, {:.1} MB/s",
+        size,
+        mean * 1e3,
+        fastest * 1e3,
+        size as f64 / (1024.0 * 1024.0) / mean
+    );
+}
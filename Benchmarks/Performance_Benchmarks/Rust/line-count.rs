@@ -0,0 +1,88 @@
+/// Line reading throughput benchmark
+///
+/// Writes files of 10 MB, 100 MB and 1 GB of text (the largest size can be
+/// changed with the first argument, in MB) and counts their lines with
+/// `BufReader::lines()` over a `File`, reporting millions of lines per second.
+/// The files go to /dev/shm, or the temporary directory where there is none,
+/// so they are read from memory rather than the disk; each is written before
+/// its reading is timed and removed afterwards. The C version in
+/// ../C/line-count.c writes the same text and reads it with getline.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::PathBuf;
+use std::time::Instant;
+
+const ALPHABET: &[u8] = b"abcdefghijklmnopqrstuvwxyz0123456789 ";
+
+// Lines are up to this many bytes long, not counting the newline.
+const MAX_LINE: u64 = 120;
+
+// xorshift64, so the text matches the C version exactly.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+// The file to write the text to, in /dev/shm if there is one.
+fn text_path() -> PathBuf {
+    let shm = PathBuf::from("/dev/shm");
+    let dir = if shm.is_dir() { shm } else { std::env::temp_dir() };
+    dir.join(format!("line-count-{}.txt", std::process::id()))
+}
+
+// Write lines of random length to `path` until there are at least `size`
+// bytes, and return the number of lines written.
+fn write_text(path: &PathBuf, size: usize) -> usize {
+    let mut file = BufWriter::new(File::create(path).unwrap());
+    let mut line = Vec::with_capacity(MAX_LINE as usize + 1);
+    let mut state = 0x2545f4914f6cdd1d;
+    let (mut written, mut lines) = (0, 0);
+    while written < size {
+        let r = next_random(&mut state);
+        let start = (r >> 8) as usize % ALPHABET.len();
+        line.clear();
+        line.extend(ALPHABET.iter().cycle().skip(start).take((r % MAX_LINE) as usize));
+        line.push(b'\n');
+        file.write_all(&line).unwrap();
+        written += line.len();
+        lines += 1;
+    }
+    file.flush().unwrap();
+    lines
+}
+
+// Count the lines of the file at `path` and return the count and the elapsed
+// time in seconds.
+fn count_lines(path: &PathBuf) -> (usize, f64) {
+    let start = Instant::now();
+    let reader = BufReader::new(File::open(path).unwrap());
+    let lines = reader.lines().map(|line| line.unwrap()).count();
+    (lines, start.elapsed().as_secs_f64())
+}
+
+fn main() {
+    let megabytes: usize = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1024);
+    let path = text_path();
+
+    for size in [10, 100, megabytes] {
+        let expected = write_text(&path, size * 1024 * 1024);
+        let (lines, elapsed) = count_lines(&path);
+        std::fs::remove_file(&path).unwrap();
+        if lines != expected {
+            eprintln!("counted {} lines, wrote {}", lines, expected);
+            std::process::exit(1);
+        }
+        println!(
+            "{:5} MB: {} lines, {:.1} M lines/s",
+            size,
+            lines,
+            lines as f64 / elapsed / 1e6
+        );
+    }
+}
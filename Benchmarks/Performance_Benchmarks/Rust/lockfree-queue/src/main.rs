@@ -0,0 +1,183 @@
+/// Lock-free queue benchmark
+///
+/// P producer threads each push M items onto a shared
+/// `crossbeam_queue::ArrayQueue` while C consumer threads pop from it until
+/// the producers are done and the queue is empty (P and C default to 4 and
+/// can be given with `--producers` and `--consumers`, M defaults to 1 000 000
+/// and can be given with `--items`). The queue has room for every item, so
+/// pushes never fail, like in the unbounded Michael-Scott queue of the C
+/// version in ../../C/lockfree-queue.c. Threads are pinned to the CPUs the
+/// process may run on, producers first, as in the C version. Every 16th push
+/// and successful pop is timed, and the 50th, 99th and 99.9th percentile and
+/// maximum latencies are reported along with the total throughput. The sum of
+/// the items popped is printed on a `verify: ` line.
+use crossbeam_queue::ArrayQueue;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Instant;
+
+// One in this many operations is timed.
+const SAMPLE_EVERY: usize = 16;
+
+// The CPUs this process may run on.
+fn allowed_cpus() -> Vec<usize> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set) != 0 {
+            return vec![0];
+        }
+        (0..libc::CPU_SETSIZE as usize)
+            .filter(|&cpu| libc::CPU_ISSET(cpu, &set))
+            .collect()
+    }
+}
+
+// Pin the calling thread to `cpu`.
+fn pin(cpu: usize) {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set);
+    }
+}
+
+// The value of the command line option `name`, or `default` without it.
+fn option(args: &[String], name: &str, default: usize) -> usize {
+    match args.iter().position(|a| a == name) {
+        Some(i) => match args.get(i + 1).and_then(|s| s.parse().ok()) {
+            Some(n) if n != 0 => n,
+            _ => {
+                eprintln!("{} takes a positive integer", name);
+                std::process::exit(1);
+            }
+        },
+        None => default,
+    }
+}
+
+// Push `items` values starting at `first`, returning the sampled latencies
+// in nanoseconds.
+fn produce(queue: &ArrayQueue<u64>, first: u64, items: usize) -> Vec<u64> {
+    let mut latencies = Vec::with_capacity(items / SAMPLE_EVERY + 1);
+    for i in 0..items {
+        let value = first + i as u64;
+        if i % SAMPLE_EVERY == 0 {
+            let start = Instant::now();
+            queue.push(value).unwrap();
+            latencies.push(start.elapsed().as_nanos() as u64);
+        } else {
+            queue.push(value).unwrap();
+        }
+    }
+    latencies
+}
+
+// Pop until the producers are `done` and the queue is empty, returning the
+// number and sum of the items popped and the sampled latencies.
+fn consume(queue: &ArrayQueue<u64>, done: &AtomicBool) -> (u64, u64, Vec<u64>) {
+    let (mut count, mut sum) = (0, 0);
+    let mut latencies = Vec::new();
+    loop {
+        // Read before popping: if the producers were done then, an empty
+        // queue stays empty.
+        let finished = done.load(Ordering::Acquire);
+        let sampled = count as usize % SAMPLE_EVERY == 0;
+        let start = Instant::now();
+        match queue.pop() {
+            Some(value) => {
+                if sampled {
+                    latencies.push(start.elapsed().as_nanos() as u64);
+                }
+                count += 1;
+                sum += value;
+            }
+            None if finished => return (count, sum, latencies),
+            None => thread::yield_now(),
+        }
+    }
+}
+
+// The `p`th percentile of `sorted`.
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+fn report(name: &str, mut latencies: Vec<u64>) {
+    latencies.sort_unstable();
+    println!(
+        "{} latency: p50 {} ns, p99 {} ns, p99.9 {} ns, max {} ns",
+        name,
+        percentile(&latencies, 50.0),
+        percentile(&latencies, 99.0),
+        percentile(&latencies, 99.9),
+        latencies[latencies.len() - 1]
+    );
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let producers = option(&args, "--producers", 4);
+    let consumers = option(&args, "--consumers", 4);
+    let items = option(&args, "--items", 1_000_000);
+    let total = producers * items;
+    let cpus = allowed_cpus();
+
+    let queue = ArrayQueue::new(total);
+    let done = AtomicBool::new(false);
+    let start = Instant::now();
+    let (pushes, pops) = thread::scope(|s| {
+        let consumer_handles: Vec<_> = (0..consumers)
+            .map(|i| {
+                let cpu = cpus[(producers + i) % cpus.len()];
+                let (queue, done) = (&queue, &done);
+                s.spawn(move || {
+                    pin(cpu);
+                    consume(queue, done)
+                })
+            })
+            .collect();
+        let producer_handles: Vec<_> = (0..producers)
+            .map(|i| {
+                let cpu = cpus[i % cpus.len()];
+                let queue = &queue;
+                s.spawn(move || {
+                    pin(cpu);
+                    produce(queue, (i * items) as u64, items)
+                })
+            })
+            .collect();
+        let pushes: Vec<_> = producer_handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect();
+        done.store(true, Ordering::Release);
+        let pops: Vec<_> = consumer_handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect();
+        (pushes, pops)
+    });
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let count: u64 = pops.iter().map(|(count, _, _)| count).sum();
+    let sum: u64 = pops.iter().map(|(_, sum, _)| sum).sum();
+    if count != total as u64 {
+        eprintln!("popped {} items, pushed {}", count, total);
+        std::process::exit(1);
+    }
+    println!(
+        "{} producers, {} consumers, {} items: {:.3} ms, {:.2} M items/s",
+        producers,
+        consumers,
+        total,
+        elapsed * 1e3,
+        total as f64 / elapsed / 1e6
+    );
+    report("enqueue", pushes.into_iter().flatten().collect());
+    report(
+        "dequeue",
+        pops.into_iter().flat_map(|(_, _, latencies)| latencies).collect(),
+    );
+    println!("verify: {} items, sum {}", count, sum);
+}
@@ -0,0 +1,136 @@
+/// Matrix multiplication benchmark
+///
+/// Multiplies square matrices of doubles of size 64, 512 and 2048 and
+/// reports GFLOPS, counting 2n^3 operations per product. The smaller sizes
+/// are repeated so that each one does as much work as a single 2048x2048
+/// product. Built with AVX2 enabled (`-C target-cpu=native` on a machine that
+/// has it) the products use a hand-written AVX2 kernel, and a plain loop that
+/// is left for LLVM to vectorize otherwise. The C version in ../C/matmul.c
+/// calls BLAS's `cblas_dgemm` on the same matrices.
+///
+/// The matrix entries are small integers, so every product is exact whatever
+/// the order of the additions, and the checksums printed on the `verify: `
+/// lines must match the C version's.
+use std::time::Instant;
+
+const SIZES: [usize; 3] = [64, 512, 2048];
+
+// The AVX2 kernel works through B in blocks of this many rows and columns,
+// which stay in cache while every row of C is computed.
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+const BLOCK: usize = 128;
+
+// xorshift64, so the matrices match the C version exactly.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+// An n x n row-major matrix of integers in [-8, 8].
+fn random_matrix(n: usize, state: &mut u64) -> Vec<f64> {
+    (0..n * n)
+        .map(|_| (next_random(state) % 17) as f64 - 8.0)
+        .collect()
+}
+
+// Computes c = a * b for n x n row-major matrices, four rows and eight
+// columns of c at a time, which keeps the partial sums in eight registers.
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+fn multiply(n: usize, a: &[f64], b: &[f64], c: &mut [f64]) {
+    use std::arch::x86_64::*;
+    use std::cmp;
+
+    assert!(n % 8 == 0 && a.len() == n * n && b.len() == n * n && c.len() == n * n);
+    for x in c.iter_mut() {
+        *x = 0.0;
+    }
+    for k0 in (0..n).step_by(BLOCK) {
+        let k1 = cmp::min(k0 + BLOCK, n);
+        for j0 in (0..n).step_by(BLOCK) {
+            let j1 = cmp::min(j0 + BLOCK, n);
+            for i in (0..n).step_by(4) {
+                for j in (j0..j1).step_by(8) {
+                    // Safe because of the assertion above: i + 3 and j + 7 are
+                    // below n.
+                    unsafe {
+                        let mut sums = [[_mm256_setzero_pd(); 2]; 4];
+                        for k in k0..k1 {
+                            let b_row = b.as_ptr().add(k * n + j);
+                            let b_lo = _mm256_loadu_pd(b_row);
+                            let b_hi = _mm256_loadu_pd(b_row.add(4));
+                            for r in 0..4 {
+                                let a_ik = _mm256_broadcast_sd(&*a.as_ptr().add((i + r) * n + k));
+                                sums[r][0] = _mm256_add_pd(sums[r][0], _mm256_mul_pd(a_ik, b_lo));
+                                sums[r][1] = _mm256_add_pd(sums[r][1], _mm256_mul_pd(a_ik, b_hi));
+                            }
+                        }
+                        for r in 0..4 {
+                            let c_row = c.as_mut_ptr().add((i + r) * n + j);
+                            _mm256_storeu_pd(
+                                c_row,
+                                _mm256_add_pd(_mm256_loadu_pd(c_row), sums[r][0]),
+                            );
+                            let c_hi = c_row.add(4);
+                            _mm256_storeu_pd(
+                                c_hi,
+                                _mm256_add_pd(_mm256_loadu_pd(c_hi), sums[r][1]),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(target_arch = "x86_64", target_feature = "avx2"))]
+const KERNEL: &str = "avx2";
+
+// Computes c = a * b for n x n row-major matrices, adding a row of b scaled
+// by a[i][k] to row i of c, which LLVM vectorizes for whatever the target
+// supports.
+#[cfg(not(all(target_arch = "x86_64", target_feature = "avx2")))]
+fn multiply(n: usize, a: &[f64], b: &[f64], c: &mut [f64]) {
+    for x in c.iter_mut() {
+        *x = 0.0;
+    }
+    for (a_row, c_row) in a.chunks(n).zip(c.chunks_mut(n)) {
+        for (a_ik, b_row) in a_row.iter().zip(b.chunks(n)) {
+            for (c_ij, b_kj) in c_row.iter_mut().zip(b_row) {
+                *c_ij += a_ik * b_kj;
+            }
+        }
+    }
+}
+
+#[cfg(not(all(target_arch = "x86_64", target_feature = "avx2")))]
+const KERNEL: &str = "portable";
+
+fn main() {
+    let largest = SIZES[SIZES.len() - 1];
+    for &n in SIZES.iter() {
+        let mut state: u64 = 0x2545f4914f6cdd1d;
+        let a = random_matrix(n, &mut state);
+        let b = random_matrix(n, &mut state);
+        let mut c = vec![0.0; n * n];
+        let repeats = (largest / n).pow(3);
+
+        let start = Instant::now();
+        for _ in 0..repeats {
+            multiply(n, &a, &b, &mut c);
+        }
+        let elapsed = start.elapsed().as_secs_f64();
+
+        let flops = 2.0 * (n as f64).powi(3) * repeats as f64;
+        let checksum: f64 = c.iter().sum();
+        println!(
+            "n {:4}: {:7.2} GFLOPS ({})",
+            n,
+            flops / elapsed / 1e9,
+            KERNEL
+        );
+        println!("verify: n {} checksum {}", n, checksum as i64);
+    }
+}
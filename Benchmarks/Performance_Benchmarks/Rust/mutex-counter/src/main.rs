@@ -0,0 +1,85 @@
+/// Mutex contention benchmark
+///
+/// N threads each increment a shared `Arc<Mutex<u64>>` M times (M defaults to
+/// 1 000 000 and can be given as the first argument), for N = 1, 2, 4, ... up
+/// to `std::thread::available_parallelism()`. Thread i is pinned to the i-th CPU the process may
+/// run on, so runs are comparable with the C version in
+/// ../../C/mutex-counter.c, which does the same with a pthread_mutex_t.
+/// Reports the wall time and the increments per second for each N.
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Instant;
+
+// The CPUs this process may run on.
+fn allowed_cpus() -> Vec<usize> {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        if libc::sched_getaffinity(0, std::mem::size_of_val(&set), &mut set) != 0 {
+            return vec![0];
+        }
+        (0..libc::CPU_SETSIZE as usize).filter(|&cpu| libc::CPU_ISSET(cpu, &set)).collect()
+    }
+}
+
+// Pin the calling thread to `cpu`.
+fn pin(cpu: usize) {
+    unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(cpu, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of_val(&set), &set);
+    }
+}
+
+// Run `threads` threads incrementing a shared counter `increments` times each
+// and return the elapsed time in seconds.
+fn run(threads: usize, increments: u64, cpus: &[usize]) -> f64 {
+    let counter = Arc::new(Mutex::new(0u64));
+    let start = Instant::now();
+    let handles: Vec<_> = (0..threads)
+        .map(|i| {
+            let counter = Arc::clone(&counter);
+            let cpu = cpus[i % cpus.len()];
+            thread::spawn(move || {
+                pin(cpu);
+                for _ in 0..increments {
+                    *counter.lock().unwrap() += 1;
+                }
+            })
+        })
+        .collect();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let total = *counter.lock().unwrap();
+    if total != threads as u64 * increments {
+        eprintln!("counter is {}, expected {}", total, threads as u64 * increments);
+        std::process::exit(1);
+    }
+    elapsed
+}
+
+fn main() {
+    let increments: u64 = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(1_000_000);
+    let cpus = allowed_cpus();
+    let max_threads = thread::available_parallelism().map_or(1, |n| n.get());
+
+    let mut threads = 1;
+    loop {
+        let elapsed = run(threads, increments, &cpus);
+        println!(
+            "threads {:3}: {:10.3} ms, {:8.2} Mops/s",
+            threads,
+            elapsed * 1e3,
+            (threads as u64 * increments) as f64 / elapsed / 1e6
+        );
+        if threads == max_threads {
+            break;
+        }
+        threads = (threads * 2).min(max_threads);
+    }
+}
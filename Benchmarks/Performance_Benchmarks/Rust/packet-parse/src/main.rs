@@ -0,0 +1,252 @@
+/// Packet header parsing benchmark
+///
+/// Generates 1 million synthetic Ethernet/IPv4/TCP packets from a seed
+/// (`--seed`, which the C version takes too) and parses their headers with
+/// `nom` parser combinators, 20 times over, reporting millions of packets
+/// per second. The IPv4 and TCP headers carry a few bytes of options, so their
+/// lengths vary, and each packet has a payload of up to 63 bytes that is
+/// skipped using the IPv4 total length. The fields extracted are hashed, and
+/// the hash is printed on a `verify: ` line, which must match the one from
+/// the C version in ../../C/packet-parse.c, which casts the bytes to packed
+/// structs.
+use nom::bytes::complete::take;
+use nom::combinator::{map, verify};
+use nom::number::complete::{be_u16, be_u32, be_u8};
+use nom::sequence::tuple;
+use nom::IResult;
+use std::time::Instant;
+
+const PACKETS: usize = 1_000_000;
+const ITERATIONS: usize = 20;
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const PROTOCOL_TCP: u8 = 6;
+
+// xorshift64, so the packets match the C version exactly.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+// The header fields we extract, in the order they are hashed.
+struct Packet {
+    dst_mac: u64,
+    src_mac: u64,
+    ethertype: u16,
+    ihl: u8,
+    total_length: u16,
+    ttl: u8,
+    protocol: u8,
+    src_ip: u32,
+    dst_ip: u32,
+    src_port: u16,
+    dst_port: u16,
+    seq: u32,
+    ack: u32,
+    data_offset: u8,
+    flags: u8,
+    window: u16,
+}
+
+// Append a packet to `buffer`, drawing everything from `state`.
+fn generate_packet(buffer: &mut Vec<u8>, state: &mut u64) {
+    let dst_mac = next_random(state);
+    let src_mac = next_random(state);
+    let r = next_random(state);
+    let ihl = 5 + (r % 3) as usize;
+    let data_offset = 5 + ((r >> 2) % 4) as usize;
+    let payload = ((r >> 4) % 64) as usize;
+    let ips = next_random(state);
+    let ports_seq = next_random(state);
+    let ack = next_random(state) as u32;
+
+    buffer.extend_from_slice(&dst_mac.to_be_bytes()[2..]);
+    buffer.extend_from_slice(&src_mac.to_be_bytes()[2..]);
+    buffer.extend_from_slice(&ETHERTYPE_IPV4.to_be_bytes());
+
+    let total_length = (ihl * 4 + data_offset * 4 + payload) as u16;
+    buffer.push(0x40 | ihl as u8);
+    buffer.push(0);
+    buffer.extend_from_slice(&total_length.to_be_bytes());
+    buffer.extend_from_slice(&((r >> 18) as u16).to_be_bytes());
+    buffer.extend_from_slice(&0x4000u16.to_be_bytes());
+    buffer.push((r >> 10) as u8);
+    buffer.push(PROTOCOL_TCP);
+    buffer.extend_from_slice(&[0, 0]);
+    buffer.extend_from_slice(&(ips as u32).to_be_bytes());
+    buffer.extend_from_slice(&((ips >> 32) as u32).to_be_bytes());
+    // Options, as no-ops.
+    buffer.resize(buffer.len() + (ihl - 5) * 4, 1);
+
+    buffer.extend_from_slice(&(ports_seq as u16).to_be_bytes());
+    buffer.extend_from_slice(&((ports_seq >> 16) as u16).to_be_bytes());
+    buffer.extend_from_slice(&((ports_seq >> 32) as u32).to_be_bytes());
+    buffer.extend_from_slice(&ack.to_be_bytes());
+    buffer.push((data_offset as u8) << 4);
+    buffer.push((r >> 34) as u8);
+    buffer.extend_from_slice(&((r >> 42) as u16).to_be_bytes());
+    buffer.extend_from_slice(&[0, 0, 0, 0]);
+    buffer.resize(buffer.len() + (data_offset - 5) * 4, 1);
+
+    buffer.resize(buffer.len() + payload, 0);
+}
+
+fn mac(input: &[u8]) -> IResult<&[u8], u64> {
+    map(take(6usize), |bytes: &[u8]| {
+        bytes.iter().fold(0, |mac, &b| mac << 8 | b as u64)
+    })(input)
+}
+
+// Parse one packet, returning the rest of the buffer after its payload.
+fn packet(input: &[u8]) -> IResult<&[u8], Packet> {
+    let (input, (dst_mac, src_mac, ethertype)) =
+        tuple((mac, mac, verify(be_u16, |&t| t == ETHERTYPE_IPV4)))(input)?;
+
+    let ip_start = input;
+    let (input, (version_ihl, _tos, total_length, _id, _fragment, ttl, protocol)) = tuple((
+        verify(be_u8, |&b| b >> 4 == 4 && b & 0xf >= 5),
+        be_u8,
+        be_u16,
+        be_u16,
+        be_u16,
+        be_u8,
+        verify(be_u8, |&p| p == PROTOCOL_TCP),
+    ))(input)?;
+    let (input, (_checksum, src_ip, dst_ip)) = tuple((be_u16, be_u32, be_u32))(input)?;
+    let ihl = version_ihl & 0xf;
+    let (input, _options) = take((ihl as usize - 5) * 4)(input)?;
+
+    let (input, (src_port, dst_port, seq, ack, offset, flags, window, _checksum, _urgent)) =
+        tuple((
+            be_u16,
+            be_u16,
+            be_u32,
+            be_u32,
+            verify(be_u8, |&b| b >> 4 >= 5),
+            be_u8,
+            be_u16,
+            be_u16,
+            be_u16,
+        ))(input)?;
+    let data_offset = offset >> 4;
+    let (input, _options) = take((data_offset as usize - 5) * 4)(input)?;
+
+    // Skip the payload, which is whatever of the IPv4 packet is left.
+    let headers = ip_start.len() - input.len();
+    let (input, _payload) = take((total_length as usize).saturating_sub(headers))(input)?;
+
+    let packet = Packet {
+        dst_mac,
+        src_mac,
+        ethertype,
+        ihl,
+        total_length,
+        ttl,
+        protocol,
+        src_ip,
+        dst_ip,
+        src_port,
+        dst_port,
+        seq,
+        ack,
+        data_offset,
+        flags,
+        window,
+    };
+    Ok((input, packet))
+}
+
+// FNV-1a over whole fields rather than bytes.
+fn hash_fields(hash: u64, packet: &Packet) -> u64 {
+    [
+        packet.dst_mac,
+        packet.src_mac,
+        packet.ethertype as u64,
+        packet.ihl as u64,
+        packet.total_length as u64,
+        packet.ttl as u64,
+        packet.protocol as u64,
+        packet.src_ip as u64,
+        packet.dst_ip as u64,
+        packet.src_port as u64,
+        packet.dst_port as u64,
+        packet.seq as u64,
+        packet.ack as u64,
+        packet.data_offset as u64,
+        packet.flags as u64,
+        packet.window as u64,
+    ]
+    .iter()
+    .fold(hash, |hash, &field| {
+        (hash ^ field).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Parse every packet in `buffer` and return how many there were and the
+// hash of their fields.
+fn parse_all(mut buffer: &[u8]) -> (usize, u64) {
+    let mut packets = 0;
+    let mut hash = 0xcbf29ce484222325;
+    while !buffer.is_empty() {
+        let (rest, packet) = match packet(buffer) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                eprintln!(
+                    "packet {} is malformed: {:?}",
+                    packets,
+                    e.map_input(|i| i.len())
+                );
+                std::process::exit(1);
+            }
+        };
+        hash = hash_fields(hash, &packet);
+        packets += 1;
+        buffer = rest;
+    }
+    (packets, hash)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let seed = match args.iter().position(|a| a == "--seed") {
+        Some(i) => match args.get(i + 1).and_then(|s| s.parse().ok()) {
+            Some(seed) if seed != 0 => seed,
+            _ => {
+                eprintln!("--seed takes a non-zero integer");
+                std::process::exit(1);
+            }
+        },
+        None => 0x2545f4914f6cdd1d,
+    };
+
+    let mut state = seed;
+    let mut buffer = Vec::new();
+    for _ in 0..PACKETS {
+        generate_packet(&mut buffer, &mut state);
+    }
+
+    let start = Instant::now();
+    let mut result = (0, 0);
+    for _ in 0..ITERATIONS {
+        result = parse_all(&buffer);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let (packets, hash) = result;
+    if packets != PACKETS {
+        eprintln!("parsed {} packets, generated {}", packets, PACKETS);
+        std::process::exit(1);
+    }
+
+    println!(
+        "{} packets, {} MB: {:.1} M packets/s",
+        packets,
+        buffer.len() / (1024 * 1024),
+        (packets * ITERATIONS) as f64 / elapsed / 1e6
+    );
+    println!(
+        "verify: seed {} {} packets, fields hash {:016x}",
+        seed, packets, hash
+    );
+}
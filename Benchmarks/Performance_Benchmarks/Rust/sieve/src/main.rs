@@ -0,0 +1,53 @@
+/// Prime sieve benchmark
+///
+/// Finds the primes up to 1e6, 1e7 and 1e8 with a single-threaded Sieve of
+/// Eratosthenes, storing one bit per number in a `bitvec::BitVec`. Each line
+/// gives the number of primes found, which is checked against the known
+/// count and by `run.py verify`, and the time per candidate in nanoseconds.
+/// The C version in ../../C/sieve.c runs the same sieve over a hand-managed
+/// bit array.
+use bitvec::prelude::*;
+use std::time::Instant;
+
+// (N, number of primes up to N)
+const LIMITS: [(usize, usize); 3] = [
+    (1_000_000, 78_498),
+    (10_000_000, 664_579),
+    (100_000_000, 5_761_455),
+];
+
+// Count the primes up to `n`.
+fn sieve(n: usize) -> usize {
+    let mut composite = bitvec![0; n + 1];
+    let mut i = 2;
+    while i * i <= n {
+        if !composite[i] {
+            let mut j = i * i;
+            while j <= n {
+                composite.set(j, true);
+                j += i;
+            }
+        }
+        i += 1;
+    }
+    composite[2..].count_zeros()
+}
+
+fn main() {
+    for &(n, expected) in LIMITS.iter() {
+        let start = Instant::now();
+        let primes = sieve(n);
+        let elapsed = start.elapsed().as_secs_f64();
+        if primes != expected {
+            eprintln!("found {} primes up to {}, expected {}", primes, n, expected);
+            std::process::exit(1);
+        }
+        println!(
+            "n {:9}: {:7} primes, {:.3} ns/candidate",
+            n,
+            primes,
+            elapsed * 1e9 / (n - 1) as f64
+        );
+        println!("verify: {} primes up to {}", primes, n);
+    }
+}
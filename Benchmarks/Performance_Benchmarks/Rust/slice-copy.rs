@@ -0,0 +1,105 @@
+/// Slice copying benchmark
+///
+/// Copies buffers of 1 KB, 64 KB, 1 MB and 256 MB of pseudo-random bytes over
+/// and over, 2 GB in all for each size, and reports GB/s. The first variant
+/// copies into a `Vec<u8>` made with `Vec::with_capacity` using
+/// `extend_from_slice`, which comes down to a `memcpy` like in the C version
+/// in ../C/slice-copy.c. The second starts from an empty `Vec` each time and
+/// extends it 4 KB at a time, so that it grows through the allocator's
+/// reallocation path; the C version grows a `realloc`ed buffer the same way,
+/// doubling its capacity. Every copy is checked against the source, and the
+/// FNV-1a hashes of the last copies of each size are printed on `verify: `
+/// lines.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+const SIZES: [(&str, usize); 4] = [
+    ("1 KB", 1024),
+    ("64 KB", 64 * 1024),
+    ("1 MB", 1024 * 1024),
+    ("256 MB", 256 * 1024 * 1024),
+];
+
+// The bytes copied for each size and variant.
+const TOTAL: usize = 2 * 1024 * 1024 * 1024;
+
+// The growing variant extends its `Vec` by this many bytes at a time.
+const CHUNK: usize = 4096;
+
+// xorshift64, so the data matches the C version exactly.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+// FNV-1a, to summarize a copy.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn check(name: &str, variant: &str, dst: &[u8], src: &[u8]) {
+    if dst != src {
+        eprintln!("{} {} copy differs from the source", name, variant);
+        std::process::exit(1);
+    }
+}
+
+fn preallocated(src: &[u8], repeats: usize) -> Vec<u8> {
+    let mut dst = Vec::with_capacity(src.len());
+    for _ in 0..repeats {
+        dst.clear();
+        dst.extend_from_slice(src);
+        black_box(&mut dst);
+    }
+    dst
+}
+
+fn growing(src: &[u8], repeats: usize) -> Vec<u8> {
+    let mut dst = Vec::new();
+    for _ in 0..repeats {
+        dst = Vec::new();
+        for chunk in src.chunks(CHUNK) {
+            dst.extend_from_slice(chunk);
+        }
+        black_box(&mut dst);
+    }
+    dst
+}
+
+// Copy `src` with `variant` and return the GB/s and the hash of the copy.
+fn measure(name: &str, variant: &str, src: &[u8], copy: fn(&[u8], usize) -> Vec<u8>) -> (f64, u64) {
+    let repeats = TOTAL / src.len();
+    let start = Instant::now();
+    let dst = copy(src, repeats);
+    let elapsed = start.elapsed().as_secs_f64();
+    check(name, variant, &dst, src);
+    let rate = (src.len() * repeats) as f64 / (1024.0 * 1024.0 * 1024.0) / elapsed;
+    (rate, fnv1a(&dst))
+}
+
+fn main() {
+    let largest = SIZES[SIZES.len() - 1].1;
+    let mut state = 0x2545f4914f6cdd1d;
+    let data: Vec<u8> = (0..largest / 8)
+        .flat_map(|_| next_random(&mut state).to_le_bytes())
+        .collect();
+
+    for &(name, size) in SIZES.iter() {
+        let src = &data[..size];
+        let (preallocated, preallocated_hash) = measure(name, "preallocated", src, preallocated);
+        let (growing, growing_hash) = measure(name, "growing", src, growing);
+        println!(
+            "{:6}: preallocated {:6.2} GB/s, growing {:6.2} GB/s",
+            name, preallocated, growing
+        );
+        println!(
+            "verify: {} copied, hashes {:016x} {:016x}",
+            name, preallocated_hash, growing_hash
+        );
+    }
+}
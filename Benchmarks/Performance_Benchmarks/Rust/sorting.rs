@@ -0,0 +1,93 @@
+/// Sorting benchmark
+///
+/// Sorts `u32` vectors of 1 000, 100 000 and 10 000 000 elements (the largest
+/// size can be changed with the first argument) drawn from four
+/// distributions: random, sorted, reverse-sorted, and nearly sorted, where 1%
+/// of the elements of a sorted vector were swapped at random. Each input is
+/// sorted with the stable, merge-based `slice::sort` and with
+/// `slice::sort_unstable` (pdqsort), and the mean time per sort is reported
+/// for each (algorithm, distribution, size). The C version in ../C/sorting.c
+/// sorts the same inputs with qsort.
+
+use std::time::Instant;
+
+// Small inputs are sorted repeatedly until this many elements were sorted.
+const ELEMENTS_PER_MEASUREMENT: usize = 10_000_000;
+
+const DISTRIBUTIONS: [&'static str; 4] = ["random", "sorted", "reversed", "nearly-sorted"];
+
+const ALGORITHMS: [(&'static str, fn(&mut [u32])); 2] =
+    [("sort", <[u32]>::sort), ("sort_unstable", <[u32]>::sort_unstable)];
+
+// xorshift64, so the inputs match the C version exactly.
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+fn input(distribution: &str, size: usize) -> Vec<u32> {
+    let mut rng = Rng(0x2545_f491_4f6c_dd1d);
+    match distribution {
+        "random" => (0..size).map(|_| rng.next() as u32).collect(),
+        "sorted" => (0..size as u32).collect(),
+        "reversed" => (0..size as u32).rev().collect(),
+        "nearly-sorted" => {
+            let mut v: Vec<u32> = (0..size as u32).collect();
+            for _ in 0..size / 100 {
+                let i = (rng.next() % size as u64) as usize;
+                let j = (rng.next() % size as u64) as usize;
+                v.swap(i, j);
+            }
+            v
+        }
+        _ => unreachable!(),
+    }
+}
+
+// Sort copies of `input`, in `v`, with `sort` and return the mean time per
+// sort in milliseconds.
+fn measure(input: &[u32], v: &mut [u32], sort: fn(&mut [u32])) -> f64 {
+    let iterations = (ELEMENTS_PER_MEASUREMENT / input.len()).max(1);
+    let mut total = 0.0;
+    for _ in 0..iterations {
+        v.copy_from_slice(input);
+        let start = Instant::now();
+        sort(v);
+        total += start.elapsed().as_secs_f64();
+        if v.windows(2).any(|w| w[0] > w[1]) {
+            eprintln!("output is not sorted");
+            std::process::exit(1);
+        }
+    }
+    total / iterations as f64 * 1e3
+}
+
+fn main() {
+    let largest = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(10_000_000);
+    let sizes = [1_000, 100_000, largest];
+
+    for &size in sizes.iter() {
+        let mut v = vec![0; size];
+        for distribution in DISTRIBUTIONS.iter() {
+            let input = input(distribution, size);
+            for &(algorithm, sort) in ALGORITHMS.iter() {
+                println!(
+                    "{:14} {:14} {:9}: {:10.3} ms",
+                    algorithm,
+                    distribution,
+                    size,
+                    measure(&input, &mut v, sort)
+                );
+            }
+        }
+    }
+}
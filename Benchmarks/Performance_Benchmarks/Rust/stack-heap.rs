@@ -0,0 +1,82 @@
+/// Stack vs heap allocation benchmark
+///
+/// Runs the same loop body twice, once on a `[u8; 4096]` local array on the
+/// stack and once on a `Box::new([0u8; 4096])`, so that the difference
+/// between the two is the cost of the allocation and free alone. Each
+/// iteration zeroes the buffer, writes two bytes that depend on the iteration
+/// and reads them back into a checksum, with the buffer passed through
+/// `black_box` so that it isn't optimized away. The number of iterations
+/// defaults to 10 000 000 and can be given with `--iterations`, which the C
+/// version in ../C/stack-heap.c takes too; both print the checksums on
+/// `verify: ` lines. Reports nanoseconds per iteration for each variant and
+/// the difference between them.
+///
+/// Run through run.py with `--allocator jemalloc` or `--allocator mimalloc` to
+/// compare the heap variant across allocators.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+const SIZE: usize = 4096;
+
+// The two positions iteration `i` writes to.
+fn positions(i: u64) -> (usize, usize) {
+    ((i % SIZE as u64) as usize, ((i * 7 + 1) % SIZE as u64) as usize)
+}
+
+// Write to `buf` for iteration `i` and return what to add to the checksum.
+fn work(buf: &mut [u8; SIZE], i: u64) -> u64 {
+    let (a, b) = positions(i);
+    buf[a] = i as u8;
+    buf[b] = buf[b].wrapping_add((i >> 8) as u8);
+    let buf = black_box(buf);
+    buf[a] as u64 + buf[b] as u64
+}
+
+fn stack(iterations: u64) -> u64 {
+    let mut checksum = 0;
+    for i in 0..iterations {
+        let mut buf = [0u8; SIZE];
+        checksum += work(&mut buf, i);
+    }
+    checksum
+}
+
+fn heap(iterations: u64) -> u64 {
+    let mut checksum = 0;
+    for i in 0..iterations {
+        let mut buf = Box::new([0u8; SIZE]);
+        checksum += work(&mut buf, i);
+    }
+    checksum
+}
+
+// Run `variant` and return the nanoseconds per iteration and the checksum.
+fn measure(iterations: u64, variant: fn(u64) -> u64) -> (f64, u64) {
+    let start = Instant::now();
+    let checksum = variant(iterations);
+    let elapsed = start.elapsed().as_secs_f64();
+    (elapsed * 1e9 / iterations as f64, checksum)
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    let iterations = match args.iter().position(|a| a == "--iterations") {
+        Some(i) => match args.get(i + 1).and_then(|s| s.parse().ok()) {
+            Some(n) if n != 0 => n,
+            _ => {
+                eprintln!("--iterations takes a positive integer");
+                std::process::exit(1);
+            }
+        },
+        None => 10_000_000,
+    };
+
+    let (stack_ns, stack_checksum) = measure(iterations, stack);
+    let (heap_ns, heap_checksum) = measure(iterations, heap);
+    println!("stack: {:8.2} ns/iteration", stack_ns);
+    println!("heap : {:8.2} ns/iteration", heap_ns);
+    println!("allocation overhead: {:8.2} ns", heap_ns - stack_ns);
+    println!("verify: stack {} iterations, checksum {}", iterations, stack_checksum);
+    println!("verify: heap {} iterations, checksum {}", iterations, heap_checksum);
+}
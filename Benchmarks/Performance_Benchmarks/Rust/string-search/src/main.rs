@@ -0,0 +1,67 @@
+/// Substring search throughput benchmark
+///
+/// Searches for a needle in haystacks of 1 KB, 1 MB and 256 MB, with the
+/// needle placed at the start, the middle and the end, using the `memchr`
+/// crate's `memmem::find` and a prebuilt `memmem::Finder`. The largest
+/// haystack can be changed with the first argument (in MB). Throughput is the
+/// number of haystack bytes up to the end of the match, in GB/s. The C version
+/// in ../../C/string-search.c performs the same searches with strstr and
+/// memmem.
+use memchr::memmem;
+use std::hint::black_box;
+use std::time::Instant;
+
+const NEEDLE: &[u8] = b"needle-in-a-haystack";
+
+// Every search is repeated until it has scanned at least this many bytes.
+const BYTES_PER_MEASUREMENT: usize = 1 << 30;
+
+const POSITIONS: [usize; 3] = [0, 50, 100];
+
+// A haystack of `size` bytes of text that never contains the needle, except at
+// `percent`% of the way through.
+fn haystack(size: usize, percent: usize) -> (Vec<u8>, usize) {
+    let filler = b"the quick brown fox jumps over the lazy dog ";
+    let mut haystack: Vec<u8> = filler.iter().copied().cycle().take(size).collect();
+    let offset = (size - NEEDLE.len()) * percent / 100;
+    haystack[offset..offset + NEEDLE.len()].copy_from_slice(NEEDLE);
+    (haystack, offset)
+}
+
+// Run `search` until it has scanned BYTES_PER_MEASUREMENT bytes, checking it
+// finds the needle at `offset`, and return the throughput in GB/s.
+fn measure(haystack: &[u8], offset: usize, search: impl Fn(&[u8]) -> Option<usize>) -> f64 {
+    let scanned = offset + NEEDLE.len();
+    let iterations = (BYTES_PER_MEASUREMENT / scanned).max(1);
+
+    let start = Instant::now();
+    for _ in 0..iterations {
+        if search(black_box(haystack)) != Some(offset) {
+            eprintln!("needle not found at offset {}", offset);
+            std::process::exit(1);
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    (scanned * iterations) as f64 / elapsed / 1e9
+}
+
+fn main() {
+    let megabytes: usize = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(256);
+    let sizes = [1024, 1024 * 1024, megabytes * 1024 * 1024];
+    let finder = memmem::Finder::new(NEEDLE);
+
+    for &size in sizes.iter() {
+        for &percent in POSITIONS.iter() {
+            let (haystack, offset) = haystack(size, percent);
+            let find = measure(&haystack, offset, |h| memmem::find(h, NEEDLE));
+            let finder = measure(&haystack, offset, |h| finder.find(h));
+            println!(
+                "haystack {:10} needle at {:3}%: memmem::find {:.2} GB/s, Finder {:.2} GB/s",
+                size, percent, find, finder
+            );
+        }
+    }
+}
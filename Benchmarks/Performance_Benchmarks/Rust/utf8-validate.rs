@@ -0,0 +1,107 @@
+/// UTF-8 validation benchmark
+///
+/// Generates 100 MB of UTF-8 text from random code points (the size can be
+/// changed with the first argument, in MB) and validates it with
+/// `std::str::from_utf8`, reporting MB/s. A second pass validates a copy in
+/// which 1% of the bytes were replaced with 0xff, which is never valid UTF-8,
+/// to measure the error path: validation restarts after each invalid
+/// sequence, and the invalid bytes are counted. The C version in
+/// ../C/utf8-validate.c validates the same text with utf8proc_iterate, and
+/// both print the number of invalid bytes on their `verify: ` lines.
+
+use std::str;
+use std::time::Instant;
+
+// Each pass validates the text this many times.
+const REPEATS: usize = 10;
+
+// xorshift64, so the text matches the C version exactly.
+fn next_random(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+// Fill `size` bytes with random code points, a quarter each of the one, two,
+// three and four byte ones, padding the end with spaces.
+fn random_text(size: usize, state: &mut u64) -> Vec<u8> {
+    const RANGES: [(u32, u32); 4] = [
+        (0, 0x80),
+        (0x80, 0x800),
+        (0x800, 0x10000),
+        (0x10000, 0x110000),
+    ];
+    let mut text = Vec::with_capacity(size);
+    while text.len() + 4 <= size {
+        let r = next_random(state);
+        let (low, high) = RANGES[(r % 4) as usize];
+        let mut c = low + ((r >> 2) % (high - low) as u64) as u32;
+        // Move surrogates, which can't be encoded, down into valid code points.
+        if c >= 0xd800 && c < 0xe000 {
+            c -= 0x800;
+        }
+        let mut buf = [0; 4];
+        text.extend_from_slice(
+            std::char::from_u32(c)
+                .unwrap()
+                .encode_utf8(&mut buf)
+                .as_bytes(),
+        );
+    }
+    text.resize(size, b' ');
+    text
+}
+
+// Replace about 1% of the bytes of `text` with 0xff.
+fn corrupt(text: &mut [u8], state: &mut u64) {
+    for byte in text.iter_mut() {
+        if next_random(state) % 100 == 0 {
+            *byte = 0xff;
+        }
+    }
+}
+
+// Count the bytes of `bytes` that aren't part of a valid UTF-8 sequence.
+fn count_invalid(mut bytes: &[u8]) -> usize {
+    let mut invalid = 0;
+    loop {
+        match str::from_utf8(bytes) {
+            Ok(_) => return invalid,
+            Err(e) => {
+                let len = e.error_len().unwrap_or(bytes.len() - e.valid_up_to());
+                invalid += len;
+                bytes = &bytes[e.valid_up_to() + len..];
+            }
+        }
+    }
+}
+
+fn validate(name: &str, text: &[u8]) {
+    let start = Instant::now();
+    let mut invalid = 0;
+    for _ in 0..REPEATS {
+        invalid = count_invalid(text);
+    }
+    let elapsed = start.elapsed().as_secs_f64();
+    let megabytes = (text.len() * REPEATS) as f64 / (1024.0 * 1024.0);
+    println!(
+        "{:10}: {:8.1} MB/s, {} invalid bytes",
+        name,
+        megabytes / elapsed,
+        invalid
+    );
+    println!("verify: {} {} invalid bytes", name, invalid);
+}
+
+fn main() {
+    let megabytes: usize = std::env::args()
+        .nth(1)
+        .and_then(|n| n.parse().ok())
+        .unwrap_or(100);
+    let mut state = 0x2545f4914f6cdd1d;
+    let mut text = random_text(megabytes * 1024 * 1024, &mut state);
+    validate("valid", &text);
+    corrupt(&mut text, &mut state);
+    validate("1% invalid", &text);
+}
@@ -0,0 +1,9 @@
+use bench_main::bench_main;
+
+// Sorts a pseudo-random vector derived from the seed.
+#[bench_main]
+fn sort(seed: u64) -> Vec<u64> {
+    let mut v: Vec<u64> = (0..100_000).map(|i| (i * 7919 + seed) % 100_003).collect();
+    v.sort();
+    v
+}
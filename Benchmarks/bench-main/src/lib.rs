@@ -0,0 +1,127 @@
+//! `#[bench_main]` turns a function into a benchmark program.
+//!
+//! ```ignore
+//! use bench_main::bench_main;
+//!
+//! #[bench_main]
+//! fn sort(seed: u64) -> Vec<u64> {
+//!     let mut v: Vec<u64> = (0..100_000).map(|i| (i * 7919 + seed) % 100_003).collect();
+//!     v.sort();
+//!     v
+//! }
+//! ```
+//!
+//! The generated `main` accepts `--iter N` (default 10), `--warmup W`
+//! (default 1) and `--seed S` (default 0). It runs the function `W` times
+//! untimed and `N` times timed, passing the seed if the function takes an
+//! argument, and prints one line of JSON:
+//!
+//! ```text
+//! {"name":"sort","iterations":10,"warmup":1,"seed":0,"mean_ns":..,"min_ns":..,"max_ns":..,"times_ns":[..]}
+//! ```
+//!
+//! `run.py` records `mean_ns` as the benchmark's time. Return values are
+//! passed through `std::hint::black_box` so the work isn't optimized away.
+
+use proc_macro::{Delimiter, TokenStream, TokenTree};
+
+const MAIN: &str = r#"
+fn main() {
+    let mut iter: u64 = 10;
+    let mut warmup: u64 = 1;
+    let mut seed: u64 = 0;
+    let mut args = ::std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let slot = match arg.as_str() {
+            "--iter" => &mut iter,
+            "--warmup" => &mut warmup,
+            "--seed" => &mut seed,
+            other => {
+                eprintln!("unknown argument `{}`, expected --iter N, --warmup W or --seed S", other);
+                ::std::process::exit(2);
+            }
+        };
+        *slot = match args.next().and_then(|value| value.parse().ok()) {
+            Some(value) => value,
+            None => {
+                eprintln!("`{}` needs a non-negative integer", arg);
+                ::std::process::exit(2);
+            }
+        };
+    }
+    let _ = seed;
+
+    for _ in 0..warmup {
+        ::std::hint::black_box(__CALL__);
+    }
+    let mut times: ::std::vec::Vec<u128> = ::std::vec::Vec::new();
+    for _ in 0..iter {
+        let start = ::std::time::Instant::now();
+        ::std::hint::black_box(__CALL__);
+        times.push(start.elapsed().as_nanos());
+    }
+
+    let mean = times.iter().sum::<u128>() / ::std::cmp::max(times.len(), 1) as u128;
+    let min = times.iter().copied().min().unwrap_or(0);
+    let max = times.iter().copied().max().unwrap_or(0);
+    println!(
+        "{{\"name\":\"__NAME__\",\"iterations\":{},\"warmup\":{},\"seed\":{},\"mean_ns\":{},\"min_ns\":{},\"max_ns\":{},\"times_ns\":{:?}}}",
+        iter, warmup, seed, mean, min, max, times
+    );
+}
+"#;
+
+/// Generates a `main` that times the annotated function. See the crate
+/// documentation for the command line and output format.
+#[proc_macro_attribute]
+pub fn bench_main(attr: TokenStream, item: TokenStream) -> TokenStream {
+    if !attr.is_empty() {
+        return compile_error("`#[bench_main]` takes no arguments");
+    }
+    let (name, takes_seed) = match signature(&item) {
+        Ok(signature) => signature,
+        Err(msg) => return compile_error(msg),
+    };
+    let call = if takes_seed { format!("{}(seed)", name) } else { format!("{}()", name) };
+    let main = MAIN.replace("__CALL__", &call).replace("__NAME__", &name);
+
+    let mut out = item;
+    out.extend(main.parse::<TokenStream>().expect("generated main is valid Rust"));
+    out
+}
+
+/// Returns the name of the function in `item` and whether it takes an
+/// argument (the seed).
+fn signature(item: &TokenStream) -> Result<(String, bool), &'static str> {
+    let tokens: Vec<TokenTree> = item.clone().into_iter().collect();
+    let fn_pos = tokens
+        .iter()
+        .position(|token| matches!(token, TokenTree::Ident(ident) if ident.to_string() == "fn"))
+        .ok_or("`#[bench_main]` can only be applied to functions")?;
+    let name = match tokens.get(fn_pos + 1) {
+        Some(TokenTree::Ident(ident)) => ident.to_string(),
+        _ => return Err("expected a function name"),
+    };
+    let params = match tokens.get(fn_pos + 2) {
+        Some(TokenTree::Group(group)) if group.delimiter() == Delimiter::Parenthesis => {
+            group.stream()
+        }
+        _ => return Err("`#[bench_main]` functions can't be generic"),
+    };
+    // A single `seed: u64` parameter has no top-level commas, except for a
+    // trailing one.
+    let params: Vec<TokenTree> = params.into_iter().collect();
+    let commas = params
+        .iter()
+        .filter(|token| matches!(token, TokenTree::Punct(punct) if punct.as_char() == ','))
+        .count();
+    let trailing = matches!(params.last(), Some(TokenTree::Punct(punct)) if punct.as_char() == ',');
+    if commas > usize::from(trailing) {
+        return Err("`#[bench_main]` functions take no arguments or just the seed");
+    }
+    Ok((name, !params.is_empty()))
+}
+
+fn compile_error(msg: &str) -> TokenStream {
+    format!("compile_error!({:?});", msg).parse().unwrap()
+}
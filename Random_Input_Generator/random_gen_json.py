@@ -0,0 +1,62 @@
+# Generates the JSON document parsed and serialized by the json-parse and
+# json-serialize benchmarks: an array of randomly nested objects, the same for
+# a given seed
+import argparse
+import json
+import random
+import string
+
+MAX_DEPTH = 5
+
+def generate_key(rng):
+  return ''.join(rng.choice(string.ascii_lowercase) for _ in range(rng.randint(3, 10)))
+
+def generate_string(rng):
+  # Mostly plain text, with the odd character that has to be escaped or
+  # encoded as more than one byte
+  letters = string.ascii_letters + string.digits + ' ' * 8 + '"\\\n\té'
+  return ''.join(rng.choice(letters) for _ in range(rng.randint(0, 40)))
+
+def generate_value(rng, depth):
+  kind = rng.random()
+  if depth < MAX_DEPTH and kind < 0.15:
+    return generate_object(rng, depth + 1)
+  if depth < MAX_DEPTH and kind < 0.25:
+    return [generate_value(rng, depth + 1) for _ in range(rng.randint(0, 8))]
+  if kind < 0.45:
+    return generate_string(rng)
+  if kind < 0.65:
+    return rng.randint(-2**31, 2**31)
+  if kind < 0.8:
+    return round(rng.uniform(-1e6, 1e6), 3)
+  if kind < 0.9:
+    return rng.random() < 0.5
+  return None
+
+def generate_object(rng, depth):
+  return {generate_key(rng): generate_value(rng, depth) for _ in range(rng.randint(1, 8))}
+
+def generate_document(seed, size):
+  # Objects are added until the serialized array is at least size bytes
+  rng = random.Random(seed)
+  objects = []
+  length = 2
+  while length < size:
+    obj = generate_object(rng, 0)
+    objects.append(obj)
+    length += len(json.dumps(obj, ensure_ascii=False).encode()) + 2
+  return objects
+
+def main():
+  parser = argparse.ArgumentParser(description='Generate the JSON benchmark input')
+  parser.add_argument('--seed', type=int, default=0, help='Random seed; the same seed always gives the same document (default: 0)')
+  parser.add_argument('--size', type=int, default=1024 * 1024, help='Minimum size of the document in bytes (default: 1 MB)')
+  parser.add_argument('-o', '--output', type=str, default='input.json', help='Output file path (default: input.json)')
+  args = parser.parse_args()
+
+  with open(args.output, 'w', encoding='utf-8') as f:
+    json.dump(generate_document(args.seed, args.size), f, ensure_ascii=False)
+  print(f"Wrote {args.output} with seed {args.seed}")
+
+if __name__ == "__main__":
+  main()
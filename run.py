@@ -3,31 +3,148 @@ import subprocess
 import time
 import random
 import glob
+import csv
+import html
+import io
+import json
 import re
 import pathlib
 import logging as log
+import math
 import argparse
+import atexit
+import ctypes.util
+import platform
+import shlex
+import shutil
+import signal
+import statistics
+import sys
+import tempfile
+from collections import namedtuple
 
 def get_benchmark_dirs():
   dirs = ['Benchmarks/Algorithm_Benchmarks', 'Benchmarks/Performance_Benchmarks']
   random.shuffle(dirs)
   return dirs
 
-def compile_c_source(c_source, c_out, opt_level):
+def compile_c_source(c_source, c_out, c_flags, c_libs=()):
   try:
-    subprocess.run(['gcc', '-w', f'-O{opt_level}', '-xc', '-', '-o', c_out, '-I/usr/include/apr-1.0', '-lapr-1', '-lpthread', '-lgmp'], input=c_source, check=True, text=True)
+    subprocess.run(['gcc', '-w', *c_flags, '-xc', '-', '-o', c_out, '-I/usr/include/apr-1.0', '-lapr-1', '-lpthread', '-lgmp', '-lm', *c_libs], input=c_source, check=True, text=True)
     return True
   except subprocess.CalledProcessError:
     log.error("C compilation failed")
     return False
 
-def compile_rust(rust_file, rust_dir, rust_out, opt_level):
-  flags = f"-A warnings -C opt-level={opt_level}"
+# Environment variables benchmarks run with, by benchmark name. OpenBLAS would
+# otherwise spread the C matmul over every CPU against the single threaded Rust
+# version
+BENCHMARK_ENV = {
+  'matmul': ['OPENBLAS_NUM_THREADS=1'],
+}
+
+# Benchmarks whose Rust version has variants that are each timed and compared
+# with the same C run under their own name, by benchmark name. Each name maps
+# to the NAME=VALUE assignments that select the variant
+RUST_VARIANTS = {
+  'hash-map': {'hash-map': ['HASH_MAP=std'], 'hash-map-fx': ['HASH_MAP=fx']},
+}
+
+def result_names(base_name):
+  # The names a benchmark's results are written under
+  return list(RUST_VARIANTS.get(base_name, {base_name: []}))
+
+# C benchmarks that link a library found through pkg-config, by benchmark name,
+# or that include the header of a header-only library
+C_PACKAGES = {
+  'regex-match': 'libpcre2-8',
+  'json-parse': 'libcjson',
+  'json-serialize': 'libcjson',
+  'sha256': 'openssl',
+  'base64': 'openssl',
+  'blake3': 'libblake3',
+  'matmul': 'blas',
+  'utf8-validate': 'libutf8proc',
+  'hash-map': 'uthash.h',
+}
+
+# The compiler and linker flags pkg-config gives for a package
+PkgConfig = namedtuple('PkgConfig', ['cflags', 'libs'])
+
+def pkg_config(package):
+  # Returns the PkgConfig of a package, or None if it or pkg-config isn't
+  # installed
+  try:
+    cflags = subprocess.run(['pkg-config', '--cflags', package], capture_output=True, text=True, check=True).stdout.split()
+    libs = subprocess.run(['pkg-config', '--libs', package], capture_output=True, text=True, check=True).stdout.split()
+  except (OSError, subprocess.CalledProcessError):
+    return None
+  return PkgConfig(cflags, libs)
+
+def header_config(header):
+  # Returns an empty PkgConfig if the C compiler finds header, or None
+  try:
+    subprocess.run(['gcc', '-fsyntax-only', '-xc', '-'], input=f"#include <{header}>\n", capture_output=True, text=True, check=True)
+  except (OSError, subprocess.CalledProcessError):
+    return None
+  return PkgConfig([], [])
+
+def detect_c_packages():
+  # Maps each benchmark in C_PACKAGES whose package is installed to its
+  # PkgConfig, warning about the others, which can't be built
+  c_packages = {}
+  for base_name, package in C_PACKAGES.items():
+    flags = header_config(package) if package.endswith('.h') else pkg_config(package)
+    if flags is None:
+      finder = 'the C compiler' if package.endswith('.h') else 'pkg-config'
+      log.warning(f"{package} was not found by {finder}, skipping the {base_name} benchmark")
+    else:
+      c_packages[base_name] = flags
+  return c_packages
+
+# The cargo profile variables that stand in for rustc's -C lto and
+# -C codegen-units in cargo projects. Cargo builds some crates with
+# -C embed-bitcode=no, which rustc rejects alongside -C lto in RUSTFLAGS
+CARGO_PROFILE_FLAGS = {
+  'lto': 'CARGO_PROFILE_RELEASE_LTO',
+  'codegen-units': 'CARGO_PROFILE_RELEASE_CODEGEN_UNITS',
+}
+
+def cargo_env(rust_flags):
+  # The environment variables that build a cargo project with rust_flags:
+  # RUSTFLAGS, with the flags in CARGO_PROFILE_FLAGS moved into the release
+  # profile
+  flags, env = ['-A', 'warnings'], {}
+  args = list(rust_flags)
+  i = 0
+  while i < len(args):
+    if args[i] == '-C' and i + 1 < len(args):
+      setting, width = args[i + 1], 2
+    elif args[i].startswith('-C'):
+      setting, width = args[i][2:], 1
+    else:
+      setting, width = '', 1
+    key, _, value = setting.partition('=')
+    if key in CARGO_PROFILE_FLAGS:
+      env[CARGO_PROFILE_FLAGS[key]] = value or 'true'
+    else:
+      flags.extend(args[i:i + width])
+    i += width
+  env['RUSTFLAGS'] = " ".join(flags)
+  return env
+
+def compile_rust(rust_file, rust_dir, rust_out, rust_flags):
+  flags = " ".join(['-A', 'warnings', *rust_flags])
   os.environ["RUSTFLAGS"] = flags
   try:
     if os.path.exists(rust_file):
       subprocess.run(['rustc', *flags.split(), rust_file, '-o', rust_out], check=True)
     else:
+      # Set in our own environment so that cargo run reuses this build
+      # rather than rebuilding with a different profile
+      for key in CARGO_PROFILE_FLAGS.values():
+        os.environ.pop(key, None)
+      os.environ.update(cargo_env(rust_flags))
       subprocess.run(['cargo', 'build', '--release'], check=True,
                      cwd=rust_dir)
     return True
@@ -35,64 +152,920 @@ def compile_rust(rust_file, rust_dir, rust_out, opt_level):
     log.error("Rust compilation failed")
     return False
 
-def run_c_benchmark(c_out, input_data_file):
+# How each --allocator is provided: the crate Rust benchmarks are rebuilt with
+# as their #[global_allocator], and the library preloaded into C benchmarks.
+# The system allocator is what both languages use by default
+Allocator = namedtuple('Allocator', ['crate', 'version', 'rust_type', 'c_library'])
+ALLOCATORS = {
+  'system': Allocator(None, None, None, None),
+  'jemalloc': Allocator('tikv-jemallocator', '0.5', 'tikv_jemallocator::Jemalloc', 'jemalloc'),
+  'mimalloc': Allocator('mimalloc', '0.1', 'mimalloc::MiMalloc', 'mimalloc'),
+}
+
+def find_allocator_library(allocator):
+  # The shared library to LD_PRELOAD for an allocator, e.g. libjemalloc.so.2,
+  # or None if it isn't installed
+  return ctypes.util.find_library(ALLOCATORS[allocator].c_library)
+
+def c_benchmark_env(allocator, stack_guard=None):
+  # Environment C benchmarks run with to use the allocator, and the
+  # stack_guard library if there is one
+  preload = [stack_guard] if stack_guard else []
+  if allocator != 'system':
+    preload.append(find_allocator_library(allocator))
+  if not preload:
+    return None
+  return {**os.environ, 'LD_PRELOAD': ' '.join(preload)}
+
+def generate_allocator_project(rust_file, rust_dir, allocator, work_dir):
+  # Copies the benchmark into a cargo project in work_dir that depends on the
+  # allocator crate and declares it as the global allocator. Returns the
+  # project directory
+  spec = ALLOCATORS[allocator]
+  project = os.path.join(work_dir, 'project')
+  if os.path.exists(rust_file):
+    os.makedirs(f'{project}/src')
+    # rustc builds single files with the 2015 edition by default
+    with open(f'{project}/Cargo.toml', 'w') as f:
+      f.write('[package]\nname = "benchmark"\nversion = "0.1.0"\nedition = "2015"\n\n[dependencies]\n')
+    shutil.copy(rust_file, f'{project}/src/main.rs')
+  else:
+    shutil.copytree(rust_dir, project, ignore=shutil.ignore_patterns('target'))
+
+  manifest = pathlib.Path(project, 'Cargo.toml')
+  dependency = f'{spec.crate} = "{spec.version}"\n'
+  text = manifest.read_text()
+  # Path dependencies, such as bench-main, are relative to the original
+  text = re.sub(r'path = "([^"]+)"', lambda m: f'path = "{os.path.abspath(os.path.join(rust_dir, m.group(1)))}"', text)
+  if '[dependencies]\n' in text:
+    text = text.replace('[dependencies]\n', f'[dependencies]\n{dependency}', 1)
+  else:
+    text += f'\n[dependencies]\n{dependency}'
+  manifest.write_text(text)
+
+  # Appended rather than prepended so inner attributes stay at the top
+  with open(f'{project}/src/main.rs', 'a') as f:
+    f.write(f'\n#[global_allocator]\nstatic BENCHMARK_ALLOCATOR: {spec.rust_type} = {spec.rust_type};\n')
+  return project
+
+def compile_rust_with_allocator(rust_file, rust_dir, rust_out, rust_flags, allocator):
+  # Builds the benchmark with the allocator crate into rust_out. Returns the
+  # version of the crate that was used, or None if the build failed
+  spec = ALLOCATORS[allocator]
+  env = {**os.environ, **cargo_env(rust_flags)}
+  with tempfile.TemporaryDirectory() as work_dir:
+    project = generate_allocator_project(rust_file, rust_dir, allocator, work_dir)
+    try:
+      messages = subprocess.run(['cargo', 'build', '--release', '--message-format=json'],
+                     cwd=project, env=env, stdout=subprocess.PIPE, text=True, check=True).stdout
+    except subprocess.CalledProcessError:
+      log.error("Rust compilation failed")
+      return None
+    executables = [m['executable'] for m in map(json.loads, messages.splitlines())
+                   if m.get('reason') == 'compiler-artifact' and m.get('executable')]
+    shutil.copy(executables[-1], rust_out)
+    lock = pathlib.Path(project, 'Cargo.lock').read_text()
+    return re.search(rf'name = "{re.escape(spec.crate)}"\nversion = "([^"]+)"', lock).group(1)
+
+def allocator_version(allocator, crate_version):
+  # Describes the allocator versions a result was measured with
+  if allocator == 'system':
+    libc, version = platform.libc_ver()
+    return f"{libc} {version}".strip() or "unknown"
+  spec = ALLOCATORS[allocator]
+  return f"rust {spec.crate} {crate_version}; c {find_allocator_library(allocator)}"
+
+# The go toolchain used by --compare-against-go, and its `go version` line
+GoCompiler = namedtuple('GoCompiler', ['path', 'version'])
+
+def detect_go():
+  # Returns the GoCompiler on PATH, or None if Go isn't installed
+  path = shutil.which('go')
+  if path is None:
+    return None
+  try:
+    version = subprocess.run([path, 'version'], capture_output=True, text=True, check=True).stdout.strip()
+  except subprocess.CalledProcessError:
+    return None
+  return GoCompiler(path, version)
+
+def compile_go(go, go_file, go_out):
+  try:
+    subprocess.run([go.path, 'build', '-o', go_out, go_file], check=True)
+    return True
+  except subprocess.CalledProcessError:
+    log.error("Go compilation failed")
+    return False
+
+# The zig toolchain used by --compare-against-zig, and its `zig version` output
+ZigCompiler = namedtuple('ZigCompiler', ['path', 'version'])
+
+def detect_zig():
+  # Returns the ZigCompiler on PATH, or None if Zig isn't installed
+  path = shutil.which('zig')
+  if path is None:
+    return None
+  try:
+    version = subprocess.run([path, 'version'], capture_output=True, text=True, check=True).stdout.strip()
+  except subprocess.CalledProcessError:
+    return None
+  return ZigCompiler(path, version)
+
+def compile_zig(zig, zig_file, zig_out):
+  try:
+    subprocess.run([zig.path, 'build-exe', '-O', 'ReleaseFast', f'-femit-bin={zig_out}', zig_file], check=True)
+    return True
+  except subprocess.CalledProcessError:
+    log.error("Zig compilation failed")
+    return False
+
+def time_c_build(c_source, c_out, c_flags, c_libs=()):
+  # Compiles with gcc -c and links with a separate gcc -o. Returns
+  # (compile_time, link_time), or None if either step failed
+  c_obj = f"{c_out}.o"
   try:
     start_time = time.time()
-    c_output = subprocess.run([c_out], stdin=open(input_data_file), capture_output=True, text=True, check=True)
+    subprocess.run(['gcc', '-w', *c_flags, '-c', '-xc', '-', '-o', c_obj, '-I/usr/include/apr-1.0'], input=c_source, check=True, text=True)
+    compile_time = time.time() - start_time
+    start_time = time.time()
+    # LTO happens at link time, so the link needs the same -flto flag
+    lto_flags = [flag for flag in c_flags if flag.startswith('-flto')]
+    subprocess.run(['gcc', *lto_flags, c_obj, '-o', c_out, '-lapr-1', '-lpthread', '-lgmp', '-lm', *c_libs], check=True)
+    link_time = time.time() - start_time
+    return compile_time, link_time
+  except subprocess.CalledProcessError:
+    log.error("C compilation failed")
+    return None
+  finally:
+    if os.path.exists(c_obj):
+      os.remove(c_obj)
+
+def time_rust_build(rust_file, rust_out, rust_flags):
+  # Times rustc --emit=obj, then replays the linker command rustc prints for a
+  # full build so that the link is timed on its own. Returns
+  # (compile_time, link_time), or None if a step failed
+  flags = ['-A', 'warnings', *rust_flags]
+  with tempfile.TemporaryDirectory() as work_dir:
+    try:
+      start_time = time.time()
+      subprocess.run(['rustc', *flags, '--emit=obj', rust_file, '-o', f'{work_dir}/main.o'], check=True)
+      compile_time = time.time() - start_time
+
+      # save-temps keeps the objects the printed command refers to
+      link_args = subprocess.run(['rustc', *flags, '-C', 'save-temps', '--print', 'link-args', os.path.abspath(rust_file), '-o', 'main'],
+                     cwd=work_dir, capture_output=True, text=True, check=True).stdout
+      link_cmd = shlex.split(link_args)
+      env = dict(os.environ)
+      # The command starts with the environment rustc sets for the linker
+      while link_cmd and re.match(r'\w+=', link_cmd[0]):
+        key, value = link_cmd.pop(0).split('=', 1)
+        env[key] = value
+      start_time = time.time()
+      subprocess.run(link_cmd, cwd=work_dir, env=env, check=True)
+      link_time = time.time() - start_time
+      shutil.copy(f'{work_dir}/main', rust_out)
+      return compile_time, link_time
+    except subprocess.CalledProcessError:
+      log.error("Rust compilation failed")
+      return None
+
+# A setting that the C and Rust flags disagree on, e.g.
+# FlagMismatch('opt-level', '3', '0')
+FlagMismatch = namedtuple('FlagMismatch', ['setting', 'rust_value', 'c_value'])
+
+def parse_rust_flags(rust_flags):
+  # Normalize the rustc flags we compare, using rustc's defaults
+  settings = {'opt-level': '0', 'target-cpu': 'generic', 'vectorize': 'yes', 'debuginfo': '0'}
+  args = list(rust_flags)
+  for i, flag in enumerate(args):
+    if flag == '-C' and i + 1 < len(args):
+      flag = '-C' + args[i + 1]
+    if flag == '-O':
+      settings['opt-level'] = '2'
+    elif flag == '-g':
+      settings['debuginfo'] = '2'
+    elif flag.startswith('-Copt-level='):
+      settings['opt-level'] = flag.split('=', 1)[1]
+    elif flag.startswith('-Ctarget-cpu='):
+      settings['target-cpu'] = flag.split('=', 1)[1]
+    elif flag.startswith('-Cdebuginfo='):
+      settings['debuginfo'] = flag.split('=', 1)[1]
+    elif flag == '-Cno-vectorize-loops':
+      settings['vectorize'] = 'no'
+  return settings
+
+def parse_c_flags(c_flags):
+  # Normalize the gcc flags we compare, mapped onto rustc's spelling
+  settings = {'opt-level': '0', 'target-cpu': 'generic', 'vectorize': 'yes', 'debuginfo': '0'}
+  for flag in c_flags:
+    if flag in ('-O', '-O1'):
+      settings['opt-level'] = '1'
+    elif flag == '-Ofast':
+      settings['opt-level'] = '3'
+    elif re.fullmatch(r'-O[0-3sz]', flag):
+      settings['opt-level'] = flag[2:]
+    elif flag.startswith('-march='):
+      settings['target-cpu'] = flag.split('=', 1)[1]
+    elif flag in ('-fno-tree-vectorize', '-fno-tree-loop-vectorize'):
+      settings['vectorize'] = 'no'
+    elif flag in ('-g', '-g3'):
+      settings['debuginfo'] = '2'
+    elif re.fullmatch(r'-g[0-2]', flag):
+      settings['debuginfo'] = flag[2:]
+  return settings
+
+# Extra (C flags, Rust flags) added by each --profile. cargo_env moves the
+# Rust LTO flags into the cargo profile for cargo projects
+PROFILES = {
+  'default': ([], []),
+  'lto': (['-flto'], ['-C', 'lto=fat', '-C', 'codegen-units=1']),
+  'thin-lto': (['-flto=thin'], ['-C', 'lto=thin']),
+}
+
+def profile_flags(profile, c_compiler='gcc'):
+  # Returns the (C flags, Rust flags) for a profile. Only Clang has thin LTO,
+  # so other C compilers fall back to fat LTO
+  c_flags, rust_flags = PROFILES[profile]
+  if profile == 'thin-lto' and c_compiler != 'clang':
+    log.warning(f"{c_compiler} does not support thin LTO, using fat LTO (-flto) for C")
+    c_flags = PROFILES['lto'][0]
+  return list(c_flags), list(rust_flags)
+
+def check_flag_equivalence(rust_flags, c_flags):
+  # Returns a FlagMismatch for every setting the two flag sets disagree on
+  rust = parse_rust_flags(rust_flags)
+  c = parse_c_flags(c_flags)
+  return [FlagMismatch(setting, rust[setting], c[setting]) for setting in rust if rust[setting] != c[setting]]
+
+def get_launcher(no_aslr):
+  # Command prefix used to start every benchmark process
+  if not no_aslr:
+    return []
+  if shutil.which('setarch') is None:
+    log.warning("--no-aslr requested but setarch was not found, running with ASLR enabled")
+    return []
+  return ['setarch', platform.machine(), '-R']
+
+# From <sys/personality.h> and <linux/capability.h>
+ADDR_NO_RANDOMIZE = 0x0040000
+CAP_SYS_NICE = 23
+
+def has_cap_sys_nice():
+  # Whether we are root or have CAP_SYS_NICE in our effective capabilities
+  if os.geteuid() == 0:
+    return True
+  try:
+    for line in pathlib.Path('/proc/self/status').read_text().splitlines():
+      if line.startswith('CapEff:'):
+        return bool(int(line.split()[1], 16) >> CAP_SYS_NICE & 1)
+  except OSError:
+    pass
+  return False
+
+def aslr_disabled():
+  # Whether --reproducible turned ASLR off for this process and its children
+  try:
+    return bool(int(pathlib.Path('/proc/self/personality').read_text(), 16) & ADDR_NO_RANDOMIZE)
+  except OSError:
+    return False
+
+def aslr_setting(launcher):
+  # The ASLR setting of benchmarks started through launcher, as the value of
+  # /proc/sys/kernel/randomize_va_space: 0 if setarch -R or --reproducible
+  # turned it off, empty if it can't be read
+  if 'setarch' in launcher or aslr_disabled():
+    return '0'
+  try:
+    return pathlib.Path('/proc/sys/kernel/randomize_va_space').read_text().strip()
+  except OSError:
+    return ''
+
+def set_governors(governor):
+  # Sets the cpufreq scaling governor of every CPU, returning the previous
+  # ones by sysfs file so they can be restored. Raises OSError if one can't be
+  # set, after restoring those already changed
+  previous = {}
+  try:
+    for path in sorted(pathlib.Path('/sys/devices/system/cpu').glob('cpu[0-9]*/cpufreq/scaling_governor')):
+      current = path.read_text().strip()
+      if current != governor:
+        path.write_text(governor)
+        previous[path] = current
+  except OSError:
+    restore_governors(previous)
+    raise
+  return previous
+
+def restore_governors(previous):
+  for path, governor in previous.items():
+    try:
+      path.write_text(governor)
+    except OSError as e:
+      log.warning(f"Could not restore {path} to {governor}: {e}")
+
+def make_output_dir(output_dir, latest):
+  # Creates output_dir, by default benchmark_results/<timestamp> so that runs
+  # don't overwrite each other, and points the latest symlink at it. Returns
+  # the directory
+  if output_dir is None:
+    output_dir = os.path.join('benchmark_results', time.strftime('%Y-%m-%d_%H-%M-%S'))
+  os.makedirs(output_dir, exist_ok=True)
+  if latest:
+    # Replaced through a temporary link, so that it always points somewhere
+    latest_dir = os.path.dirname(latest) or '.'
+    os.makedirs(latest_dir, exist_ok=True)
+    tmp_link = f"{latest}.tmp"
+    if os.path.lexists(tmp_link):
+      os.remove(tmp_link)
+    os.symlink(output_dir if os.path.isabs(output_dir) else os.path.relpath(output_dir, latest_dir), tmp_link)
+    try:
+      os.replace(tmp_link, latest)
+    except OSError as e:
+      os.remove(tmp_link)
+      log.warning(f"Could not point {latest} at {output_dir}: {e}")
+  return output_dir
+
+def make_reproducible():
+  # Removes the sources of run-to-run noise we can from this process, whose
+  # personality and priority the benchmarks inherit: ASLR is disabled,
+  # everything is given the highest priority, and the CPUs run with the
+  # performance governor until we exit. Each benchmark is pinned separately,
+  # see affinity_launcher. Returns False, after warning about each, if some of
+  # it couldn't be done
+  if platform.system() != 'Linux':
+    log.warning("--reproducible only configures the system on Linux, running as is")
+    return False
+  complete = True
+  if not has_cap_sys_nice():
+    log.warning("--reproducible needs root or CAP_SYS_NICE to raise the priority and root to set the CPU governor")
+    complete = False
+
+  libc = ctypes.CDLL(None, use_errno=True)
+  persona = libc.personality(0xffffffff)
+  if persona == -1 or libc.personality(persona | ADDR_NO_RANDOMIZE) == -1:
+    log.warning(f"Could not disable ASLR: {os.strerror(ctypes.get_errno())}")
+    complete = False
+
+  if shutil.which('taskset') is None:
+    log.warning("taskset was not found, running the benchmarks on any CPU")
+    complete = False
+
+  try:
+    os.setpriority(os.PRIO_PROCESS, 0, -20)
+  except OSError as e:
+    log.warning(f"Could not raise the priority: {e}")
+    complete = False
+
+  if not any(pathlib.Path('/sys/devices/system/cpu').glob('cpu[0-9]*/cpufreq/scaling_governor')):
+    log.warning("No cpufreq scaling governors found, leaving frequency scaling as is")
+    complete = False
+  else:
+    try:
+      atexit.register(restore_governors, set_governors('performance'))
+    except OSError as e:
+      log.warning(f"Could not set the performance governor: {e}")
+      complete = False
+  return complete
+
+# Benchmarks that spread their threads over the CPUs they may run on
+MULTI_THREADED_BENCHMARKS = {'atomic-counter', 'lockfree-queue', 'mutex-counter'}
+
+def affinity_launcher(name):
+  # Command prefix pinning a benchmark, but not the harness or the compilers,
+  # to the last CPU, as CPU 0 tends to handle more interrupts. Multi-threaded
+  # benchmarks get every CPU but CPU 0 instead
+  if shutil.which('taskset') is None:
+    return []
+  cpus = sorted(os.sched_getaffinity(0))
+  if name in MULTI_THREADED_BENCHMARKS:
+    cpus = cpus[1:] or cpus
+  else:
+    cpus = cpus[-1:]
+  return ['taskset', '--cpu-list', ','.join(map(str, cpus))]
+
+def env_launcher(assignments):
+  # Command prefix setting the NAME=VALUE assignments for a benchmark only
+  if not assignments:
+    return []
+  return ['env', *assignments]
+
+def stack_size_launcher(stack_size):
+  # Command prefix raising the stack limit to stack_size bytes. The limit
+  # sizes the main thread of both languages, while the threads Rust spawns
+  # use RUST_MIN_STACK, which main sets. C benchmarks also preload
+  # build_stack_guard's library to report their overflows
+  if stack_size is None:
+    return []
+  kib = -(-stack_size // 1024)
+  return ['sh', '-c', 'ulimit -s "$0" && exec "$@"', str(kib)]
+
+# The exit code of C benchmarks whose main thread overflowed its stack, which
+# stack_guard.c reports
+STACK_OVERFLOW_EXIT = 86
+
+def build_stack_guard(out_dir):
+  # Builds stack_guard.c into out_dir, to preload into C benchmarks so that
+  # their stack overflows are told apart from other crashes. Returns the
+  # library, or None if it failed to build
+  source = os.path.join(os.path.dirname(os.path.abspath(__file__)), 'stack_guard.c')
+  library = os.path.join(out_dir, 'libstack_guard.so')
+  try:
+    subprocess.run(['gcc', '-shared', '-fPIC', '-O2', f'-DSTACK_OVERFLOW_EXIT={STACK_OVERFLOW_EXIT}', source, '-o', library],
+                   check=True, capture_output=True, text=True)
+  except subprocess.CalledProcessError as e:
+    log.warning(f"Could not build the stack overflow guard, C stack overflows will be reported as crashes: {e.stderr.strip()}")
+    return None
+  return os.path.abspath(library)
+
+class BenchmarkCrashed(Exception):
+  # Raised when a benchmark process crashes, with the outcome written to the
+  # results for it, 'stack_overflow' or 'crash'
+  def __init__(self, outcome):
+    super().__init__(outcome)
+    self.outcome = outcome
+
+def crash_outcome(completed):
+  # 'stack_overflow' or 'crash' if a benchmark process crashed, or None. Rust
+  # reports stack overflows from its guard page handler before aborting, and C
+  # benchmarks from stack_guard.c when it is preloaded
+  stderr = completed.stderr or ''
+  if 'has overflowed its stack' in stderr or (completed.returncode == STACK_OVERFLOW_EXIT and 'stack_guard:' in stderr):
+    return 'stack_overflow'
+  if completed.returncode < 0:
+    return 'crash'
+  return None
+
+def check_crash(language, completed):
+  # Raises BenchmarkCrashed, after logging why, if completed crashed
+  outcome = crash_outcome(completed)
+  if outcome == 'stack_overflow':
+    log.error(f"{language} benchmark overflowed its stack, try a larger --stack-size")
+  elif outcome == 'crash':
+    log.error(f"{language} benchmark was killed by {signal.Signals(-completed.returncode).name}")
+  if outcome:
+    raise BenchmarkCrashed(outcome)
+
+def get_l3_cache_size():
+  # Size in bytes of the L3 cache of CPU 0, e.g. "32768K" in sysfs
+  try:
+    size = pathlib.Path('/sys/devices/system/cpu/cpu0/cache/index3/size').read_text().strip()
+  except OSError:
+    return None
+  units = {'K': 1024, 'M': 1024 * 1024, 'G': 1024 * 1024 * 1024}
+  if size[-1] in units:
+    return int(size[:-1]) * units[size[-1]]
+  return int(size)
+
+# Instruction set extensions worth knowing about when comparing results, as
+# they are spelled in /proc/cpuinfo on x86 and ARM
+CPU_FEATURES = ['sse4_2', 'popcnt', 'avx', 'avx2', 'avx512f', 'fma', 'bmi1', 'bmi2', 'asimd', 'sve', 'sve2']
+
+# The machine a results file was produced on, stored next to it as JSON
+EnvSnapshot = namedtuple('EnvSnapshot', ['cpu_model', 'cpu_features', 'target_cpu', 'go_version', 'zig_version'], defaults=[None, None])
+
+def detect_cpu():
+  # Returns (model name, sorted list of CPU_FEATURES present) of the host
+  try:
+    cpuinfo = pathlib.Path('/proc/cpuinfo').read_text()
+  except OSError:
+    return platform.processor() or platform.machine(), []
+  model = platform.machine()
+  flags = set()
+  for line in cpuinfo.splitlines():
+    key, _, value = line.partition(':')
+    key = key.strip()
+    if key in ('model name', 'Hardware') and model == platform.machine():
+      model = value.strip()
+    elif key in ('flags', 'Features'):
+      flags.update(value.split())
+  return model, [feature for feature in CPU_FEATURES if feature in flags]
+
+def read_env_snapshot(snapshot_file):
+  # Snapshots written by other versions may lack some fields, which are None,
+  # or have ones this version doesn't know, which are ignored
+  if not os.path.exists(snapshot_file):
+    return None
+  with open(snapshot_file) as f:
+    snapshot = json.load(f)
+  return EnvSnapshot(**{field: snapshot.get(field) for field in EnvSnapshot._fields})
+
+def write_env_snapshot(snapshot_file, snapshot):
+  with open(snapshot_file, 'w') as f:
+    json.dump(snapshot._asdict(), f, indent=2)
+
+def flush_cache(size_bytes):
+  # Writing every byte of a buffer larger than the cache evicts whatever the
+  # previous benchmark left behind
+  buf = b'\x01' * size_bytes
+  del buf
+
+# How many times each build of a benchmark is run, and how many bytes to flush
+# from the caches before each of those runs, if any
+Timing = namedtuple('Timing', ['iterations', 'cache_flush_size'])
+
+def timed_iterations(timing, run_once):
+  # Calls run_once, which returns the time of one run or None if it failed,
+  # timing.iterations times, flushing the caches before each iteration.
+  # Returns the mean time, or None as soon as an iteration fails. A
+  # BenchmarkCrashed from run_once is passed on
+  times = []
+  for _ in range(timing.iterations):
+    if timing.cache_flush_size:
+      flush_cache(timing.cache_flush_size)
+    elapsed = run_once()
+    if elapsed is None:
+      return None
+    times.append(elapsed)
+  return statistics.fmean(times)
+
+def run_with_timeout(cmd, timeout, **kwargs):
+  # Runs cmd in its own process group and SIGKILLs the whole group if it is
+  # still running after timeout seconds, so a hung benchmark started through
+  # cargo or setarch doesn't outlive the harness. Returns the CompletedProcess,
+  # or None if the deadline was exceeded
+  proc = subprocess.Popen(cmd, start_new_session=True, **kwargs)
+  try:
+    stdout, stderr = proc.communicate(timeout=timeout)
+  except subprocess.TimeoutExpired:
+    os.killpg(proc.pid, signal.SIGKILL)
+    proc.communicate()
+    return None
+  return subprocess.CompletedProcess(cmd, proc.returncode, stdout, stderr)
+
+def run_c_benchmark(c_out, input_data_file, launcher, timeout, env=None):
+  try:
+    start_time = time.time()
+    c_output = run_with_timeout([*launcher, c_out], timeout, stdin=open(input_data_file), stdout=subprocess.PIPE, stderr=subprocess.PIPE, text=True, env=env)
+    if c_output is None:
+      log.error(f"C benchmark timed out after {timeout}s")
+      return None
+    check_crash("C", c_output)
+    c_output.check_returncode()
     # c_time = float(re.search(r'(\d+\.?\d+)', c_output.stdout).group(1))
     elapsed_time = time.time() - start_time
     log.info(f"C output: {c_output.stdout}")
     return elapsed_time
+  except BenchmarkCrashed:
+    raise
   except:
     log.error("C benchmark failed")
     return None
 
-def run_rust_benchmark(rust_file, rust_out, rust_dir, input_data_file):
+def run_extra_benchmark(language, exe, input_data_file, launcher, timeout):
+  # Runs the Go or Zig build of a benchmark, which take the same input as C
   try:
     start_time = time.time()
-    if os.path.exists(rust_file):
-      rust_output = subprocess.run([rust_out], stdin=open(input_data_file), capture_output=True, text=True, check=True)
+    output = run_with_timeout([*launcher, exe], timeout, stdin=open(input_data_file), stdout=subprocess.PIPE, stderr=subprocess.PIPE, text=True)
+    if output is None:
+      log.error(f"{language} benchmark timed out after {timeout}s")
+      return None
+    output.check_returncode()
+    elapsed_time = time.time() - start_time
+    log.info(f"{language} output: {output.stdout}")
+    return elapsed_time
+  except:
+    log.error(f"{language} benchmark failed")
+    return None
+
+def bench_main_time(output):
+  # The mean time in seconds of the timed runs that a benchmark built with
+  # #[bench_main] (Benchmarks/bench-main) reports on its JSON line, or None if
+  # output has no such line
+  for line in reversed(output.splitlines()):
+    if not line.startswith('{'):
+      continue
+    try:
+      report = json.loads(line)
+    except json.JSONDecodeError:
+      continue
+    if isinstance(report, dict) and 'mean_ns' in report:
+      return report['mean_ns'] / 1e9
+  return None
+
+def run_rust_benchmark(rust_file, rust_out, rust_dir, input_data_file, launcher, timeout, prebuilt=False):
+  # Cargo projects are run through cargo unless they were already built into
+  # rust_out (prebuilt). Benchmarks built with #[bench_main] time themselves,
+  # without cargo's or the process's start-up, and their own time is returned
+  try:
+    start_time = time.time()
+    if prebuilt or os.path.exists(rust_file):
+      rust_output = run_with_timeout([*launcher, rust_out], timeout, stdin=open(input_data_file), stdout=subprocess.PIPE, stderr=subprocess.PIPE, text=True)
     else:
-      rust_output = subprocess.run(['cargo', 'run', '--release'],
+      rust_output = run_with_timeout([*launcher, 'cargo', 'run', '--release'], timeout,
                      cwd=rust_dir,
                      stdin=open(input_data_file),
-                     capture_output=True,
-                     text=True,
-                     check=True)
+                     stdout=subprocess.PIPE,
+                     stderr=subprocess.PIPE,
+                     text=True)
+    if rust_output is None:
+      log.error(f"Rust benchmark timed out after {timeout}s")
+      return None
+    check_crash("Rust", rust_output)
+    rust_output.check_returncode()
     elapsed_time = time.time() - start_time
     # Keep original time parsing logic as backup/verification
     # parsed_time = float(re.search(r'(\d+\.?\d+)', rust_output.stdout).group(1))
     log.info(f"Rust output: {rust_output.stdout}")
+    harness_time = bench_main_time(rust_output.stdout)
+    if harness_time is not None:
+      return harness_time
     return elapsed_time
+  except BenchmarkCrashed:
+    raise
   except:
     log.error("Rust benchmark failed")
     return None
 
-def write_results(results_file, base_name, c_time, rust_time):
+# How much longer than a native run a benchmark may take under callgrind
+# before it is killed, as a multiple of --timeout
+VALGRIND_SLOWDOWN = 50
+
+def count_instructions(exe, input_data_file, launcher, timeout, out_file, env=None):
+  # Runs exe under callgrind, which writes its profile to out_file, and
+  # returns the number of instructions it retired (callgrind's Ir event), or
+  # None if it failed. Unlike the time, the count doesn't depend on what else
+  # the machine is doing. The launcher goes first, or callgrind would count it
+  # instead of exe
+  name = os.path.basename(exe)
+  output = run_with_timeout([*launcher, 'valgrind', '--tool=callgrind', f'--callgrind-out-file={out_file}', exe],
+                            timeout * VALGRIND_SLOWDOWN, stdin=open(input_data_file),
+                            stdout=subprocess.DEVNULL, stderr=subprocess.PIPE, text=True, env=env)
+  if output is None:
+    log.error(f"{name} timed out under callgrind after {timeout * VALGRIND_SLOWDOWN}s")
+    return None
+  if output.returncode != 0 or not os.path.exists(out_file):
+    log.error(f"{name} failed under callgrind: {output.stderr.strip()}")
+    return None
+  # The counts of all events, in the order of the "events:" line, are on the
+  # "summary:" line, or "totals:" in files written by older versions
+  events, totals = [], None
+  for line in pathlib.Path(out_file).read_text().splitlines():
+    if line.startswith('events:'):
+      events = line.split()[1:]
+    elif line.startswith(('summary:', 'totals:')):
+      totals = line.split()[1:]
+  if totals is None or 'Ir' not in events:
+    log.error(f"No instruction count in the callgrind output of {name}")
+    return None
+  return int(totals[events.index('Ir')])
+
+# How results are serialized for each --format. Every format is written one
+# row at a time so a partial run still leaves a usable file: JSON is written as
+# one object per line, and the HTML table's footer is written after every row
+# and cut off again before the next one is appended. header and row take the
+# columns to write, which are those of the file when appending to one
+ResultFormat = namedtuple('ResultFormat', ['header', 'row', 'footer'])
+
+# Columns describing how a result was produced, written after the timings
+METADATA_COLUMNS = ['profile', 'allocator', 'allocator_version', 'aslr']
+INSTRUCTION_COLUMNS = ['c_instructions', 'rust_instructions']
+# go_time and zig_time are left empty unless --compare-against-go or
+# --compare-against-zig ran that implementation, and the instruction counts
+# unless --compare-with-valgrind measured them. outcome is 'ok', or
+# 'stack_overflow' or 'crash' if the C or Rust version crashed, leaving its time
+# and the speedup empty. Files are read by their own header, so new columns go
+# at the end, where the rows appended to a file written by an older version can
+# leave them out
+COLUMNS = ['algorithm', 'c_time', 'rust_time', 'go_time', 'zig_time', 'speedup', *METADATA_COLUMNS, *INSTRUCTION_COLUMNS, 'outcome']
+
+def _cells(columns, name, c_time, rust_time, go_time, zig_time, instructions, speedup, metadata, outcome):
+  # The cells of a row for columns, empty for those this version doesn't know
+  times = {'c_time': c_time, 'rust_time': rust_time, 'go_time': go_time, 'zig_time': zig_time}
+  cells = {column: f"{t:.3f}" if t is not None else "" for column, t in times.items()}
+  cells.update(algorithm=name, speedup=f"{speedup:.2f}" if speedup is not None else "", outcome=outcome)
+  cells.update((column, str(metadata[column])) for column in METADATA_COLUMNS)
+  cells.update((column, str(count) if count is not None else "") for column, count in zip(INSTRUCTION_COLUMNS, instructions))
+  return [cells.get(column, "") for column in columns]
+
+def _delimited(cells, delimiter):
+  # cells as one line of a csv or tsv file, quoted where needed
+  line = io.StringIO()
+  csv.writer(line, delimiter=delimiter, lineterminator="\n").writerow(cells)
+  return line.getvalue()
+
+def _json_row(columns, name, c_time, rust_time, go_time, zig_time, instructions, speedup, metadata, outcome):
+  times = {'c_time': c_time, 'rust_time': rust_time, 'go_time': go_time, 'zig_time': zig_time}
+  c_instructions, rust_instructions = instructions
+  row = {'algorithm': name, **{column: round(t, 3) if t is not None else None for column, t in times.items()},
+         'speedup': round(speedup, 2) if speedup is not None else None}
+  row.update((column, metadata[column]) for column in METADATA_COLUMNS)
+  row.update(c_instructions=c_instructions, rust_instructions=rust_instructions, outcome=outcome)
+  return json.dumps(row)
+
+RESULT_FORMATS = {
+  'csv': ResultFormat(
+    lambda columns: _delimited(columns, ","),
+    lambda *row: _delimited(_cells(*row), ","),
+    ""),
+  'tsv': ResultFormat(
+    lambda columns: _delimited(columns, "\t"),
+    lambda *row: _delimited(_cells(*row), "\t"),
+    ""),
+  'json': ResultFormat(
+    lambda columns: "",
+    lambda *row: _json_row(*row) + "\n",
+    ""),
+  'html': ResultFormat(
+    lambda columns: "<table>\n<tr>" + "".join(f"<th>{html.escape(column)}</th>" for column in columns) + "</tr>\n",
+    lambda *row: "<tr>" + "".join(f"<td>{html.escape(cell)}</td>" for cell in _cells(*row)) + "</tr>\n",
+    "</table>\n"),
+  'markdown': ResultFormat(
+    lambda columns: "| " + " | ".join(columns) + " |\n|" + "".join("---|" if column in ('algorithm', *METADATA_COLUMNS, 'outcome') else "---:|" for column in columns) + "\n",
+    lambda *row: "| " + " | ".join(_cells(*row)) + " |\n",
+    ""),
+}
+
+def results_columns(results_file):
+  # The columns of an existing results file, in its order, or None if there
+  # is no such file. JSON rows name their own columns
+  if not os.path.exists(results_file) or os.path.getsize(results_file) == 0:
+    return None
+  with open(results_file) as f:
+    header = f.readline()
+    if header.startswith('<table>'):
+      header = f.readline()
+  if header.startswith('{'):
+    return COLUMNS
+  if header.startswith('<tr>'):
+    return [html.unescape(column) for column in re.findall(r'<th>(.*?)</th>', header)]
+  if header.startswith('|'):
+    return [column.strip() for column in header.strip().strip('|').split('|')]
+  return next(csv.reader([header], delimiter='\t' if '\t' in header else ','))
+
+def read_rows(results_file):
+  # The rows of results_file, in any of RESULT_FORMATS, as dicts keyed by its
+  # columns, with the cells as written. A missing or empty file has no rows.
+  # Raises ValueError for a file run.py didn't write
+  columns = results_columns(results_file)
+  if columns is None:
+    return []
+  with open(results_file) as f:
+    lines = f.read().splitlines()
+  if lines[0].startswith('{'):
+    return [json.loads(line) for line in lines if line.strip()]
+  if lines[0].startswith('<table>'):
+    lines = lines[1:]
+  if lines and lines[0].startswith('<tr>'):
+    cells = [[html.unescape(cell) for cell in re.findall(r'<td>(.*?)</td>', line)] for line in lines[1:] if line.startswith('<tr>')]
+  elif lines and lines[0].startswith('|'):
+    cells = [[cell.strip() for cell in line.strip().strip('|').split('|')] for line in lines[2:]]
+  elif lines and 'algorithm' in columns:
+    cells = list(csv.reader(lines[1:], delimiter='\t' if '\t' in lines[0] else ','))
+  else:
+    raise ValueError(f"{results_file} is not a results file in any of the {', '.join(RESULT_FORMATS)} formats")
+  return [dict(zip(columns, row)) for row in cells]
+
+def evaluated(results_file, name, metadata):
+  # Whether results_file, in any format, has a row for benchmark name run with
+  # the profile and allocator in metadata. The allocator version is only known
+  # once the benchmark is built. Files written before the metadata columns
+  # were added only hold runs with the defaults
+  rows = read_rows(results_file)
+  wanted = {'algorithm': name, 'profile': metadata['profile'], 'allocator': metadata['allocator']}
+  defaults = {'profile': 'default', 'allocator': 'system'}
+  return any(all(str(row.get(column, defaults.get(column))) == value for column, value in wanted.items())
+             for row in rows)
+
+# Files whose missing columns were already warned about
+_warned_columns = set()
+
+def write_results(results_file, base_name, c_time, rust_time, result_format, metadata, go_time=None, zig_time=None, instructions=(None, None), outcome='ok'):
+  # instructions holds the C and Rust instruction counts, if they were
+  # measured. The time of a version that crashed, as outcome tells, is None
   log.info(f"\nResults for {base_name}:")
-  log.info(f"C time: {c_time:.3f}s")
-  log.info(f"Rust time: {rust_time:.3f}s")
-  log.info(f"Rust is {c_time/rust_time:.2f}x faster than C")
-  
-  if not os.path.exists(results_file):
+  if outcome != 'ok':
+    log.info(f"Outcome: {outcome}")
+  for language, t in (("C", c_time), ("Rust", rust_time), ("Go", go_time), ("Zig", zig_time)):
+    if t is not None:
+      log.info(f"{language} time: {t:.3f}s")
+  if c_time is not None:
+    for language, t in (("Rust", rust_time), ("Go", go_time), ("Zig", zig_time)):
+      if t is not None:
+        log.info(f"{language} is {c_time/t:.2f}x faster than C")
+  c_instructions, rust_instructions = instructions
+  if c_instructions and rust_instructions:
+    log.info(f"C instructions: {c_instructions}")
+    log.info(f"Rust instructions: {rust_instructions}")
+    log.info(f"Rust retires {rust_instructions/c_instructions:.2f}x the instructions of C")
+
+  serializer = RESULT_FORMATS[result_format]
+  columns = results_columns(results_file)
+  if columns is None:
+    columns = COLUMNS
     with open(results_file, "w") as f:
-      f.write("algorithm,c_time,rust_time,speedup\n")
-      
-  with open(results_file, "a") as f:
-    speedup = c_time/rust_time
-    f.write(f"{base_name},{c_time:.3f},{rust_time:.3f},{speedup:.2f}\n")
+      f.write(serializer.header(columns))
+  missing = [column for column in COLUMNS if column not in columns]
+  if missing and results_file not in _warned_columns:
+    _warned_columns.add(results_file)
+    log.warning(f"{results_file} was written without the {', '.join(missing)} columns, which are left out of the rows added to it")
+
+  with open(results_file, "r+b") as f:
+    # Cut off the footer left by the previous row
+    footer = serializer.footer.encode()
+    f.seek(0, os.SEEK_END)
+    if footer and f.tell() >= len(footer):
+      f.seek(-len(footer), os.SEEK_END)
+      if f.read() == footer:
+        f.seek(-len(footer), os.SEEK_END)
+        f.truncate()
+    speedup = c_time/rust_time if c_time is not None and rust_time is not None else None
+    row = serializer.row(columns, base_name, c_time, rust_time, go_time, zig_time, instructions, speedup, metadata, outcome)
+    f.write((row + serializer.footer).encode())
+
+def write_build_times(build_times_file, base_name, c_times, rust_times):
+  # Compile and link times per language. Cargo projects are built as a whole,
+  # so their link time is left empty
+  if not os.path.exists(build_times_file):
+    with open(build_times_file, "w") as f:
+      f.write("algorithm,c_compile,c_link,rust_compile,rust_link\n")
+
+  with open(build_times_file, "a") as f:
+    rust_link = f"{rust_times[1]:.3f}" if rust_times[1] is not None else ""
+    f.write(f"{base_name},{c_times[0]:.3f},{c_times[1]:.3f},{rust_times[0]:.3f},{rust_link}\n")
+
+# Columns holding a time in seconds, which merge_results averages across hosts
+TIME_COLUMNS = ['c_time', 'rust_time', 'go_time', 'zig_time']
 
-def run_benchmark(d, c_file, input_data_file, opt_level, results_file):
+def read_results(results_file):
+  # Reads the rows of a results file, as read_rows does, with the times as
+  # floats, the instruction counts as ints and missing ones as None
+  if not os.path.exists(results_file):
+    raise ValueError(f"{results_file} does not exist")
+  rows = read_rows(results_file)
+  for row in rows:
+    for column in TIME_COLUMNS:
+      row[column] = float(row[column]) if row.get(column) not in (None, '') else None
+    for column in INSTRUCTION_COLUMNS:
+      row[column] = int(row[column]) if row.get(column) not in (None, '') else None
+  return rows
+
+def host_labels(results_files):
+  # Names the machine of each results file after the file, or after its
+  # directory if the files have the same name, as when each is the
+  # results.csv of a machine's output directory
+  paths = [pathlib.Path(f).absolute() for f in results_files]
+  stems = [path.stem for path in paths]
+  if len(set(stems)) == len(stems):
+    return stems
+  return [path.parent.name for path in paths]
+
+def merge_results(results):
+  # results maps a host name to the rows it produced. Rows for the same
+  # algorithm, profile and allocator are merged into one holding the geometric
+  # mean of each time across the hosts that have it, with the hosts' own times
+  # under per_host. A host whose time is more than 2 standard deviations from
+  # the mean, both taken over the logarithms of the times, is listed under
+  # outliers for that column. It takes at least 6 hosts for that to happen
+  groups = {}
+  for host, rows in results.items():
+    for row in rows:
+      key = (row['algorithm'], row.get('profile', 'default'), row.get('allocator', 'system'))
+      groups.setdefault(key, {})[host] = row
+  merged = []
+  for (name, profile, allocator), per_host in sorted(groups.items()):
+    row = {'algorithm': name}
+    outliers = []
+    for column in TIME_COLUMNS:
+      # Times that were rounded down to 0 have no logarithm and are left out
+      times = {host: r[column] for host, r in per_host.items() if r[column]}
+      row[column] = round(statistics.geometric_mean(times.values()), 3) if times else None
+      if len(times) < 2:
+        continue
+      logs = {host: math.log(t) for host, t in times.items()}
+      mean = statistics.fmean(logs.values())
+      deviation = statistics.pstdev(logs.values())
+      outliers.extend({'host': host, 'column': column} for host, value in logs.items()
+                      if deviation > 0 and abs(value - mean) > 2 * deviation)
+    row['speedup'] = round(row['c_time'] / row['rust_time'], 2) if row['c_time'] and row['rust_time'] else None
+    versions = sorted({str(r.get('allocator_version', '')) for r in per_host.values()})
+    aslr = sorted({str(r.get('aslr', '')) for r in per_host.values()})
+    row.update(profile=profile, allocator=allocator, allocator_version=','.join(versions), aslr=','.join(aslr))
+    row['per_host'] = {host: {column: r[column] for column in TIME_COLUMNS} for host, r in sorted(per_host.items())}
+    row['outliers'] = outliers
+    merged.append(row)
+  return merged
+
+def run_benchmark(d, c_file, input_data_file, c_flags, rust_flags, results_file, result_format, launcher, timing, timeout, build_times_file, profile='default', allocator='system', go=None, zig=None, c_package=None, callgrind_dir=None, stack_guard=None):
+  # c_package is the PkgConfig of the library the C benchmark links, if any.
+  # With a callgrind_dir, both versions are also run under callgrind, after
+  # the timed runs so as not to slow them down, to count their instructions,
+  # and their profiles are left there. Returns (c_time, rust_time) keyed by
+  # the name of each Rust variant that ran, as in RUST_VARIANTS. A crash is
+  # written with its outcome but not returned. stack_guard is the library
+  # from build_stack_guard to preload into the C version, if any
   base_name = os.path.splitext(os.path.basename(c_file))[0]
   rust_file = f"{d}/Rust/{base_name}.rs"
   rust_dir = f"{d}/Rust/{base_name}"
+  variants = RUST_VARIANTS.get(base_name, {base_name: []})
 
-  # Check if already evaluated in results.csv
-  if os.path.exists(results_file):
-    with open(results_file, "r") as f:
-      if any(line.startswith(base_name + ",") for line in f):
-        print(f"Skipping {base_name} as it was already evaluated")
-        return
+  # Check if already evaluated in the results file. Without a results file
+  # (in --watch mode) the times are only returned
+  if results_file:
+    variants = {name: assignments for name, assignments in variants.items()
+                if not evaluated(results_file, name, {'profile': profile, 'allocator': allocator})}
+    if not variants:
+      print(f"Skipping {base_name} as it was already evaluated with the {profile} profile and the {allocator} allocator")
+      return
 
   log.info(f"Evaluating {base_name}")
 
@@ -110,33 +1083,504 @@ def run_benchmark(d, c_file, input_data_file, opt_level, results_file):
   c_out = f"{d}/C/{base_name}.elf"
   c_source = pathlib.Path(c_file).read_text()
   c_source = c_source.replace("int n = 97;", f"int n = {len(input_data_list)};")
-  if not compile_c_source(c_source, c_out, opt_level):
-    return
-
   rust_out = f"{d}/Rust/{base_name}.elf"
-  if not compile_rust(rust_file, rust_dir, rust_out, opt_level):
-    return
+  c_libs = ()
+  if c_package is not None:
+    c_flags = [*c_flags, *c_package.cflags]
+    c_libs = c_package.libs
+
+  crate_version = None
+  if build_times_file:
+    c_times = time_c_build(c_source, c_out, c_flags, c_libs)
+    if c_times is None:
+      return
+    if os.path.exists(rust_file):
+      rust_times = time_rust_build(rust_file, rust_out, rust_flags)
+      if rust_times is None:
+        return
+    else:
+      start_time = time.time()
+      if not compile_rust(rust_file, rust_dir, rust_out, rust_flags):
+        return
+      rust_times = (time.time() - start_time, None)
+    write_build_times(build_times_file, base_name, c_times, rust_times)
+  elif allocator != 'system':
+    if not compile_c_source(c_source, c_out, c_flags, c_libs):
+      return
+    crate_version = compile_rust_with_allocator(rust_file, rust_dir, rust_out, rust_flags, allocator)
+    if crate_version is None:
+      return
+  else:
+    if not compile_c_source(c_source, c_out, c_flags, c_libs):
+      return
+    if not compile_rust(rust_file, rust_dir, rust_out, rust_flags):
+      return
     
-  c_time = run_c_benchmark(c_out, input_data_file)
-  if c_time is None:
+  c_outcome = 'ok'
+  try:
+    c_time = timed_iterations(timing, lambda: run_c_benchmark(c_out, input_data_file, launcher, timeout, c_benchmark_env(allocator, stack_guard)))
+  except BenchmarkCrashed as crash:
+    c_time, c_outcome = None, crash.outcome
+  if c_time is None and c_outcome == 'ok':
     return
     
-  rust_time = run_rust_benchmark(rust_file, rust_out, rust_dir, input_data_file)
-  if rust_time is None:
+  rust_times, outcomes = {}, {}
+  for name, assignments in variants.items():
+    variant_launcher = [*launcher, *env_launcher(assignments)]
+    try:
+      rust_time = timed_iterations(timing, lambda: run_rust_benchmark(rust_file, rust_out, rust_dir, input_data_file, variant_launcher, timeout, prebuilt=allocator != 'system'))
+      outcome = c_outcome
+    except BenchmarkCrashed as crash:
+      rust_time, outcome = None, crash.outcome
+    if rust_time is not None or outcome != 'ok':
+      rust_times[name], outcomes[name] = rust_time, outcome
+  if not rust_times:
     return
+
+  # The Go and Zig implementations are optional, so failing to build or run
+  # one only leaves its column empty
+  go_time = None
+  go_file = f"{d}/bench_go/{base_name}.go"
+  if go is not None and os.path.exists(go_file):
+    go_out = f"{d}/bench_go/{base_name}.elf"
+    if compile_go(go, go_file, go_out):
+      go_time = timed_iterations(timing, lambda: run_extra_benchmark("Go", go_out, input_data_file, launcher, timeout))
+
+  zig_time = None
+  zig_file = f"{d}/bench_zig/{base_name}.zig"
+  if zig is not None and os.path.exists(zig_file):
+    zig_out = f"{d}/bench_zig/{base_name}.elf"
+    if compile_zig(zig, zig_file, zig_out):
+      zig_time = timed_iterations(timing, lambda: run_extra_benchmark("Zig", zig_out, input_data_file, launcher, timeout))
+
+  instructions = {name: (None, None) for name in rust_times}
+  if callgrind_dir:
+    # Cargo projects are run through cargo, which mustn't be counted along
+    # with the benchmark
+    rust_exe = rust_out if allocator != 'system' or os.path.exists(rust_file) else cargo_executable(rust_dir)
+    os.makedirs(callgrind_dir, exist_ok=True)
+    c_instructions = count_instructions(c_out, input_data_file, launcher, timeout, f"{callgrind_dir}/{base_name}.c.out", c_benchmark_env(allocator)) if c_time is not None else None
+    for name in rust_times:
+      variant_launcher = [*launcher, *env_launcher(variants[name])]
+      instructions[name] = (c_instructions,
+                            count_instructions(rust_exe, input_data_file, variant_launcher, timeout, f"{callgrind_dir}/{name}.rust.out") if rust_exe and rust_times[name] is not None else None)
     
-  write_results(results_file, base_name, c_time, rust_time)
+  if results_file:
+    metadata = {'profile': profile, 'allocator': allocator, 'allocator_version': allocator_version(allocator, crate_version),
+                'aslr': aslr_setting(launcher)}
+    for name, rust_time in rust_times.items():
+      write_results(results_file, name, c_time, rust_time, result_format, metadata, go_time, zig_time, instructions[name], outcomes[name])
+  return {name: (c_time, rust_time) for name, rust_time in rust_times.items() if outcomes[name] == 'ok'}
+
+# Rust/C time ratios between these count as comparable in the GitHub step
+# summary, below as Rust being faster and above as it being slower
+COMPARABLE_RATIOS = (0.95, 1.05)
+
+def on_github_actions():
+  # The same check as CiEnv::detect in src/bootstrap/util.rs of the modified
+  # Rust tree
+  return os.environ.get('GITHUB_ACTIONS') == 'true'
+
+def on_azure_pipelines():
+  # The same check as CiEnv::detect
+  return os.environ.get('TF_BUILD') == 'True'
+
+def vso_escape(message):
+  # Escapes message for the body of a ##vso logging command, which would
+  # otherwise end at a newline or let a ; or ] be read as part of the command
+  for char, escaped in (('%', '%AZP25'), ('\r', '%0D'), ('\n', '%0A'), (';', '%3B'), (']', '%5D')):
+    message = message.replace(char, escaped)
+  return message
+
+def find_regressions(results, baseline, profile, allocator):
+  # Returns (name, baseline_ratio, ratio) for each benchmark in results whose
+  # Rust/C time ratio rose past the comparable range around the one in the
+  # baseline rows, as read_results returns them, for the same profile and
+  # allocator. Comparing ratios rather than times allows baselines from other
+  # machines
+  baseline_ratios = {row['algorithm']: row['rust_time'] / row['c_time'] for row in baseline
+                     if row.get('profile', 'default') == profile and row.get('allocator', 'system') == allocator
+                     and row['c_time'] and row['rust_time']}
+  regressions = []
+  for name, (c_time, rust_time) in sorted(results.items()):
+    if name in baseline_ratios and rust_time / c_time > baseline_ratios[name] * COMPARABLE_RATIOS[1]:
+      regressions.append((name, baseline_ratios[name], rust_time / c_time))
+  return regressions
+
+def step_summary(results, profile, allocator):
+  # A Markdown table of the Rust/C time ratio of each benchmark in results,
+  # which maps names to (c_time, rust_time)
+  low, high = COMPARABLE_RATIOS
+  lines = [f"### Rust vs C ({profile} profile, {allocator} allocator)", "",
+           f"🟢 Rust is faster, 🔴 slower, 🟡 within {low:.2f}-{high:.2f}x of C", "",
+           "| | benchmark | C time | Rust time | Rust/C |", "|---|---|---:|---:|---:|"]
+  for name, (c_time, rust_time) in sorted(results.items()):
+    ratio = rust_time / c_time
+    mark = "🟢" if ratio < low else "🔴" if ratio > high else "🟡"
+    lines.append(f"| {mark} | {name} | {c_time:.3f}s | {rust_time:.3f}s | {ratio:.2f} |")
+  return "\n".join(lines) + "\n"
+
+# A benchmark whose measured speedup (C time / Rust time) fell outside the
+# range it is expected to stay within. speedup is None if it has no result
+Violation = namedtuple('Violation', ['algorithm', 'speedup', 'min_speedup', 'max_speedup'])
+
+def read_expectations(expectations_file):
+  # Reads "algorithm,min_speedup,max_speedup" lines, e.g. "quick_sort,0.9,1.1"
+  # for "Rust should be within 10% of C". Blank lines and # comments are skipped
+  expectations = {}
+  for line in pathlib.Path(expectations_file).read_text().splitlines():
+    line = line.split('#', 1)[0].strip()
+    if not line or line.startswith('algorithm,'):
+      continue
+    name, low, high = (field.strip() for field in line.split(','))
+    expectations[name] = (float(low), float(high))
+  return expectations
+
+def check_expectations(results, expectations, names):
+  # results maps a benchmark name to (c_time, rust_time). Of the benchmarks in
+  # names, those that have an expectation but no result, because they failed
+  # or were skipped, are violations too. Benchmarks without an expectation, or
+  # not in names, are not checked
+  violations = []
+  for name in sorted(names):
+    if name not in expectations:
+      continue
+    low, high = expectations[name]
+    if name not in results:
+      violations.append(Violation(name, None, low, high))
+      continue
+    c_time, rust_time = results[name]
+    speedup = c_time / rust_time
+    if not low <= speedup <= high:
+      violations.append(Violation(name, speedup, low, high))
+  return violations
+
+def benchmark_sources(d, c_file):
+  # The source files a benchmark is built from
+  base_name = os.path.splitext(os.path.basename(c_file))[0]
+  rust_file = f"{d}/Rust/{base_name}.rs"
+  rust_dir = f"{d}/Rust/{base_name}"
+  if os.path.exists(rust_file):
+    return [c_file, rust_file]
+  return [c_file, f"{rust_dir}/Cargo.toml", *glob.glob(f"{rust_dir}/src/**/*.rs", recursive=True)]
+
+def watch(targets, run, interval):
+  # Runs every (d, c_file) in targets, then polls their sources every interval
+  # seconds and re-runs only the benchmarks whose .c or .rs files changed. The
+  # latest times are kept in a table that is redrawn at the top of the screen
+  def mtimes(d, c_file):
+    return {path: os.path.getmtime(path) for path in benchmark_sources(d, c_file) if os.path.exists(path)}
+
+  names = [os.path.splitext(os.path.basename(c_file))[0] for _, c_file in targets]
+  rows = {name: f"{name:<30} pending" for name in names}
+
+  def redraw():
+    # Clear the whole screen rather than moving back over the table, which
+    # compiler or benchmark output printed since the last redraw would shift
+    print("\033[H\033[2J", end="")
+    for name in names:
+      print(rows[name])
+
+  def update(name, d, c_file):
+    rows[name] = f"{name:<30} running..."
+    redraw()
+    times = run(d, c_file)
+    if not times:
+      rows[name] = f"{name:<30} failed"
+    else:
+      rows[name] = "\n".join(f"{variant:<30} C {c_time:.3f}s  Rust {rust_time:.3f}s  speedup {c_time/rust_time:.2f}x"
+                             for variant, (c_time, rust_time) in times.items())
+    redraw()
+
+  seen = {}
+  for name, (d, c_file) in zip(names, targets):
+    seen[name] = mtimes(d, c_file)
+    update(name, d, c_file)
+  while True:
+    time.sleep(interval)
+    for name, (d, c_file) in zip(names, targets):
+      current = mtimes(d, c_file)
+      if current != seen[name]:
+        seen[name] = current
+        update(name, d, c_file)
+
+# (level, C flags, Rust flags) for --compare-debug-vs-release. The Rust flags
+# mirror cargo's dev and release profiles
+BUILD_LEVELS = [
+  ('debug', ['-O0'], ['-C', 'opt-level=0', '-C', 'debug-assertions=on', '-C', 'overflow-checks=on']),
+  ('release', ['-O2'], ['-C', 'opt-level=3']),
+]
+
+def compare_debug_vs_release(d, c_file, input_data_file, launcher, timing, timeout, c_package=None):
+  # Builds and runs a benchmark at every level in BUILD_LEVELS. Returns the
+  # times keyed by (language, level), or None if any build or run failed
+  base_name = os.path.splitext(os.path.basename(c_file))[0]
+  rust_file = f"{d}/Rust/{base_name}.rs"
+  rust_dir = f"{d}/Rust/{base_name}"
+
+  if not (os.path.exists(rust_file) or os.path.exists(rust_dir)):
+    log.info(f"Skipping {base_name} because it doesn't exist for rust")
+    return None
+
+  log.info(f"Comparing debug and release builds of {base_name}")
+
+  input_data_list = pathlib.Path(input_data_file).read_text().strip().split()
+  c_source = pathlib.Path(c_file).read_text()
+  c_source = c_source.replace("int n = 97;", f"int n = {len(input_data_list)};")
+
+  times = {}
+  c_libs = c_package.libs if c_package is not None else ()
+  for level, c_flags, rust_flags in BUILD_LEVELS:
+    c_out = f"{d}/C/{base_name}-{level}.elf"
+    rust_out = f"{d}/Rust/{base_name}-{level}.elf"
+    if c_package is not None:
+      c_flags = [*c_flags, *c_package.cflags]
+    if not compile_c_source(c_source, c_out, c_flags, c_libs):
+      return None
+    if not compile_rust(rust_file, rust_dir, rust_out, rust_flags):
+      return None
+
+    try:
+      times['c', level] = timed_iterations(timing, lambda: run_c_benchmark(c_out, input_data_file, launcher, timeout))
+      times['rust', level] = timed_iterations(timing, lambda: run_rust_benchmark(rust_file, rust_out, rust_dir, input_data_file, launcher, timeout))
+    except BenchmarkCrashed:
+      return None
+    if times['c', level] is None or times['rust', level] is None:
+      return None
+  return times
+
+def print_comparison(comparison):
+  # Prints the debug/release ratio of each language and the Rust/C ratio at
+  # each level, one row per benchmark
+  print(f"{'algorithm':<30}{'C -O0':>9}{'C -O2':>9}{'Rust dbg':>10}{'Rust rel':>10}"
+        f"{'C d/r':>8}{'Rust d/r':>10}{'R/C dbg':>9}{'R/C rel':>9}")
+  for base_name, t in sorted(comparison.items()):
+    print(f"{base_name:<30}{t['c', 'debug']:>9.3f}{t['c', 'release']:>9.3f}"
+          f"{t['rust', 'debug']:>10.3f}{t['rust', 'release']:>10.3f}"
+          f"{t['c', 'debug'] / t['c', 'release']:>8.2f}{t['rust', 'debug'] / t['rust', 'release']:>10.2f}"
+          f"{t['rust', 'debug'] / t['c', 'debug']:>9.2f}{t['rust', 'release'] / t['c', 'release']:>9.2f}")
+
+# The debug information of a binary: its size, the total size of its .debug_*
+# sections and each of them by name, and the number of lines objdump
+# --dwarf=info prints for it, a rough measure of the number of DWARF entries
+DebugInfo = namedtuple('DebugInfo', ['binary_bytes', 'dwarf_sections_bytes', 'sections', 'dwarf_info_lines'])
+
+def debug_info(binary):
+  headers = subprocess.run(['objdump', '-h', binary], stdout=subprocess.PIPE, text=True, check=True).stdout
+  sections = {}
+  for line in headers.splitlines():
+    # "  Idx Name          Size      VMA  LMA  File off  Algn"
+    fields = line.split()
+    if len(fields) > 2 and fields[0].isdigit() and fields[1].startswith('.debug_'):
+      sections[fields[1]] = int(fields[2], 16)
+  # The output runs to hundreds of megabytes for Rust binaries, which carry
+  # the debug information of std, so it is counted as it streams
+  lines = 0
+  with subprocess.Popen(['objdump', '--dwarf=info', binary], stdout=subprocess.PIPE, stderr=subprocess.DEVNULL) as dump:
+    for chunk in iter(lambda: dump.stdout.read(1 << 20), b''):
+      lines += chunk.count(b'\n')
+  return DebugInfo(os.path.getsize(binary), sum(sections.values()), sections, lines)
+
+def cargo_executable(rust_dir, env=None):
+  # Builds the cargo project in rust_dir in release mode, which does nothing
+  # if it is up to date, and returns the path of its binary, or None if the
+  # build failed
+  try:
+    messages = subprocess.run(['cargo', 'build', '--release', '--message-format=json'],
+                   cwd=rust_dir, env=env, stdout=subprocess.PIPE, text=True, check=True).stdout
+  except subprocess.CalledProcessError:
+    log.error("Rust compilation failed")
+    return None
+  executables = [m['executable'] for m in map(json.loads, messages.splitlines())
+                 if m.get('reason') == 'compiler-artifact' and m.get('executable')]
+  return executables[-1]
+
+def compare_debug_info(d, c_file, c_flags, rust_flags, c_package=None):
+  # Builds a benchmark with full debug information in both languages. Returns
+  # the DebugInfo of each keyed by language, or None if a build failed
+  base_name = os.path.splitext(os.path.basename(c_file))[0]
+  rust_file = f"{d}/Rust/{base_name}.rs"
+  rust_dir = f"{d}/Rust/{base_name}"
+
+  if not (os.path.exists(rust_file) or os.path.exists(rust_dir)):
+    log.info(f"Skipping {base_name} because it doesn't exist for rust")
+    return None
+
+  log.info(f"Measuring the debug information of {base_name}")
+  c_out = f"{d}/C/{base_name}-debug-info.elf"
+  rust_out = f"{d}/Rust/{base_name}-debug-info.elf"
+  c_libs = c_package.libs if c_package is not None else ()
+  if c_package is not None:
+    c_flags = [*c_flags, *c_package.cflags]
+  if not compile_c_source(pathlib.Path(c_file).read_text(), c_out, [*c_flags, '-g'], c_libs):
+    return None
+  rust_flags = [*rust_flags, '-C', 'debuginfo=2']
+  if os.path.exists(rust_file):
+    if not compile_rust(rust_file, rust_dir, rust_out, rust_flags):
+      return None
+  else:
+    # cargo leaves the binary under target/ with the package's name, and
+    # strips the debug information from release builds unless the profile
+    # asks for it
+    env = {**os.environ, **cargo_env(rust_flags), 'CARGO_PROFILE_RELEASE_DEBUG': 'true'}
+    executable = cargo_executable(rust_dir, env)
+    if executable is None:
+      return None
+    shutil.copy(executable, rust_out)
+  return {'c': debug_info(c_out), 'rust': debug_info(rust_out)}
+
+def print_debug_info(debug_infos):
+  # Prints the binary and DWARF sizes of each language, and the Rust/C ratio
+  # of the DWARF sizes, one row per benchmark
+  print(f"{'algorithm':<30}{'C binary':>12}{'Rust binary':>13}{'C DWARF':>12}{'Rust DWARF':>12}{'R/C DWARF':>11}")
+  for base_name, info in sorted(debug_infos.items()):
+    c, rust = info['c'], info['rust']
+    ratio = f"{rust.dwarf_sections_bytes / c.dwarf_sections_bytes:>11.2f}" if c.dwarf_sections_bytes else f"{'-':>11}"
+    print(f"{base_name:<30}{c.binary_bytes:>12}{rust.binary_bytes:>13}"
+          f"{c.dwarf_sections_bytes:>12}{rust.dwarf_sections_bytes:>12}{ratio}")
+
+def write_debug_info(debug_info_file, debug_infos):
+  # One JSON object per benchmark and language
+  with open(debug_info_file, 'w') as f:
+    for base_name, info in sorted(debug_infos.items()):
+      for language, i in info.items():
+        f.write(json.dumps({'algorithm': base_name, 'language': language, **i._asdict()}) + "\n")
+
+def write_debug_info_html(report_file, debug_infos):
+  # A table of binary and DWARF sizes per benchmark, then one of the size of
+  # every .debug_* section either language has
+  cell = lambda value: f"<td>{html.escape(str(value))}</td>"
+  with open(report_file, 'w') as f:
+    f.write("<h2>Debug binary sizes</h2>\n<table>\n<tr><th>algorithm</th><th>C binary</th><th>Rust binary</th>"
+            "<th>C DWARF</th><th>Rust DWARF</th><th>C DWARF info lines</th><th>Rust DWARF info lines</th></tr>\n")
+    for base_name, info in sorted(debug_infos.items()):
+      c, rust = info['c'], info['rust']
+      f.write("<tr>" + "".join(map(cell, [base_name, c.binary_bytes, rust.binary_bytes, c.dwarf_sections_bytes,
+                                           rust.dwarf_sections_bytes, c.dwarf_info_lines, rust.dwarf_info_lines])) + "</tr>\n")
+    f.write("</table>\n<h2>DWARF sections</h2>\n<table>\n<tr><th>algorithm</th><th>section</th><th>C</th><th>Rust</th></tr>\n")
+    for base_name, info in sorted(debug_infos.items()):
+      c, rust = info['c'].sections, info['rust'].sections
+      for section in sorted(c.keys() | rust.keys()):
+        f.write("<tr>" + "".join(map(cell, [base_name, section, c.get(section, 0), rust.get(section, 0)])) + "</tr>\n")
+    f.write("</table>\n")
+
+# Benchmarks print the results their C and Rust versions must agree on, such
+# as a digest, on lines starting with this, which `run.py verify` compares
+VERIFY_PREFIX = 'verify: '
+
+def verify_lines(output):
+  return [line for line in output.splitlines() if line.startswith(VERIFY_PREFIX)]
+
+def benchmark_output(cmd, input_data_file, timeout, cwd=None):
+  # The stdout of a benchmark run, or None if it failed or timed out
+  completed = run_with_timeout(cmd, timeout, cwd=cwd, stdin=open(input_data_file), stdout=subprocess.PIPE, stderr=subprocess.PIPE, text=True)
+  if completed is None or completed.returncode != 0:
+    return None
+  return completed.stdout
+
+def verify_benchmark(d, c_file, input_data_file, c_flags, rust_flags, launcher, timeout, c_package=None):
+  # Builds and runs both versions of a benchmark and compares the lines they
+  # print with VERIFY_PREFIX. Returns True if they match, False if they differ
+  # or a build or run failed, and None if the benchmark prints none
+  base_name = os.path.splitext(os.path.basename(c_file))[0]
+  rust_file = f"{d}/Rust/{base_name}.rs"
+  rust_dir = f"{d}/Rust/{base_name}"
+  if not (os.path.exists(rust_file) or os.path.exists(rust_dir)):
+    return None
+
+  c_out = f"{d}/C/{base_name}.elf"
+  rust_out = f"{d}/Rust/{base_name}.elf"
+  input_data_list = pathlib.Path(input_data_file).read_text().strip().split()
+  c_source = pathlib.Path(c_file).read_text()
+  c_source = c_source.replace("int n = 97;", f"int n = {len(input_data_list)};")
+  c_libs = ()
+  if c_package is not None:
+    c_flags = [*c_flags, *c_package.cflags]
+    c_libs = c_package.libs
+  if not compile_c_source(c_source, c_out, c_flags, c_libs):
+    return False
+  if not compile_rust(rust_file, rust_dir, rust_out, rust_flags):
+    return False
+
+  c_output = benchmark_output([*launcher, c_out], input_data_file, timeout)
+  if os.path.exists(rust_file):
+    rust_output = benchmark_output([*launcher, rust_out], input_data_file, timeout)
+  else:
+    rust_output = benchmark_output([*launcher, 'cargo', 'run', '--release'], input_data_file, timeout, cwd=rust_dir)
+  if c_output is None or rust_output is None:
+    log.error(f"{base_name}: {'C' if c_output is None else 'Rust'} benchmark failed")
+    return False
+
+  c_lines, rust_lines = verify_lines(c_output), verify_lines(rust_output)
+  if not c_lines and not rust_lines:
+    return None
+  if c_lines != rust_lines:
+    log.error(f"{base_name}: C and Rust disagree")
+    for c_line, rust_line in zip(c_lines, rust_lines):
+      if c_line != rust_line:
+        log.error(f"  C:    {c_line[len(VERIFY_PREFIX):]}")
+        log.error(f"  Rust: {rust_line[len(VERIFY_PREFIX):]}")
+    if len(c_lines) != len(rust_lines):
+      log.error(f"  C printed {len(c_lines)} results, Rust {len(rust_lines)}")
+    return False
+  log.info(f"{base_name}: {len(c_lines)} results match")
+  return True
 
 def main():
   parser = argparse.ArgumentParser(description='Run C vs Rust benchmarks')
+  parser.add_argument('command', nargs='?', choices=['run', 'verify', 'merge'], default='run', help='run times the benchmarks; verify checks that the C and Rust versions print the same results; merge combines the results files of several machines (default: run)')
+  parser.add_argument('results', nargs='*', help='With merge, the results files to combine, one per machine and named after it')
+  parser.add_argument('--label', action='append', help='With merge, the name of the machine of each results file, given once per file in the same order (default: the file name, or the name of its directory when file names repeat)')
   parser.add_argument('--benchmark', type=str, help='Specific benchmark to run (without extension)')
   parser.add_argument('--opt-level', type=int, default=2, help='Optimization level (default: 2)')
   parser.add_argument('--input-data', type=str, default='Benchmarks/Algorithm_Benchmarks/input', help='Input data file path')
-  parser.add_argument('-o', '--output', type=str, default='results.csv', help='Output file path')
+  parser.add_argument('-o', '--output', type=str, default='results.csv', help='Output file path, relative to --output-dir (default: results.csv)')
+  parser.add_argument('--output-dir', type=str, help='Directory every output file is written to, created if needed (default: benchmark_results/<timestamp>)')
+  parser.add_argument('--output-dir-latest', type=str, default='benchmark_results/latest', help='Symlink pointed at the output directory of the latest run, or empty for none (default: benchmark_results/latest)')
+  parser.add_argument('--resume', action='store_true', help='Write to the output directory of the latest run instead of a new one, skipping the benchmarks it already evaluated')
+  parser.add_argument('--format', choices=list(RESULT_FORMATS), default='csv', help='Output file format (default: csv)')
+  parser.add_argument('--profile', choices=list(PROFILES), default='default', help='Build profile: lto adds fat LTO and thin-lto adds thin LTO to both languages (default: default)')
+  parser.add_argument('--target-cpu', type=str, help='CPU to generate code for in both languages, e.g. native to use every instruction set extension of the host')
+  parser.add_argument('--allocator', choices=list(ALLOCATORS), default='system', help='Memory allocator for both languages: linked into Rust as the global allocator and preloaded into C (default: system)')
+  parser.add_argument('--cflags', type=str, default='', help='Extra flags passed to gcc')
+  parser.add_argument('--rustflags', type=str, default='', help='Extra flags passed to rustc')
+  parser.add_argument('--check-flags', action='store_true', help='Warn if the C and Rust flags use different optimization, vectorization or debug info settings')
+  parser.add_argument('--strict', action='store_true', help='With --check-flags, exit instead of warning on a mismatch')
+  parser.add_argument('--iterations', type=int, default=1, help='Run each build of a benchmark this many times and record the mean time (default: 1)')
+  parser.add_argument('--cache-flush', action='store_true', help='Flush CPU caches before each timed iteration (cold-cache measurements)')
+  parser.add_argument('--cache-flush-size', type=int, help='Bytes written to flush caches (default: 2x the L3 cache size)')
+  parser.add_argument('--timeout', type=float, default=600, help='Seconds before a benchmark process is killed (default: 600)')
+  parser.add_argument('--compare-debug-vs-release', action='store_true', help='Run each benchmark as C -O0/-O2 and Rust debug/release and print a table of ratios')
+  parser.add_argument('--compare-debug-info', action='store_true', help='Build each benchmark with debug information, print the binary and DWARF sizes and write them to OUTPUT.debug-info.json, and with --format html an OUTPUT.debug-info.html report')
+  parser.add_argument('--compare-against-go', action='store_true', help='Also build and run the Go implementation in bench_go/ where one exists, reported as go_time')
+  parser.add_argument('--compare-with-valgrind', action='store_true', help='Also run each benchmark under valgrind --tool=callgrind and report the instructions it retired as c_instructions and rust_instructions, which unlike times are the same from run to run. The profiles are left in the callgrind directory of --output-dir')
+  parser.add_argument('--compare-against-zig', action='store_true', help='Also build and run the Zig implementation in bench_zig/ where one exists, reported as zig_time')
+  parser.add_argument('--build-times', type=str, help='Time compilation and linking separately and write them to this CSV file, relative to --output-dir')
+  parser.add_argument('--watch', action='store_true', help='Keep running and re-run benchmarks whose .c or .rs sources change')
+  parser.add_argument('--watch-interval', type=float, default=1.0, help='Seconds between checks for changed sources in --watch mode (default: 1)')
+  parser.add_argument('--github-step-summary', action='store_true', default=None, help='Append a Markdown table of the Rust/C ratios to the file in $GITHUB_STEP_SUMMARY, shown on the job\'s summary page (default: when running on GitHub Actions)')
+  parser.add_argument('--no-github-step-summary', dest='github_step_summary', action='store_false', default=None, help='Don\'t write the GitHub Actions job summary')
+  parser.add_argument('--azure-pipelines', action='store_true', default=None, help='Mark each benchmark with a ##[section] line and each --baseline regression with a ##vso[task.logissue] warning for Azure Pipelines (default: when running on Azure Pipelines)')
+  parser.add_argument('--no-azure-pipelines', dest='azure_pipelines', action='store_false', default=None, help='Don\'t print Azure Pipelines logging commands')
+  parser.add_argument('--baseline', type=str, help='Results file of an earlier run; warn about benchmarks whose Rust/C time ratio rose by more than 5%% since')
+  parser.add_argument('--expectations', type=str, help='CSV of algorithm,min_speedup,max_speedup; exit with an error if a benchmark run falls outside its range')
+  parser.add_argument('--stack-size', type=int, help='Stack size in bytes for benchmark processes and the threads Rust benchmarks spawn (default: the system limit). Stack overflows are written with the stack_overflow outcome')
+  parser.add_argument('--env', action='append', default=[], metavar='NAME=VALUE', help='Set an environment variable for the benchmark processes but not the compilers, like MALLOC_ARENA_MAX=1 or GODEBUG=gctrace=1 (repeatable)')
+  parser.add_argument('--no-aslr', action='store_true', help='Disable address space layout randomization for benchmark processes (Linux only)')
+  parser.add_argument('--seed', type=int, help='Seed for the order the benchmarks run in (default: a different order each time)')
+  parser.add_argument('--reproducible', action='store_true', help='Cut run-to-run noise: disable ASLR, pin each benchmark to one CPU (multi-threaded ones to all but CPU 0), raise the priority, use the performance CPU governor and fix --seed to 0 unless given (Linux only, needs root for all of it)')
   args = parser.parse_args()
-
-  benchmark_dirs = get_benchmark_dirs()
-  input_data_file = pathlib.Path(args.input_data).absolute()
+  if args.results and args.command != 'merge':
+    parser.error(f"{args.command} doesn't take results files")
+  for assignment in args.env:
+    if not re.match(r'[A-Za-z_][A-Za-z0-9_]*=', assignment):
+      parser.error(f"--env takes NAME=VALUE, not {assignment}")
+  if args.iterations < 1:
+    parser.error("--iterations must be at least 1")
+  if args.label and len(args.label) != len(args.results):
+    parser.error(f"--label was given {len(args.label)} times for {len(args.results)} results files")
+  if args.resume and args.output_dir:
+    parser.error("--resume continues in the latest run's output directory, it can't be combined with --output-dir")
 
   log.basicConfig(
       level=log.INFO,
@@ -144,14 +1588,198 @@ def main():
       datefmt='%Y-%m-%d %H:%M:%S'
   )
 
-  total_benchmarks = 0
+  if args.azure_pipelines is None:
+    args.azure_pipelines = on_azure_pipelines()
+
+  if args.command == 'merge':
+    if len(args.results) < 2:
+      log.error("merge needs at least two results files")
+      sys.exit(1)
+    labels = args.label or host_labels(args.results)
+    duplicates = sorted({label for label in labels if labels.count(label) > 1})
+    if duplicates:
+      log.error(f"merge: more than one results file is labelled {', '.join(duplicates)}, give each a different --label")
+      sys.exit(1)
+    try:
+      merged = merge_results({label: read_results(f) for label, f in zip(labels, args.results)})
+    except ValueError as e:
+      log.error(f"merge: {e}")
+      sys.exit(1)
+    # Merged rows have nested per-host times, so they are always written as
+    # json, by default next to results.csv rather than over it. The results
+    # files may well be under the latest link, which is only for runs
+    output_dir = make_output_dir(args.output_dir, None)
+    output = os.path.join(output_dir, args.output if args.output != parser.get_default('output') else 'merged.json')
+    with open(output, "w") as f:
+      f.writelines(json.dumps(row) + "\n" for row in merged)
+    for row in merged:
+      for outlier in row['outliers']:
+        log.warning(f"{row['algorithm']}: {outlier['column']} on {outlier['host']} is more than 2 standard deviations from the mean")
+    log.info(f"Merged {len(merged)} results from {len(args.results)} files into {output}")
+    return
+
+  # Verification only reads the benchmarks' output, so it writes nothing.
+  # Absolute paths are left as they are by join
+  output_dir = None
+  previous_output = None
+  if args.command != 'verify':
+    # The latest run's directory is looked up before the link moves on to
+    # this run's: --resume continues in it, and this run's environment is
+    # compared with the one it recorded
+    previous_dir = None
+    if args.output_dir_latest and os.path.isdir(args.output_dir_latest):
+      previous_dir = os.path.realpath(args.output_dir_latest)
+      previous_output = os.path.join(previous_dir, args.output)
+    if args.resume:
+      if previous_dir is None:
+        log.error(f"--resume: there is no latest run, {args.output_dir_latest} doesn't point to a directory")
+        sys.exit(1)
+      args.output_dir = previous_dir
+    output_dir = make_output_dir(args.output_dir, args.output_dir_latest)
+    log.info(f"Writing results to {output_dir}")
+    args.output = os.path.join(output_dir, args.output)
+    if args.build_times:
+      args.build_times = os.path.join(output_dir, args.build_times)
+
+  if args.reproducible:
+    if args.seed is None:
+      args.seed = 0
+    if make_reproducible():
+      log.info("Reproducible mode: ASLR disabled, benchmarks pinned, priority raised, performance governor set")
+    else:
+      log.warning("Reproducible mode could only be partly set up, results may vary between runs")
+  if args.seed is not None:
+    random.seed(args.seed)
+
+  benchmark_dirs = get_benchmark_dirs()
+  input_data_file = pathlib.Path(args.input_data).absolute()
+
+  profile_c_flags, profile_rust_flags = profile_flags(args.profile)
+  c_flags = [f'-O{args.opt_level}', *profile_c_flags, *args.cflags.split()]
+  rust_flags = ['-C', f'opt-level={args.opt_level}', *profile_rust_flags, *args.rustflags.split()]
+  if args.target_cpu:
+    c_flags.insert(1, f'-march={args.target_cpu}')
+    rust_flags[2:2] = ['-C', f'target-cpu={args.target_cpu}']
+
+  # Results from machines with different instruction sets aren't comparable,
+  # so warn before appending to results produced elsewhere
+  cpu_model, cpu_features = detect_cpu()
+  go = None
+  if args.compare_against_go:
+    go = detect_go()
+    if go is None:
+      log.warning("--compare-against-go: go was not found in PATH, only running C and Rust")
+    else:
+      log.info(f"Go: {go.version}")
+  zig = None
+  if args.compare_against_zig:
+    zig = detect_zig()
+    if zig is None:
+      log.warning("--compare-against-zig: zig was not found in PATH, skipping Zig implementations")
+    else:
+      log.info(f"Zig: {zig.version}")
+  snapshot = EnvSnapshot(cpu_model, cpu_features, args.target_cpu, go.version if go else None, zig.version if zig else None)
+  log.info(f"CPU: {cpu_model} ({', '.join(cpu_features) or 'no notable extensions'})")
+  snapshot_file = f"{args.output}.env.json"
+  # Results appended to are compared with their own snapshot, new ones with
+  # the latest run's
+  if previous_output is None or os.path.exists(snapshot_file):
+    previous_output = args.output
+  previous = read_env_snapshot(f"{previous_output}.env.json")
+  if previous is not None and set(previous.cpu_features) != set(cpu_features):
+    log.warning(f"{previous_output} was produced on a CPU with different features: "
+                f"{', '.join(sorted(set(previous.cpu_features) ^ set(cpu_features)))} differ")
+  # An existing snapshot stays the reference for the results it describes, so
+  # appending from another machine keeps warning instead of adopting its CPU
+  if (args.command == 'run' and not args.watch and not args.compare_debug_vs_release
+      and not args.compare_debug_info and not os.path.exists(snapshot_file)):
+    write_env_snapshot(snapshot_file, snapshot)
+  log.info(f"Profile: {args.profile}")
+  if args.check_flags:
+    mismatches = check_flag_equivalence(rust_flags, c_flags)
+    for m in mismatches:
+      message = f"Flag mismatch for {m.setting}: Rust uses {m.rust_value}, C uses {m.c_value}"
+      if args.strict:
+        log.error(message)
+      else:
+        log.warning(message)
+    if mismatches and args.strict:
+      sys.exit(1)
+
+  if args.allocator != 'system' and args.build_times:
+    log.error("--build-times can't be combined with --allocator")
+    sys.exit(1)
+  if args.allocator != 'system' and find_allocator_library(args.allocator) is None:
+    log.error(f"--allocator {args.allocator} needs lib{ALLOCATORS[args.allocator].c_library}.so to preload into C benchmarks, but it was not found")
+    sys.exit(1)
+
+  if args.compare_with_valgrind and shutil.which('valgrind') is None:
+    log.error("--compare-with-valgrind needs valgrind, but it was not found in PATH")
+    sys.exit(1)
+
+  # Benchmarks whose C version needs a missing library are skipped rather than
+  # failing to build
+  c_packages = detect_c_packages()
+
+  launcher = get_launcher(args.no_aslr)
+  log.info(f"ASLR: {'disabled' if launcher or aslr_disabled() else 'enabled'}")
+  launcher += env_launcher(args.env)
+  stack_guard = None
+  if args.stack_size:
+    launcher += stack_size_launcher(args.stack_size)
+    os.environ["RUST_MIN_STACK"] = str(args.stack_size)
+    log.info(f"Stack size: {args.stack_size} bytes")
+    # Rust reports its own stack overflows. Verification writes nothing
+    if output_dir is not None:
+      stack_guard = build_stack_guard(output_dir)
+
+  cache_flush_size = None
+  if args.cache_flush:
+    cache_flush_size = args.cache_flush_size
+    if cache_flush_size is None:
+      l3_size = get_l3_cache_size()
+      if l3_size is None:
+        log.warning("Could not read the L3 cache size, flushing 64MB between runs")
+        l3_size = 32 * 1024 * 1024
+      cache_flush_size = 2 * l3_size
+    log.info(f"Flushing {cache_flush_size} bytes of cache before each iteration")
+  timing = Timing(args.iterations, cache_flush_size)
+
+  def c_package(c_file):
+    return c_packages.get(os.path.splitext(os.path.basename(c_file))[0])
+
+  def benchmark_launcher(c_file):
+    name = os.path.splitext(os.path.basename(c_file))[0]
+    affinity = affinity_launcher(name) if args.reproducible else []
+    return launcher + env_launcher(BENCHMARK_ENV.get(name)) + affinity
+
+  callgrind_dir = os.path.join(output_dir, 'callgrind') if args.compare_with_valgrind else None
+  comparison = {}
+  debug_infos = {}
+  results = {}
+  def evaluate(d, c_file):
+    if args.compare_debug_info:
+      info = compare_debug_info(d, c_file, c_flags, rust_flags, c_package(c_file))
+      if info is not None:
+        debug_infos[os.path.splitext(os.path.basename(c_file))[0]] = info
+    elif args.compare_debug_vs_release:
+      times = compare_debug_vs_release(d, c_file, input_data_file, benchmark_launcher(c_file), timing, args.timeout, c_package(c_file))
+      if times is not None:
+        comparison[os.path.splitext(os.path.basename(c_file))[0]] = times
+    else:
+      if args.azure_pipelines:
+        print(f"##[section]Benchmark: {os.path.splitext(os.path.basename(c_file))[0]}", flush=True)
+      times = run_benchmark(d, c_file, input_data_file, c_flags, rust_flags, args.output, args.format, benchmark_launcher(c_file), timing, args.timeout, args.build_times, args.profile, args.allocator, go, zig, c_package(c_file), callgrind_dir, stack_guard)
+      if times is not None:
+        results.update(times)
+
+  targets = []
   if args.benchmark:
     # Run specific benchmark
     for d in benchmark_dirs:
       c_file = f"{d}/C/{args.benchmark}.c"
       if os.path.exists(c_file):
-        run_benchmark(d, c_file, input_data_file, args.opt_level, args.output)
-        total_benchmarks += 1
+        targets.append((d, c_file))
         break
     else:
       log.error(f"Benchmark {args.benchmark} not found")
@@ -160,11 +1788,75 @@ def main():
     for d in benchmark_dirs:
       c_files = glob.glob(f"{d}/C/*.c")
       random.shuffle(c_files)
-      
-      for c_file in c_files:
-        run_benchmark(d, c_file, input_data_file, args.opt_level, args.output)
-        total_benchmarks += 1
-  log.info(f"Total benchmarks: {total_benchmarks}")
+      targets.extend((d, c_file) for c_file in c_files)
+  # detect_c_packages already warned about these
+  targets = [(d, c_file) for d, c_file in targets
+             if os.path.splitext(os.path.basename(c_file))[0] not in C_PACKAGES.keys() - c_packages.keys()]
+
+  if args.command == 'verify':
+    failed = []
+    for d, c_file in targets:
+      if verify_benchmark(d, c_file, input_data_file, c_flags, rust_flags, benchmark_launcher(c_file), args.timeout, c_package(c_file)) is False:
+        failed.append(os.path.splitext(os.path.basename(c_file))[0])
+    if failed:
+      log.error(f"Verification failed for {', '.join(sorted(failed))}")
+      sys.exit(1)
+    return
+
+  if args.watch:
+    # Keep the log quiet so it doesn't scroll the results table away
+    log.getLogger().setLevel(log.WARNING)
+    try:
+      watch(targets, lambda d, c_file: run_benchmark(d, c_file, input_data_file, c_flags, rust_flags, None, args.format, benchmark_launcher(c_file), timing, args.timeout, None, args.profile, args.allocator, c_package=c_package(c_file), stack_guard=stack_guard), args.watch_interval)
+    except KeyboardInterrupt:
+      pass
+    return
+
+  for d, c_file in targets:
+    evaluate(d, c_file)
+  log.info(f"Total benchmarks: {len(targets)}")
+  if comparison:
+    print_comparison(comparison)
+  if debug_infos:
+    print_debug_info(debug_infos)
+    write_debug_info(f"{args.output}.debug-info.json", debug_infos)
+    if args.format == 'html':
+      write_debug_info_html(f"{args.output}.debug-info.html", debug_infos)
+
+  if args.baseline:
+    try:
+      baseline = read_results(args.baseline)
+    except ValueError as e:
+      log.error(f"--baseline: {e}")
+      sys.exit(1)
+    for name, baseline_ratio, ratio in find_regressions(results, baseline, args.profile, args.allocator):
+      message = f"{name}: the Rust/C time ratio rose from {baseline_ratio:.2f} in {args.baseline} to {ratio:.2f}"
+      if args.azure_pipelines:
+        print(f"##vso[task.logissue type=warning]{vso_escape(message)}", flush=True)
+      else:
+        log.warning(message)
+
+  # Explicitly asking for a summary outside of GitHub Actions only warns, so
+  # that the same command line works locally
+  if args.github_step_summary is None:
+    args.github_step_summary = on_github_actions()
+  elif args.github_step_summary and not (on_github_actions() and os.environ.get('GITHUB_STEP_SUMMARY')):
+    log.warning("--github-step-summary: not running on GitHub Actions, not writing a step summary")
+    args.github_step_summary = False
+  if args.github_step_summary and results and os.environ.get('GITHUB_STEP_SUMMARY'):
+    with open(os.environ['GITHUB_STEP_SUMMARY'], "a", encoding="utf-8") as f:
+      f.write(step_summary(results, args.profile, args.allocator))
+
+  if args.expectations:
+    names = {name for _, c_file in targets for name in result_names(os.path.splitext(os.path.basename(c_file))[0])}
+    violations = check_expectations(results, read_expectations(args.expectations), names)
+    for v in violations:
+      if v.speedup is None:
+        log.error(f"{v.algorithm}: no result to check against the expected range {v.min_speedup:.2f}x-{v.max_speedup:.2f}x, it failed or was skipped")
+      else:
+        log.error(f"{v.algorithm}: speedup {v.speedup:.2f}x is outside the expected range {v.min_speedup:.2f}x-{v.max_speedup:.2f}x")
+    if violations:
+      sys.exit(1)
 
 if __name__ == "__main__":
   main()
\ No newline at end of file
@@ -19,11 +19,15 @@
 use crate::doc;
 use crate::flags::{Color, Subcommand};
 use crate::install;
+use crate::metrics::{self, BuildMetrics};
 use crate::native;
 use crate::run;
 use crate::test;
 use crate::tool::{self, SourceType};
-use crate::util::{self, add_dylib_path, add_link_lib_path, exe, libdir, output, t};
+use crate::util::{
+    self, add_dylib_path, add_llvm_link_path, exe, libdir, t, FreshnessCache, HashStamps,
+    PathPlacement, ResourceUsage,
+};
 use crate::EXTRA_CHECK_CFGS;
 use crate::{Build, CLang, DocTests, GitRepo, Mode};
 
@@ -38,7 +42,11 @@ pub struct Builder<'a> {
     cache: Cache,
     stack: RefCell<Vec<Box<dyn Any>>>,
     time_spent_on_dependencies: Cell<Duration>,
+    cpu_spent_on_dependencies: Cell<Duration>,
     pub paths: Vec<PathBuf>,
+    pub freshness: FreshnessCache,
+    pub hash_stamps: HashStamps,
+    pub metrics: BuildMetrics,
 }
 
 impl<'a> Deref for Builder<'a> {
@@ -666,7 +674,15 @@ fn new_internal(build: &Build, kind: Kind, paths: Vec<PathBuf>) -> Builder<'_> {
             cache: Cache::new(),
             stack: RefCell::new(Vec::new()),
             time_spent_on_dependencies: Cell::new(Duration::new(0, 0)),
+            cpu_spent_on_dependencies: Cell::new(Duration::new(0, 0)),
             paths,
+            freshness: FreshnessCache::new(),
+            hash_stamps: HashStamps::new(build.out.join("hash-stamps"), build.config.dry_run),
+            metrics: BuildMetrics::new(
+                build.config.metrics.then(|| build.out.join("metrics.json")),
+                build.config.dry_run,
+                build.config.print_step_timings || build.is_verbose(),
+            ),
         }
     }
 
@@ -682,6 +698,7 @@ pub fn new(build: &Build) -> Builder<'_> {
             Subcommand::Dist { ref paths } => (Kind::Dist, &paths[..]),
             Subcommand::Install { ref paths } => (Kind::Install, &paths[..]),
             Subcommand::Run { ref paths } => (Kind::Run, &paths[..]),
+            Subcommand::ShowPaths { .. } => (Kind::Build, &[][..]),
             Subcommand::Format { .. } | Subcommand::Clean { .. } | Subcommand::Setup { .. } => {
                 panic!()
             }
@@ -692,6 +709,10 @@ pub fn new(build: &Build) -> Builder<'_> {
 
     pub fn execute_cli(&self) {
         self.run_step_descriptions(&Builder::get_step_descriptions(self.kind), &self.paths);
+        let hits = self.freshness.hits();
+        if hits > 0 {
+            self.verbose(&format!("freshness cache avoided {} up-to-date checks", hits));
+        }
     }
 
     pub fn default_doc(&self, paths: &[PathBuf]) {
@@ -750,6 +771,24 @@ pub fn sysroot(&self, compiler: Compiler) -> Interned<PathBuf> {
         self.ensure(compile::Sysroot { compiler })
     }
 
+    /// Returns where the sysroot of `compiler` is, without assembling it.
+    pub fn sysroot_path(&self, compiler: Compiler) -> PathBuf {
+        if compiler.stage == 0 {
+            self.out.join(compiler.host.triple).join("stage0-sysroot")
+        } else {
+            self.out.join(compiler.host.triple).join(format!("stage{}", compiler.stage))
+        }
+    }
+
+    /// Returns where `sysroot_libdir` is, without creating it.
+    pub fn sysroot_libdir_path(&self, compiler: Compiler, target: TargetSelection) -> PathBuf {
+        self.sysroot_path(compiler)
+            .join(self.sysroot_libdir_relative(compiler))
+            .join("rustlib")
+            .join(target.triple)
+            .join("lib")
+    }
+
     /// Returns the libdir where the standard library and other artifacts are
     /// found for a compiler's sysroot.
     pub fn sysroot_libdir(&self, compiler: Compiler, target: TargetSelection) -> Interned<PathBuf> {
@@ -766,13 +805,8 @@ fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
             }
 
             fn run(self, builder: &Builder<'_>) -> Interned<PathBuf> {
-                let lib = builder.sysroot_libdir_relative(self.compiler);
-                let sysroot = builder
-                    .sysroot(self.compiler)
-                    .join(lib)
-                    .join("rustlib")
-                    .join(self.target.triple)
-                    .join("lib");
+                builder.sysroot(self.compiler);
+                let sysroot = builder.sysroot_libdir_path(self.compiler, self.target);
                 // Avoid deleting the rustlib/ directory we just copied
                 // (in `impl Step for Sysroot`).
                 if !builder.config.download_rustc {
@@ -795,14 +829,22 @@ pub fn sysroot_codegen_backends(&self, compiler: Compiler) -> PathBuf {
     /// For example this returns `<sysroot>/lib` on Unix and `<sysroot>/bin` on
     /// Windows.
     pub fn rustc_libdir(&self, compiler: Compiler) -> PathBuf {
+        if !compiler.is_snapshot(self) {
+            self.sysroot(compiler);
+        }
+        self.rustc_libdir_path(compiler)
+    }
+
+    /// Returns where `rustc_libdir` is, without assembling the sysroot.
+    pub fn rustc_libdir_path(&self, compiler: Compiler) -> PathBuf {
         if compiler.is_snapshot(self) {
             self.rustc_snapshot_libdir()
         } else {
             match self.config.libdir_relative() {
                 Some(relative_libdir) if compiler.stage >= 1 => {
-                    self.sysroot(compiler).join(relative_libdir)
+                    self.sysroot_path(compiler).join(relative_libdir)
                 }
-                _ => self.sysroot(compiler).join(libdir(compiler.host)),
+                _ => self.sysroot_path(compiler).join(libdir(compiler.host)),
             }
         }
     }
@@ -838,6 +880,12 @@ pub fn sysroot_libdir_relative(&self, compiler: Compiler) -> &Path {
     /// Adds the compiler's directory of dynamic libraries to `cmd`'s dynamic
     /// library lookup path.
     pub fn add_rustc_lib_path(&self, compiler: Compiler, cmd: &mut Command) {
+        self.add_rustc_libdir(compiler, self.rustc_libdir(compiler), cmd);
+    }
+
+    /// `add_rustc_lib_path` given the compiler's `libdir`, so that it can be
+    /// called without assembling the sysroot.
+    pub fn add_rustc_libdir(&self, compiler: Compiler, libdir: PathBuf, cmd: &mut Command) {
         // Windows doesn't need dylib path munging because the dlls for the
         // compiler live next to the compiler and the system will find them
         // automatically.
@@ -845,7 +893,15 @@ pub fn add_rustc_lib_path(&self, compiler: Compiler, cmd: &mut Command) {
             return;
         }
 
-        let mut dylib_dirs = vec![self.rustc_libdir(compiler)];
+        util::label_lookup_paths("Builder::add_rustc_lib_path", || {
+            add_dylib_path(self.rustc_lib_dirs(compiler, libdir), PathPlacement::Prepend, cmd);
+        });
+    }
+
+    /// Returns the directories `add_rustc_lib_path` puts on the dynamic
+    /// library lookup path, given the compiler's `libdir`.
+    pub fn rustc_lib_dirs(&self, compiler: Compiler, libdir: PathBuf) -> Vec<PathBuf> {
+        let mut dylib_dirs = vec![libdir];
 
         // Ensure that the downloaded LLVM libraries can be found.
         if self.config.llvm_from_ci {
@@ -853,7 +909,7 @@ pub fn add_rustc_lib_path(&self, compiler: Compiler, cmd: &mut Command) {
             dylib_dirs.push(ci_llvm_lib);
         }
 
-        add_dylib_path(dylib_dirs, cmd);
+        dylib_dirs
     }
 
     /// Gets a path to the compiler specified.
@@ -1057,7 +1113,7 @@ pub fn cargo(
                         "error: `x.py clippy` requires a host `rustc` toolchain with the `clippy` component"
                     );
                     eprintln!("help: try `rustup component add clippy`");
-                    std::process::exit(1);
+                    util::exit(1);
                 });
                 if !t!(std::str::from_utf8(&output.stdout)).contains("nightly") {
                     rustflags.arg("--cfg=bootstrap");
@@ -1366,11 +1422,12 @@ pub fn cargo(
         // running `dsymutil` to preserve its historical default, but when compiling the compiler
         // itself, we skip it by default since we know it's safe to do so in that case.
         // See https://github.com/rust-lang/rust/issues/79361 for more info on this flag.
-        if target.contains("apple") {
-            if self.config.rust_run_dsymutil {
-                rustflags.arg("-Csplit-debuginfo=packed");
-            } else {
-                rustflags.arg("-Csplit-debuginfo=unpacked");
+        if target.contains("apple") || self.config.rust_split_debuginfo.is_some() {
+            let split_debuginfo = self.config.split_debuginfo(target);
+            rustflags.arg(&format!("-Csplit-debuginfo={}", split_debuginfo));
+            // Only the platform's default is stable outside of Apple targets.
+            if !target.contains("apple") {
+                rustflags.arg("-Zunstable-options");
             }
         }
 
@@ -1437,8 +1494,7 @@ pub fn cargo(
         // platform-specific environment variable as a workaround.
         if mode == Mode::ToolRustc || mode == Mode::Codegen {
             if let Some(llvm_config) = self.llvm_config(target) {
-                let llvm_libdir = output(Command::new(&llvm_config).arg("--libdir"));
-                add_link_lib_path(vec![llvm_libdir.trim().into()], &mut cargo);
+                add_llvm_link_path(&llvm_config, &mut cargo);
             }
         }
 
@@ -1688,6 +1744,12 @@ pub fn cargo(
         Cargo { command: cargo, rustflags, rustdocflags }
     }
 
+    /// Tells the freshness cache that `path` has been written, so that later
+    /// `up_to_date` checks involving it look at the filesystem again.
+    pub fn invalidate(&self, path: &Path) {
+        self.freshness.invalidate(path);
+    }
+
     /// Ensure that a given step is built, returning its output. This will
     /// cache the step, so it is safe (and good!) to call this as often as
     /// needed to ensure that all dependencies are built.
@@ -1715,16 +1777,33 @@ pub fn ensure<S: Step>(&'a self, step: S) -> S::Output {
             stack.push(Box::new(step.clone()));
         }
 
-        let (out, dur) = {
+        let name = format!("{:?}", step);
+        self.metrics.enter_step(name.clone(), metrics::step_target(&name));
+        self.hash_stamps.enter_step();
+
+        let (out, dur, usage) = {
+            // Only group the real build, not the dry run that precedes it.
+            let _group = if self.config.dry_run { None } else { Some(self.ci_env.group(&name)) };
             let start = Instant::now();
+            let start_usage = util::children_usage();
             let zero = Duration::new(0, 0);
             let parent = self.time_spent_on_dependencies.replace(zero);
+            let parent_cpu = self.cpu_spent_on_dependencies.replace(zero);
             let out = step.clone().run(self);
             let dur = start.elapsed();
+            let usage =
+                start_usage.zip(util::children_usage()).map(|(start, end)| end.since(&start));
             let deps = self.time_spent_on_dependencies.replace(parent + dur);
-            (out, dur - deps)
+            let cpu = usage.map_or(zero, |usage| usage.cpu);
+            let deps_cpu = self.cpu_spent_on_dependencies.replace(parent_cpu + cpu);
+            let usage =
+                usage.map(|usage| ResourceUsage { cpu: cpu.saturating_sub(deps_cpu), ..usage });
+            (out, dur - deps, usage)
         };
 
+        self.metrics.exit_step(dur, usage);
+        self.hash_stamps.exit_step();
+
         if self.config.print_step_timings && !self.config.dry_run {
             println!("[TIMING] {:?} -- {}.{:03}", step, dur.as_secs(), dur.subsec_millis());
         }
@@ -1871,6 +1950,14 @@ pub fn add_rustc_lib_path(&mut self, builder: &Builder<'_>, compiler: Compiler)
         builder.add_rustc_lib_path(compiler, &mut self.command);
     }
 
+    /// Makes `libdir` available to the programs built for `target` that cargo
+    /// runs, through the target's dynamic library lookup path if they run
+    /// under a `runner` wrapper like an emulator or Wine, and through the
+    /// host's otherwise.
+    pub fn add_target_lib_path(&mut self, libdir: PathBuf, target: TargetSelection, runner: bool) {
+        util::add_target_lib_path(libdir, target, runner, &mut self.command);
+    }
+
     pub fn current_dir(&mut self, dir: &Path) -> &mut Cargo {
         self.command.current_dir(dir);
         self
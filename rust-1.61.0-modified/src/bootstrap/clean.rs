@@ -9,7 +9,7 @@
 use std::io::{self, ErrorKind};
 use std::path::Path;
 
-use crate::util::t;
+use crate::util::{remove_retrying, t};
 use crate::Build;
 
 pub fn clean(build: &Build, all: bool) {
@@ -62,6 +62,11 @@ fn rm_rf(path: &Path) {
                             eprintln!("warning: failed to delete '{}'.", p.display());
                             return Ok(());
                         }
+                        // Delete symlinked directories on Windows
+                        #[cfg(windows)]
+                        if p.is_dir() {
+                            return fs::remove_dir(p);
+                        }
                         Err(e)
                     })
                 });
@@ -88,31 +93,16 @@ fn rm_rf(path: &Path) {
     };
 }
 
-fn do_op<F>(path: &Path, desc: &str, mut f: F)
+// This walks the tree itself rather than using `util::remove_dir_all_retrying`
+// so that it can skip the running `bootstrap.exe` on Windows, but retries the
+// same way: files held open by a virus scanner or a test binary that just
+// exited can't be removed for a moment, and read-only files need to be made
+// writable first.
+fn do_op<F>(path: &Path, desc: &str, f: F)
 where
     F: FnMut(&Path) -> io::Result<()>,
 {
-    match f(path) {
-        Ok(()) => {}
-        // On windows we can't remove a readonly file, and git will often clone files as readonly.
-        // As a result, we have some special logic to remove readonly files on windows.
-        // This is also the reason that we can't use things like fs::remove_dir_all().
-        Err(ref e) if cfg!(windows) && e.kind() == ErrorKind::PermissionDenied => {
-            let m = t!(path.symlink_metadata());
-            let mut p = m.permissions();
-            p.set_readonly(false);
-            t!(fs::set_permissions(path, p));
-            f(path).unwrap_or_else(|e| {
-                // Delete symlinked directories on Windows
-                #[cfg(windows)]
-                if m.file_type().is_symlink() && path.is_dir() && fs::remove_dir(path).is_ok() {
-                    return;
-                }
-                panic!("failed to {} {}: {}", desc, path.display(), e);
-            });
-        }
-        Err(e) => {
-            panic!("failed to {} {}: {}", desc, path.display(), e);
-        }
+    if let Err(e) = remove_retrying(path, f) {
+        panic!("failed to {} {}: {}", desc, path.display(), e);
     }
 }
@@ -13,7 +13,7 @@
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
-use std::process::{exit, Command, Stdio};
+use std::process::{Command, Stdio};
 use std::str;
 
 use serde::Deserialize;
@@ -25,7 +25,10 @@
 use crate::dist;
 use crate::native;
 use crate::tool::SourceType;
-use crate::util::{exe, is_debug_info, is_dylib, output, symlink_dir, t, up_to_date};
+use crate::util::{
+    exe, exit, is_dylib, is_dylib_file, output, prune_dangling_links, staticlib, symlink_dir, t,
+    up_to_date_checked, write_file_atomic, ArtifactKind, FollowLinks,
+};
 use crate::LLVM_TOOLS;
 use crate::{CLang, Compiler, DependencyType, GitRepo, Mode};
 
@@ -143,8 +146,9 @@ fn copy_and_stamp(
 
 fn copy_llvm_libunwind(builder: &Builder<'_>, target: TargetSelection, libdir: &Path) -> PathBuf {
     let libunwind_path = builder.ensure(native::Libunwind { target });
-    let libunwind_source = libunwind_path.join("libunwind.a");
-    let libunwind_target = libdir.join("libunwind.a");
+    let libunwind = staticlib("unwind", target);
+    let libunwind_source = libunwind_path.join(&libunwind);
+    let libunwind_target = libdir.join(&libunwind);
     builder.copy(&libunwind_source, &libunwind_target);
     libunwind_target
 }
@@ -491,7 +495,8 @@ fn run(self, builder: &Builder<'_>) -> Vec<(PathBuf, DependencyType)> {
         for file in &["rsbegin", "rsend"] {
             let src_file = &src_dir.join(file.to_string() + ".rs");
             let dst_file = &dst_dir.join(file.to_string() + ".o");
-            if !up_to_date(src_file, dst_file) {
+            // A missing source means rebuild, and rustc will report it.
+            if !up_to_date_checked(builder, src_file, dst_file, FollowLinks::Yes).unwrap_or(false) {
                 let mut cmd = Command::new(&builder.initial_rustc);
                 cmd.env("RUSTC_BOOTSTRAP", "1");
                 if !builder.local_rebuild {
@@ -506,6 +511,7 @@ fn run(self, builder: &Builder<'_>) -> Vec<(PathBuf, DependencyType)> {
                         .arg(dst_file)
                         .arg(src_file),
                 );
+                builder.invalidate(dst_file);
             }
 
             let target = sysroot_dir.join((*file).to_string() + ".o");
@@ -870,7 +876,7 @@ fn run(self, builder: &Builder<'_>) {
         }
         let stamp = codegen_backend_stamp(builder, compiler, target, backend);
         let codegen_backend = codegen_backend.to_str().unwrap();
-        t!(fs::write(&stamp, &codegen_backend));
+        t!(write_file_atomic(&stamp, codegen_backend));
     }
 }
 
@@ -985,11 +991,7 @@ fn should_run(run: ShouldRun<'_>) -> ShouldRun<'_> {
     /// 1-3.
     fn run(self, builder: &Builder<'_>) -> Interned<PathBuf> {
         let compiler = self.compiler;
-        let sysroot = if compiler.stage == 0 {
-            builder.out.join(&compiler.host.triple).join("stage0-sysroot")
-        } else {
-            builder.out.join(&compiler.host.triple).join(format!("stage{}", compiler.stage))
-        };
+        let sysroot = builder.sysroot_path(compiler);
         let _ = fs::remove_dir_all(&sysroot);
         t!(fs::create_dir_all(&sysroot));
 
@@ -1011,10 +1013,14 @@ fn run(self, builder: &Builder<'_>) -> Interned<PathBuf> {
         // so that any tools relying on `rust-src` also work for local builds,
         // and also for translating the virtual `/rustc/$hash` back to the real
         // directory (for running tests with `rust.remap-debuginfo = true`).
+        // Copying the whole source tree, build directory included, into every
+        // sysroot would be far too costly, so this is never a copy.
         let sysroot_lib_rustlib_src = sysroot.join("lib/rustlib/src");
         t!(fs::create_dir_all(&sysroot_lib_rustlib_src));
         let sysroot_lib_rustlib_src_rust = sysroot_lib_rustlib_src.join("rust");
-        if let Err(e) = symlink_dir(&builder.config, &builder.src, &sysroot_lib_rustlib_src_rust) {
+        if let Err(e) =
+            symlink_dir(&builder.config, &builder.src, &sysroot_lib_rustlib_src_rust, false, false)
+        {
             eprintln!(
                 "warning: creating symbolic link `{}` to `{}` failed with {}",
                 sysroot_lib_rustlib_src_rust.display(),
@@ -1138,14 +1144,44 @@ fn run(self, builder: &Builder<'_>) -> Compiler {
             .collect::<HashSet<_>>();
 
         let sysroot = builder.sysroot(target_compiler);
+        // Links left behind by an earlier build, for example on another
+        // branch, would otherwise be copied around and packaged broken.
+        if !builder.config.dry_run {
+            for link in t!(prune_dangling_links(&sysroot)) {
+                builder.verbose(&format!("Removed dangling link {}", link.display()));
+            }
+        }
         let rustc_libdir = builder.rustc_libdir(target_compiler);
         t!(fs::create_dir_all(&rustc_libdir));
         let src_libdir = builder.sysroot_libdir(build_compiler, host);
         for f in builder.read_dir(&src_libdir) {
             let filename = f.file_name().into_string().unwrap();
-            if (is_dylib(&filename) || is_debug_info(&filename)) && !proc_macros.contains(&filename)
-            {
-                builder.copy(&f.path(), &rustc_libdir.join(&filename));
+            if proc_macros.contains(&filename) {
+                continue;
+            }
+            let path = f.path();
+            let dst = rustc_libdir.join(&filename);
+            match ArtifactKind::classify(&path, host) {
+                // A `.dSYM` bundle
+                ArtifactKind::DebugInfo if path.is_dir() => {
+                    t!(fs::create_dir_all(&dst));
+                    builder.cp_r(&path, &dst);
+                }
+                ArtifactKind::DebugInfo => builder.copy(&path, &dst),
+                // Import libraries go next to the DLLs they belong to, which
+                // on Windows is `bin`, so that linking against them works
+                // from the sysroot just as it does in the stage's libdir.
+                ArtifactKind::ImportLib => builder.copy(&path, &dst),
+                // Check the header rather than trusting the name, which lets
+                // through data files ending in `.so` and misses libraries
+                // with unusual names. Directories like `self-contained` are
+                // neither.
+                ArtifactKind::Dylib | ArtifactKind::Other
+                    if path.is_file() && t!(is_dylib_file(&path)) =>
+                {
+                    builder.copy(&path, &dst)
+                }
+                _ => {}
             }
         }
 
@@ -1253,6 +1289,10 @@ pub fn run_cargo(
     let target_root_dir = stamp.parent().unwrap();
     // `target_deps_dir` looks like $dir/$target/release/deps
     let target_deps_dir = target_root_dir.join("deps");
+    // The target the artifacts are built for, from the `$target` above
+    let target = TargetSelection::from_user(
+        target_root_dir.parent().unwrap().file_name().unwrap().to_str().unwrap(),
+    );
     // `host_root_dir` looks like $dir/release
     let host_root_dir = target_root_dir
         .parent()
@@ -1276,14 +1316,18 @@ pub fn run_cargo(
             _ => return,
         };
         for filename in filenames {
-            // Skip files like executables
-            if !(filename.ends_with(".rlib")
-                || filename.ends_with(".lib")
-                || filename.ends_with(".a")
-                || is_debug_info(&filename)
-                || is_dylib(&filename)
-                || (is_check && filename.ends_with(".rmeta")))
-            {
+            // Skip files like executables. `.dSYM` bundles are directories,
+            // which the stamp can't track, so they are skipped too.
+            let keep = match ArtifactKind::classify(Path::new(&*filename), target) {
+                ArtifactKind::Rlib
+                | ArtifactKind::StaticLib
+                | ArtifactKind::ImportLib
+                | ArtifactKind::Dylib => true,
+                ArtifactKind::DebugInfo => !filename.ends_with(".dSYM"),
+                ArtifactKind::Rmeta => is_check,
+                ArtifactKind::Exe | ArtifactKind::Other => false,
+            };
+            if !keep {
                 continue;
             }
 
@@ -1374,7 +1418,7 @@ pub fn run_cargo(
         new_contents.extend(dep.to_str().unwrap().as_bytes());
         new_contents.extend(b"\0");
     }
-    t!(fs::write(&stamp, &new_contents));
+    t!(write_file_atomic(stamp, new_contents));
     deps.into_iter().map(|(d, _)| d).collect()
 }
 
@@ -10,6 +10,7 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
 use crate::builder::TaskPath;
 use crate::cache::{Interned, INTERNER};
@@ -65,6 +66,8 @@ pub struct Config {
     pub include_default_paths: bool,
     pub rustc_error_format: Option<String>,
     pub json_output: bool,
+    pub timestamps: bool,
+    pub trace_file: Option<PathBuf>,
     pub test_compare_mode: bool,
     pub llvm_libunwind: LlvmLibunwind,
     pub color: Color,
@@ -131,6 +134,9 @@ pub struct Config {
     pub rust_debuginfo_level_tools: u32,
     pub rust_debuginfo_level_tests: u32,
     pub rust_run_dsymutil: bool,
+    pub rust_split_debuginfo: Option<SplitDebuginfo>,
+    pub rust_strip: bool,
+    pub rust_debuginfo_packages: bool,
     pub rust_rpath: bool,
     pub rustc_parallel: bool,
     pub rustc_default_linker: Option<String>,
@@ -169,7 +175,17 @@ pub struct Config {
     pub save_toolstates: Option<PathBuf>,
     pub print_step_timings: bool,
     pub print_step_rusage: bool,
+    /// `timeit` scopes that took less than this are only printed with `-vv`.
+    pub timeit_threshold: Duration,
+    /// With `print_step_timings`, steps that took more than this many times
+    /// as long as in the previous build, and more than
+    /// `step_timings_regression_floor` longer, are pointed out.
+    pub step_timings_regression_factor: f64,
+    pub step_timings_regression_floor: Duration,
+    pub metrics: bool,
+    pub symlink_copy_fallback: bool,
     pub missing_tools: bool,
+    pub equal_mtime: EqualMtime,
 
     // Fallback musl-root for all targets
     pub musl_root: Option<PathBuf>,
@@ -184,6 +200,7 @@ pub struct Config {
     pub nodejs: Option<PathBuf>,
     pub npm: Option<PathBuf>,
     pub gdb: Option<PathBuf>,
+    pub dylib_audit: Option<PathBuf>,
     pub python: Option<PathBuf>,
     pub cargo_native_static: bool,
     pub configure_args: Vec<String>,
@@ -221,6 +238,80 @@ fn from_str(value: &str) -> Result<Self, Self::Err> {
     }
 }
 
+/// Where rustc puts debug info, as given to `-Csplit-debuginfo`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitDebuginfo {
+    /// In one file next to each binary: a `.dSYM` bundle on Apple platforms,
+    /// a `.pdb` on MSVC and a `.dwp` elsewhere.
+    Packed,
+    /// Left in the object files on Apple platforms, or in `.dwo` files next
+    /// to the binary elsewhere.
+    Unpacked,
+    /// In the binary itself.
+    Off,
+}
+
+impl FromStr for SplitDebuginfo {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "packed" => Ok(Self::Packed),
+            "unpacked" => Ok(Self::Unpacked),
+            "off" => Ok(Self::Off),
+            invalid => Err(format!("Invalid value '{}' for rust.split-debuginfo config.", invalid)),
+        }
+    }
+}
+
+impl fmt::Display for SplitDebuginfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Packed => "packed",
+            Self::Unpacked => "unpacked",
+            Self::Off => "off",
+        })
+    }
+}
+
+/// How `util::up_to_date` treats a source file whose modification time is
+/// equal to that of its destination.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EqualMtime {
+    /// Stale if the build directory's filesystem only records whole seconds
+    /// (FAT, exFAT, some network filesystems), up to date otherwise.
+    Auto,
+    /// Always up to date.
+    Fresh,
+    /// Always stale.
+    Stale,
+    /// Up to date only if the source hashes the same as when the destination
+    /// was last generated from it.
+    Content,
+}
+
+// `#[default]` on a variant needs Rust 1.62, newer than the stage0 compiler.
+#[allow(clippy::derivable_impls)]
+impl Default for EqualMtime {
+    fn default() -> Self {
+        Self::Auto
+    }
+}
+
+impl FromStr for EqualMtime {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(Self::Auto),
+            "fresh" => Ok(Self::Fresh),
+            "stale" => Ok(Self::Stale),
+            "content" => Ok(Self::Content),
+            invalid => Err(format!("Invalid value '{}' for build.equal-mtime config.", invalid)),
+        }
+    }
+}
+
 #[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TargetSelection {
     pub triple: Interned<String>,
@@ -465,6 +556,7 @@ struct Build {
         submodules: Option<bool> = "submodules",
         fast_submodules: Option<bool> = "fast-submodules",
         gdb: Option<String> = "gdb",
+        dylib_audit: Option<String> = "dylib-audit",
         nodejs: Option<String> = "nodejs",
         npm: Option<String> = "npm",
         python: Option<String> = "python",
@@ -482,6 +574,9 @@ struct Build {
         local_rebuild: Option<bool> = "local-rebuild",
         print_step_timings: Option<bool> = "print-step-timings",
         print_step_rusage: Option<bool> = "print-step-rusage",
+        timeit_threshold: Option<u64> = "timeit-threshold",
+        step_timings_regression_factor: Option<f64> = "step-timings-regression-factor",
+        step_timings_regression_floor: Option<u64> = "step-timings-regression-floor",
         check_stage: Option<u32> = "check-stage",
         doc_stage: Option<u32> = "doc-stage",
         build_stage: Option<u32> = "build-stage",
@@ -490,6 +585,10 @@ struct Build {
         dist_stage: Option<u32> = "dist-stage",
         bench_stage: Option<u32> = "bench-stage",
         patch_binaries_for_nix: Option<bool> = "patch-binaries-for-nix",
+        equal_mtime: Option<String> = "equal-mtime",
+        ci_env: Option<String> = "ci-env",
+        metrics: Option<bool> = "metrics",
+        symlink_copy_fallback: Option<bool> = "symlink-copy-fallback",
     }
 }
 
@@ -581,6 +680,9 @@ struct Rust {
         debuginfo_level_tools: Option<u32> = "debuginfo-level-tools",
         debuginfo_level_tests: Option<u32> = "debuginfo-level-tests",
         run_dsymutil: Option<bool> = "run-dsymutil",
+        split_debuginfo: Option<String> = "split-debuginfo",
+        strip: Option<bool> = "strip",
+        debuginfo_packages: Option<bool> = "debuginfo-packages",
         backtrace: Option<bool> = "backtrace",
         incremental: Option<bool> = "incremental",
         parallel_compiler: Option<bool> = "parallel-compiler",
@@ -659,7 +761,11 @@ pub fn default_opts() -> Config {
         config.rust_dist_src = true;
         config.rust_codegen_backends = vec![INTERNER.intern_str("llvm")];
         config.deny_warnings = true;
+        config.symlink_copy_fallback = true;
         config.bindir = "bin".into();
+        config.timeit_threshold = Duration::from_millis(100);
+        config.step_timings_regression_factor = 1.5;
+        config.step_timings_regression_floor = Duration::from_secs(30);
 
         // set by build.rs
         config.build = TargetSelection::from_user(&env!("BUILD_TRIPLE"));
@@ -682,6 +788,8 @@ pub fn parse(args: &[String]) -> Config {
         config.include_default_paths = flags.include_default_paths;
         config.rustc_error_format = flags.rustc_error_format;
         config.json_output = flags.json_output;
+        config.timestamps = flags.timestamps;
+        config.trace_file = flags.trace_file;
         config.on_fail = flags.on_fail;
         config.jobs = flags.jobs.map(threads_from_config);
         config.cmd = flags.cmd;
@@ -750,6 +858,7 @@ pub fn parse(args: &[String]) -> Config {
         config.config = toml_path;
 
         let build = toml.build.unwrap_or_default();
+        crate::util::CiEnv::init(build.ci_env.as_deref());
 
         set(&mut config.initial_rustc, build.rustc.map(PathBuf::from));
         set(&mut config.out, build.build_dir.map(PathBuf::from));
@@ -786,6 +895,7 @@ pub fn parse(args: &[String]) -> Config {
         config.nodejs = build.nodejs.map(PathBuf::from);
         config.npm = build.npm.map(PathBuf::from);
         config.gdb = build.gdb.map(PathBuf::from);
+        config.dylib_audit = build.dylib_audit.map(PathBuf::from);
         config.python = build.python.map(PathBuf::from);
         config.submodules = build.submodules;
         set(&mut config.low_priority, build.low_priority);
@@ -809,6 +919,19 @@ pub fn parse(args: &[String]) -> Config {
         set(&mut config.local_rebuild, build.local_rebuild);
         set(&mut config.print_step_timings, build.print_step_timings);
         set(&mut config.print_step_rusage, build.print_step_rusage);
+        if let Some(ms) = build.timeit_threshold {
+            config.timeit_threshold = Duration::from_millis(ms);
+        }
+        set(&mut config.step_timings_regression_factor, build.step_timings_regression_factor);
+        if let Some(secs) = build.step_timings_regression_floor {
+            config.step_timings_regression_floor = Duration::from_secs(secs);
+        }
+        set(&mut config.metrics, build.metrics);
+        set(&mut config.symlink_copy_fallback, build.symlink_copy_fallback);
+        config.equal_mtime = build
+            .equal_mtime
+            .map(|v| v.parse().expect("failed to parse build.equal-mtime"))
+            .unwrap_or_default();
 
         config.verbose = cmp::max(config.verbose, flags.verbose);
 
@@ -987,6 +1110,11 @@ pub fn parse(args: &[String]) -> Config {
             debuginfo_level_tools = rust.debuginfo_level_tools;
             debuginfo_level_tests = rust.debuginfo_level_tests;
             config.rust_run_dsymutil = rust.run_dsymutil.unwrap_or(false);
+            config.rust_split_debuginfo = rust
+                .split_debuginfo
+                .map(|v| v.parse().expect("failed to parse rust.split-debuginfo"));
+            set(&mut config.rust_strip, rust.strip);
+            set(&mut config.rust_debuginfo_packages, rust.debuginfo_packages);
             optimize = rust.optimize;
             ignore_git = rust.ignore_git;
             config.rust_new_symbol_mangling = rust.new_symbol_mangling;
@@ -1147,7 +1275,7 @@ pub fn parse(args: &[String]) -> Config {
             Subcommand::Doc { .. } => {
                 flags.stage.or(build.doc_stage).unwrap_or(if download_rustc { 2 } else { 0 })
             }
-            Subcommand::Build { .. } => {
+            Subcommand::Build { .. } | Subcommand::ShowPaths { .. } => {
                 flags.stage.or(build.build_stage).unwrap_or(if download_rustc { 2 } else { 1 })
             }
             Subcommand::Test { .. } => {
@@ -1166,9 +1294,16 @@ pub fn parse(args: &[String]) -> Config {
             | Subcommand::Format { .. } => flags.stage.unwrap_or(0),
         };
 
-        // CI should always run stage 2 builds, unless it specifically states otherwise
+        // CI should always run stage 2 builds, unless it specifically states otherwise.
+        // This only holds for the providers rust-lang's own CI runs on: other ones,
+        // and anything that merely sets `CI=true`, may well build other stages.
         #[cfg(not(test))]
-        if flags.stage.is_none() && crate::CiEnv::current() != crate::CiEnv::None {
+        if flags.stage.is_none()
+            && matches!(
+                crate::CiEnv::current(),
+                crate::CiEnv::AzurePipelines | crate::CiEnv::GitHubActions
+            )
+        {
             match config.cmd {
                 Subcommand::Test { .. }
                 | Subcommand::Doc { .. }
@@ -1188,6 +1323,7 @@ pub fn parse(args: &[String]) -> Config {
                 | Subcommand::Fix { .. }
                 | Subcommand::Run { .. }
                 | Subcommand::Setup { .. }
+                | Subcommand::ShowPaths { .. }
                 | Subcommand::Format { .. } => {}
             }
         }
@@ -1240,6 +1376,19 @@ pub fn any_profiler_enabled(&self) -> bool {
         self.target_config.values().any(|t| t.profiler == Some(true)) || self.profiler
     }
 
+    /// The split debug info mode of the compiler and tools built for
+    /// `target`: `rust.split-debuginfo` if set, and otherwise rustc's default
+    /// for the target, except that Apple targets only run `dsymutil` to pack
+    /// it with `rust.run-dsymutil`.
+    pub fn split_debuginfo(&self, target: TargetSelection) -> SplitDebuginfo {
+        match self.rust_split_debuginfo {
+            Some(mode) => mode,
+            None if target.contains("apple") && !self.rust_run_dsymutil => SplitDebuginfo::Unpacked,
+            None if target.contains("apple") || target.contains("msvc") => SplitDebuginfo::Packed,
+            None => SplitDebuginfo::Off,
+        }
+    }
+
     pub fn llvm_enabled(&self) -> bool {
         self.rust_codegen_backends.contains(&INTERNER.intern_str("llvm"))
     }
@@ -20,7 +20,10 @@
 use crate::config::TargetSelection;
 use crate::tarball::{GeneratedTarball, OverlayKind, Tarball};
 use crate::tool::{self, Tool};
-use crate::util::{exe, is_dylib, output, t, timeit};
+use crate::util::{
+    self, exe, is_dylib, newest_mtime, output, remove_dir_all_retrying, strip_artifact, t,
+    timeit_labeled, ArtifactKind,
+};
 use crate::{Compiler, DependencyType, Mode, LLVM_TOOLS};
 
 pub fn pkgname(builder: &Builder<'_>, component: &str) -> String {
@@ -356,7 +359,14 @@ fn prepare_image(builder: &Builder<'_>, compiler: Compiler, image: &Path) {
             t!(fs::create_dir_all(image.join("bin")));
             builder.cp_r(&src.join("bin"), &image.join("bin"));
 
-            builder.install(&builder.rustdoc(compiler), &image.join("bin"), 0o755);
+            // The compiler the sysroot's rustc and rustdoc were built with.
+            let build_compiler =
+                builder.compiler(compiler.stage.saturating_sub(1), builder.config.build);
+
+            let rustdoc = builder.rustdoc(compiler);
+            builder.install(&rustdoc, &image.join("bin"), 0o755);
+            let out = builder.cargo_out(build_compiler, Mode::ToolRustc, host);
+            copy_debuginfo(builder, &rustdoc, host, &out, &image.join("bin"));
 
             let libdir_relative = builder.libdir_relative(compiler);
 
@@ -370,6 +380,8 @@ fn prepare_image(builder: &Builder<'_>, compiler: Compiler, image: &Path) {
                             // Don't use custom libdir here because ^lib/ will be resolved again
                             // with installer
                             builder.install(&entry.path(), &image.join("lib"), 0o644);
+                            let out = builder.cargo_out(build_compiler, Mode::Rustc, host);
+                            copy_debuginfo(builder, &entry.path(), host, &out, &image.join("lib"));
                         }
                     }
                 }
@@ -414,6 +426,10 @@ fn prepare_image(builder: &Builder<'_>, compiler: Compiler, image: &Path) {
                 }
             }
 
+            for dir in [&image.join("bin"), &image.join("lib"), &backends_dst, &dst_dir] {
+                strip_dir(builder, dir, host);
+            }
+
             // Man pages
             t!(fs::create_dir_all(image.join("share/man/man1")));
             let man_src = builder.src.join("src/doc/man");
@@ -436,7 +452,11 @@ fn prepare_image(builder: &Builder<'_>, compiler: Compiler, image: &Path) {
 
             // Misc license info
             let cp = |file: &str| {
-                builder.install(&builder.src.join(file), &image.join("share/doc/rust"), 0o644);
+                builder.install_linked(
+                    &builder.src.join(file),
+                    &image.join("share/doc/rust"),
+                    0o644,
+                );
             };
             cp("COPYRIGHT");
             cp("LICENSE-APACHE");
@@ -532,10 +552,43 @@ fn copy_target_libs(builder: &Builder<'_>, target: TargetSelection, image: &Path
             builder.copy(&path, &self_contained_dst.join(path.file_name().unwrap()));
         } else if dependency_type == DependencyType::Target || builder.config.build == target {
             builder.copy(&path, &dst.join(path.file_name().unwrap()));
+            if is_dylib(&path.file_name().unwrap().to_string_lossy()) {
+                copy_debuginfo(builder, &path, target, stamp.parent().unwrap(), &dst);
+            }
+        }
+    }
+    strip_dir(builder, &dst, target);
+}
+
+/// Strips the executables and dylibs for `target` directly in `dir` if
+/// `rust.strip` is set.
+fn strip_dir(builder: &Builder<'_>, dir: &Path, target: TargetSelection) {
+    if !builder.config.rust_strip {
+        return;
+    }
+    for entry in builder.read_dir(dir) {
+        let path = entry.path();
+        // Symlinks point at a binary that is stripped itself.
+        if t!(entry.file_type()).is_file() {
+            strip_artifact(builder, &path, ArtifactKind::classify(&path, target), target);
         }
     }
 }
 
+/// Copies the split debug info of `artifact` that exists into `dst_dir`, so
+/// that the binaries shipped there can be symbolicated. `out` is the cargo
+/// output directory the artifact was built in.
+fn copy_debuginfo(
+    builder: &Builder<'_>,
+    artifact: &Path,
+    target: TargetSelection,
+    out: &Path,
+    dst_dir: &Path,
+) {
+    let build_dirs = [out.join("deps"), out.to_path_buf()];
+    t!(util::copy_debuginfo(&builder.config, artifact, target, &build_dirs, dst_dir));
+}
+
 #[derive(Debug, PartialOrd, Ord, Copy, Clone, Hash, PartialEq, Eq)]
 pub struct Std {
     pub compiler: Compiler,
@@ -799,6 +852,22 @@ fn make_run(run: RunConfig<'_>) {
 
     /// Creates the `rust-src` installer component
     fn run(self, builder: &Builder<'_>) -> GeneratedTarball {
+        // This is the reduced set of paths which will become the rust-src component
+        // (essentially libstd and all of its path dependencies).
+        let src_dirs = ["library", "src/llvm-project/libunwind"];
+        let src_files = ["Cargo.lock"];
+
+        let newest_input = src_dirs
+            .iter()
+            .chain(src_files.iter())
+            .map(|path| t!(newest_mtime(&builder.src.join(path))))
+            .max()
+            .unwrap();
+        if let Some(tarball) = Tarball::reuse_targetless(builder, "rust-src", newest_input) {
+            builder.info("Dist rust-src is up to date");
+            return tarball;
+        }
+
         let tarball = Tarball::new_targetless(builder, "rust-src");
 
         // A lot of tools expect the rust-src component to be entirely in this directory, so if you
@@ -810,13 +879,10 @@ fn run(self, builder: &Builder<'_>) -> GeneratedTarball {
         // translation code in `imported_source_files` in `src/librustc_metadata/rmeta/decoder.rs`
         let dst_src = tarball.image_dir().join("lib/rustlib/src/rust");
 
-        let src_files = ["Cargo.lock"];
-        // This is the reduced set of paths which will become the rust-src component
-        // (essentially libstd and all of its path dependencies).
         copy_src_dirs(
             builder,
             &builder.src,
-            &["library", "src/llvm-project/libunwind"],
+            &src_dirs,
             &[
                 // not needed and contains symlinks which rustup currently
                 // chokes on when unpacking.
@@ -1437,7 +1503,7 @@ fn filter(contents: &str, marker: &str) -> String {
         if target.contains("apple-darwin") {
             builder.info("building pkg installer");
             let pkg = tmp.join("pkg");
-            let _ = fs::remove_dir_all(&pkg);
+            t!(remove_dir_all_retrying(&pkg));
 
             let pkgbuild = |component: &str| {
                 let mut cmd = Command::new("pkgbuild");
@@ -1488,13 +1554,13 @@ fn filter(contents: &str, marker: &str) -> String {
                 )))
                 .arg("--package-path")
                 .arg(&pkg);
-            let _time = timeit(builder);
+            let _time = timeit_labeled(builder, "pkg installer");
             builder.run(&mut cmd);
         }
 
         if target.contains("windows") {
             let exe = tmp.join("exe");
-            let _ = fs::remove_dir_all(&exe);
+            t!(remove_dir_all_retrying(&exe));
 
             let prepare = |name: &str| {
                 builder.create_dir(&exe.join(name));
@@ -1843,7 +1909,7 @@ fn filter(contents: &str, marker: &str) -> String {
             // ICE57 wrongly complains about the shortcuts
             cmd.arg("-sice:ICE57");
 
-            let _time = timeit(builder);
+            let _time = timeit_labeled(builder, "msi installer");
             builder.run(&mut cmd);
 
             if !builder.config.dry_run {
@@ -17,7 +17,7 @@
 use crate::compile;
 use crate::config::{Config, TargetSelection};
 use crate::tool::{self, prepare_tool_cargo, SourceType, Tool};
-use crate::util::{symlink_dir, t, up_to_date};
+use crate::util::{self, symlink_dir_relative, t, up_to_date, up_to_date_checked, FollowLinks};
 use crate::Mode;
 
 macro_rules! submodule_helper {
@@ -161,13 +161,18 @@ fn run(self, builder: &Builder<'_>) {
         let index = out.join("index.html");
         let rustbook = builder.tool_exe(Tool::Rustbook);
         let mut rustbook_cmd = builder.tool_cmd(Tool::Rustbook);
-        if builder.config.dry_run || up_to_date(&src, &index) && up_to_date(&rustbook, &index) {
+        if builder.config.dry_run
+            || up_to_date_checked(builder, &src, &index, FollowLinks::Yes)
+                .unwrap_or_else(|e| util::fail(&e.to_string()))
+                && up_to_date(builder, &rustbook, &index, FollowLinks::Yes)
+        {
             return;
         }
         builder.info(&format!("Rustbook ({}) - {}", target, name));
         let _ = fs::remove_dir_all(&out);
 
-        builder.run(rustbook_cmd.arg("build").arg(&src).arg("-d").arg(out));
+        builder.run(rustbook_cmd.arg("build").arg(&src).arg("-d").arg(&out));
+        builder.invalidate(&out);
     }
 }
 
@@ -331,12 +336,15 @@ fn run(self, builder: &Builder<'_>) {
         let version_input = builder.src.join("src/doc/version_info.html.template");
         let version_info = out.join("version_info.html");
 
-        if !builder.config.dry_run && !up_to_date(&version_input, &version_info) {
+        if !builder.config.dry_run
+            && !up_to_date(builder, &version_input, &version_info, FollowLinks::Yes)
+        {
             let info = t!(fs::read_to_string(&version_input))
                 .replace("VERSION", &builder.rust_release())
                 .replace("SHORT_HASH", builder.rust_info.sha_short().unwrap_or(""))
                 .replace("STAMP", builder.rust_info.sha().unwrap_or(""));
-            t!(fs::write(&version_info, &info));
+            t!(util::write_if_changed(&version_info, &info));
+            builder.invalidate(&version_info);
         }
 
         for file in t!(fs::read_dir(builder.src.join("src/doc"))) {
@@ -349,12 +357,14 @@ fn run(self, builder: &Builder<'_>) {
 
             let html = out.join(filename).with_extension("html");
             let rustdoc = builder.rustdoc(compiler);
-            if up_to_date(&path, &html)
-                && up_to_date(&footer, &html)
-                && up_to_date(&favicon, &html)
-                && up_to_date(&full_toc, &html)
-                && (builder.config.dry_run || up_to_date(&version_info, &html))
-                && (builder.config.dry_run || up_to_date(&rustdoc, &html))
+            if up_to_date(builder, &path, &html, FollowLinks::Yes)
+                && up_to_date(builder, &footer, &html, FollowLinks::Yes)
+                && up_to_date(builder, &favicon, &html, FollowLinks::Yes)
+                && up_to_date(builder, &full_toc, &html, FollowLinks::Yes)
+                && (builder.config.dry_run
+                    || up_to_date(builder, &version_info, &html, FollowLinks::Yes))
+                && (builder.config.dry_run
+                    || up_to_date(builder, &rustdoc, &html, FollowLinks::Yes))
             {
                 continue;
             }
@@ -394,6 +404,7 @@ fn run(self, builder: &Builder<'_>) {
                     .arg("rust.css");
             }
             builder.run(&mut cmd);
+            builder.invalidate(&html);
         }
 
         // We open doc/index.html as the default if invoked as `x.py doc --open`
@@ -824,20 +835,7 @@ fn run(self, builder: &Builder<'_>) {
 }
 
 fn symlink_dir_force(config: &Config, src: &Path, dst: &Path) -> io::Result<()> {
-    if config.dry_run {
-        return Ok(());
-    }
-    if let Ok(m) = fs::symlink_metadata(dst) {
-        if m.file_type().is_dir() {
-            fs::remove_dir_all(dst)?;
-        } else {
-            // handle directory junctions on windows by falling back to
-            // `remove_dir`.
-            fs::remove_file(dst).or_else(|_| fs::remove_dir(dst))?;
-        }
-    }
-
-    symlink_dir(config, src, dst)
+    symlink_dir_relative(config, src, dst, true)
 }
 
 #[derive(Ord, PartialOrd, Debug, Copy, Clone, Hash, PartialEq, Eq)]
@@ -874,7 +872,11 @@ fn run(self, builder: &Builder<'_>) {
         let out_base = builder.md_doc_out(self.target).join("rustc");
         t!(fs::create_dir_all(&out_base));
         let out_listing = out_base.join("src/lints");
-        builder.cp_r(&builder.src.join("src/doc/rustc"), &out_base);
+        // The lint docs tool rewrites files in `src/lints`, so those are copied
+        // rather than hard linked.
+        builder.cp_link_r(&builder.src.join("src/doc/rustc"), &out_base, &|path| {
+            path.starts_with("src/lints")
+        });
         builder.info(&format!("Generating lint docs ({})", self.target));
 
         let rustc = builder.rustc(self.compiler);
@@ -898,7 +900,7 @@ fn run(self, builder: &Builder<'_>) {
         // If the lib directories are in an unusual location (changed in
         // config.toml), then this needs to explicitly update the dylib search
         // path.
-        builder.add_rustc_lib_path(self.compiler, &mut cmd);
+        util::prepare_tool_cmd(builder, self.compiler, &mut cmd);
         builder.run(&mut cmd);
         // Run rustbook/mdbook to generate the HTML pages.
         builder.ensure(RustbookSrc {
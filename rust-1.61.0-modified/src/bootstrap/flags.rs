@@ -58,6 +58,8 @@ pub struct Flags {
     pub rustc_error_format: Option<String>,
     pub json_output: bool,
     pub dry_run: bool,
+    pub timestamps: bool,
+    pub trace_file: Option<PathBuf>,
     pub color: Color,
 
     // This overrides the deny-warnings configuration option,
@@ -134,6 +136,9 @@ pub enum Subcommand {
     Setup {
         profile: Profile,
     },
+    ShowPaths {
+        json: bool,
+    },
 }
 
 impl Default for Subcommand {
@@ -162,6 +167,7 @@ pub fn parse(args: &[String]) -> Flags {
     install     Install distribution artifacts
     run, r      Run tools contained in this repository
     setup       Create a config.toml (making it easier to use `x.py` itself)
+    show-paths  Print the library search paths bootstrap sets for the programs it runs
 
 To learn more about a subcommand, run `./x.py <subcommand> -h`",
         );
@@ -182,6 +188,17 @@ pub fn parse(args: &[String]) -> Flags {
         );
         opts.optopt("", "on-fail", "command to run on failure", "CMD");
         opts.optflag("", "dry-run", "dry run; don't build anything");
+        opts.optflag(
+            "",
+            "timestamps",
+            "prefix log lines with the time since the build started (always on in CI)",
+        );
+        opts.optopt(
+            "",
+            "trace-file",
+            "write the timed scopes and commands of the build to PATH as a Chrome trace",
+            "PATH",
+        );
         opts.optopt(
             "",
             "stage",
@@ -262,6 +279,7 @@ pub fn parse(args: &[String]) -> Flags {
                 || (s == "run")
                 || (s == "r")
                 || (s == "setup")
+                || (s == "show-paths")
         });
         let subcommand = match subcommand {
             Some(s) => s,
@@ -334,6 +352,9 @@ pub fn parse(args: &[String]) -> Flags {
             "fmt" => {
                 opts.optflag("", "check", "check formatting instead of applying.");
             }
+            "show-paths" => {
+                opts.optflag("", "json", "print the paths as JSON");
+            }
             _ => {}
         };
 
@@ -348,7 +369,7 @@ pub fn parse(args: &[String]) -> Flags {
 
                 let maybe_rules_help = Builder::get_help(&build, subcommand.as_str());
                 extra_help.push_str(maybe_rules_help.unwrap_or_default().as_str());
-            } else if !(subcommand.as_str() == "clean" || subcommand.as_str() == "fmt") {
+            } else if !matches!(subcommand.as_str(), "clean" | "fmt" | "show-paths") {
                 extra_help.push_str(
                     format!("Run `./x.py {} -h -v` to see a list of available paths.", subcommand)
                         .as_str(),
@@ -535,6 +556,19 @@ pub fn parse(args: &[String]) -> Flags {
                     Profile::all_for_help("        ").trim_end()
                 ));
             }
+            "show-paths" => {
+                subcommand_help.push_str(
+                    "\n
+Arguments:
+    This subcommand prints, for the stage and each target being built, the
+    library search path variables bootstrap sets when it runs the compiler,
+    tests and the linker: the variable name, each directory in order, whether
+    it exists, and which step added it. For example:
+
+        ./x.py show-paths --stage 1
+        ./x.py show-paths --target wasm32-unknown-unknown --json",
+                );
+            }
             _ => {}
         };
         // Get any optional paths which occur after the subcommand
@@ -618,6 +652,13 @@ pub fn parse(args: &[String]) -> Flags {
                 };
                 Subcommand::Setup { profile }
             }
+            "show-paths" => {
+                if !paths.is_empty() {
+                    println!("\nshow-paths does not take a path argument\n");
+                    usage(1, &opts, verbose, &subcommand_help);
+                }
+                Subcommand::ShowPaths { json: matches.opt_present("json") }
+            }
             _ => {
                 usage(1, &opts, verbose, &subcommand_help);
             }
@@ -636,6 +677,8 @@ pub fn parse(args: &[String]) -> Flags {
             verbose: matches.opt_count("verbose"),
             stage: matches.opt_str("stage").map(|j| j.parse().expect("`stage` should be a number")),
             dry_run: matches.opt_present("dry-run"),
+            timestamps: matches.opt_present("timestamps"),
+            trace_file: matches.opt_str("trace-file").map(PathBuf::from),
             on_fail: matches.opt_str("on-fail"),
             rustc_error_format: matches.opt_str("error-format"),
             json_output: matches.opt_present("json-output"),
@@ -1,6 +1,6 @@
 //! Runs rustfmt on the repository.
 
-use crate::util::{output, t};
+use crate::util::{exit, output, t};
 use crate::Build;
 use ignore::WalkBuilder;
 use std::collections::VecDeque;
@@ -32,7 +32,7 @@ fn rustfmt(src: &Path, rustfmt: &Path, paths: &[PathBuf], check: bool) -> impl F
                         code, run `./x.py fmt` instead.",
                 cmd_debug,
             );
-            std::process::exit(1);
+            exit(1);
         }
     }
 }
@@ -118,7 +118,7 @@ pub fn format(build: &Build, check: bool, paths: &[PathBuf]) {
         .as_ref()
         .unwrap_or_else(|| {
             eprintln!("./x.py fmt is not supported on this channel");
-            std::process::exit(1);
+            exit(1);
         })
         .to_path_buf();
     let src = build.src.clone();
@@ -106,10 +106,11 @@
 use std::cell::{Cell, RefCell};
 use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs::{self, File};
+use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::{self, Command};
+use std::process::Command;
 use std::str;
+use std::time::Instant;
 
 #[cfg(unix)]
 use std::os::unix::fs::symlink as symlink_file;
@@ -122,7 +123,8 @@
 use crate::builder::Kind;
 use crate::config::{LlvmLibunwind, TargetSelection};
 use crate::util::{
-    exe, libdir, mtime, output, run, run_suppressed, t, try_run, try_run_suppressed, CiEnv,
+    exe, hardlink_file, libdir, mtime, output, run, run_suppressed, t, try_run, try_run_suppressed,
+    CiEnv, CopyOpts, FollowLinks, Level, LinkKind,
 };
 
 mod builder;
@@ -139,14 +141,17 @@
 mod format;
 mod install;
 mod metadata;
+mod metrics;
 mod native;
 mod run;
 mod sanity;
 mod setup;
+mod show_paths;
 mod tarball;
 mod test;
 mod tool;
 mod toolstate;
+mod trace;
 pub mod util;
 
 #[cfg(windows)]
@@ -188,6 +193,14 @@ pub unsafe fn setup(_build: &mut crate::Build) {}
 
 pub const VERSION: usize = 2;
 
+/// How many steps the report printed with `build.print-step-timings` or
+/// `--verbose` lists.
+const SLOWEST_STEPS: usize = 20;
+
+/// Where `build.print-step-timings` keeps the step durations, in the build
+/// directory, for the next build to compare with.
+const LAST_TIMINGS: &str = ".last-timings.json";
+
 /// Extra --check-cfg to add when building
 /// (Mode restriction, config name, config values (if any))
 const EXTRA_CHECK_CFGS: &[(Option<Mode>, &'static str, Option<&[&'static str]>)] = &[
@@ -400,6 +413,8 @@ pub fn new(config: Config) -> Build {
             None => false,
         };
 
+        util::enable_timestamps(config.timestamps || CiEnv::current() != CiEnv::None);
+
         let ignore_git = config.ignore_git;
         let rust_info = channel::GitInfo::new(ignore_git, &src);
         let cargo_info = channel::GitInfo::new(ignore_git, &src.join("src/tools/cargo"));
@@ -413,6 +428,10 @@ pub fn new(config: Config) -> Build {
         // we always try to use git for LLVM builds
         let in_tree_llvm_info = channel::GitInfo::new(false, &src.join("src/llvm-project"));
 
+        if !config.dry_run {
+            util::set_target_spec_rustc(&config.initial_rustc);
+        }
+
         let initial_target_libdir_str = if config.dry_run {
             "/dummy/lib/path/to/lib/".to_string()
         } else {
@@ -639,6 +658,14 @@ pub fn maybe_update_submodules(&self) {
 
     /// Executes the entire build, as configured by the flags and configuration.
     pub fn build(&mut self) {
+        let start = Instant::now();
+        let _trace = self.config.trace_file.clone().map(trace::start);
+        let last_timings = self.out.join(LAST_TIMINGS);
+        let previous_timings = if self.config.print_step_timings {
+            metrics::load_history(&last_timings)
+        } else {
+            None
+        };
         unsafe {
             job::setup(self);
         }
@@ -657,6 +684,11 @@ pub fn build(&mut self) {
             return setup::setup(&self.config, *profile);
         }
 
+        if let Subcommand::ShowPaths { json } = self.config.cmd {
+            let builder = builder::Builder::new(self);
+            return show_paths::show_paths(&builder, json);
+        }
+
         {
             let builder = builder::Builder::new(&self);
             if let Some(path) = builder.paths.get(0) {
@@ -674,7 +706,24 @@ pub fn build(&mut self) {
             }
             self.config.dry_run = false;
             let builder = builder::Builder::new(&self);
+            if let Some(previous) = previous_timings {
+                builder.metrics.compare_with(
+                    previous,
+                    self.config.step_timings_regression_factor,
+                    self.config.step_timings_regression_floor,
+                );
+            }
             builder.execute_cli();
+            let total = start.elapsed();
+            if let Some(report) = builder.metrics.step_report(SLOWEST_STEPS, total) {
+                println!("\n{}", report);
+            }
+            if let Some(summary) = builder.metrics.step_summary(SLOWEST_STEPS, total) {
+                builder.ci_env.step_summary(&summary);
+            }
+            if self.config.print_step_timings {
+                builder.metrics.save_history(&last_timings);
+            }
         } else {
             let builder = builder::Builder::new(&self);
             builder.execute_cli();
@@ -687,7 +736,7 @@ pub fn build(&mut self) {
             for failure in failures.iter() {
                 println!("  - {}\n", failure);
             }
-            process::exit(1);
+            util::exit(1);
         }
     }
 
@@ -705,7 +754,7 @@ fn clear_if_dirty(&self, dir: &Path, input: &Path) -> bool {
             return cleared;
         }
         t!(fs::create_dir_all(dir));
-        t!(File::create(stamp));
+        t!(util::write_file_atomic(&stamp, ""));
         cleared
     }
 
@@ -979,7 +1028,16 @@ fn info(&self, msg: &str) {
         if self.config.dry_run {
             return;
         }
-        println!("{}", msg);
+        util::log_line(msg);
+    }
+
+    /// Like `info`, but annotated as a warning so that CI shows it in the
+    /// summary of the run.
+    fn warn(&self, msg: &str) {
+        if self.config.dry_run {
+            return;
+        }
+        self.ci_env.annotate(Level::Warning, msg);
     }
 
     /// Returns the number of parallel jobs that have been configured for this
@@ -1427,33 +1485,33 @@ pub fn cp_r(&self, src: &Path, dst: &Path) {
         }
     }
 
-    /// Copies the `src` directory recursively to `dst`. Both are assumed to exist
-    /// when this function is called. Unwanted files or directories can be skipped
-    /// by returning `false` from the filter function.
+    /// Copies the `src` directory recursively to `dst`, hard linking the
+    /// files where possible like `Build::copy`. Unwanted files or directories
+    /// can be skipped by returning `false` from the filter function, which
+    /// gets their path relative to `src`; they are removed from `dst` if an
+    /// earlier copy left them there, as is anything no longer in `src`.
+    /// Symlinks are copied as links.
     pub fn cp_filtered(&self, src: &Path, dst: &Path, filter: &dyn Fn(&Path) -> bool) {
-        // Immediately recurse with an empty relative path
-        self.recurse_(src, dst, Path::new(""), filter)
+        let opts = CopyOpts {
+            links: FollowLinks::No,
+            filter: Some(filter),
+            hard_link: Some(&|_| false),
+            prune: true,
+            ..Default::default()
+        };
+        let copied = t!(util::copy_recursive(&self.config, src, dst, &opts));
+        self.verbose(&format!("Copied {} files from {:?} to {:?}", copied, src, dst));
     }
 
-    // Inner function does the actual work
-    fn recurse_(&self, src: &Path, dst: &Path, relative: &Path, filter: &dyn Fn(&Path) -> bool) {
-        for f in self.read_dir(src) {
-            let path = f.path();
-            let name = path.file_name().unwrap();
-            let dst = dst.join(name);
-            let relative = relative.join(name);
-            // Only copy file or directory if the filter function returns true
-            if filter(&relative) {
-                if t!(f.file_type()).is_dir() {
-                    let _ = fs::remove_dir_all(&dst);
-                    self.create_dir(&dst);
-                    self.recurse_(&path, &dst, &relative, filter);
-                } else {
-                    let _ = fs::remove_file(&dst);
-                    self.copy(&path, &dst);
-                }
-            }
-        }
+    /// Like `cp_filtered` without a filter, but hard links the files where
+    /// possible instead of copying them. Files for which `modified`, given
+    /// their path relative to `src`, returns `true` are always copied, as
+    /// writing to them would also change the originals.
+    pub fn cp_link_r(&self, src: &Path, dst: &Path, modified: &dyn Fn(&Path) -> bool) {
+        let opts =
+            CopyOpts { links: FollowLinks::No, hard_link: Some(modified), ..Default::default() };
+        let linked = t!(util::copy_recursive(&self.config, src, dst, &opts));
+        self.verbose(&format!("Linked {} files from {:?} to {:?}", linked, src, dst));
     }
 
     fn copy_to_folder(&self, src: &Path, dest_folder: &Path) {
@@ -1486,11 +1544,31 @@ fn install(&self, src: &Path, dstdir: &Path, perms: u32) {
         chmod(&dst, perms);
     }
 
+    /// Like `install`, but hard links `src` into `dstdir` where the filesystem
+    /// allows rather than copying it. A hard link shares the permissions of
+    /// `src`, so `src` is only linked if it already has the permissions
+    /// `perms`.
+    fn install_linked(&self, src: &Path, dstdir: &Path, perms: u32) {
+        if self.config.dry_run {
+            return;
+        }
+        if !has_perms(src, perms) {
+            return self.install(src, dstdir, perms);
+        }
+        let dst = dstdir.join(src.file_name().unwrap());
+        self.verbose_than(1, &format!("Link {:?} to {:?}", src, dst));
+        t!(fs::create_dir_all(dstdir));
+        // `hardlink_file` copies without the timestamps `install` keeps.
+        if t!(hardlink_file(&self.config, src, &dst)) == LinkKind::Copy {
+            self.install(src, dstdir, perms);
+        }
+    }
+
     fn create(&self, path: &Path, s: &str) {
         if self.config.dry_run {
             return;
         }
-        t!(fs::write(path, s));
+        t!(util::write_if_changed(path, s));
     }
 
     fn read(&self, path: &Path) -> String {
@@ -1552,7 +1630,7 @@ fn ninja(&self) -> bool {
 to download LLVM rather than building it.
 "
                 );
-                std::process::exit(1);
+                util::exit(1);
             }
         }
 
@@ -1581,6 +1659,17 @@ fn chmod(path: &Path, perms: u32) {
 #[cfg(windows)]
 fn chmod(_path: &Path, _perms: u32) {}
 
+/// Whether `path` has exactly the permissions `chmod` would give it.
+#[cfg(unix)]
+fn has_perms(path: &Path, perms: u32) -> bool {
+    use std::os::unix::fs::*;
+    t!(fs::metadata(path)).permissions().mode() & 0o7777 == perms
+}
+#[cfg(windows)]
+fn has_perms(_path: &Path, _perms: u32) -> bool {
+    true
+}
+
 impl Compiler {
     pub fn with_stage(mut self, stage: u32) -> Compiler {
         self.stage = stage;
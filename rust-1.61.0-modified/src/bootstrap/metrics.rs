@@ -0,0 +1,409 @@
+//! Machine-readable metrics about the steps executed by rustbuild.
+//!
+//! When `build.metrics = true` is set in `config.toml`, every step run by
+//! `Builder::ensure` is recorded with its duration, outcome and target, and
+//! the result is written to `build/metrics.json` when the build finishes.
+//! Builds that fail, whether by panicking or through `util::exit`, still write
+//! the steps recorded so far, with the steps that were running marked as
+//! unsuccessful.
+//!
+//! The same records back the table of the slowest steps printed at the end of
+//! the build with `--verbose` or `build.print-step-timings = true`. With the
+//! latter, the durations are also kept in `build/.last-timings.json`, and the
+//! table compares them with the ones kept by the previous build.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::util::ResourceUsage;
+
+/// Bumped whenever the layout of `metrics.json` changes incompatibly.
+pub const FORMAT_VERSION: usize = 1;
+
+/// Bumped whenever the layout of `.last-timings.json` changes incompatibly.
+/// Files of other versions are ignored.
+pub const HISTORY_FORMAT_VERSION: usize = 1;
+
+/// The metrics of the most recently created `Builder`, flushed by `util::exit`.
+static ACTIVE: Lazy<Mutex<Option<Arc<Mutex<MetricsState>>>>> = Lazy::new(|| Mutex::new(None));
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct JsonRoot {
+    pub format_version: usize,
+    pub steps: Vec<JsonStep>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JsonStep {
+    /// The `Debug` representation of the step.
+    pub name: String,
+    pub target: Option<String>,
+    /// Time spent in the step itself, excluding the steps it depends on.
+    /// Always zero in dry runs.
+    pub duration_sec: f64,
+    /// CPU time of the processes the step ran, excluding the steps it
+    /// depends on. `None` in dry runs and where it isn't known.
+    pub cpu_sec: Option<f64>,
+    /// The peak RSS of the processes the step or the steps it depends on ran,
+    /// if higher than any earlier step's. See `ResourceUsage::max_rss_kb`.
+    pub max_rss_kb: Option<u64>,
+    pub success: bool,
+}
+
+/// The layout of `.last-timings.json`.
+#[derive(Serialize, Deserialize, Debug)]
+struct JsonHistory {
+    format_version: usize,
+    steps: Vec<JsonStepTiming>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct JsonStepTiming {
+    name: String,
+    duration_sec: f64,
+}
+
+/// Reads the step durations kept by `BuildMetrics::save_history`, keyed by
+/// step. Returns `None`, without complaining, if there are none or they
+/// can't be read: they are only a point of comparison.
+pub fn load_history(path: &Path) -> Option<HashMap<String, f64>> {
+    let history: JsonHistory = serde_json::from_str(&fs::read_to_string(path).ok()?).ok()?;
+    if history.format_version != HISTORY_FORMAT_VERSION {
+        return None;
+    }
+    Some(history.steps.into_iter().map(|step| (step.name, step.duration_sec)).collect())
+}
+
+/// The durations of the previous build, and how much longer a step has to
+/// take than it did then to have regressed: more than `factor` times as long,
+/// and more than `floor` longer. Like in the history, the durations of the
+/// runs of a step that ran more than once are added up before comparing.
+struct Comparison {
+    previous: HashMap<String, f64>,
+    factor: f64,
+    floor: Duration,
+}
+
+impl Comparison {
+    /// Returns how many times as long as in the previous build the step
+    /// `name` took, in all, if it regressed. A step that took no measurable
+    /// time then has no ratio to regress by.
+    fn regressed(&self, name: &str, duration_sec: f64) -> Option<f64> {
+        let previous = *self.previous.get(name)?;
+        let longer = previous > 0.0
+            && duration_sec > previous * self.factor
+            && duration_sec - previous > self.floor.as_secs_f64();
+        longer.then(|| duration_sec / previous)
+    }
+}
+
+struct MetricsState {
+    /// Where to write `metrics.json`, if anywhere.
+    path: Option<PathBuf>,
+    dry_run: bool,
+    finished: Vec<JsonStep>,
+    running: Vec<(String, Option<String>, Instant)>,
+    comparison: Option<Comparison>,
+}
+
+impl MetricsState {
+    fn persist(&self) {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return,
+        };
+        let mut steps = self.finished.clone();
+        // Anything still running when the build stops has failed. Innermost
+        // steps are listed first, as they would have finished first.
+        for (name, target, start) in self.running.iter().rev() {
+            let duration = if self.dry_run { Duration::ZERO } else { start.elapsed() };
+            steps.push(JsonStep {
+                name: name.clone(),
+                target: target.clone(),
+                duration_sec: duration.as_secs_f64(),
+                cpu_sec: None,
+                max_rss_kb: None,
+                success: false,
+            });
+        }
+        let root = JsonRoot { format_version: FORMAT_VERSION, steps };
+        let json = serde_json::to_string_pretty(&root).expect("failed to serialize metrics");
+        // This may run while exiting after an error, so don't panic here.
+        if let Err(e) = fs::write(path, json) {
+            eprintln!("failed to write build metrics to {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Collects the metrics of one `Builder`. Does nothing unless metrics or the
+/// step report were enabled in the configuration.
+pub struct BuildMetrics {
+    state: Option<Arc<Mutex<MetricsState>>>,
+}
+
+impl BuildMetrics {
+    /// Creates a collector writing to `path` if there is one, and keeping the
+    /// steps for `step_report` if `report`. It is disabled if neither.
+    pub fn new(path: Option<PathBuf>, dry_run: bool, report: bool) -> BuildMetrics {
+        let state = (path.is_some() || report).then(|| {
+            let state = MetricsState {
+                path,
+                dry_run,
+                finished: Vec::new(),
+                running: Vec::new(),
+                comparison: None,
+            };
+            let state = Arc::new(Mutex::new(state));
+            *ACTIVE.lock().unwrap_or_else(|e| e.into_inner()) = Some(state.clone());
+            state
+        });
+        BuildMetrics { state }
+    }
+
+    fn with_state(&self, f: impl FnOnce(&mut MetricsState)) {
+        if let Some(state) = &self.state {
+            f(&mut state.lock().unwrap_or_else(|e| e.into_inner()));
+        }
+    }
+
+    /// Records that a step has started running.
+    pub fn enter_step(&self, name: String, target: Option<String>) {
+        self.with_state(|state| state.running.push((name, target, Instant::now())));
+    }
+
+    /// Records that the innermost running step succeeded after spending
+    /// `duration` in its own work, and `usage` in the processes it ran.
+    pub fn exit_step(&self, duration: Duration, usage: Option<ResourceUsage>) {
+        self.with_state(|state| {
+            let (name, target, _) = state.running.pop().expect("no step is running");
+            let (duration, usage) =
+                if state.dry_run { (Duration::ZERO, None) } else { (duration, usage) };
+            state.finished.push(JsonStep {
+                name,
+                target,
+                duration_sec: duration.as_secs_f64(),
+                cpu_sec: usage.map(|usage| usage.cpu.as_secs_f64()),
+                max_rss_kb: usage.and_then(|usage| usage.max_rss_kb),
+                success: true,
+            });
+        });
+    }
+
+    /// Writes the metrics collected so far.
+    pub fn persist(&self) {
+        self.with_state(|state| state.persist());
+    }
+
+    /// Makes `step_report` compare the durations with `previous`, from
+    /// `load_history`, and point out the steps that took more than `factor`
+    /// times and more than `floor` longer than they did then.
+    pub fn compare_with(&self, previous: HashMap<String, f64>, factor: f64, floor: Duration) {
+        self.with_state(|state| state.comparison = Some(Comparison { previous, factor, floor }));
+    }
+
+    /// Keeps the durations of the steps that finished in `path`, for the next
+    /// build to `compare_with`. Nothing is kept for dry runs.
+    pub fn save_history(&self, path: &Path) {
+        self.with_state(|state| {
+            if state.dry_run {
+                return;
+            }
+            // The same step can run more than once, for example after a
+            // failed first attempt under `--keep-going`, so its times add up.
+            let mut durations: Vec<(&str, f64)> = Vec::new();
+            for step in &state.finished {
+                match durations.iter_mut().find(|(name, _)| *name == step.name) {
+                    Some((_, duration)) => *duration += step.duration_sec,
+                    None => durations.push((&step.name, step.duration_sec)),
+                }
+            }
+            let history = JsonHistory {
+                format_version: HISTORY_FORMAT_VERSION,
+                steps: durations
+                    .into_iter()
+                    .map(|(name, duration_sec)| JsonStepTiming { name: name.into(), duration_sec })
+                    .collect(),
+            };
+            let json = serde_json::to_string(&history).expect("failed to serialize step timings");
+            if let Err(e) = fs::write(path, json) {
+                eprintln!("failed to write step timings to {}: {}", path.display(), e);
+            }
+        });
+    }
+
+    /// Returns a table of the `len` steps that took the longest, slowest
+    /// first, with their share of `total`, the wall time of the whole build.
+    /// With `compare_with`, it also has the change since the previous build
+    /// and marks the steps that regressed. Returns `None` if the steps
+    /// weren't kept, none finished, or this is a dry run, whose durations
+    /// mean nothing.
+    pub fn step_report(&self, len: usize, total: Duration) -> Option<String> {
+        let mut report = None;
+        self.with_state(|state| {
+            if !state.dry_run && !state.finished.is_empty() {
+                report = Some(format_step_report(
+                    &state.finished,
+                    len,
+                    total,
+                    state.comparison.as_ref(),
+                ));
+            }
+        });
+        report
+    }
+
+    /// Like `step_report`, but as a Markdown table for the GitHub Actions job
+    /// summary.
+    pub fn step_summary(&self, len: usize, total: Duration) -> Option<String> {
+        self.summary(|steps| format_step_summary(steps, len, total))
+    }
+
+    fn summary(&self, format: impl FnOnce(&[JsonStep]) -> String) -> Option<String> {
+        let mut report = None;
+        self.with_state(|state| {
+            if !state.dry_run && !state.finished.is_empty() {
+                report = Some(format(&state.finished));
+            }
+        });
+        report
+    }
+}
+
+/// Returns `steps`, slowest first.
+fn by_duration(steps: &[JsonStep]) -> Vec<&JsonStep> {
+    let mut steps: Vec<_> = steps.iter().collect();
+    steps.sort_by(|a, b| b.duration_sec.partial_cmp(&a.duration_sec).unwrap_or(Ordering::Equal));
+    steps
+}
+
+/// The share of `total` seconds that `step` took, in percent.
+fn share(step: &JsonStep, total: f64) -> f64 {
+    if total > 0.0 { step.duration_sec / total * 100.0 } else { 0.0 }
+}
+
+fn format_step_report(
+    steps: &[JsonStep],
+    len: usize,
+    total: Duration,
+    comparison: Option<&Comparison>,
+) -> String {
+    let steps = by_duration(steps);
+    let total = total.as_secs_f64();
+    let mut out = format!(
+        "Slowest {} of {} steps, out of {:.2}s in total{}:\n",
+        len.min(steps.len()),
+        steps.len(),
+        total,
+        if comparison.is_some() { ", compared with the previous build" } else { "" }
+    );
+    let mut totals: HashMap<&str, f64> = HashMap::new();
+    for step in &steps {
+        *totals.entry(step.name.as_str()).or_default() += step.duration_sec;
+    }
+    let mut regressed = HashSet::new();
+    for step in steps.iter().take(len) {
+        let cpu = step.cpu_sec.map_or("-".to_string(), |cpu| format!("{:.2}s", cpu));
+        let rss = step.max_rss_kb.map_or("-".to_string(), |kb| format!("{}MB", kb / 1024));
+        let (delta, regression) = match comparison {
+            Some(comparison) => {
+                let total = totals[step.name.as_str()];
+                let delta = match comparison.previous.get(&step.name) {
+                    Some(previous) => format!("{:>+10.2}s  ", total - previous),
+                    None => format!("{:>11}  ", "new"),
+                };
+                let regression =
+                    comparison.regressed(&step.name, total).map_or(String::new(), |factor| {
+                        regressed.insert(step.name.as_str());
+                        format!("  <- {:.1}x slower", factor)
+                    });
+                (delta, regression)
+            }
+            None => (String::new(), String::new()),
+        };
+        out += &format!(
+            "{:>10.2}s {:>5.1}%  {}cpu {:>9}  rss {:>7}  {}{}\n",
+            step.duration_sec,
+            share(step, total),
+            delta,
+            cpu,
+            rss,
+            step.name,
+            regression
+        );
+    }
+    if let Some(comparison) = comparison.filter(|_| !regressed.is_empty()) {
+        out += &format!(
+            "{} of these took over {}x as long as in the previous build, and over {}s longer; \
+             a cache may have been invalidated.\n",
+            regressed.len(),
+            comparison.factor,
+            comparison.floor.as_secs_f64()
+        );
+    }
+    out
+}
+
+fn format_step_summary(steps: &[JsonStep], len: usize, total: Duration) -> String {
+    let steps = by_duration(steps);
+    let total = total.as_secs_f64();
+    let mut out = format!(
+        "### Slowest bootstrap steps\n\n\
+         The slowest {} of {} steps, out of {:.2}s in total.\n\n\
+         | step | duration | share | CPU |\n\
+         |---|---:|---:|---:|\n",
+        len.min(steps.len()),
+        steps.len(),
+        total
+    );
+    for step in steps.iter().take(len) {
+        let cpu = step.cpu_sec.map_or("-".to_string(), |cpu| format!("{:.2}s", cpu));
+        out += &format!(
+            "| {} | {:.2}s | {:.1}% | {} |\n",
+            step.name.replace('|', "\\|"),
+            step.duration_sec,
+            share(step, total),
+            cpu
+        );
+    }
+    out
+}
+
+impl Drop for BuildMetrics {
+    fn drop(&mut self) {
+        // Builders that never ran a step, like the one `Build::build` creates
+        // to inspect the paths, would only overwrite real results.
+        if let Some(state) = &self.state {
+            let state = state.lock().unwrap_or_else(|e| e.into_inner());
+            if !state.finished.is_empty() || !state.running.is_empty() {
+                state.persist();
+            }
+        }
+    }
+}
+
+/// Writes the metrics of the current builder, if it collects any. Called
+/// before exiting the process, which skips the destructors that would
+/// otherwise do this.
+pub fn persist_active() {
+    if let Some(state) = &*ACTIVE.lock().unwrap_or_else(|e| e.into_inner()) {
+        state.lock().unwrap_or_else(|e| e.into_inner()).persist();
+    }
+}
+
+/// Extracts the target triple from the `Debug` representation of a step,
+/// which for most steps includes a `target: TargetSelection { .. }` field.
+pub fn step_target(step: &str) -> Option<String> {
+    const FIELD: &str = "target: TargetSelection { triple: \"";
+    let rest = &step[step.find(FIELD)? + FIELD.len()..];
+    Some(rest[..rest.find('"')?].to_string())
+}
+
+#[cfg(test)]
+mod tests;
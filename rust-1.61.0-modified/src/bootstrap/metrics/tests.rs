@@ -0,0 +1,241 @@
+use super::*;
+use std::env;
+use std::thread;
+
+/// Returns the path of a fresh `metrics.json` unique to the calling test.
+fn metrics_path() -> PathBuf {
+    let dir = env::temp_dir()
+        .join("rustbuild-metrics-tests")
+        .join(thread::current().name().unwrap_or("unknown").replace(":", "-"));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.join("metrics.json")
+}
+
+fn read(path: &PathBuf) -> JsonRoot {
+    serde_json::from_str(&fs::read_to_string(path).unwrap()).unwrap()
+}
+
+#[test]
+fn records_finished_steps() {
+    let path = metrics_path();
+    {
+        let metrics = BuildMetrics::new(Some(path.clone()), false, false);
+        metrics.enter_step("Assemble".into(), None);
+        metrics.enter_step("Std".into(), Some("x86_64-unknown-linux-gnu".into()));
+        let usage = ResourceUsage { cpu: Duration::from_millis(6000), max_rss_kb: Some(2048) };
+        metrics.exit_step(Duration::from_millis(1500), Some(usage));
+        metrics.exit_step(Duration::from_millis(250), None);
+    }
+    let root = read(&path);
+    assert_eq!(root.format_version, FORMAT_VERSION);
+    assert_eq!(
+        root.steps,
+        [
+            JsonStep {
+                name: "Std".into(),
+                target: Some("x86_64-unknown-linux-gnu".into()),
+                duration_sec: 1.5,
+                cpu_sec: Some(6.0),
+                max_rss_kb: Some(2048),
+                success: true,
+            },
+            JsonStep {
+                name: "Assemble".into(),
+                target: None,
+                duration_sec: 0.25,
+                cpu_sec: None,
+                max_rss_kb: None,
+                success: true,
+            },
+        ]
+    );
+}
+
+#[test]
+fn dry_run_has_zero_durations() {
+    let path = metrics_path();
+    {
+        let metrics = BuildMetrics::new(Some(path.clone()), true, false);
+        metrics.enter_step("Std".into(), None);
+        let usage = ResourceUsage { cpu: Duration::from_secs(3), max_rss_kb: Some(2048) };
+        metrics.exit_step(Duration::from_secs(3), Some(usage));
+    }
+    let root = read(&path);
+    assert_eq!(root.steps.len(), 1);
+    assert_eq!(root.steps[0].duration_sec, 0.0);
+    assert_eq!((root.steps[0].cpu_sec, root.steps[0].max_rss_kb), (None, None));
+}
+
+#[test]
+fn failed_build_flushes_running_steps() {
+    let path = metrics_path();
+    let metrics = BuildMetrics::new(Some(path.clone()), false, false);
+    metrics.enter_step("Assemble".into(), None);
+    metrics.enter_step("Std".into(), None);
+    metrics.exit_step(Duration::from_secs(1), None);
+    metrics.enter_step("Rustc".into(), None);
+    // What `util::exit` does before exiting the process.
+    metrics.persist();
+    let steps: Vec<_> =
+        read(&path).steps.into_iter().map(|step| (step.name, step.success)).collect();
+    assert_eq!(
+        steps,
+        [("Std".to_string(), true), ("Rustc".to_string(), false), ("Assemble".to_string(), false)]
+    );
+}
+
+#[test]
+fn disabled_writes_nothing() {
+    let path = metrics_path();
+    {
+        let metrics = BuildMetrics::new(None, false, false);
+        metrics.enter_step("Std".into(), None);
+        metrics.exit_step(Duration::from_secs(1), None);
+    }
+    assert!(!path.exists());
+}
+
+#[test]
+fn step_report_sorted_by_duration() {
+    let metrics = BuildMetrics::new(None, false, true);
+    let usage =
+        |cpu_ms, max_rss_kb| Some(ResourceUsage { cpu: Duration::from_millis(cpu_ms), max_rss_kb });
+    for (name, ms, usage) in [
+        ("Std", 1500, usage(5800, None)),
+        ("Rustc", 6000, usage(47_250, Some(1_500_000))),
+        ("Rustdoc", 500, None),
+        ("Llvm", 2000, None),
+    ] {
+        metrics.enter_step(name.into(), None);
+        metrics.exit_step(Duration::from_millis(ms), usage);
+    }
+    assert_eq!(
+        metrics.step_report(3, Duration::from_secs(10)).unwrap(),
+        "Slowest 3 of 4 steps, out of 10.00s in total:\n\
+         \x20     6.00s  60.0%  cpu    47.25s  rss  1464MB  Rustc\n\
+         \x20     2.00s  20.0%  cpu         -  rss       -  Llvm\n\
+         \x20     1.50s  15.0%  cpu     5.80s  rss       -  Std\n"
+    );
+
+    // Neither dry runs nor collectors without the report have one.
+    let metrics = BuildMetrics::new(None, true, true);
+    metrics.enter_step("Std".into(), None);
+    metrics.exit_step(Duration::from_secs(1), None);
+    assert_eq!(metrics.step_report(3, Duration::from_secs(1)), None);
+    let metrics = BuildMetrics::new(None, false, false);
+    metrics.enter_step("Std".into(), None);
+    metrics.exit_step(Duration::from_secs(1), None);
+    assert_eq!(metrics.step_report(3, Duration::from_secs(1)), None);
+}
+
+#[test]
+fn step_report_compares_with_history() {
+    let path = metrics_path().with_file_name(".last-timings.json");
+    let previous = BuildMetrics::new(None, false, true);
+    for (name, secs) in [("Rustc", 100), ("Std", 10), ("Std", 10), ("Llvm", 600)] {
+        previous.enter_step(name.into(), None);
+        previous.exit_step(Duration::from_secs(secs), None);
+    }
+    previous.save_history(&path);
+    let history = load_history(&path).unwrap();
+    assert_eq!(history.len(), 3);
+    assert_eq!(history["Std"], 20.0);
+
+    let metrics = BuildMetrics::new(None, false, true);
+    for (name, secs) in [("Rustc", 200), ("Std", 45), ("Llvm", 620), ("Cargo", 50)] {
+        metrics.enter_step(name.into(), None);
+        metrics.exit_step(Duration::from_secs(secs), None);
+    }
+    metrics.compare_with(history, 1.5, Duration::from_secs(30));
+    // Std is over the factor but not the floor, and Llvm the other way round.
+    assert_eq!(
+        metrics.step_report(4, Duration::from_secs(1000)).unwrap(),
+        "Slowest 4 of 4 steps, out of 1000.00s in total, compared with the previous build:\n\
+         \x20   620.00s  62.0%      +20.00s  cpu         -  rss       -  Llvm\n\
+         \x20   200.00s  20.0%     +100.00s  cpu         -  rss       -  Rustc  <- 2.0x slower\n\
+         \x20    50.00s   5.0%          new  cpu         -  rss       -  Cargo\n\
+         \x20    45.00s   4.5%      +25.00s  cpu         -  rss       -  Std\n\
+         1 of these took over 1.5x as long as in the previous build, and over 30s longer; \
+         a cache may have been invalidated.\n"
+    );
+}
+
+#[test]
+fn step_report_compares_repeated_steps_in_all() {
+    let path = metrics_path().with_file_name(".last-timings-repeated.json");
+    let previous = BuildMetrics::new(None, false, true);
+    for (name, secs) in [("Std", 60), ("Rustc", 0)] {
+        previous.enter_step(name.into(), None);
+        previous.exit_step(Duration::from_secs(secs), None);
+    }
+    previous.save_history(&path);
+
+    // Std ran twice, and took as long in all as before: it didn't regress.
+    // Rustc took no time before, so it has no ratio to have regressed by.
+    let metrics = BuildMetrics::new(None, false, true);
+    for (name, secs) in [("Std", 30), ("Std", 30), ("Rustc", 100)] {
+        metrics.enter_step(name.into(), None);
+        metrics.exit_step(Duration::from_secs(secs), None);
+    }
+    metrics.compare_with(load_history(&path).unwrap(), 1.5, Duration::from_secs(10));
+    assert_eq!(
+        metrics.step_report(3, Duration::from_secs(200)).unwrap(),
+        "Slowest 3 of 3 steps, out of 200.00s in total, compared with the previous build:\n\
+         \x20   100.00s  50.0%     +100.00s  cpu         -  rss       -  Rustc\n\
+         \x20    30.00s  15.0%       +0.00s  cpu         -  rss       -  Std\n\
+         \x20    30.00s  15.0%       +0.00s  cpu         -  rss       -  Std\n"
+    );
+}
+
+#[test]
+fn unusable_history_is_ignored() {
+    let path = metrics_path().with_file_name(".last-timings.json");
+    assert!(load_history(&path).is_none());
+    fs::write(&path, "{\"format_version\": 1, \"steps\": [").unwrap();
+    assert!(load_history(&path).is_none());
+    let future = format!(r#"{{"format_version": {}, "steps": []}}"#, HISTORY_FORMAT_VERSION + 1);
+    fs::write(&path, future).unwrap();
+    assert!(load_history(&path).is_none());
+
+    // Dry runs keep nothing.
+    let _ = fs::remove_file(&path);
+    let metrics = BuildMetrics::new(None, true, true);
+    metrics.enter_step("Std".into(), None);
+    metrics.exit_step(Duration::from_secs(1), None);
+    metrics.save_history(&path);
+    assert!(!path.exists());
+}
+
+#[test]
+fn step_summary_markdown() {
+    let metrics = BuildMetrics::new(None, false, true);
+    let usage = ResourceUsage { cpu: Duration::from_millis(47_250), max_rss_kb: None };
+    for (name, ms, usage) in
+        [("Std", 1500, None), ("Rustc", 6000, Some(usage)), ("Tool { name: \"a|b\" }", 500, None)]
+    {
+        metrics.enter_step(name.into(), None);
+        metrics.exit_step(Duration::from_millis(ms), usage);
+    }
+    assert_eq!(
+        metrics.step_summary(2, Duration::from_secs(10)).unwrap(),
+        "### Slowest bootstrap steps\n\n\
+         The slowest 2 of 3 steps, out of 10.00s in total.\n\n\
+         | step | duration | share | CPU |\n\
+         |---|---:|---:|---:|\n\
+         | Rustc | 6.00s | 60.0% | 47.25s |\n\
+         | Std | 1.50s | 15.0% | - |\n"
+    );
+    assert!(metrics.step_summary(3, Duration::from_secs(10)).unwrap().contains("a\\|b"));
+}
+
+#[test]
+fn target_from_step_debug() {
+    assert_eq!(
+        step_target(
+            r#"Std { target: TargetSelection { triple: "x86_64-unknown-linux-gnu", file: None }, compiler: Compiler { stage: 0, host: TargetSelection { triple: "aarch64-unknown-linux-gnu", file: None } } }"#
+        ),
+        Some("x86_64-unknown-linux-gnu".to_string())
+    );
+    assert_eq!(step_target("Rustfmt"), None);
+}
@@ -11,18 +11,18 @@
 use std::env;
 use std::env::consts::EXE_EXTENSION;
 use std::ffi::{OsStr, OsString};
-use std::fs::{self, File};
-use std::io;
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
 use crate::builder::{Builder, RunConfig, ShouldRun, Step};
 use crate::config::TargetSelection;
-use crate::util::{self, exe, output, t, up_to_date};
+use crate::util::{self, dylib, exe, output, staticlib, t, up_to_date_checked, FollowLinks, Stamp};
 use crate::{CLang, GitRepo};
 
 pub struct Meta {
-    stamp: HashStamp,
+    stamp: Stamp,
+    stamp_content: String,
     build_llvm_config: PathBuf,
     out_dir: PathBuf,
     root: String,
@@ -82,8 +82,15 @@ pub fn prebuilt_llvm_config(
 
     let build_llvm_config = llvm_config_ret_dir.join(exe("llvm-config", builder.config.build));
 
-    let stamp = out_dir.join("llvm-finished-building");
-    let stamp = HashStamp::new(stamp, builder.in_tree_llvm_info.sha());
+    let stamp = Stamp::new(out_dir.join("llvm-finished-building"));
+    // Record the extra CMake flags alongside the submodule commit so that
+    // changing `llvm.build-config` also triggers a rebuild.
+    let mut build_config: Vec<_> = builder.config.llvm_build_config.iter().collect();
+    build_config.sort();
+    let mut stamp_content = builder.in_tree_llvm_info.sha().unwrap_or("").to_string();
+    for (key, val) in build_config {
+        stamp_content.push_str(&format!("\n{}={}", key, val));
+    }
 
     if builder.config.llvm_skip_rebuild && stamp.path.exists() {
         builder.info(
@@ -94,8 +101,8 @@ pub fn prebuilt_llvm_config(
         return Ok(build_llvm_config);
     }
 
-    if stamp.is_done() {
-        if stamp.hash.is_none() {
+    if stamp.is_recorded(&stamp_content) {
+        if builder.in_tree_llvm_info.sha().is_none() {
             builder.info(
                 "Could not determine the LLVM submodule commit hash. \
                      Assuming that an LLVM rebuild is not necessary.",
@@ -108,7 +115,7 @@ pub fn prebuilt_llvm_config(
         return Ok(build_llvm_config);
     }
 
-    Err(Meta { stamp, build_llvm_config, out_dir, root: root.into() })
+    Err(Meta { stamp, stamp_content, build_llvm_config, out_dir, root: root.into() })
 }
 
 #[derive(Debug, Copy, Clone, Hash, PartialEq, Eq)]
@@ -146,7 +153,7 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
             target.to_string()
         };
 
-        let Meta { stamp, build_llvm_config, out_dir, root } =
+        let Meta { stamp, stamp_content, build_llvm_config, out_dir, root } =
             match prebuilt_llvm_config(builder, target) {
                 Ok(p) => return p,
                 Err(m) => m,
@@ -160,8 +167,8 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
         }
 
         builder.info(&format!("Building LLVM for {}", target));
-        t!(stamp.remove());
-        let _time = util::timeit(&builder);
+        t!(stamp.remove(&builder.config));
+        let _time = util::timeit_labeled(&builder, &format!("LLVM for {}", target));
         t!(fs::create_dir_all(&out_dir));
 
         // https://llvm.org/docs/CMake.html
@@ -374,7 +381,7 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
 
         cfg.build();
 
-        t!(stamp.write());
+        t!(stamp.record(&builder.config, &stamp_content));
 
         build_llvm_config
     }
@@ -464,7 +471,7 @@ fn configure_cmake(
     // Handle msvc + ninja + ccache specially (this is what the bots use)
     if target.contains("msvc") && builder.ninja() && builder.config.ccache.is_some() {
         let mut wrap_cc = env::current_exe().expect("failed to get cwd");
-        wrap_cc.set_file_name("sccache-plus-cl.exe");
+        wrap_cc.set_file_name(exe("sccache-plus-cl", builder.config.build));
 
         cfg.define("CMAKE_C_COMPILER", sanitize_cc(&wrap_cc))
             .define("CMAKE_CXX_COMPILER", sanitize_cc(&wrap_cc));
@@ -628,7 +635,7 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
         }
 
         builder.info(&format!("Building LLD for {}", target));
-        let _time = util::timeit(&builder);
+        let _time = util::timeit_labeled(&builder, &format!("LLD for {}", target));
         t!(fs::create_dir_all(&out_dir));
 
         let mut cfg = cmake::Config::new(builder.src.join("src/llvm-project/lld"));
@@ -692,7 +699,7 @@ fn run(self, builder: &Builder<'_>) -> PathBuf {
 
         cfg.build();
 
-        t!(File::create(&done_stamp));
+        t!(util::write_file_atomic(&done_stamp, ""));
         out_dir
     }
 }
@@ -729,7 +736,9 @@ fn run(self, builder: &Builder<'_>) {
         };
         let dst = builder.test_helpers_out(target);
         let src = builder.src.join("src/test/auxiliary/rust_test_helpers.c");
-        if up_to_date(&src, &dst.join("librust_test_helpers.a")) {
+        // A missing source means rebuild, and cc will report it.
+        let helpers = dst.join(staticlib("rust_test_helpers", target));
+        if up_to_date_checked(builder, &src, &helpers, FollowLinks::Yes).unwrap_or(false) {
             return;
         }
 
@@ -759,6 +768,7 @@ fn run(self, builder: &Builder<'_>) {
             .debug(false)
             .file(builder.src.join("src/test/auxiliary/rust_test_helpers.c"))
             .compile("rust_test_helpers");
+        builder.invalidate(&dst);
     }
 }
 
@@ -796,11 +806,11 @@ fn run(self, builder: &Builder<'_>) -> Self::Output {
             return runtimes;
         }
 
-        let stamp = out_dir.join("sanitizers-finished-building");
-        let stamp = HashStamp::new(stamp, builder.in_tree_llvm_info.sha());
+        let stamp = Stamp::new(out_dir.join("sanitizers-finished-building"));
+        let llvm_sha = builder.in_tree_llvm_info.sha();
 
-        if stamp.is_done() {
-            if stamp.hash.is_none() {
+        if stamp.is_recorded(llvm_sha.unwrap_or("")) {
+            if llvm_sha.is_none() {
                 builder.info(&format!(
                     "Rebuild sanitizers by removing the file `{}`",
                     stamp.path.display()
@@ -810,8 +820,8 @@ fn run(self, builder: &Builder<'_>) -> Self::Output {
         }
 
         builder.info(&format!("Building sanitizers for {}", self.target));
-        t!(stamp.remove());
-        let _time = util::timeit(&builder);
+        t!(stamp.remove(&builder.config));
+        let _time = util::timeit_labeled(&builder, &format!("sanitizers for {}", self.target));
 
         let mut cfg = cmake::Config::new(&compiler_rt_dir);
         cfg.profile("Release");
@@ -839,7 +849,7 @@ fn run(self, builder: &Builder<'_>) -> Self::Output {
             cfg.build_target(&runtime.cmake_target);
             cfg.build();
         }
-        t!(stamp.write());
+        t!(stamp.record(&builder.config, llvm_sha.unwrap_or("")));
 
         runtimes
     }
@@ -867,8 +877,9 @@ fn supported_sanitizers(
             .map(move |c| SanitizerRuntime {
                 cmake_target: format!("clang_rt.{}_{}_dynamic", c, os),
                 path: out_dir
-                    .join(&format!("build/lib/darwin/libclang_rt.{}_{}_dynamic.dylib", c, os)),
-                name: format!("librustc-{}_rt.{}.dylib", channel, c),
+                    .join("build/lib/darwin")
+                    .join(dylib(&format!("clang_rt.{}_{}_dynamic", c, os), target)),
+                name: dylib(&format!("rustc-{}_rt.{}", channel, c), target),
             })
             .collect()
     };
@@ -878,8 +889,11 @@ fn supported_sanitizers(
             .iter()
             .map(move |c| SanitizerRuntime {
                 cmake_target: format!("clang_rt.{}-{}", c, arch),
-                path: out_dir.join(&format!("build/lib/{}/libclang_rt.{}-{}.a", os, c, arch)),
-                name: format!("librustc-{}_rt.{}.a", channel, c),
+                path: out_dir
+                    .join("build/lib")
+                    .join(os)
+                    .join(staticlib(&format!("clang_rt.{}-{}", c, arch), target)),
+                name: staticlib(&format!("rustc-{}_rt.{}", channel, c), target),
             })
             .collect()
     };
@@ -908,44 +922,6 @@ fn supported_sanitizers(
     }
 }
 
-struct HashStamp {
-    path: PathBuf,
-    hash: Option<Vec<u8>>,
-}
-
-impl HashStamp {
-    fn new(path: PathBuf, hash: Option<&str>) -> Self {
-        HashStamp { path, hash: hash.map(|s| s.as_bytes().to_owned()) }
-    }
-
-    fn is_done(&self) -> bool {
-        match fs::read(&self.path) {
-            Ok(h) => self.hash.as_deref().unwrap_or(b"") == h.as_slice(),
-            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
-            Err(e) => {
-                panic!("failed to read stamp file `{}`: {}", self.path.display(), e);
-            }
-        }
-    }
-
-    fn remove(&self) -> io::Result<()> {
-        match fs::remove_file(&self.path) {
-            Ok(()) => Ok(()),
-            Err(e) => {
-                if e.kind() == io::ErrorKind::NotFound {
-                    Ok(())
-                } else {
-                    Err(e)
-                }
-            }
-        }
-    }
-
-    fn write(&self) -> io::Result<()> {
-        fs::write(&self.path, self.hash.as_deref().unwrap_or(b""))
-    }
-}
-
 #[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub struct CrtBeginEnd {
     pub target: TargetSelection,
@@ -972,8 +948,12 @@ fn run(self, builder: &Builder<'_>) -> Self::Output {
 
         let crtbegin_src = builder.src.join("src/llvm-project/compiler-rt/lib/crt/crtbegin.c");
         let crtend_src = builder.src.join("src/llvm-project/compiler-rt/lib/crt/crtend.c");
-        if up_to_date(&crtbegin_src, &out_dir.join("crtbegin.o"))
-            && up_to_date(&crtend_src, &out_dir.join("crtendS.o"))
+        let fresh = |src: &Path, dst: &Path| {
+            up_to_date_checked(builder, src, dst, FollowLinks::Yes)
+                .unwrap_or_else(|e| util::fail(&e.to_string()))
+        };
+        if fresh(&crtbegin_src, &out_dir.join("crtbegin.o"))
+            && fresh(&crtend_src, &out_dir.join("crtendS.o"))
         {
             return out_dir;
         }
@@ -1008,6 +988,7 @@ fn run(self, builder: &Builder<'_>) -> Self::Output {
 
         t!(fs::copy(out_dir.join("crtbegin.o"), out_dir.join("crtbeginS.o")));
         t!(fs::copy(out_dir.join("crtend.o"), out_dir.join("crtendS.o")));
+        builder.invalidate(&out_dir);
         out_dir
     }
 }
@@ -1037,11 +1018,18 @@ fn run(self, builder: &Builder<'_>) -> Self::Output {
         let out_dir = builder.native_dir(self.target).join("libunwind");
         let root = builder.src.join("src/llvm-project/libunwind");
 
-        if up_to_date(&root, &out_dir.join("libunwind.a")) {
+        let libunwind = out_dir.join(staticlib("unwind", self.target));
+        if up_to_date_checked(builder, &root, &libunwind, FollowLinks::Yes)
+            .unwrap_or_else(|e| util::fail(&e.to_string()))
+        {
             return out_dir;
         }
 
-        builder.info(&format!("Building libunwind.a for {}", self.target.triple));
+        builder.info(&format!(
+            "Building {} for {}",
+            staticlib("unwind", self.target),
+            self.target.triple
+        ));
         t!(fs::create_dir_all(&out_dir));
 
         let mut cc_cfg = cc::Build::new();
@@ -1159,6 +1147,7 @@ fn run(self, builder: &Builder<'_>) -> Self::Output {
         assert_eq!(cpp_len, count, "Can't get object files from {:?}", &out_dir);
 
         cc_cfg.compile("unwind");
+        builder.invalidate(&out_dir);
         out_dir
     }
 }
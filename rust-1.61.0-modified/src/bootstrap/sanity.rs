@@ -16,8 +16,8 @@
 use std::process::Command;
 
 use crate::cache::INTERNER;
-use crate::config::Target;
-use crate::util::output;
+use crate::config::{Target, TargetSelection};
+use crate::util::{exe, output};
 use crate::Build;
 
 pub struct Finder {
@@ -33,17 +33,18 @@ pub fn new() -> Self {
     pub fn maybe_have<S: Into<OsString>>(&mut self, cmd: S) -> Option<PathBuf> {
         let cmd: OsString = cmd.into();
         let path = &self.path;
+        // The programs looked for run on the build machine.
+        let cmd_exe = exe(&cmd, TargetSelection::from_user(env!("BUILD_TRIPLE")));
+        let has_suffix = cmd_exe.as_os_str() != cmd;
         self.cache
             .entry(cmd.clone())
             .or_insert_with(|| {
                 for path in env::split_paths(path) {
                     let target = path.join(&cmd);
-                    let mut cmd_exe = cmd.clone();
-                    cmd_exe.push(".exe");
 
                     if target.is_file()                   // some/path/git
-                    || path.join(&cmd_exe).exists()   // some/path/git.exe
-                    || target.join(&cmd_exe).exists()
+                    || has_suffix && path.join(&cmd_exe).exists()   // some/path/git.exe
+                    || has_suffix && target.join(&cmd_exe).exists()
                     // some/path/git/git.exe
                     {
                         return Some(target);
@@ -1,3 +1,4 @@
+use crate::util::exit;
 use crate::{t, VERSION};
 use crate::{Config, TargetSelection};
 use std::env::consts::EXE_SUFFIX;
@@ -94,7 +95,7 @@ pub fn setup(config: &Config, profile: Profile) {
             "note: this will use the configuration in {}",
             profile.include_path(&config.src).display()
         );
-        std::process::exit(1);
+        exit(1);
     }
 
     let settings = format!(
@@ -287,7 +288,7 @@ fn parse_with_abbrev(input: &str) -> Result<Profile, String> {
         io::stdin().read_line(&mut input)?;
         if input.is_empty() {
             eprintln!("EOF on stdin, when expecting answer to question.  Giving up.");
-            std::process::exit(1);
+            exit(1);
         }
         break match parse_with_abbrev(&input) {
             Ok(profile) => profile,
@@ -0,0 +1,131 @@
+//! Implementation of `x.py show-paths`.
+//!
+//! Prints the library search path variables that bootstrap sets when it runs
+//! the compiler, the tests of a target and the linker for tools built against
+//! LLVM, along with where each directory came from. Nothing is built: the
+//! helpers the steps use are called on a command that is never run, given the
+//! paths the steps would pass them, and what they add is recorded with
+//! `util::record_lookup_paths` under the labels they give it.
+
+use std::path::PathBuf;
+use std::process::Command;
+
+use serde::Serialize;
+
+use crate::builder::Builder;
+use crate::util::{
+    add_llvm_link_path, add_target_lib_path, record_lookup_paths, split_lookup_path,
+    LookupPathEntry,
+};
+use crate::{native, test};
+use crate::{Compiler, TargetSelection};
+
+#[derive(Serialize)]
+struct TargetPaths {
+    stage: u32,
+    target: String,
+    contexts: Vec<Context>,
+}
+
+/// The variables set on the commands bootstrap runs in one situation.
+#[derive(Serialize)]
+struct Context {
+    name: &'static str,
+    vars: Vec<Var>,
+}
+
+#[derive(Serialize)]
+struct Var {
+    name: String,
+    dirs: Vec<Dir>,
+}
+
+#[derive(Serialize)]
+struct Dir {
+    path: PathBuf,
+    exists: bool,
+    /// The step that added the directory, or `inherited` for directories
+    /// that were already in bootstrap's own environment.
+    source: String,
+}
+
+pub fn show_paths(builder: &Builder<'_>, json: bool) {
+    let reports: Vec<_> = builder.targets.iter().map(|&target| report(builder, target)).collect();
+    if json {
+        println!("{}", serde_json::to_string_pretty(&reports).expect("failed to serialize paths"));
+        return;
+    }
+    for report in reports {
+        println!("stage {}, target {}", report.stage, report.target);
+        for context in report.contexts {
+            if context.vars.is_empty() {
+                println!("  {}: nothing set", context.name);
+            }
+            for var in context.vars {
+                println!("  {}: {}", context.name, var.name);
+                for dir in var.dirs {
+                    let exists = if dir.exists { "exists" } else { "missing" };
+                    println!("    {} ({}, from {})", dir.path.display(), exists, dir.source);
+                }
+            }
+        }
+    }
+}
+
+fn report(builder: &Builder<'_>, target: TargetSelection) -> TargetPaths {
+    let stage = builder.top_stage;
+    let host = builder.config.build;
+    let compiler = Compiler { stage, host };
+    let mut contexts = vec![];
+
+    // As `Builder::add_rustc_lib_path` sets it up.
+    let mut cmd = Command::new("rustc");
+    let entries = record_lookup_paths(|| {
+        builder.add_rustc_libdir(compiler, builder.rustc_libdir_path(compiler), &mut cmd);
+    });
+    contexts.push(context("compiler", &cmd, &entries));
+
+    // As `test::Crate` sets it up.
+    let mut cmd = Command::new("cargo");
+    let entries = record_lookup_paths(|| {
+        let libdir = builder.sysroot_libdir_path(compiler, target);
+        add_target_lib_path(libdir, target, test::runs_under_runner(builder, target), &mut cmd);
+    });
+    contexts.push(context("tests", &cmd, &entries));
+
+    // `Builder::cargo` for tools and codegen backends that link against LLVM.
+    // LLVM isn't built here, so this is only known once it has been.
+    if builder.config.llvm_enabled() {
+        if let Ok(llvm_config) = native::prebuilt_llvm_config(builder, target) {
+            if llvm_config.is_file() {
+                let mut cmd = Command::new("cargo");
+                let entries = record_lookup_paths(|| add_llvm_link_path(&llvm_config, &mut cmd));
+                contexts.push(context("linking", &cmd, &entries));
+            }
+        }
+    }
+
+    TargetPaths { stage, target: target.triple.to_string(), contexts }
+}
+
+fn context(name: &'static str, cmd: &Command, entries: &[LookupPathEntry]) -> Context {
+    let vars = cmd
+        .get_envs()
+        .filter_map(|(key, value)| {
+            let key = key.to_string_lossy();
+            let dirs = split_lookup_path(&key, value?)
+                .into_iter()
+                .map(|path| {
+                    let source = entries
+                        .iter()
+                        .find(|entry| entry.var == key && entry.dir == path)
+                        .map_or("inherited", |entry| &entry.label)
+                        .to_string();
+                    Dir { exists: path.is_dir(), path, source }
+                })
+                .collect();
+            Some(Var { name: key.into_owned(), dirs })
+        })
+        .collect();
+    Context { name, vars }
+}
@@ -1,6 +1,8 @@
 use std::{
+    fs,
     path::{Path, PathBuf},
     process::Command,
+    time::SystemTime,
 };
 
 use crate::builder::Builder;
@@ -113,6 +115,36 @@ pub(crate) fn new_targetless(builder: &'a Builder<'a>, component: &str) -> Self
         Self::new_inner(builder, component, None)
     }
 
+    /// Returns the tarball an earlier invocation generated for the targetless
+    /// `component`, if it is newer than `newest_input` in every configured
+    /// compression format and its work directory is still around, so that the
+    /// step can skip generating it again.
+    pub(crate) fn reuse_targetless(
+        builder: &Builder<'_>,
+        component: &str,
+        newest_input: SystemTime,
+    ) -> Option<GeneratedTarball> {
+        if builder.config.dry_run {
+            return None;
+        }
+        let package_name = crate::dist::pkgname(builder, component);
+        let work = builder.out.join("tmp").join("tarball").join(component);
+        let distdir = crate::dist::distdir(builder);
+        for format in compression_formats(builder) {
+            let path = distdir.join(format!("{}.tar.{}", package_name, format));
+            let generated = fs::metadata(&path).and_then(|m| m.modified()).ok()?;
+            if generated <= newest_input {
+                return None;
+            }
+        }
+        let tarball = GeneratedTarball {
+            path: distdir.join(format!("{}.tar.{}", package_name, compression_ext(builder))),
+            decompressed_output: work.join(&package_name),
+            work,
+        };
+        if tarball.decompressed_output.exists() { Some(tarball) } else { None }
+    }
+
     fn new_inner(builder: &'a Builder<'a>, component: &str, target: Option<String>) -> Self {
         let pkgname = crate::dist::pkgname(builder, component);
 
@@ -120,7 +152,7 @@ fn new_inner(builder: &'a Builder<'a>, component: &str, target: Option<String>)
         if let Some(target) = &target {
             temp_dir = temp_dir.join(target);
         }
-        let _ = std::fs::remove_dir_all(&temp_dir);
+        t!(crate::util::remove_dir_all_retrying(&temp_dir));
 
         let image_dir = temp_dir.join("image");
         let overlay_dir = temp_dir.join("overlay");
@@ -304,7 +336,7 @@ fn run(self, build_cli: impl FnOnce(&Tarball<'a>, &mut Command)) -> GeneratedTar
 
         let package_name = self.package_name();
         self.builder.info(&format!("Dist {}", package_name));
-        let _time = crate::util::timeit(self.builder);
+        let _time = crate::util::timeit_labeled(self.builder, &format!("dist {}", package_name));
 
         build_cli(&self, &mut cmd);
         cmd.arg("--work-dir").arg(&self.temp_dir);
@@ -314,16 +346,7 @@ fn run(self, build_cli: impl FnOnce(&Tarball<'a>, &mut Command)) -> GeneratedTar
         }
         self.builder.run(&mut cmd);
 
-        // Use either the first compression format defined, or "gz" as the default.
-        let ext = self
-            .builder
-            .config
-            .dist_compression_formats
-            .as_ref()
-            .and_then(|formats| formats.get(0))
-            .map(|s| s.as_str())
-            .unwrap_or("gz");
-
+        let ext = compression_ext(self.builder);
         GeneratedTarball {
             path: crate::dist::distdir(self.builder).join(format!("{}.tar.{}", package_name, ext)),
             decompressed_output: self.temp_dir.join(package_name),
@@ -332,6 +355,27 @@ fn run(self, build_cli: impl FnOnce(&Tarball<'a>, &mut Command)) -> GeneratedTar
     }
 }
 
+/// The formats rust-installer compresses tarballs in: those configured, or
+/// its own default of gzip and xz.
+fn compression_formats<'b>(builder: &'b Builder<'_>) -> Vec<&'b str> {
+    match &builder.config.dist_compression_formats {
+        Some(formats) => formats.iter().map(|s| s.as_str()).collect(),
+        None => vec!["gz", "xz"],
+    }
+}
+
+/// The extension of the tarball rust-installer produces: either the first
+/// compression format defined, or "gz" as the default.
+fn compression_ext<'b>(builder: &'b Builder<'_>) -> &'b str {
+    builder
+        .config
+        .dist_compression_formats
+        .as_ref()
+        .and_then(|formats| formats.get(0))
+        .map(|s| s.as_str())
+        .unwrap_or("gz")
+}
+
 #[derive(Debug, Clone)]
 pub struct GeneratedTarball {
     path: PathBuf,
@@ -20,7 +20,9 @@
 use crate::native;
 use crate::tool::{self, SourceType, Tool};
 use crate::toolstate::ToolState;
-use crate::util::{self, add_link_lib_path, dylib_path, dylib_path_var, output, t};
+use crate::util::{
+    self, add_exe_path, add_link_lib_path, dylib_path, dylib_path_var, output, t, PathPlacement,
+};
 use crate::Crate as CargoCrate;
 use crate::{envify, CLang, DocTests, GitRepo, Mode};
 
@@ -141,7 +143,7 @@ fn run(self, builder: &Builder<'_>) {
         builder.default_doc(&[]);
 
         // Run the linkchecker.
-        let _time = util::timeit(&builder);
+        let _time = util::timeit_labeled(&builder, "linkchecker");
         try_run(
             builder,
             builder.tool_cmd(Tool::Linkchecker).arg(builder.out.join(host.triple).join("doc")),
@@ -235,7 +237,7 @@ fn run(self, builder: &Builder<'_>) {
         let out_dir = builder.out.join("ct");
         t!(fs::create_dir_all(&out_dir));
 
-        let _time = util::timeit(&builder);
+        let _time = util::timeit_labeled(&builder, "cargotest");
         let mut cmd = builder.tool_cmd(Tool::CargoTest);
         try_run(
             builder,
@@ -682,7 +684,7 @@ fn run(self, builder: &Builder<'_>) {
         }
 
         if !builder.config.cmd.bless() {
-            std::process::exit(1);
+            util::exit(1);
         }
 
         let mut cargo = builder.cargo(compiler, Mode::ToolRustc, SourceType::InTree, host, "run");
@@ -746,6 +748,7 @@ fn run(self, builder: &Builder<'_>) {
         if builder.is_fuse_ld_lld(self.compiler.host) {
             cmd.env("RUSTDOC_FUSE_LD_LLD", "1");
         }
+        util::prepare_tool_cmd(builder, self.compiler, &mut cmd);
         try_run(builder, &mut cmd);
     }
 }
@@ -1030,7 +1033,7 @@ fn run(self, builder: &Builder<'_>) {
                     PATH = inferred_rustfmt_dir.display(),
                     CHAN = builder.config.channel,
                 );
-                std::process::exit(1);
+                util::exit(1);
             }
             crate::format::format(&builder.build, !builder.config.cmd.bless(), &[]);
         }
@@ -1074,6 +1077,15 @@ fn make_run(run: RunConfig<'_>) {
     }
 }
 
+/// Returns whether the tests of `target` run under a wrapper such as an
+/// emulator, Node or `remote-test-client` rather than directly on the host.
+pub fn runs_under_runner(builder: &Builder<'_>, target: TargetSelection) -> bool {
+    target.contains("emscripten")
+        || target.starts_with("wasm32")
+        || builder.remote_tested(target)
+        || env::var_os(format!("CARGO_TARGET_{}_RUNNER", envify(&target.triple))).is_some()
+}
+
 fn testdir(builder: &Builder<'_>, host: TargetSelection) -> PathBuf {
     builder.out.join(host.triple).join("test")
 }
@@ -1265,7 +1277,7 @@ fn run(self, builder: &Builder<'_>) {
 help: to test the standard library, use `--stage 0 library/std` instead
 note: if you're sure you want to do this, please open an issue as to why. In the meantime, you can override this with `COMPILETEST_FORCE_STAGE0=1`."
             );
-            std::process::exit(1);
+            util::exit(1);
         }
 
         let compiler = self.compiler;
@@ -1492,7 +1504,11 @@ fn run(self, builder: &Builder<'_>) {
             // platform-specific environment variable as a workaround.
             if !builder.config.dry_run && suite.ends_with("fulldeps") {
                 let llvm_libdir = output(Command::new(&llvm_config).arg("--libdir"));
-                add_link_lib_path(vec![llvm_libdir.trim().into()], &mut cmd);
+                add_link_lib_path(
+                    vec![llvm_libdir.trim().into()],
+                    PathPlacement::Prepend,
+                    &mut cmd,
+                );
             }
 
             // Only pass correct values for these flags for the `run-make` suite as it
@@ -1613,7 +1629,7 @@ fn run(self, builder: &Builder<'_>) {
             "Check compiletest suite={} mode={} ({} -> {})",
             suite, mode, &compiler.host, target
         ));
-        let _time = util::timeit(&builder);
+        let _time = util::timeit_labeled(&builder, &format!("compiletest {} {}", suite, target));
         try_run(builder, &mut cmd);
 
         if let Some(compare_mode) = compare_mode {
@@ -1622,7 +1638,10 @@ fn run(self, builder: &Builder<'_>) {
                 "Check compiletest suite={} mode={} compare_mode={} ({} -> {})",
                 suite, mode, compare_mode, &compiler.host, target
             ));
-            let _time = util::timeit(&builder);
+            let _time = util::timeit_labeled(
+                &builder,
+                &format!("compiletest {} {} compare_mode={}", suite, target, compare_mode),
+            );
             try_run(builder, &mut cmd);
         }
     }
@@ -1677,16 +1696,15 @@ fn run_ext_doc(self, builder: &Builder<'_>) {
         // PATH so that it points to our rustdoc.
         let mut rustdoc_path = builder.rustdoc(compiler);
         rustdoc_path.pop();
-        let old_path = env::var_os("PATH").unwrap_or_default();
-        let new_path = env::join_paths(iter::once(rustdoc_path).chain(env::split_paths(&old_path)))
-            .expect("could not add rustdoc to PATH");
 
         let mut rustbook_cmd = builder.tool_cmd(Tool::Rustbook);
+        add_exe_path(vec![rustdoc_path], PathPlacement::Prepend, &mut rustbook_cmd);
+        util::prepare_tool_cmd(builder, compiler, &mut rustbook_cmd);
         let path = builder.src.join(&self.path);
-        rustbook_cmd.env("PATH", new_path).arg("test").arg(path);
+        rustbook_cmd.arg("test").arg(path);
         builder.add_rust_test_threads(&mut rustbook_cmd);
         builder.info(&format!("Testing rustbook {}", self.path.display()));
-        let _time = util::timeit(&builder);
+        let _time = util::timeit_labeled(&builder, &format!("rustbook {}", self.path.display()));
         let toolstate = if try_run(builder, &mut rustbook_cmd) {
             ToolState::TestPass
         } else {
@@ -1703,8 +1721,9 @@ fn run_local_doc(self, builder: &Builder<'_>) {
 
         // Do a breadth-first traversal of the `src/doc` directory and just run
         // tests for all files that end in `*.md`
-        let mut stack = vec![builder.src.join(self.path)];
-        let _time = util::timeit(&builder);
+        let mut stack = vec![builder.src.join(&self.path)];
+        let _time =
+            util::timeit_labeled(&builder, &format!("doc tests in {}", self.path.display()));
         let mut files = Vec::new();
         while let Some(p) = stack.pop() {
             if p.is_dir() {
@@ -1814,7 +1833,7 @@ fn run(self, builder: &Builder<'_>) {
         tool.arg("markdown").arg(&output);
 
         builder.info(&format!("Testing error-index stage{}", compiler.stage));
-        let _time = util::timeit(&builder);
+        let _time = util::timeit_labeled(&builder, "error-index");
         builder.run_quiet(&mut tool);
         // The tests themselves need to link to std, so make sure it is
         // available.
@@ -2023,14 +2042,20 @@ fn run(self, builder: &Builder<'_>) {
 
         cargo.arg("-p").arg(krate);
 
+        let runner_var = format!("CARGO_TARGET_{}_RUNNER", envify(&target.triple));
+        let runner = runs_under_runner(builder, target);
+
         // The tests are going to run with the *target* libraries, so we need to
-        // ensure that those libraries show up in the LD_LIBRARY_PATH equivalent.
+        // ensure that those libraries show up in the LD_LIBRARY_PATH equivalent,
+        // which under a runner is the target's rather than the host's.
         //
         // Note that to run the compiler we need to run with the *host* libraries,
         // but our wrapper scripts arrange for that to be the case anyway.
-        let mut dylib_path = dylib_path();
-        dylib_path.insert(0, PathBuf::from(&*builder.sysroot_libdir(compiler, target)));
-        cargo.env(dylib_path_var(), env::join_paths(&dylib_path).unwrap());
+        cargo.add_target_lib_path(
+            PathBuf::from(&*builder.sysroot_libdir(compiler, target)),
+            target,
+            runner,
+        );
 
         cargo.arg("--");
         cargo.args(&builder.config.cmd.test_args());
@@ -2040,18 +2065,15 @@ fn run(self, builder: &Builder<'_>) {
         }
 
         if target.contains("emscripten") {
-            cargo.env(
-                format!("CARGO_TARGET_{}_RUNNER", envify(&target.triple)),
-                builder.config.nodejs.as_ref().expect("nodejs not configured"),
-            );
+            cargo.env(&runner_var, builder.config.nodejs.as_ref().expect("nodejs not configured"));
         } else if target.starts_with("wasm32") {
             let node = builder.config.nodejs.as_ref().expect("nodejs not configured");
             let runner =
                 format!("{} {}/src/etc/wasm32-shim.js", node.display(), builder.src.display());
-            cargo.env(format!("CARGO_TARGET_{}_RUNNER", envify(&target.triple)), &runner);
+            cargo.env(&runner_var, &runner);
         } else if builder.remote_tested(target) {
             cargo.env(
-                format!("CARGO_TARGET_{}_RUNNER", envify(&target.triple)),
+                &runner_var,
                 format!("{} run 0", builder.tool_exe(Tool::RemoteTestClient).display()),
             );
         }
@@ -2060,7 +2082,7 @@ fn run(self, builder: &Builder<'_>) {
             "{} {} stage{} ({} -> {})",
             test_kind, krate, compiler.stage, &compiler.host, target
         ));
-        let _time = util::timeit(&builder);
+        let _time = util::timeit_labeled(&builder, &format!("{} {} {}", test_kind, krate, target));
         try_run(builder, &mut cargo.into());
     }
 }
@@ -2156,7 +2178,7 @@ fn run(self, builder: &Builder<'_>) {
             "{} rustdoc stage{} ({} -> {})",
             test_kind, compiler.stage, &compiler.host, target
         ));
-        let _time = util::timeit(&builder);
+        let _time = util::timeit_labeled(&builder, &format!("{} rustdoc {}", test_kind, target));
 
         try_run(builder, &mut cargo.into());
     }
@@ -2227,7 +2249,8 @@ fn run(self, builder: &Builder<'_>) {
             "{} rustdoc-json-types stage{} ({} -> {})",
             test_kind, compiler.stage, &compiler.host, target
         ));
-        let _time = util::timeit(&builder);
+        let _time =
+            util::timeit_labeled(&builder, &format!("{} rustdoc-json-types {}", test_kind, target));
 
         try_run(builder, &mut cargo.into());
     }
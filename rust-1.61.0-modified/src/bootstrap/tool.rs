@@ -2,14 +2,14 @@
 use std::env;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::{exit, Command};
+use std::process::Command;
 
 use crate::builder::{Builder, Cargo as CargoCommand, RunConfig, ShouldRun, Step};
 use crate::channel::GitInfo;
 use crate::compile;
 use crate::config::TargetSelection;
 use crate::toolstate::ToolState;
-use crate::util::{add_dylib_path, exe, t};
+use crate::util::{add_dylib_audit, add_dylib_path, exe, exit, prepare_tool_cmd, t, PathPlacement};
 use crate::Compiler;
 use crate::Mode;
 
@@ -388,13 +388,7 @@ pub fn command(builder: &Builder<'_>) -> Command {
         // use new syntax, but it should work otherwise.)
         let compiler = builder.compiler(builder.top_stage.saturating_sub(1), builder.config.build);
         let mut cmd = Command::new(builder.ensure(ErrorIndex { compiler }));
-        add_dylib_path(
-            vec![
-                PathBuf::from(&builder.sysroot_libdir(compiler, compiler.host)),
-                builder.rustc_libdir(compiler),
-            ],
-            &mut cmd,
-        );
+        prepare_tool_cmd(builder, compiler, &mut cmd);
         cmd
     }
 }
@@ -818,7 +812,10 @@ pub fn tool_cmd(&self, tool: Tool) -> Command {
             }
         }
 
-        add_dylib_path(lib_paths, &mut cmd);
+        add_dylib_path(lib_paths, PathPlacement::Prepend, &mut cmd);
+        if let Some(audit) = &self.config.dylib_audit {
+            add_dylib_audit(audit.clone(), &mut cmd);
+        }
 
         // Provide a RUSTC for this command to use.
         cmd.env("RUSTC", &self.initial_rustc);
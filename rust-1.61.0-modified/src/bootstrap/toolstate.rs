@@ -1,5 +1,5 @@
 use crate::builder::{Builder, RunConfig, ShouldRun, Step};
-use crate::util::t;
+use crate::util::{self, t};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::env;
@@ -93,7 +93,7 @@ fn print_error(tool: &str, submodule: &str) {
     eprintln!("If you do NOT intend to update '{}', please ensure you did not accidentally", tool);
     eprintln!("change the submodule at '{}'. You may ask your reviewer for the", submodule);
     eprintln!("proper steps.");
-    std::process::exit(3);
+    util::exit(3);
 }
 
 fn check_changed_files(toolstates: &HashMap<Box<str>, ToolState>) {
@@ -108,7 +108,7 @@ fn check_changed_files(toolstates: &HashMap<Box<str>, ToolState>) {
         Ok(o) => o,
         Err(e) => {
             eprintln!("Failed to get changed files: {:?}", e);
-            std::process::exit(1);
+            util::exit(1);
         }
     };
 
@@ -179,7 +179,7 @@ fn run(self, builder: &Builder<'_>) {
         }
 
         if did_error {
-            std::process::exit(1);
+            util::exit(1);
         }
 
         check_changed_files(&toolstates);
@@ -225,7 +225,7 @@ fn run(self, builder: &Builder<'_>) {
         }
 
         if did_error {
-            std::process::exit(1);
+            util::exit(1);
         }
 
         if builder.config.channel == "nightly" && env::var_os("TOOLSTATE_PUBLISH").is_some() {
@@ -0,0 +1,192 @@
+//! Chrome trace-event output of a build.
+//!
+//! With `--trace-file <path>`, every labeled `TimeIt` scope and every command
+//! run through `util::run` and its variants is recorded as a complete (`"X"`)
+//! event of the [Trace Event Format], and the events are written to `path` as
+//! a JSON array when the build finishes, or fails. The file can be loaded into
+//! `chrome://tracing` or <https://ui.perfetto.dev>. Each thread that records
+//! an event gets its own `tid`, so work done in parallel shows up on separate
+//! tracks.
+//!
+//! [Trace Event Format]: https://docs.google.com/document/d/1CvAClvFfyA5R-PhYUmn5OOQtYMH4h6I0nSsKchNAySU
+
+use std::cell::Cell;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use once_cell::sync::OnceCell;
+
+/// The trace of this build, if `--trace-file` was given.
+static ACTIVE: OnceCell<Trace> = OnceCell::new();
+
+/// The `tid` given to the next thread that records an event.
+static NEXT_TID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static TID: Cell<u64> = const { Cell::new(0) };
+}
+
+/// The `tid` of the current thread, assigned the first time it is asked for.
+fn current_tid() -> u64 {
+    TID.with(|tid| {
+        if tid.get() == 0 {
+            tid.set(NEXT_TID.fetch_add(1, Ordering::Relaxed));
+        }
+        tid.get()
+    })
+}
+
+#[derive(Clone, Debug)]
+struct Event {
+    name: String,
+    cat: &'static str,
+    /// Since the trace started.
+    start: Duration,
+    dur: Duration,
+    tid: u64,
+    /// The `args` shown when the event is selected.
+    args: Vec<(&'static str, String)>,
+}
+
+/// The events recorded so far, and where to write them.
+pub struct Trace {
+    path: PathBuf,
+    start: Instant,
+    events: Mutex<Vec<Event>>,
+}
+
+impl Trace {
+    pub fn new(path: PathBuf) -> Trace {
+        Trace { path, start: Instant::now(), events: Mutex::new(Vec::new()) }
+    }
+
+    /// Starts an event that ends when the returned `Span` is dropped.
+    pub fn span(&self, cat: &'static str, name: String) -> Span<'_> {
+        Span { trace: self, name, cat, start: Instant::now(), args: Vec::new() }
+    }
+
+    fn record(&self, event: Event) {
+        self.events.lock().unwrap_or_else(|e| e.into_inner()).push(event);
+    }
+
+    /// Returns the events recorded so far as a JSON array.
+    pub fn to_json(&self) -> String {
+        let events = self.events.lock().unwrap_or_else(|e| e.into_inner());
+        let pid = process::id();
+        let mut out = String::from("[\n");
+        for (i, event) in events.iter().enumerate() {
+            let _ = write!(
+                out,
+                r#"{{"name":{},"cat":"{}","ph":"X","ts":{},"dur":{},"pid":{},"tid":{},"args":{{"#,
+                json_string(&event.name),
+                event.cat,
+                event.start.as_micros(),
+                event.dur.as_micros(),
+                pid,
+                event.tid
+            );
+            for (j, (key, value)) in event.args.iter().enumerate() {
+                let sep = if j == 0 { "" } else { "," };
+                let _ = write!(out, "{}\"{}\":{}", sep, key, json_string(value));
+            }
+            out.push_str(if i + 1 == events.len() { "}}\n" } else { "}},\n" });
+        }
+        out.push(']');
+        out
+    }
+
+    /// Writes the events recorded so far.
+    pub fn persist(&self) {
+        // This may run while exiting after an error, so don't panic here.
+        if let Err(e) = fs::write(&self.path, self.to_json()) {
+            eprintln!("failed to write the trace to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// An event that is recorded when dropped, lasting from its creation.
+pub struct Span<'a> {
+    trace: &'a Trace,
+    name: String,
+    cat: &'static str,
+    start: Instant,
+    args: Vec<(&'static str, String)>,
+}
+
+impl Span<'_> {
+    /// Adds `key` to the details of the event.
+    pub fn arg(mut self, key: &'static str, value: String) -> Self {
+        self.args.push((key, value));
+        self
+    }
+}
+
+impl Drop for Span<'_> {
+    fn drop(&mut self) {
+        self.trace.record(Event {
+            name: std::mem::take(&mut self.name),
+            cat: self.cat,
+            start: self.start.saturating_duration_since(self.trace.start),
+            dur: self.start.elapsed(),
+            tid: current_tid(),
+            args: std::mem::take(&mut self.args),
+        });
+    }
+}
+
+/// Writes the trace when dropped, including while unwinding from a panic.
+pub struct TraceGuard(());
+
+impl Drop for TraceGuard {
+    fn drop(&mut self) {
+        persist_active();
+    }
+}
+
+/// Starts tracing this build into `path`.
+pub fn start(path: PathBuf) -> TraceGuard {
+    let _ = ACTIVE.set(Trace::new(path));
+    TraceGuard(())
+}
+
+/// Starts an event of the build's trace, or returns `None` without one. The
+/// name is only computed when tracing.
+pub fn span(cat: &'static str, name: impl FnOnce() -> String) -> Option<Span<'static>> {
+    ACTIVE.get().map(|trace| trace.span(cat, name()))
+}
+
+/// Writes the trace of the build, if there is one. Called by `util::exit`.
+pub fn persist_active() {
+    if let Some(trace) = ACTIVE.get() {
+        trace.persist();
+    }
+}
+
+/// Returns `s` as a quoted JSON string.
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,47 @@
+use super::*;
+use serde_json::Value;
+use std::sync::Arc;
+use std::thread;
+
+#[test]
+fn trace_round_trips_through_serde_json() {
+    let trace = Arc::new(Trace::new(PathBuf::new()));
+    {
+        let _outer = trace.span("timeit", "LLVM for x86_64-unknown-linux-gnu".into());
+        drop(
+            trace
+                .span("command", "cmake".into())
+                .arg("command", "\"cmake\" \"-G\" \"Ninja\"\n\t\\\u{1}".into()),
+        );
+    }
+    let worker = Arc::clone(&trace);
+    let worker_tid = thread::spawn(move || {
+        drop(worker.span("command", "rustfmt".into()));
+        current_tid()
+    })
+    .join()
+    .unwrap();
+
+    let events: Vec<Value> = serde_json::from_str(&trace.to_json()).unwrap();
+    let names: Vec<_> = events.iter().map(|e| e["name"].as_str().unwrap()).collect();
+    assert_eq!(names, ["cmake", "LLVM for x86_64-unknown-linux-gnu", "rustfmt"]);
+    assert!(events.iter().all(|e| e["ph"] == "X" && e["pid"] == process::id()));
+    assert_eq!(events[0]["args"]["command"], "\"cmake\" \"-G\" \"Ninja\"\n\t\\\u{1}");
+    assert_eq!(events[1]["cat"], "timeit");
+    assert_eq!(events[1]["args"], serde_json::json!({}));
+
+    // The command ran within its scope, on the same thread, and the worker
+    // thread got a track of its own.
+    let ts = |i: usize| events[i]["ts"].as_u64().unwrap();
+    let end = |i: usize| ts(i) + events[i]["dur"].as_u64().unwrap();
+    assert!(ts(1) <= ts(0) && end(0) <= end(1));
+    assert_eq!(events[0]["tid"], events[1]["tid"]);
+    assert_eq!(events[2]["tid"], worker_tid);
+    assert_ne!(events[2]["tid"], events[1]["tid"]);
+}
+
+#[test]
+fn empty_trace_is_an_empty_array() {
+    let events: Vec<Value> = serde_json::from_str(&Trace::new(PathBuf::new()).to_json()).unwrap();
+    assert!(events.is_empty());
+}
@@ -3,16 +3,30 @@
 //! Simple things like testing the various filesystem operations here and there,
 //! not a lot of interesting happenings here unfortunately.
 
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use std::fs;
-use std::io;
+use std::ffi::{OsStr, OsString};
+use std::fmt;
+use std::fs::{self, File};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
 use std::path::{Path, PathBuf};
-use std::process::{Command, Stdio};
+use std::process::{Command, ExitStatus, Stdio};
 use std::str;
-use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-use crate::builder::Builder;
-use crate::config::{Config, TargetSelection};
+use filetime::FileTime;
+use ignore::{WalkBuilder, WalkState};
+use once_cell::sync::{Lazy, OnceCell};
+
+use crate::builder::{Builder, Compiler};
+use crate::config::{Config, EqualMtime, SplitDebuginfo, TargetSelection};
+use crate::sanity::Finder;
 
 /// A helper macro to `unwrap` a result except also print out details like:
 ///
@@ -39,48 +53,580 @@ macro_rules! t {
 }
 pub(crate) use t;
 
-/// Given an executable called `name`, return the filename for the
-/// executable for a particular target.
-pub fn exe(name: &str, target: TargetSelection) -> String {
-    if target.contains("windows") { format!("{}.exe", name) } else { name.to_string() }
+/// The parts of a rustc target specification that rustbuild cares about, as
+/// printed by `rustc --print target-spec-json`. Fields left at rustc's
+/// defaults are omitted from that output, so those defaults are filled in
+/// here.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct TargetSpec {
+    #[serde(default = "TargetSpec::default_os")]
+    pub os: String,
+    #[serde(default = "TargetSpec::default_dll_prefix")]
+    pub dll_prefix: String,
+    #[serde(default = "TargetSpec::default_dll_suffix")]
+    pub dll_suffix: String,
+    #[serde(default)]
+    pub exe_suffix: String,
+    #[serde(default)]
+    pub linker_flavor: Option<String>,
 }
 
-/// Returns `true` if the file name given looks like a dynamic library.
+impl TargetSpec {
+    fn default_os() -> String {
+        "none".to_string()
+    }
+
+    fn default_dll_prefix() -> String {
+        "lib".to_string()
+    }
+
+    fn default_dll_suffix() -> String {
+        ".so".to_string()
+    }
+
+    pub fn parse(json: &str) -> Option<TargetSpec> {
+        serde_json::from_str(json).ok()
+    }
+
+    /// Returns `true` if the target links with a special purpose linker like
+    /// the Emscripten, WebAssembly, PTX or BPF ones, which the host's C
+    /// compiler can't stand in for. Knows the flavor names of both older rustc
+    /// versions (`em`, `ptx-linker`) and newer ones (`em-cc`, `ptx`).
+    pub fn uses_special_linker(&self) -> bool {
+        let flavor = match self.linker_flavor.as_deref() {
+            Some(flavor) => flavor,
+            None => return false,
+        };
+        ["em", "wasm", "ptx", "bpf"]
+            .iter()
+            .any(|special| flavor == *special || flavor.starts_with(&format!("{}-", special)))
+    }
+}
+
+/// Specs keyed on the rustc asked and its `--target` argument.
+type TargetSpecCache = HashMap<(PathBuf, String), Option<TargetSpec>>;
+
+static TARGET_SPEC_RUSTC: OnceCell<PathBuf> = OnceCell::new();
+static TARGET_SPECS: Lazy<Mutex<TargetSpecCache>> = Lazy::new(Default::default);
+
+/// Sets the rustc that `target_spec` asks for target specifications. Until
+/// this is called, and for targets that rustc doesn't know about, the
+/// helpers below fall back to guessing from the target triple.
+pub fn set_target_spec_rustc(rustc: &Path) {
+    let _ = TARGET_SPEC_RUSTC.set(rustc.to_path_buf());
+}
+
+/// Returns the specification of `target`, which may be a custom target JSON
+/// file, as reported by the rustc registered with `set_target_spec_rustc`.
+/// rustc is only run once per target, and `None` is returned if there's no
+/// rustc to ask or it doesn't know the target.
+pub fn target_spec(target: TargetSelection) -> Option<TargetSpec> {
+    target_spec_from(TARGET_SPEC_RUSTC.get()?, target)
+}
+
+fn target_spec_from(rustc: &Path, target: TargetSelection) -> Option<TargetSpec> {
+    let key = (rustc.to_path_buf(), target.rustc_target_arg().to_string());
+    let mut specs = t!(TARGET_SPECS.lock());
+    specs
+        .entry(key)
+        .or_insert_with(|| {
+            let output = Command::new(rustc)
+                .env("RUSTC_BOOTSTRAP", "1")
+                .args(["--print", "target-spec-json", "-Z", "unstable-options", "--target"])
+                .arg(target.rustc_target_arg())
+                .stderr(Stdio::null())
+                .output()
+                .ok()?;
+            if !output.status.success() {
+                return None;
+            }
+            TargetSpec::parse(str::from_utf8(&output.stdout).ok()?)
+        })
+        .clone()
+}
+
+/// The file name suffixes of executables, for targets whose triple has the
+/// given component. The first match wins, so Emscripten comes before wasm.
+const EXE_SUFFIXES: &[(&str, &str)] = &[
+    ("windows", ".exe"),
+    ("emscripten", ".js"),
+    ("wasm32", ".wasm"),
+    ("wasm64", ".wasm"),
+    ("uefi", ".efi"),
+];
+
+/// Returns the file name suffix of executables for `target`, which is empty
+/// for most targets.
+pub fn exe_suffix(target: TargetSelection) -> String {
+    if let Some(spec) = target_spec(target) {
+        return spec.exe_suffix;
+    }
+    EXE_SUFFIXES
+        .iter()
+        .find(|(component, _)| target.triple.split('-').any(|c| c == *component))
+        .map_or("", |(_, suffix)| suffix)
+        .to_string()
+}
+
+/// Given an executable at `path`, which may be a bare name or have
+/// directories in front, return its path for a particular target. The suffix
+/// is added to the file name only, and not if it's already there.
+pub fn exe(path: impl Into<PathBuf>, target: TargetSelection) -> PathBuf {
+    let mut path = path.into();
+    let suffix = exe_suffix(target);
+    if let Some(name) = path.file_name() {
+        if !suffix.is_empty() && !name.to_string_lossy().ends_with(&suffix) {
+            let mut name = name.to_os_string();
+            name.push(&suffix);
+            path.set_file_name(name);
+        }
+    }
+    path
+}
+
+/// Like `exe`, for callers that need the file name as a `String`.
+pub fn exe_name(name: &str, target: TargetSelection) -> String {
+    exe(name, target).to_str().unwrap().to_string()
+}
+
+/// Given a static library called `name`, return its filename for a
+/// particular target: `name.lib` for MSVC and `libname.a` everywhere else,
+/// including windows-gnu and wasm.
+pub fn staticlib(name: &str, target: TargetSelection) -> String {
+    if target.contains("msvc") { format!("{}.lib", name) } else { format!("lib{}.a", name) }
+}
+
+/// Given a dynamic library called `name`, return its filename for a
+/// particular target: `name.dll` on Windows, `libname.dylib` on Apple
+/// platforms and `libname.so` everywhere else.
+pub fn dylib(name: &str, target: TargetSelection) -> String {
+    if let Some(spec) = target_spec(target) {
+        format!("{}{}{}", spec.dll_prefix, name, spec.dll_suffix)
+    } else if target.contains("windows") {
+        format!("{}.dll", name)
+    } else if target.contains("apple") {
+        format!("lib{}.dylib", name)
+    } else {
+        format!("lib{}.so", name)
+    }
+}
+
+/// Returns `true` if the file name given looks like a dynamic library,
+/// including versioned ones like `libLLVM-14.so.1` or `libc++.1.dylib`.
 pub fn is_dylib(name: &str) -> bool {
-    name.ends_with(".dylib") || name.ends_with(".so") || name.ends_with(".dll")
+    name.ends_with(".dylib")
+        || name.ends_with(".so")
+        || name.ends_with(".dll")
+        || is_versioned_so(name)
+}
+
+/// Returns `true` for names like `libLLVM-14.so.1` or `libstdc++.so.6.0.30`,
+/// which have a dotted numeric version after `.so`.
+fn is_versioned_so(name: &str) -> bool {
+    match name.find(".so.") {
+        Some(i) => name[i + ".so.".len()..].split('.').all(is_version_number),
+        None => false,
+    }
+}
+
+fn is_version_number(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Returns `true` if the file at `path` is a dynamic library according to its
+/// header: an ELF shared object (`ET_DYN`, which PIE executables are too), a
+/// Mach-O `MH_DYLIB`, or a PE image with the DLL flag set. This catches
+/// libraries with unconventional names and rejects data files that merely end
+/// in `.so`, but reads the file, so hot loops should stick to `is_dylib`.
+///
+/// Falls back to `is_dylib` on the name if the file can be opened but not
+/// read, or for formats the header isn't checked for, like universal Mach-O
+/// binaries.
+pub fn is_dylib_file(path: &Path) -> io::Result<bool> {
+    let name_check = || matches!(path.file_name().and_then(|n| n.to_str()), Some(n) if is_dylib(n));
+    let mut header = Vec::new();
+    if File::open(path)?.take(4096).read_to_end(&mut header).is_err() {
+        return Ok(name_check());
+    }
+    let u16_at = |at: usize, big_endian: bool| {
+        let bytes = [*header.get(at)?, *header.get(at + 1)?];
+        Some(if big_endian { u16::from_be_bytes(bytes) } else { u16::from_le_bytes(bytes) })
+    };
+    let u32_at = |at: usize, big_endian: bool| {
+        let bytes =
+            [*header.get(at)?, *header.get(at + 1)?, *header.get(at + 2)?, *header.get(at + 3)?];
+        Some(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    };
+
+    const ET_DYN: u16 = 3;
+    const MH_DYLIB: u32 = 6;
+    const IMAGE_FILE_DLL: u16 = 0x2000;
+    if header.starts_with(b"\x7fELF") {
+        // EI_DATA is 2 for big-endian files, and e_type follows the 16 byte
+        // e_ident.
+        let big_endian = header.get(5) == Some(&2);
+        return Ok(u16_at(16, big_endian) == Some(ET_DYN));
+    }
+    match u32_at(0, false) {
+        Some(0xfeedface | 0xfeedfacf) => return Ok(u32_at(12, false) == Some(MH_DYLIB)),
+        Some(0xcefaedfe | 0xcffaedfe) => return Ok(u32_at(12, true) == Some(MH_DYLIB)),
+        // Universal binaries, which share their magic with Java class files
+        Some(0xbebafeca) => return Ok(name_check()),
+        _ => {}
+    }
+    if header.starts_with(b"MZ") {
+        // e_lfanew points at the "PE\0\0" signature, which is followed by the
+        // COFF header with Characteristics at offset 18.
+        let pe = match u32_at(0x3c, false) {
+            Some(pe) => pe as usize,
+            None => return Ok(false),
+        };
+        if header.get(pe..pe + 4) != Some(b"PE\0\0") {
+            return Ok(name_check());
+        }
+        return Ok(matches!(u16_at(pe + 4 + 18, false), Some(c) if c & IMAGE_FILE_DLL != 0));
+    }
+    Ok(false)
+}
+
+/// Returns the names a dynamic library may be found under, from the given
+/// name down to the unversioned one: `libfoo.so.1.2` gives `libfoo.so.1.2`,
+/// `libfoo.so.1` and `libfoo.so`, and `libfoo.1.dylib` gives `libfoo.1.dylib`
+/// and `libfoo.dylib`. Other names are returned as they are.
+pub fn dylib_variants(name: &str) -> Vec<String> {
+    let mut variants = vec![name.to_string()];
+    if is_versioned_so(name) {
+        let mut rest = name;
+        while !rest.ends_with(".so") {
+            rest = &rest[..rest.rfind('.').unwrap()];
+            variants.push(rest.to_string());
+        }
+    } else if let Some(mut stem) = name.strip_suffix(".dylib") {
+        while let Some((rest, version)) = stem.rsplit_once('.') {
+            if !is_version_number(version) {
+                break;
+            }
+            stem = rest;
+            variants.push(format!("{}.dylib", stem));
+        }
+    }
+    variants
 }
 
-/// Returns `true` if the file name given looks like a debug info file
-pub fn is_debug_info(name: &str) -> bool {
-    // FIXME: consider split debug info on other platforms (e.g., Linux, macOS)
-    name.ends_with(".pdb")
+/// Returns `true` if the path given looks like split debug info: a `.pdb`,
+/// `.dwp`, `.dwo` or `.debug` file, or a `.dSYM` bundle, which is a directory.
+pub fn is_debug_info(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("pdb" | "dwp" | "dwo" | "debug") => true,
+        Some("dSYM") => path.is_dir(),
+        _ => false,
+    }
+}
+
+/// Returns the split debug info of `artifact`, an executable or dylib built
+/// for `target`, for the split debug info mode in `config`. That's a `.pdb`
+/// file on MSVC, a `.dSYM` bundle, which is a directory, on Apple platforms
+/// and a `.dwp` file elsewhere when packed, and the `.dwo` files belonging to
+/// the artifact when unpacked. They are looked for next to the artifact and
+/// in `build_dirs`, where cargo left them if the artifact was copied from
+/// there. Packed companions are returned whether or not they exist, so
+/// callers copying them must check.
+pub fn debuginfo_companions(
+    config: &Config,
+    artifact: &Path,
+    target: TargetSelection,
+    build_dirs: &[PathBuf],
+) -> Vec<PathBuf> {
+    let name = match artifact.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None => return vec![],
+    };
+    // The crate's file stem, like `rustc_driver-<hash>` or `rustc-main`. The
+    // debug info of executables is named after the crate instead.
+    let stem = name.strip_prefix("lib").filter(|_| is_dylib(name)).unwrap_or(name);
+    let stem = stem.split('.').next().unwrap();
+    let mut stems = vec![stem.to_string()];
+    if stem.contains('-') && !is_dylib(name) {
+        stems.push(stem.replace('-', "_"));
+    }
+
+    let dirs: Vec<&Path> =
+        artifact.parent().into_iter().chain(build_dirs.iter().map(|d| d.as_path())).collect();
+    let in_dirs = |file: String| dirs.iter().map(|dir| dir.join(&file)).collect::<Vec<_>>();
+
+    if target.contains("msvc") {
+        return stems.iter().flat_map(|stem| in_dirs(format!("{}.pdb", stem))).collect();
+    }
+    match config.split_debuginfo(target) {
+        SplitDebuginfo::Packed if target.contains("apple") => in_dirs(format!("{}.dSYM", name)),
+        SplitDebuginfo::Packed => in_dirs(format!("{}.dwp", name)),
+        SplitDebuginfo::Unpacked if !target.contains("apple") => {
+            // `.dwo` files are named after the crate, with the hash of the
+            // build it was part of for executables, and then the codegen unit.
+            let belongs = |dwo: &str| {
+                stems.iter().any(|stem| {
+                    matches!(dwo.strip_prefix(stem.as_str()), Some(rest) if rest.starts_with('.') || rest.starts_with('-'))
+                })
+            };
+            let mut dwos = vec![];
+            for dir in dirs {
+                let entries = match fs::read_dir(dir) {
+                    Ok(entries) => entries,
+                    Err(_) => continue,
+                };
+                let mut found: Vec<_> = entries
+                    .filter_map(|e| e.ok())
+                    .map(|e| e.path())
+                    .filter(|path| {
+                        matches!(path.file_name().and_then(|n| n.to_str()), Some(n) if n.ends_with(".dwo") && belongs(n))
+                    })
+                    .collect();
+                found.sort();
+                dwos.extend(found);
+            }
+            dwos
+        }
+        SplitDebuginfo::Unpacked | SplitDebuginfo::Off => vec![],
+    }
+}
+
+/// Copies the split debug info of `artifact` that exists, as found by
+/// `debuginfo_companions`, into `dst_dir`. Of companions with the same name
+/// in several directories, the first is copied. Returns the number copied,
+/// which is zero in a dry run.
+pub fn copy_debuginfo(
+    config: &Config,
+    artifact: &Path,
+    target: TargetSelection,
+    build_dirs: &[PathBuf],
+    dst_dir: &Path,
+) -> io::Result<usize> {
+    if config.dry_run {
+        return Ok(0);
+    }
+    let mut copied = HashSet::new();
+    for companion in debuginfo_companions(config, artifact, target, build_dirs) {
+        let name = companion.file_name().unwrap().to_owned();
+        if !companion.exists() || !copied.insert(name.clone()) {
+            continue;
+        }
+        let dst = dst_dir.join(name);
+        if companion.is_dir() {
+            copy_tree(&companion, &dst, &CopyOpts::default())?;
+        } else {
+            let _ = fs::remove_file(&dst);
+            fs::copy(&companion, &dst)?;
+        }
+    }
+    Ok(copied.len())
+}
+
+/// What kind of build artifact a file is, as far as copying it around the
+/// sysroot and into dist tarballs is concerned.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum ArtifactKind {
+    Exe,
+    Dylib,
+    StaticLib,
+    Rlib,
+    Rmeta,
+    DebugInfo,
+    /// A Windows import library for a dylib: `foo.dll.lib` for MSVC and
+    /// `libfoo.dll.a` for MinGW.
+    ImportLib,
+    Other,
+}
+
+impl ArtifactKind {
+    /// Classifies `path` by its name, as produced for `target`. Executables
+    /// are recognized by the target's suffix, or by having no extension on
+    /// targets whose executables have no suffix.
+    pub fn classify(path: &Path, target: TargetSelection) -> ArtifactKind {
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => name,
+            None => return ArtifactKind::Other,
+        };
+        if is_debug_info(path) {
+            ArtifactKind::DebugInfo
+        } else if is_import_lib(name, target) {
+            ArtifactKind::ImportLib
+        } else if name.ends_with(".rlib") {
+            ArtifactKind::Rlib
+        } else if name.ends_with(".rmeta") {
+            ArtifactKind::Rmeta
+        } else if is_dylib(name) {
+            ArtifactKind::Dylib
+        } else if name.ends_with(".a") || name.ends_with(".lib") {
+            ArtifactKind::StaticLib
+        } else if is_exe(name, target) {
+            ArtifactKind::Exe
+        } else {
+            ArtifactKind::Other
+        }
+    }
+}
+
+/// Whether `name` is the import library of a DLL built for `target`:
+/// `foo.dll.lib` with the MSVC toolchain and `libfoo.dll.a` (or `foo.dll.a`)
+/// with MinGW. Only the whole double extension counts, so plain static
+/// libraries like `libfoo.a` and `foo.lib` aren't import libraries, and
+/// there are none on targets other than Windows.
+pub fn is_import_lib(name: &str, target: TargetSelection) -> bool {
+    let suffix = if target.contains("windows-msvc") {
+        ".dll.lib"
+    } else if target.contains("windows-gnu") {
+        ".dll.a"
+    } else {
+        return false;
+    };
+    name.len() > suffix.len()
+        && name.is_char_boundary(name.len() - suffix.len())
+        && name[name.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+}
+
+fn is_exe(name: &str, target: TargetSelection) -> bool {
+    let exe = exe_suffix(target);
+    if exe.is_empty() {
+        !name.contains('.')
+    } else {
+        name.ends_with(&exe) && name.len() > exe.len()
+    }
 }
 
 /// Returns the corresponding relative library directory that the compiler's
 /// dylibs will be found in.
 pub fn libdir(target: TargetSelection) -> &'static str {
-    if target.contains("windows") { "bin" } else { "lib" }
+    let windows = match target_spec(target) {
+        Some(spec) => spec.os == "windows",
+        None => target.contains("windows"),
+    };
+    if windows { "bin" } else { "lib" }
 }
 
-/// Adds a list of lookup paths to `cmd`'s dynamic library lookup path.
-/// If The dylib_path_par is already set for this cmd, the old value will be overwritten!
-pub fn add_dylib_path(path: Vec<PathBuf>, cmd: &mut Command) {
-    let mut list = dylib_path();
-    for path in path {
-        list.insert(0, path);
+/// Where `add_dylib_path` and `add_link_lib_path` put the new directories
+/// relative to the ones already on the lookup path.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PathPlacement {
+    /// Search the new directories first.
+    Prepend,
+    /// Search the new directories after the existing ones.
+    Append,
+}
+
+/// Adds a list of lookup paths to `cmd`'s dynamic library lookup path,
+/// keeping whatever the lookup path already is: the value set on `cmd` by an
+/// earlier call, or otherwise the one in our environment.
+pub fn add_dylib_path(path: Vec<PathBuf>, placement: PathPlacement, cmd: &mut Command) {
+    if let Some(var) = dylib_fallback_path_var() {
+        add_lookup_paths(var, path.clone(), placement, cmd);
     }
-    cmd.env(dylib_path_var(), t!(env::join_paths(list)));
+    add_lookup_paths(dylib_path_var(), path, placement, cmd);
 }
 
-include!("dylib_util.rs");
+/// Adds a list of lookup paths to the dynamic library lookup path of programs
+/// built for `target` that `cmd` runs, such as tests under a runner wrapper.
+/// Unlike `add_dylib_path`, which is for programs built for the host, this
+/// sets the variable of the target's OS, see `dylib_path_var_for`.
+pub fn add_dylib_path_for_target(path: Vec<PathBuf>, cmd: &mut Command, target: TargetSelection) {
+    let var = dylib_path_var_for(target);
+    if var == dylib_path_var() {
+        add_dylib_path(path, PathPlacement::Prepend, cmd);
+    } else {
+        add_lookup_paths(var, path, PathPlacement::Prepend, cmd);
+    }
+}
+
+/// Makes `libdir` available to the programs built for `target` that `cmd`
+/// runs, through the target's dynamic library lookup path if they run under a
+/// `runner` wrapper like an emulator or Wine, and through the host's otherwise.
+pub fn add_target_lib_path(
+    libdir: PathBuf,
+    target: TargetSelection,
+    runner: bool,
+    cmd: &mut Command,
+) {
+    label_lookup_paths("Cargo::add_target_lib_path", || {
+        if runner {
+            add_dylib_path_for_target(vec![libdir], cmd, target);
+        } else {
+            add_dylib_path(vec![libdir], PathPlacement::Prepend, cmd);
+        }
+    })
+}
+
+/// Returns the environment variable which the dynamic library lookup path of
+/// programs built for `target` resides in when they run on this host, going
+/// by the target's OS rather than the host's. Windows programs run on other
+/// hosts under Wine, which reads `WINEPATH`.
+pub fn dylib_path_var_for(target: TargetSelection) -> &'static str {
+    dylib_path_var_on(env::consts::OS, target)
+}
 
-/// Adds a list of lookup paths to `cmd`'s link library lookup path.
-pub fn add_link_lib_path(path: Vec<PathBuf>, cmd: &mut Command) {
-    let mut list = link_lib_path();
-    for path in path {
-        list.insert(0, path);
+/// `dylib_path_var_for` on a host running `host_os`, as in `std::env::consts::OS`.
+fn dylib_path_var_on(host_os: &str, target: TargetSelection) -> &'static str {
+    if target.contains("windows") {
+        if host_os == "windows" { "PATH" } else { "WINEPATH" }
+    } else if target.contains("apple") {
+        "DYLD_LIBRARY_PATH"
+    } else if target.contains("haiku") {
+        "LIBRARY_PATH"
+    } else if target.contains("aix") {
+        "LIBPATH"
+    } else {
+        "LD_LIBRARY_PATH"
+    }
+}
+
+/// Returns the environment variable the dynamic loader searches after
+/// `dylib_path_var()` on this platform, if any. On macOS `add_dylib_path`
+/// sets `DYLD_FALLBACK_LIBRARY_PATH` too, so that the libraries are still
+/// found by the children of programs that replace `DYLD_LIBRARY_PATH` for
+/// them. Neither variable gets past SIP, which purges every `DYLD_*`
+/// variable when it starts a protected binary such as `/bin/sh`, so commands
+/// that rely on them must not be started through one.
+fn dylib_fallback_path_var() -> Option<&'static str> {
+    if cfg!(target_os = "macos") { Some("DYLD_FALLBACK_LIBRARY_PATH") } else { None }
+}
+
+/// The directories searched through `var` when it isn't set at all, which
+/// setting it would otherwise lose.
+fn default_lookup_path(var: &str) -> Vec<PathBuf> {
+    if var != "DYLD_FALLBACK_LIBRARY_PATH" {
+        return vec![];
     }
-    cmd.env(link_lib_path_var(), t!(env::join_paths(list)));
+    // As documented in dyld(1).
+    let mut list: Vec<PathBuf> =
+        env::var_os("HOME").map(|home| Path::new(&home).join("lib")).into_iter().collect();
+    list.extend(["/usr/local/lib", "/lib", "/usr/lib"].iter().map(PathBuf::from));
+    list
+}
+
+/// Prepends the audit library `path` to `cmd`'s `LD_AUDIT`, keeping the ones
+/// already there, like `add_dylib_path`. The glibc dynamic loader loads these
+/// into every process it starts, which lets tracing libraries observe the
+/// calls a command makes into shared libraries. Other platforms ignore the
+/// variable.
+pub fn add_dylib_audit(path: PathBuf, cmd: &mut Command) {
+    add_lookup_paths("LD_AUDIT", vec![path], PathPlacement::Prepend, cmd);
+}
+
+include!("dylib_util.rs");
+
+/// Adds a list of lookup paths to `cmd`'s link library lookup path, like
+/// `add_dylib_path`.
+pub fn add_link_lib_path(path: Vec<PathBuf>, placement: PathPlacement, cmd: &mut Command) {
+    add_lookup_paths(link_lib_path_var(), path, placement, cmd);
+}
+
+/// Adds the directory of the LLVM libraries that `llvm_config` reports to
+/// `cmd`'s link library lookup path, for tools that link against LLVM through
+/// the compiler's libraries.
+pub fn add_llvm_link_path(llvm_config: &Path, cmd: &mut Command) {
+    label_lookup_paths("Builder::cargo", || {
+        let llvm_libdir = output(Command::new(llvm_config).arg("--libdir"));
+        add_link_lib_path(vec![llvm_libdir.trim().into()], PathPlacement::Prepend, cmd);
+    });
 }
 
 /// Returns the environment variable which the link library lookup path
@@ -89,40 +635,544 @@ fn link_lib_path_var() -> &'static str {
     if cfg!(target_env = "msvc") { "LIB" } else { "LIBRARY_PATH" }
 }
 
-/// Parses the `link_lib_path_var()` environment variable, returning a list of
-/// paths that are members of this lookup path.
-fn link_lib_path() -> Vec<PathBuf> {
-    let var = match env::var_os(link_lib_path_var()) {
-        Some(v) => v,
-        None => return vec![],
+/// Merges `paths` into the lookup path in `var` on `cmd`, starting from the
+/// value already set on `cmd` if there is one, so that several steps can each
+/// extend the path. Directories appear once, where they first occur.
+fn add_lookup_paths(var: &str, paths: Vec<PathBuf>, placement: PathPlacement, cmd: &mut Command) {
+    LOOKUP_PATH_LOG.with(|log| {
+        if let Some(entries) = &mut *log.borrow_mut() {
+            let label = LOOKUP_PATH_LABEL.with(Cell::get);
+            entries.extend(paths.iter().map(|dir| LookupPathEntry {
+                var: var.to_string(),
+                dir: dir.clone(),
+                label: label.to_string(),
+            }));
+        }
+    });
+    let current = effective_lookup_path(var, cmd);
+    let mut list = match placement {
+        PathPlacement::Prepend => paths.into_iter().chain(current).collect::<Vec<_>>(),
+        PathPlacement::Append => current.into_iter().chain(paths).collect(),
     };
-    env::split_paths(&var).collect()
+    dedup_lookup_paths(&mut list);
+    // Past the limit, child processes fail to spawn with an error that says
+    // nothing about the environment.
+    if cfg!(windows) {
+        list = match fit_lookup_path(var, list, WINDOWS_ENV_VAR_LIMIT) {
+            Ok(list) => list,
+            Err(e) => fail(&e),
+        };
+    }
+    cmd.env(var, join_lookup_path(var, &list));
+}
+
+/// Returns the lookup path in `var` that `cmd` will run with: the value set on
+/// `cmd` if there is one, and our own otherwise.
+fn effective_lookup_path(var: &str, cmd: &Command) -> Vec<PathBuf> {
+    // Variable names are case-insensitive on Windows, where `PATH` is often
+    // spelled `Path`.
+    let is_var = |key: &OsStr| {
+        key == var
+            || cfg!(windows) && matches!(key.to_str(), Some(k) if k.eq_ignore_ascii_case(var))
+    };
+    match cmd.get_envs().find(|(key, _)| is_var(key)) {
+        Some((_, Some(value))) => split_lookup_path(var, value),
+        // Removed from the command's environment.
+        Some((_, None)) => vec![],
+        None => match env::var_os(var) {
+            Some(value) => split_lookup_path(var, &value),
+            None => default_lookup_path(var),
+        },
+    }
 }
 
-pub struct TimeIt(bool, Instant);
+/// Adds a list of directories to the `PATH` that `cmd` looks up programs in,
+/// like `add_dylib_path`. On Windows that is also where DLLs are looked up, so
+/// this keeps the directories `add_dylib_path` may already have put there.
+pub fn add_exe_path(paths: Vec<PathBuf>, placement: PathPlacement, cmd: &mut Command) {
+    add_lookup_paths("PATH", paths, placement, cmd);
+}
+
+/// Prepares `cmd`, which runs a tool that is or links against `compiler`, to
+/// find the dynamic libraries it needs: the compiler's own and the standard
+/// library of its host. Unlike `Builder::add_rustc_lib_path` this isn't
+/// skipped on Windows, where the loader looks for DLLs on `PATH` and a tool
+/// that misses one exits with `STATUS_DLL_NOT_FOUND` and no explanation.
+pub fn prepare_tool_cmd(builder: &Builder<'_>, compiler: Compiler, cmd: &mut Command) {
+    let mut dirs = builder.rustc_lib_dirs(compiler, builder.rustc_libdir(compiler));
+    dirs.push(builder.sysroot_libdir(compiler, compiler.host).to_path_buf());
+    add_dylib_path(dirs, PathPlacement::Prepend, cmd);
+}
+
+/// `STATUS_DLL_NOT_FOUND` and `STATUS_ENTRYPOINT_NOT_FOUND`, which Windows
+/// programs exit with when the loader can't find a DLL or a function in one.
+const DLL_LOAD_FAILURE_CODES: [u32; 2] = [0xC0000135, 0xC0000139];
+
+/// Explains a failure of `cmd` with `status` if it looks like the loader
+/// couldn't find a DLL, listing the `PATH` it was searched on.
+pub fn dll_load_failure_hint(cmd: &Command, status: ExitStatus) -> Option<String> {
+    let code = status.code()? as u32;
+    if !DLL_LOAD_FAILURE_CODES.contains(&code) {
+        return None;
+    }
+    let path = effective_lookup_path("PATH", cmd);
+    Some(format!(
+        "the tool likely failed to locate a DLL (exit code {:#X}); PATH was:\n{}",
+        code,
+        path.iter().map(|dir| format!("    {}\n", dir.display())).collect::<String>()
+    ))
+}
+
+/// A directory that one of the path helpers put on a lookup path, and the
+/// label of the code that asked for it, as recorded by `record_lookup_paths`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LookupPathEntry {
+    pub var: String,
+    pub dir: PathBuf,
+    pub label: String,
+}
+
+thread_local! {
+    /// The entries of the `record_lookup_paths` call in progress.
+    static LOOKUP_PATH_LOG: RefCell<Option<Vec<LookupPathEntry>>> = const { RefCell::new(None) };
+    /// The label of the innermost `label_lookup_paths` call in progress.
+    static LOOKUP_PATH_LABEL: Cell<&'static str> = const { Cell::new("bootstrap") };
+}
+
+/// Runs `f`, recording every directory that `add_dylib_path`,
+/// `add_link_lib_path` and the like add to a lookup path along the way with
+/// the label given to `label_lookup_paths` by the code that added it, and
+/// returns them in the order they were added. Nothing is recorded outside of
+/// this, even if `f` panics, which is how `x.py show-paths` attributes each
+/// directory to where it came from.
+pub fn record_lookup_paths(f: impl FnOnce()) -> Vec<LookupPathEntry> {
+    struct StopRecording;
+    impl Drop for StopRecording {
+        fn drop(&mut self) {
+            LOOKUP_PATH_LOG.with(|log| log.borrow_mut().take());
+        }
+    }
+
+    LOOKUP_PATH_LOG.with(|log| *log.borrow_mut() = Some(vec![]));
+    let _stop = StopRecording;
+    f();
+    LOOKUP_PATH_LOG.with(|log| log.borrow_mut().take()).unwrap_or_default()
+}
+
+/// Runs `f`, attributing the directories it adds to lookup paths to `label`
+/// if `record_lookup_paths` is recording them. The innermost label counts;
+/// directories added outside of any are attributed to `bootstrap`.
+pub fn label_lookup_paths<T>(label: &'static str, f: impl FnOnce() -> T) -> T {
+    struct RestoreLabel(&'static str);
+    impl Drop for RestoreLabel {
+        fn drop(&mut self) {
+            LOOKUP_PATH_LABEL.with(|label| label.set(self.0));
+        }
+    }
+
+    let _restore = RestoreLabel(LOOKUP_PATH_LABEL.with(|outer| outer.replace(label)));
+    f()
+}
+
+/// Splits the value of the lookup path `var` into its directories. Wine
+/// separates them with `;` whatever the host, the other variables with the
+/// host's separator.
+pub fn split_lookup_path(var: &str, value: &OsStr) -> Vec<PathBuf> {
+    if var == "WINEPATH" {
+        value
+            .to_string_lossy()
+            .split(';')
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .collect()
+    } else {
+        env::split_paths(value).collect()
+    }
+}
+
+/// The inverse of `split_lookup_path`.
+fn join_lookup_path(var: &str, list: &[PathBuf]) -> OsString {
+    if var == "WINEPATH" {
+        let mut value = OsString::new();
+        for (i, dir) in list.iter().enumerate() {
+            if i > 0 {
+                value.push(";");
+            }
+            value.push(dir);
+        }
+        value
+    } else {
+        t!(env::join_paths(list))
+    }
+}
+
+/// The most UTF-16 code units Windows allows for an environment variable,
+/// counting its name, the `=` and the terminating NUL.
+const WINDOWS_ENV_VAR_LIMIT: usize = 32767;
+
+/// Removes the later of any two entries of `list` naming the same directory,
+/// ignoring trailing separators and, on Windows, the case and the kind of
+/// slash.
+fn dedup_lookup_paths(list: &mut Vec<PathBuf>) {
+    let mut seen = HashSet::new();
+    list.retain(|path| {
+        let mut key = path.to_string_lossy().into_owned();
+        if cfg!(windows) {
+            key = key.replace('/', "\\").to_lowercase();
+        }
+        let trimmed = key.trim_end_matches(std::path::is_separator).len();
+        if trimmed == 0 {
+            // A root like `/`.
+            key.truncate(1);
+        } else if !key[..trimmed].ends_with(':') {
+            // Anything but a drive root like `C:\`.
+            key.truncate(trimmed);
+        }
+        seen.insert(key)
+    });
+}
+
+/// How many UTF-16 code units `var` set to `list` takes up in the
+/// environment block, as measured against `WINDOWS_ENV_VAR_LIMIT`.
+fn env_var_len(var: &str, list: &[PathBuf]) -> usize {
+    let value = join_lookup_path(var, list);
+    var.encode_utf16().count() + 1 + value.to_string_lossy().encode_utf16().count() + 1
+}
+
+/// Shortens the deduplicated lookup path `list` for `var` to fit in `limit`
+/// by dropping the directories that don't exist, if it doesn't fit already.
+fn fit_lookup_path(var: &str, list: Vec<PathBuf>, limit: usize) -> Result<Vec<PathBuf>, String> {
+    let len = env_var_len(var, &list);
+    if len <= limit {
+        return Ok(list);
+    }
+    let existing: Vec<_> = list.into_iter().filter(|path| path.exists()).collect();
+    let shortened = env_var_len(var, &existing);
+    if shortened <= limit {
+        return Ok(existing);
+    }
+    Err(format!(
+        "the {} environment variable would be {} characters long ({} without missing \
+         directories), over the limit of {}",
+        var, len, shortened, limit
+    ))
+}
+
+static TIMESTAMPS: AtomicBool = AtomicBool::new(false);
+static START: Lazy<Instant> = Lazy::new(Instant::now);
+
+/// Turns on the `[HH:MM:SS]` prefixes added by `log_line`, measured from the
+/// first call to this function.
+pub fn enable_timestamps(enable: bool) {
+    Lazy::force(&START);
+    TIMESTAMPS.store(enable, Ordering::Relaxed);
+}
+
+/// Prints `msg`, prefixing each line with the time elapsed since the build
+/// started if timestamps are enabled (`--timestamps`, or running in CI).
+pub fn log_line(msg: &str) {
+    println!("{}", log_text(msg));
+}
+
+/// Returns `msg` as `log_line` prints it.
+fn log_text(msg: &str) -> String {
+    if TIMESTAMPS.load(Ordering::Relaxed) {
+        timestamp_lines(msg, START.elapsed())
+    } else {
+        msg.to_string()
+    }
+}
+
+/// Prefixes every non-empty line of `msg` with `elapsed` as `[HH:MM:SS] `.
+/// CI workflow commands like `::group::` or `##vso[...]` are only recognized
+/// at the start of a line, so they are left alone.
+fn timestamp_lines(msg: &str, elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    let prefix = format!("[{:02}:{:02}:{:02}] ", secs / 3600, secs / 60 % 60, secs % 60);
+    msg.split('\n')
+        .map(|line| {
+            if line.is_empty() || line.starts_with("::") || line.starts_with("##") {
+                line.to_string()
+            } else {
+                format!("{}{}", prefix, line)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Measures the time until it is dropped and then prints it, labeled and
+/// indented by how many other `TimeIt`s were alive on this thread when it was
+/// created, so that nested scopes print under their parents:
+///
+/// ```text
+///     llvm cmake configure: 312.44s
+///   LLVM for x86_64-unknown-linux-gnu: 1520.03s
+/// ```
+///
+/// Scopes that took less than `Config::timeit_threshold` are only printed
+/// with `-vv`, which also adds the `ResourceUsage` of the processes the scope
+/// ran. Nothing is printed in dry runs.
+pub struct TimeIt<'a, W: Write = io::Stdout> {
+    config: &'a Config,
+    start: Instant,
+    usage: Option<ResourceUsage>,
+    /// The scope's event in the `--trace-file`, if it is labeled.
+    _span: Option<crate::trace::Span<'static>>,
+    label: Option<String>,
+    depth: usize,
+    out: W,
+}
+
+/// Returns an RAII structure that prints out how long it took to drop,
+/// labeled with `label`.
+pub fn timeit_labeled<'a>(builder: &'a Builder<'_>, label: &str) -> TimeIt<'a> {
+    TimeIt::new(&builder.config, Some(label), io::stdout())
+}
 
 /// Returns an RAII structure that prints out how long it took to drop.
-pub fn timeit(builder: &Builder<'_>) -> TimeIt {
-    TimeIt(builder.config.dry_run, Instant::now())
+#[deprecated(note = "use `timeit_labeled`, so that the time says what it measured")]
+pub fn timeit<'a>(builder: &'a Builder<'_>) -> TimeIt<'a> {
+    TimeIt::new(&builder.config, None, io::stdout())
+}
+
+impl<'a, W: Write> TimeIt<'a, W> {
+    /// Like `timeit_labeled`, but writes to `out` instead of stdout.
+    pub fn new(config: &'a Config, label: Option<&str>, out: W) -> TimeIt<'a, W> {
+        let depth = TIMEIT_DEPTH.with(|depth| {
+            depth.set(depth.get() + 1);
+            depth.get()
+        });
+        let usage = if config.verbose >= 2 { children_usage() } else { None };
+        let span = match label {
+            Some(label) if !config.dry_run => crate::trace::span("timeit", || label.to_string()),
+            _ => None,
+        };
+        TimeIt {
+            config,
+            start: Instant::now(),
+            usage,
+            _span: span,
+            label: label.map(str::to_string),
+            depth,
+            out,
+        }
+    }
 }
 
-impl Drop for TimeIt {
+impl<W: Write> Drop for TimeIt<'_, W> {
     fn drop(&mut self) {
-        let time = self.1.elapsed();
-        if !self.0 {
-            println!("\tfinished in {}.{:03} seconds", time.as_secs(), time.subsec_millis());
+        // Restored first, so that a panic while printing can't leave the
+        // following scopes indented.
+        TIMEIT_DEPTH.with(|depth| depth.set(depth.get() - 1));
+        let elapsed = self.start.elapsed();
+        if self.config.dry_run
+            || (elapsed < self.config.timeit_threshold && self.config.verbose < 2)
+        {
+            return;
+        }
+        let mut line = timeit_line(self.label.as_deref(), self.depth, elapsed);
+        if let (Some(start), Some(end)) = (&self.usage, children_usage()) {
+            line += &format!(" ({})", end.since(start));
+        }
+        let _ = writeln!(self.out, "{}", log_text(&line));
+        let _ = self.out.flush();
+    }
+}
+
+thread_local! {
+    static TIMEIT_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The line a `TimeIt` at `depth` prints after `elapsed`.
+fn timeit_line(label: Option<&str>, depth: usize, elapsed: Duration) -> String {
+    let indent = "  ".repeat(depth);
+    match label {
+        Some(label) => format!("{}{}: {:.2}s", indent, label, elapsed.as_secs_f64()),
+        None => format!(
+            "{}finished in {}.{:03} seconds",
+            indent,
+            elapsed.as_secs(),
+            elapsed.subsec_millis()
+        ),
+    }
+}
+
+/// The resources used by the processes bootstrap has spawned, as returned by
+/// `children_usage`, or used between two such snapshots, as returned by
+/// `ResourceUsage::since`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResourceUsage {
+    /// User and system CPU time.
+    pub cpu: Duration,
+    /// The peak resident set size of the largest process, in kilobytes. On
+    /// Windows this is the peak committed memory instead. Between two
+    /// snapshots, this is only known if a new peak was reached.
+    pub max_rss_kb: Option<u64>,
+}
+
+impl ResourceUsage {
+    /// Returns what was used between `start` and `self`, both snapshots.
+    pub fn since(&self, start: &ResourceUsage) -> ResourceUsage {
+        // The peak is a maximum over every process that ever finished, so a
+        // scope whose processes stayed below an earlier peak can't tell what
+        // theirs was.
+        let max_rss_kb = match (self.max_rss_kb, start.max_rss_kb) {
+            (Some(end), Some(start)) if end > start => Some(end),
+            _ => None,
+        };
+        ResourceUsage { cpu: self.cpu.saturating_sub(start.cpu), max_rss_kb }
+    }
+}
+
+impl fmt::Display for ResourceUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cpu {:.2}s", self.cpu.as_secs_f64())?;
+        match self.max_rss_kb {
+            Some(kb) => write!(f, ", max rss {} MB", kb / 1024),
+            None => Ok(()),
         }
     }
 }
 
-/// Symlinks two directories, using junctions on Windows and normal symlinks on
-/// Unix.
-pub fn symlink_dir(config: &Config, src: &Path, dest: &Path) -> io::Result<()> {
+/// Returns the resources used so far by the child processes of bootstrap that
+/// have finished, including their own children, or `None` where that isn't
+/// known.
+#[cfg(unix)]
+pub fn children_usage() -> Option<ResourceUsage> {
+    let usage = unsafe {
+        let mut usage: libc::rusage = std::mem::zeroed();
+        if libc::getrusage(libc::RUSAGE_CHILDREN, &mut usage) != 0 {
+            return None;
+        }
+        usage
+    };
+    let time = |t: libc::timeval| Duration::new(t.tv_sec as u64, t.tv_usec as u32 * 1000);
+    // macOS reports the maxrss in bytes, not kilobytes.
+    let divisor = if cfg!(target_os = "macos") { 1024 } else { 1 };
+    Some(ResourceUsage {
+        cpu: time(usage.ru_utime) + time(usage.ru_stime),
+        max_rss_kb: Some(usage.ru_maxrss as u64 / divisor),
+    })
+}
+
+/// Returns the resources used so far by the child processes of bootstrap that
+/// have finished, including their own children, or `None` where that isn't
+/// known.
+///
+/// `job::setup` puts bootstrap in a job object that every process it spawns
+/// joins, so this is the job's accounting, which also counts bootstrap's own
+/// CPU time.
+#[cfg(windows)]
+pub fn children_usage() -> Option<ResourceUsage> {
+    use std::mem;
+    use std::ptr;
+    use winapi::um::jobapi2::QueryInformationJobObject;
+    use winapi::um::winnt::{
+        JobObjectBasicAccountingInformation, JobObjectExtendedLimitInformation,
+        JOBOBJECT_BASIC_ACCOUNTING_INFORMATION, JOBOBJECT_EXTENDED_LIMIT_INFORMATION,
+    };
+
+    unsafe {
+        // A null handle is the job of the current process.
+        let mut accounting: JOBOBJECT_BASIC_ACCOUNTING_INFORMATION = mem::zeroed();
+        let r = QueryInformationJobObject(
+            ptr::null_mut(),
+            JobObjectBasicAccountingInformation,
+            &mut accounting as *mut _ as *mut _,
+            mem::size_of_val(&accounting) as u32,
+            ptr::null_mut(),
+        );
+        if r == 0 {
+            return None;
+        }
+        let mut limits: JOBOBJECT_EXTENDED_LIMIT_INFORMATION = mem::zeroed();
+        let r = QueryInformationJobObject(
+            ptr::null_mut(),
+            JobObjectExtendedLimitInformation,
+            &mut limits as *mut _ as *mut _,
+            mem::size_of_val(&limits) as u32,
+            ptr::null_mut(),
+        );
+        if r == 0 {
+            return None;
+        }
+        // In units of 100 nanoseconds.
+        let ticks = *accounting.TotalUserTime.QuadPart() + *accounting.TotalKernelTime.QuadPart();
+        Some(ResourceUsage {
+            cpu: Duration::from_nanos(ticks as u64 * 100),
+            max_rss_kb: Some(limits.PeakProcessMemoryUsed as u64 / 1024),
+        })
+    }
+}
+
+/// Returns the resources used so far by the child processes of bootstrap that
+/// have finished, including their own children, or `None` where that isn't
+/// known.
+#[cfg(not(any(unix, windows)))]
+pub fn children_usage() -> Option<ResourceUsage> {
+    None
+}
+
+/// Symlinks two directories. On Windows this creates a real symlink when the
+/// user is allowed to, and a junction otherwise; setting
+/// `RUSTBUILD_WINDOWS_SYMLINKS` to `junction` or `symlink` forces either.
+///
+/// Junctions can't be created on some filesystems, like network shares and
+/// exFAT drives. When linking fails, `src` is copied to `dest` instead if
+/// `copy_fallback` is set and `build.symlink-copy-fallback` isn't disabled.
+/// Pass `false` for links to large trees that are not worth copying.
+///
+/// Nothing is done if `dest` already links to `src`. Other links, empty
+/// directories and copies made by the fallback are replaced. Anything else at
+/// `dest`, like a non-empty directory, is an error unless `overwrite` is set,
+/// in which case it is removed.
+pub fn symlink_dir(
+    config: &Config,
+    src: &Path,
+    dest: &Path,
+    overwrite: bool,
+    copy_fallback: bool,
+) -> io::Result<()> {
+    symlink_dir_to(config, src, src, dest, overwrite, copy_fallback)
+}
+
+/// Like `symlink_dir`, but the link refers to `src` relative to the directory
+/// containing `dest`, so it keeps working when a tree containing both is moved,
+/// for example into a Docker container or an artifact. Use this for links
+/// within the build directory.
+///
+/// Junctions must be absolute, so on Windows this is the same as `symlink_dir`.
+pub fn symlink_dir_relative(
+    config: &Config,
+    src: &Path,
+    dest: &Path,
+    overwrite: bool,
+) -> io::Result<()> {
+    let base = dest.parent().unwrap_or_else(|| Path::new(""));
+    match path_relative_from(base, src) {
+        Some(relative) if !cfg!(windows) => {
+            symlink_dir_to(config, src, &relative, dest, overwrite, true)
+        }
+        _ => symlink_dir(config, src, dest, overwrite, true),
+    }
+}
+
+/// Links `dest` to `src`, writing `target` into the link. `src` is used to
+/// copy the directory if linking fails.
+fn symlink_dir_to(
+    config: &Config,
+    src: &Path,
+    target: &Path,
+    dest: &Path,
+    overwrite: bool,
+    copy_fallback: bool,
+) -> io::Result<()> {
     if config.dry_run {
         return Ok(());
     }
-    let _ = fs::remove_dir(dest);
-    return symlink_dir_inner(src, dest);
+    let fallback = copy_fallback && config.symlink_copy_fallback;
+    return symlink_dir_or_copy(src, target, dest, fallback, overwrite, &symlink_dir_inner);
 
     #[cfg(not(windows))]
     fn symlink_dir_inner(src: &Path, dest: &Path) -> io::Result<()> {
@@ -130,13 +1180,40 @@ fn symlink_dir_inner(src: &Path, dest: &Path) -> io::Result<()> {
         fs::symlink(src, dest)
     }
 
+    // Real symlinks can also point at files and work better over SMB, but
+    // creating them needs either elevation or Developer Mode. The first link
+    // probes for that, and if it isn't available every link is a junction.
+    #[cfg(windows)]
+    fn symlink_dir_inner(target: &Path, dest: &Path) -> io::Result<()> {
+        // `std` passes `SYMBOLIC_LINK_FLAG_ALLOW_UNPRIVILEGED_CREATE` to
+        // `CreateSymbolicLinkW`, which is what Developer Mode allows.
+        const ERROR_PRIVILEGE_NOT_HELD: i32 = 1314;
+
+        let mut kind = WINDOWS_DIR_LINK.lock().unwrap_or_else(|e| e.into_inner());
+        match *kind {
+            Some(WindowsDirLink::Junction) => create_junction(target, dest),
+            Some(WindowsDirLink::Symlink) => std::os::windows::fs::symlink_dir(target, dest),
+            None => match std::os::windows::fs::symlink_dir(target, dest) {
+                Ok(()) => {
+                    *kind = Some(WindowsDirLink::Symlink);
+                    Ok(())
+                }
+                Err(e) if e.raw_os_error() == Some(ERROR_PRIVILEGE_NOT_HELD) => {
+                    *kind = Some(WindowsDirLink::Junction);
+                    create_junction(target, dest)
+                }
+                Err(e) => Err(e),
+            },
+        }
+    }
+
     // Creating a directory junction on windows involves dealing with reparse
     // points and the DeviceIoControl function, and this code is a skeleton of
     // what can be found here:
     //
     // http://www.flexhex.com/docs/articles/hard-links.phtml
     #[cfg(windows)]
-    fn symlink_dir_inner(target: &Path, junction: &Path) -> io::Result<()> {
+    fn create_junction(target: &Path, junction: &Path) -> io::Result<()> {
         use std::ffi::OsStr;
         use std::os::windows::ffi::OsStrExt;
         use std::ptr;
@@ -225,6 +1302,458 @@ fn to_u16s<S: AsRef<OsStr>>(s: S) -> io::Result<Vec<u16>> {
     }
 }
 
+/// How `symlink_dir` links directories on Windows.
+#[cfg(windows)]
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+enum WindowsDirLink {
+    Junction,
+    Symlink,
+}
+
+/// The kind of link `symlink_dir` creates on Windows. Set from
+/// `RUSTBUILD_WINDOWS_SYMLINKS`, or else by whether the first attempt to
+/// create a symlink is allowed.
+#[cfg(windows)]
+static WINDOWS_DIR_LINK: Lazy<Mutex<Option<WindowsDirLink>>> = Lazy::new(|| {
+    let kind = env::var("RUSTBUILD_WINDOWS_SYMLINKS").ok().map(|value| {
+        match &*value.to_lowercase() {
+            "junction" => WindowsDirLink::Junction,
+            "symlink" => WindowsDirLink::Symlink,
+            // Let's make sure typos don't go unnoticed
+            _ => panic!(
+                "Unrecognized option '{}' set in RUSTBUILD_WINDOWS_SYMLINKS, \
+                 expected one of: junction, symlink",
+                value
+            ),
+        }
+    });
+    Mutex::new(kind)
+});
+
+/// Returns the path which, resolved relative to `base`, refers to `target`.
+/// Both paths must be absolute, or both relative to the same directory; this
+/// doesn't touch the filesystem, so symlinks in `base` aren't accounted for.
+/// Returns `None` if there is no such path, like for targets on another drive,
+/// and `.` if `target` is `base`.
+pub fn path_relative_from(base: &Path, target: &Path) -> Option<PathBuf> {
+    use std::path::Component;
+
+    if base.is_absolute() != target.is_absolute() {
+        return None;
+    }
+    let mut base = base.components().peekable();
+    let mut target = target.components().peekable();
+    // Skip the common prefix.
+    while let (Some(a), Some(b)) = (base.peek(), target.peek()) {
+        if a != b {
+            break;
+        }
+        base.next();
+        target.next();
+    }
+    let mut relative = PathBuf::new();
+    for component in base {
+        match component {
+            Component::Normal(_) => relative.push(".."),
+            Component::CurDir => {}
+            // Where `..` leads, or which drive or root a path is on, can't be
+            // known without the filesystem.
+            _ => return None,
+        }
+    }
+    for component in target {
+        match component {
+            Component::Normal(_) | Component::ParentDir => relative.push(component),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    if relative.as_os_str().is_empty() {
+        relative.push(".");
+    }
+    Some(relative)
+}
+
+/// Creates a link to, or copy of, the first path at the second.
+type LinkFn<'a> = dyn Fn(&Path, &Path) -> io::Result<()> + 'a;
+
+/// How `symlink_file` or `hardlink_file` exposed a file at another path.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LinkKind {
+    Symlink,
+    HardLink,
+    Copy,
+}
+
+/// Exposes the file `src` at `dest`, replacing whatever `dest` was (including a
+/// stale symlink). Tries a symlink first, which on Windows only works with
+/// Developer Mode enabled or elevated privileges, then a hard link, and
+/// finally copies the file. Returns the mechanism that was used; in a dry run
+/// nothing happens and `LinkKind::Symlink` is returned.
+pub fn symlink_file(config: &Config, src: &Path, dest: &Path) -> io::Result<LinkKind> {
+    #[cfg(unix)]
+    use std::os::unix::fs::symlink as symlink_file_inner;
+    #[cfg(windows)]
+    use std::os::windows::fs::symlink_file as symlink_file_inner;
+
+    if config.dry_run {
+        return Ok(LinkKind::Symlink);
+    }
+    link_file_with(
+        src,
+        dest,
+        &[
+            (LinkKind::Symlink, &|src, dest| symlink_file_inner(src, dest)),
+            (LinkKind::HardLink, &|src, dest| fs::hard_link(src, dest)),
+            (LinkKind::Copy, &copy_file),
+        ],
+    )
+}
+
+/// Like `symlink_file`, but never creates a symlink. Use this for files that
+/// end up in an archive, where a link pointing back into the build would
+/// dangle.
+pub fn hardlink_file(config: &Config, src: &Path, dest: &Path) -> io::Result<LinkKind> {
+    if config.dry_run {
+        return Ok(LinkKind::HardLink);
+    }
+    link_file_with(
+        src,
+        dest,
+        &[
+            (LinkKind::HardLink, &|src, dest| fs::hard_link(src, dest)),
+            (LinkKind::Copy, &copy_file),
+        ],
+    )
+}
+
+fn copy_file(src: &Path, dest: &Path) -> io::Result<()> {
+    fs::copy(src, dest).map(drop)
+}
+
+/// Tries each way of exposing `src` at `dest` in turn, returning the first
+/// that worked or the error of the last one.
+fn link_file_with(
+    src: &Path,
+    dest: &Path,
+    attempts: &[(LinkKind, &LinkFn<'_>)],
+) -> io::Result<LinkKind> {
+    if dest.symlink_metadata().is_ok() {
+        fs::remove_file(dest)?;
+    }
+    let mut last_err = None;
+    for (kind, link) in attempts {
+        match link(src, dest) {
+            Ok(()) => return Ok(*kind),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.expect("no way to link files given"))
+}
+
+/// Whether `symlink_dir` already warned about falling back to a copy.
+static WARNED_SYMLINK_COPY: AtomicBool = AtomicBool::new(false);
+
+/// The marker recording that `dest` is a copy made by `symlink_dir_or_copy`
+/// rather than a link.
+fn symlink_copy_marker(dest: &Path) -> PathBuf {
+    let mut name = dest.file_name().expect("link destination has no name").to_os_string();
+    name.push(".copy");
+    dest.with_file_name(name)
+}
+
+/// Links `dest` to `target` with `link`, copying `src` to `dest` instead if that
+/// fails and `copy_fallback` is set. See `symlink_dir` for how an existing
+/// `dest` is handled.
+fn symlink_dir_or_copy(
+    src: &Path,
+    target: &Path,
+    dest: &Path,
+    copy_fallback: bool,
+    overwrite: bool,
+    link: &LinkFn<'_>,
+) -> io::Result<()> {
+    // A copy doesn't follow changes to `src` like a link would, so replace it
+    // on every build.
+    let marker = symlink_copy_marker(dest);
+    if marker.exists() {
+        if dest.exists() {
+            fs::remove_dir_all(dest)?;
+        }
+        fs::remove_file(&marker)?;
+    }
+    if links_to(dest, target) {
+        return Ok(());
+    }
+    clear_link_dest(dest, target, overwrite)?;
+    let err = match link(target, dest) {
+        Ok(()) => return Ok(()),
+        Err(e) if copy_fallback => e,
+        Err(e) => return Err(e),
+    };
+    if !WARNED_SYMLINK_COPY.swap(true, Ordering::Relaxed) {
+        eprintln!(
+            "warning: linking `{}` to `{}` failed with {}; copying instead \
+             (set `build.symlink-copy-fallback = false` to make this an error)",
+            dest.display(),
+            src.display(),
+            err,
+        );
+    }
+    let _ = fs::remove_dir_all(dest);
+    copy_tree(src, dest, &CopyOpts::default())?;
+    fs::write(&marker, src.to_string_lossy().as_bytes())
+}
+
+/// Whether `dest` is a symlink or junction whose target is `target`.
+fn links_to(dest: &Path, target: &Path) -> bool {
+    match fs::read_link(dest) {
+        Ok(link) => {
+            // Junctions are created from, and read back as, the canonical path.
+            link == target || (cfg!(windows) && fs::canonicalize(target).ok() == Some(link))
+        }
+        Err(_) => false,
+    }
+}
+
+/// Removes whatever is at `dest` so a link to `target` can be created there,
+/// refusing to remove anything but links and empty directories unless
+/// `overwrite` is set.
+fn clear_link_dest(dest: &Path, target: &Path, overwrite: bool) -> io::Result<()> {
+    let metadata = match fs::symlink_metadata(dest) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    // On Windows junctions and directory symlinks are directories that are
+    // also reported as symlinks, and are removed with `remove_dir` without
+    // touching what they point to. Check for them before ordinary directories,
+    // which would otherwise be removed along with their contents.
+    if metadata.file_type().is_symlink() {
+        return fs::remove_file(dest).or_else(|_| fs::remove_dir(dest));
+    }
+    let is_dir = metadata.is_dir();
+    if is_dir && fs::read_dir(dest)?.next().is_none() {
+        return fs::remove_dir(dest);
+    }
+    if !overwrite {
+        let what = if is_dir { "a non-empty directory" } else { "a file" };
+        let msg = format!(
+            "can't link `{}` to `{}`: it already exists and is {}; remove it and try again",
+            dest.display(),
+            target.display(),
+            what,
+        );
+        return Err(io::Error::new(io::ErrorKind::AlreadyExists, msg));
+    }
+    if is_dir { fs::remove_dir_all(dest) } else { fs::remove_file(dest) }
+}
+
+/// Removes the symlinks and junctions under `dir` whose targets don't exist,
+/// such as links to binaries of a stage that was since rebuilt differently,
+/// and returns their paths. Links to directories aren't walked into. Nothing
+/// is removed if `dir` doesn't exist.
+pub fn prune_dangling_links(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut removed = Vec::new();
+    match prune_dangling_links_inner(dir, &mut removed) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !dir.exists() => Ok(removed),
+        result => result.map(|()| removed),
+    }
+}
+
+fn prune_dangling_links_inner(dir: &Path, removed: &mut Vec<PathBuf>) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let metadata = fs::symlink_metadata(&path)?;
+        if is_link(&metadata) {
+            if is_dangling(&path) {
+                // Junctions are directories.
+                fs::remove_file(&path).or_else(|_| fs::remove_dir(&path))?;
+                removed.push(path);
+            }
+        } else if metadata.is_dir() {
+            prune_dangling_links_inner(&path, removed)?;
+        }
+    }
+    Ok(())
+}
+
+/// Whether following the link at `path` leads nowhere: to nothing, or in a
+/// loop. Other errors, like a permission denied on the way to the target, say
+/// nothing about whether it exists.
+fn is_dangling(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => true,
+        #[cfg(unix)]
+        Err(e) if e.raw_os_error() == Some(libc::ELOOP) => true,
+        _ => false,
+    }
+}
+
+/// Whether `metadata`, from `fs::symlink_metadata`, is that of a link.
+fn is_link(metadata: &fs::Metadata) -> bool {
+    // `read_link` and `is_symlink` only recognize some kinds of reparse points,
+    // but any of them, like junctions, can be left dangling.
+    #[cfg(windows)]
+    {
+        use std::os::windows::fs::MetadataExt;
+        const FILE_ATTRIBUTE_REPARSE_POINT: u32 = 0x400;
+        metadata.file_attributes() & FILE_ATTRIBUTE_REPARSE_POINT != 0
+    }
+    #[cfg(not(windows))]
+    metadata.file_type().is_symlink()
+}
+
+/// How `copy_recursive` copies a directory.
+pub struct CopyOpts<'a> {
+    /// Whether to copy what symlinks point to, or the links themselves.
+    pub links: FollowLinks,
+    /// Called with the path of each file and directory relative to the source;
+    /// those for which it returns `false` are skipped, and removed from the
+    /// destination if an earlier copy left them there.
+    pub filter: Option<&'a dyn Fn(&Path) -> bool>,
+    /// Whether copies get the modification times of the originals, rather than
+    /// the current time. Permissions are always preserved.
+    pub preserve_mtime: bool,
+    /// If set, files are hard linked rather than copied where the filesystem
+    /// allows it, so staging directories don't duplicate every file. Writing
+    /// to a linked file would modify the original as well, so this is called
+    /// with the path of each file relative to the source, and those that will
+    /// be modified afterwards, for which it returns `true`, are always copied.
+    pub hard_link: Option<&'a dyn Fn(&Path) -> bool>,
+    /// Whether entries of the destination that don't exist in the source, such
+    /// as files deleted since an earlier copy, are removed.
+    pub prune: bool,
+}
+
+impl Default for CopyOpts<'_> {
+    fn default() -> Self {
+        CopyOpts {
+            links: FollowLinks::Yes,
+            filter: None,
+            preserve_mtime: true,
+            hard_link: None,
+            prune: false,
+        }
+    }
+}
+
+/// Copies the directory `src` to `dest`, creating `dest` and its parents if
+/// needed and replacing files that already exist. If `dest` is inside `src` it
+/// is skipped rather than copied into itself. Returns the number of files
+/// copied, which is zero in a dry run.
+pub fn copy_recursive(
+    config: &Config,
+    src: &Path,
+    dest: &Path,
+    opts: &CopyOpts<'_>,
+) -> io::Result<usize> {
+    if config.dry_run {
+        return Ok(0);
+    }
+    copy_tree(src, dest, opts)
+}
+
+fn copy_tree(src: &Path, dest: &Path, opts: &CopyOpts<'_>) -> io::Result<usize> {
+    let skip = dest.canonicalize().ok();
+    fs::create_dir_all(dest)?;
+    let skip = skip.or_else(|| dest.canonicalize().ok());
+    copy_tree_inner(src, dest, Path::new(""), opts, skip.as_deref())
+}
+
+fn copy_tree_inner(
+    src: &Path,
+    dest: &Path,
+    relative: &Path,
+    opts: &CopyOpts<'_>,
+    skip: Option<&Path>,
+) -> io::Result<usize> {
+    let mut copied = 0;
+    let mut names = HashSet::new();
+    for entry in fs::read_dir(src)? {
+        let path = entry?.path();
+        let name = path.file_name().unwrap();
+        names.insert(name.to_owned());
+        let (target, relative) = (dest.join(name), relative.join(name));
+        match opts.filter {
+            Some(filter) if !filter(&relative) => {
+                remove_entry(&target)?;
+                continue;
+            }
+            _ => {}
+        }
+        let metadata = match opts.links {
+            FollowLinks::Yes => fs::metadata(&path)?,
+            FollowLinks::No => fs::symlink_metadata(&path)?,
+        };
+        if metadata.file_type().is_symlink() {
+            let _ = fs::remove_file(&target);
+            copy_link(&path, &target)?;
+            copied += 1;
+            continue;
+        }
+        if metadata.is_dir() {
+            if skip.is_some() && path.canonicalize().ok().as_deref() == skip {
+                continue;
+            }
+            fs::create_dir_all(&target)?;
+            copied += copy_tree_inner(&path, &target, &relative, opts, skip)?;
+        } else {
+            // Don't write through a link left at the destination.
+            let _ = fs::remove_file(&target);
+            copied += 1;
+            // A hard link already shares the permissions and times of `path`.
+            if let Some(modified) = opts.hard_link {
+                if !modified(&relative) && fs::hard_link(&path, &target).is_ok() {
+                    continue;
+                }
+            }
+            fs::copy(&path, &target)?;
+        }
+        // Set after copying, as filling a directory changes its mtime.
+        fs::set_permissions(&target, metadata.permissions())?;
+        if opts.preserve_mtime {
+            let mtime = FileTime::from_last_modification_time(&metadata);
+            filetime::set_file_mtime(&target, mtime)?;
+        }
+    }
+    if opts.prune {
+        for entry in fs::read_dir(dest)? {
+            let entry = entry?;
+            if !names.contains(&entry.file_name()) {
+                remove_entry(&entry.path())?;
+            }
+        }
+    }
+    if opts.preserve_mtime {
+        let metadata = fs::metadata(src)?;
+        filetime::set_file_mtime(dest, FileTime::from_last_modification_time(&metadata))?;
+    }
+    Ok(copied)
+}
+
+/// Removes the file, link or directory at `path`, if there is one.
+fn remove_entry(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(m) if m.is_dir() => remove_dir_all_retrying(path),
+        Ok(_) => fs::remove_file(path),
+        Err(_) => Ok(()),
+    }
+}
+
+/// Creates a symlink at `dest` pointing where the symlink `src` does.
+fn copy_link(src: &Path, dest: &Path) -> io::Result<()> {
+    let link = fs::read_link(src)?;
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(link, dest);
+    #[cfg(windows)]
+    return if src.is_dir() {
+        std::os::windows::fs::symlink_dir(link, dest)
+    } else {
+        std::os::windows::fs::symlink_file(link, dest)
+    };
+}
+
 /// The CI environment rustbuild is running in. This mainly affects how the logs
 /// are printed.
 #[derive(Copy, Clone, PartialEq, Eq, Debug)]
@@ -235,15 +1764,96 @@ pub enum CiEnv {
     AzurePipelines,
     /// The GitHub Actions environment, for Linux (including Docker), Windows and macOS builds.
     GitHubActions,
+    /// GitLab CI/CD.
+    GitLab,
+    /// CircleCI.
+    CircleCi,
+    /// Buildkite.
+    Buildkite,
+    /// JetBrains TeamCity.
+    TeamCity,
+    /// Some other CI provider that follows the common `CI=true` convention.
+    Generic,
 }
 
+/// The CI environment for this run, fixed the first time it is asked for.
+static CI_ENV: OnceCell<CiEnv> = OnceCell::new();
+
+/// Accepted values of `RUSTBUILD_CI_ENV` and `build.ci-env`.
+const CI_ENV_NAMES: &[(&str, CiEnv)] = &[
+    ("github", CiEnv::GitHubActions),
+    ("azure", CiEnv::AzurePipelines),
+    ("gitlab", CiEnv::GitLab),
+    ("circleci", CiEnv::CircleCi),
+    ("buildkite", CiEnv::Buildkite),
+    ("teamcity", CiEnv::TeamCity),
+    ("generic", CiEnv::Generic),
+    ("none", CiEnv::None),
+];
+
 impl CiEnv {
     /// Obtains the current CI environment.
+    ///
+    /// `RUSTBUILD_CI_ENV` overrides `build.ci-env`, which overrides detection
+    /// from the environment. The result is cached for the rest of the run.
     pub fn current() -> CiEnv {
-        if env::var("TF_BUILD").map_or(false, |e| e == "True") {
+        *CI_ENV.get_or_init(|| CiEnv::resolve(env::var("RUSTBUILD_CI_ENV").ok().as_deref(), None))
+    }
+
+    /// Fixes the CI environment for this run, taking `build.ci-env` into
+    /// account. Has no effect if `current` was already called.
+    pub fn init(config: Option<&str>) {
+        let _ = CI_ENV
+            .get_or_init(|| CiEnv::resolve(env::var("RUSTBUILD_CI_ENV").ok().as_deref(), config));
+    }
+
+    /// Picks the CI environment from the `RUSTBUILD_CI_ENV` value, then the
+    /// `build.ci-env` value, and only then the usual environment variables.
+    pub fn resolve(env_override: Option<&str>, config: Option<&str>) -> CiEnv {
+        if let Some(value) = env_override {
+            CiEnv::from_override(value, "RUSTBUILD_CI_ENV")
+        } else if let Some(value) = config {
+            CiEnv::from_override(value, "build.ci-env")
+        } else {
+            CiEnv::detect()
+        }
+    }
+
+    fn from_override(value: &str, source: &str) -> CiEnv {
+        let value = value.to_lowercase();
+        match CI_ENV_NAMES.iter().find(|(name, _)| *name == value) {
+            Some(&(_, ci_env)) => ci_env,
+            None => {
+                // Let's make sure typos don't go unnoticed
+                let names: Vec<_> = CI_ENV_NAMES.iter().map(|(name, _)| *name).collect();
+                panic!(
+                    "Unrecognized option '{}' set in {}, expected one of: {}",
+                    value,
+                    source,
+                    names.join(", ")
+                )
+            }
+        }
+    }
+
+    /// Detects the CI environment from the variables the providers set,
+    /// ignoring any override.
+    pub fn detect() -> CiEnv {
+        let is = |var: &str, value: &str| env::var(var).map_or(false, |e| e == value);
+        if is("TF_BUILD", "True") {
             CiEnv::AzurePipelines
-        } else if env::var("GITHUB_ACTIONS").map_or(false, |e| e == "true") {
+        } else if is("GITHUB_ACTIONS", "true") {
             CiEnv::GitHubActions
+        } else if is("GITLAB_CI", "true") {
+            CiEnv::GitLab
+        } else if is("CIRCLECI", "true") {
+            CiEnv::CircleCi
+        } else if is("BUILDKITE", "true") {
+            CiEnv::Buildkite
+        } else if env::var_os("TEAMCITY_VERSION").is_some() {
+            CiEnv::TeamCity
+        } else if is("CI", "true") {
+            CiEnv::Generic
         } else {
             CiEnv::None
         }
@@ -260,6 +1870,221 @@ pub fn force_coloring_in_ci(self, cmd: &mut Command) {
             cmd.env("TERM", "xterm").args(&["--color", "always"]);
         }
     }
+
+    /// Prints `msg` as a warning or error annotation, which Azure Pipelines,
+    /// GitHub Actions and TeamCity show in the summary of the run.
+    pub fn annotate(self, level: Level, msg: &str) {
+        log_line(&self.annotation(level, msg));
+    }
+
+    /// The line `annotate` prints. Outside of Azure Pipelines, GitHub Actions
+    /// and TeamCity this is just `msg` prefixed with the level.
+    pub fn annotation(self, level: Level, msg: &str) -> String {
+        let name = match level {
+            Level::Warning => "warning",
+            Level::Error => "error",
+        };
+        match (self, level) {
+            (CiEnv::AzurePipelines, _) => {
+                format!("##vso[task.logissue type={}]{}", name, escape_vso(msg))
+            }
+            (CiEnv::GitHubActions, _) => format!("::{}::{}", name, escape_github(msg)),
+            (CiEnv::TeamCity, Level::Error) => CiEnv::build_problem(msg),
+            (CiEnv::TeamCity, Level::Warning) => {
+                format!("##teamcity[message text='{}' status='WARNING']", escape_teamcity(msg))
+            }
+            _ => format!("{}: {}", name, msg),
+        }
+    }
+
+    /// The TeamCity service message opening a block of the log named `name`.
+    pub fn block_open(name: &str) -> String {
+        format!("##teamcity[blockOpened name='{}']", escape_teamcity(name))
+    }
+
+    /// The TeamCity service message closing the block opened by `block_open`.
+    pub fn block_close(name: &str) -> String {
+        format!("##teamcity[blockClosed name='{}']", escape_teamcity(name))
+    }
+
+    /// The TeamCity service message failing the build with `description`.
+    pub fn build_problem(description: &str) -> String {
+        format!("##teamcity[buildProblem description='{}']", escape_teamcity(description))
+    }
+
+    /// Starts a collapsible section of the log named `name`, which ends when
+    /// the returned guard is dropped.
+    ///
+    /// Only GitHub Actions, Azure Pipelines and TeamCity support this. GitHub
+    /// can't nest sections, so groups opened while another one is open print
+    /// nothing there; TeamCity shows nested blocks as such.
+    pub fn group(self, name: &str) -> GroupGuard {
+        self.group_to(name, io::stdout())
+    }
+
+    /// Like `group`, but writes the markers to `out` instead of stdout.
+    pub fn group_to<W: Write>(self, name: &str, mut out: W) -> GroupGuard<W> {
+        let depth = GROUP_DEPTH.with(|depth| {
+            depth.set(depth.get() + 1);
+            depth.get()
+        });
+        let markers = match self {
+            CiEnv::GitHubActions if depth == 1 => {
+                Some((format!("::group::{}", name), "::endgroup::".to_string()))
+            }
+            CiEnv::AzurePipelines if depth == 1 => {
+                Some((format!("##[group]{}", name), "##[endgroup]".to_string()))
+            }
+            CiEnv::TeamCity => Some((CiEnv::block_open(name), CiEnv::block_close(name))),
+            _ => None,
+        };
+        let close = markers.map(|(open, close)| {
+            let _ = writeln!(out, "{}", open);
+            let _ = out.flush();
+            close
+        });
+        GroupGuard { out, close }
+    }
+
+    /// Appends `markdown` to the job summary shown on the page of the run, if
+    /// this is GitHub Actions. See `append_step_summary`.
+    pub fn step_summary(self, markdown: &str) {
+        if self == CiEnv::GitHubActions {
+            if let Some(path) = env::var_os("GITHUB_STEP_SUMMARY") {
+                append_step_summary(Path::new(&path), markdown);
+            }
+        }
+    }
+}
+
+/// GitHub Actions refuses to show job summaries larger than this.
+pub const STEP_SUMMARY_LIMIT: u64 = 1024 * 1024;
+
+/// Appends `markdown` to the step summary file at `path`, unless that would
+/// make it larger than `STEP_SUMMARY_LIMIT`. The summary isn't worth failing
+/// the build over, so this only warns if it can't be written. Returns whether
+/// it was.
+pub fn append_step_summary(path: &Path, markdown: &str) -> bool {
+    let len = fs::metadata(path).map_or(0, |m| m.len());
+    if len + markdown.len() as u64 > STEP_SUMMARY_LIMIT {
+        eprintln!(
+            "warning: not adding to the step summary in {}, it would grow past {} bytes",
+            path.display(),
+            STEP_SUMMARY_LIMIT
+        );
+        return false;
+    }
+    let written = fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(markdown.as_bytes()));
+    if let Err(e) = written {
+        eprintln!("warning: failed to write the step summary to {}: {}", path.display(), e);
+        return false;
+    }
+    true
+}
+
+/// The step summary section of a build that failed after `command` did, if
+/// a command is to blame.
+pub fn failure_summary(command: Option<&str>) -> String {
+    // Commands can be very long, and the summary has a size limit.
+    const MAX_COMMAND_LEN: usize = 2000;
+    let mut out = String::from("### Build failed\n\n");
+    match command {
+        Some(command) => {
+            let mut end = command.len().min(MAX_COMMAND_LEN);
+            while !command.is_char_boundary(end) {
+                end -= 1;
+            }
+            let ellipsis = if end < command.len() { " ..." } else { "" };
+            out += &format!(
+                "The last command that failed was:\n\n```text\n{}{}\n```\n",
+                &command[..end],
+                ellipsis
+            );
+        }
+        None => out += "See the log for the error.\n",
+    }
+    out
+}
+
+/// The last command run through `try_run` or `try_run_suppressed` that failed,
+/// for the step summary of a failed build.
+static LAST_FAILED_COMMAND: Lazy<Mutex<Option<String>>> = Lazy::new(|| Mutex::new(None));
+
+fn record_failed_command(cmd: &Command) {
+    *LAST_FAILED_COMMAND.lock().unwrap_or_else(|e| e.into_inner()) = Some(format!("{:?}", cmd));
+}
+
+/// The severity of a `CiEnv::annotate` message.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum Level {
+    Warning,
+    Error,
+}
+
+/// Escapes the message of a `##vso[...]` logging command, which ends at the
+/// first newline and uses `;` and `]` to delimit its properties.
+fn escape_vso(msg: &str) -> String {
+    msg.replace('%', "%AZP25")
+        .replace(';', "%3B")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+        .replace(']', "%5D")
+}
+
+/// Escapes a value of a TeamCity `##teamcity[...]` service message, where `|`
+/// is the escape character.
+fn escape_teamcity(msg: &str) -> String {
+    msg.replace('|', "||")
+        .replace('\'', "|'")
+        .replace('[', "|[")
+        .replace(']', "|]")
+        .replace('\n', "|n")
+        .replace('\r', "|r")
+}
+
+/// Escapes the message of a GitHub Actions `::command::` line.
+fn escape_github(msg: &str) -> String {
+    msg.replace('%', "%25").replace('\r', "%0D").replace('\n', "%0A")
+}
+
+thread_local! {
+    static GROUP_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A collapsible section of the CI log, closed on drop (including while
+/// unwinding from a failed step). See `CiEnv::group`.
+pub struct GroupGuard<W: Write = io::Stdout> {
+    out: W,
+    /// The line ending the group, if its start was printed.
+    close: Option<String>,
+}
+
+impl<W: Write> Drop for GroupGuard<W> {
+    fn drop(&mut self) {
+        GROUP_DEPTH.with(|depth| depth.set(depth.get() - 1));
+        if let Some(close) = &self.close {
+            let _ = writeln!(self.out, "{}", close);
+            let _ = self.out.flush();
+        }
+    }
+}
+
+/// Exits the process with `code`, first writing the metrics and the trace of
+/// the running build so that failed builds still record the steps they
+/// executed, and on GitHub Actions a step summary naming the command that
+/// failed.
+pub fn exit(code: i32) -> ! {
+    crate::metrics::persist_active();
+    crate::trace::persist_active();
+    if code != 0 {
+        let command = LAST_FAILED_COMMAND.lock().unwrap_or_else(|e| e.into_inner()).take();
+        CiEnv::current().step_summary(&failure_summary(command.as_deref()));
+    }
+    std::process::exit(code)
 }
 
 pub fn forcing_clang_based_tests() -> bool {
@@ -284,12 +2109,14 @@ pub fn forcing_clang_based_tests() -> bool {
 pub fn use_host_linker(target: TargetSelection) -> bool {
     // FIXME: this information should be gotten by checking the linker flavor
     // of the rustc target
-    !(target.contains("emscripten")
+    let by_triple = !(target.contains("emscripten")
         || target.contains("wasm32")
         || target.contains("nvptx")
         || target.contains("fortanix")
         || target.contains("fuchsia")
-        || target.contains("bpf"))
+        || target.contains("bpf"));
+    // Custom targets don't necessarily name their special linker.
+    by_triple && !matches!(target_spec(target), Some(spec) if spec.uses_special_linker())
 }
 
 pub fn is_valid_test_suite_arg<'a, P: AsRef<Path>>(
@@ -309,7 +2136,7 @@ pub fn is_valid_test_suite_arg<'a, P: AsRef<Path>>(
     let exists = abs_path.is_dir() || abs_path.is_file();
     if !exists {
         if let Some(p) = abs_path.to_str() {
-            builder.info(&format!("Warning: Skipping \"{}\": not a regular file or directory", p));
+            builder.warn(&format!("Skipping \"{}\": not a regular file or directory", p));
         }
         return None;
     }
@@ -327,63 +2154,289 @@ pub fn is_valid_test_suite_arg<'a, P: AsRef<Path>>(
 
 pub fn run(cmd: &mut Command, print_cmd_on_fail: bool) {
     if !try_run(cmd, print_cmd_on_fail) {
-        std::process::exit(1);
+        exit(1);
     }
 }
 
 pub fn try_run(cmd: &mut Command, print_cmd_on_fail: bool) -> bool {
+    let span = trace_command(cmd);
     let status = match cmd.status() {
         Ok(status) => status,
-        Err(e) => fail(&format!("failed to execute command: {:?}\nerror: {}", cmd, e)),
+        Err(e) => {
+            record_failed_command(cmd);
+            fail(&format!("failed to execute command: {:?}\nerror: {}", cmd, e))
+        }
     };
-    if !status.success() && print_cmd_on_fail {
-        println!(
-            "\n\ncommand did not execute successfully: {:?}\n\
-             expected success, got: {}\n\n",
+    if !status.success() {
+        record_failed_command(cmd);
+    }
+    // A missing DLL is worth explaining even when the command isn't printed,
+    // as the tool itself can't say anything about it.
+    let hint = if status.success() { None } else { dll_load_failure_hint(cmd, status) };
+    if !status.success() && (print_cmd_on_fail || hint.is_some()) {
+        let mut msg = format!(
+            "command did not execute successfully: {:?}\n\
+             expected success, got: {}",
             cmd, status
         );
+        if let Some(hint) = hint {
+            msg.push_str(&format!("\n{}", hint));
+        }
+        log_line(&format!("\n\n{}\n\n", CiEnv::current().annotation(Level::Error, &msg)));
     }
+    drop(span);
     status.success()
 }
 
+/// Like `try_run`, but runs `cmd` with the variables in `overrides` set, such
+/// as `MALLOC_ARENA_MAX=1` or `GODEBUG=gctrace=1`, and afterwards puts back
+/// what `cmd` had for them. A variable `cmd` didn't set itself gets the value
+/// it would have inherited from us, or is removed if we don't have it, which
+/// spawns the same way.
+pub fn run_with_env_override(
+    cmd: &mut Command,
+    overrides: &[(&str, &str)],
+    print_cmd_on_fail: bool,
+) -> bool {
+    let saved = overrides
+        .iter()
+        .map(|&(key, _)| {
+            let set = cmd.get_envs().find(|&(k, _)| k == key).map(|(_, v)| v.map(OsStr::to_owned));
+            (key.to_owned(), set.unwrap_or_else(|| env::var_os(key)))
+        })
+        .collect();
+    let guard = RestoreEnv { cmd, saved };
+    guard.cmd.envs(overrides.iter().copied());
+    try_run(guard.cmd, print_cmd_on_fail)
+}
+
+/// Puts back the variables `run_with_env_override` overrode when dropped, so
+/// they are restored even if running the command panics.
+struct RestoreEnv<'a> {
+    cmd: &'a mut Command,
+    saved: Vec<(String, Option<OsString>)>,
+}
+
+impl Drop for RestoreEnv<'_> {
+    fn drop(&mut self) {
+        for (key, value) in self.saved.drain(..) {
+            match value {
+                Some(value) => self.cmd.env(key, value),
+                None => self.cmd.env_remove(key),
+            };
+        }
+    }
+}
+
+/// Starts the trace event of running `cmd`, named after the program.
+fn trace_command(cmd: &Command) -> Option<crate::trace::Span<'static>> {
+    let span = crate::trace::span("command", || {
+        let program = Path::new(cmd.get_program());
+        program.file_name().unwrap_or(program.as_os_str()).to_string_lossy().into_owned()
+    });
+    span.map(|span| span.arg("command", format!("{:?}", cmd)))
+}
+
 pub fn run_suppressed(cmd: &mut Command) {
     if !try_run_suppressed(cmd) {
-        std::process::exit(1);
+        exit(1);
     }
 }
 
 pub fn try_run_suppressed(cmd: &mut Command) -> bool {
+    let span = trace_command(cmd);
     let output = match cmd.output() {
         Ok(status) => status,
-        Err(e) => fail(&format!("failed to execute command: {:?}\nerror: {}", cmd, e)),
+        Err(e) => {
+            record_failed_command(cmd);
+            fail(&format!("failed to execute command: {:?}\nerror: {}", cmd, e))
+        }
     };
+    drop(span);
+    if !output.status.success() {
+        record_failed_command(cmd);
+    }
     if !output.status.success() {
-        println!(
-            "\n\ncommand did not execute successfully: {:?}\n\
+        let mut msg = format!(
+            "command did not execute successfully: {:?}\n\
              expected success, got: {}\n\n\
              stdout ----\n{}\n\
-             stderr ----\n{}\n\n",
+             stderr ----\n{}",
             cmd,
             output.status,
             String::from_utf8_lossy(&output.stdout),
             String::from_utf8_lossy(&output.stderr)
         );
+        if let Some(hint) = dll_load_failure_hint(cmd, output.status) {
+            msg.push_str(&format!("\n{}", hint));
+        }
+        log_line(&format!("\n\n{}\n\n", CiEnv::current().annotation(Level::Error, &msg)));
     }
     output.status.success()
 }
 
+/// Returns the `make` to run on `host`. The BSDs' own `make` doesn't
+/// understand the makefiles we generate, so GNU make is needed there, which
+/// their packages install as `gmake`.
 pub fn make(host: &str) -> PathBuf {
     if host.contains("dragonfly")
         || host.contains("freebsd")
         || host.contains("netbsd")
         || host.contains("openbsd")
     {
-        PathBuf::from("gmake")
+        Finder::new().maybe_have("gmake").unwrap_or_else(|| {
+            fail(&format!(
+                "GNU make is required on {}, but `gmake` wasn't found in PATH; \
+                 install the gmake package",
+                host
+            ))
+        })
     } else {
         PathBuf::from("make")
     }
 }
 
+/// A tool that can strip binaries, as picked by `strip_tool`.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum StripTool {
+    /// The system's `strip`, GNU's or Apple's.
+    Strip(PathBuf),
+    /// `llvm-strip`, which takes the flags of whichever `strip` is usual for
+    /// the binary's format.
+    LlvmStrip(PathBuf),
+    /// `llvm-objcopy`, which is only used to remove debug info.
+    LlvmObjcopy(PathBuf),
+}
+
+impl StripTool {
+    /// Returns a command writing a stripped copy of the `kind` artifact `src`,
+    /// built for `target`, to `dst`.
+    fn command(
+        &self,
+        src: &Path,
+        dst: &Path,
+        kind: ArtifactKind,
+        target: TargetSelection,
+    ) -> Command {
+        match self {
+            StripTool::Strip(path) | StripTool::LlvmStrip(path) => {
+                let mut cmd = Command::new(path);
+                if target.contains("apple") {
+                    // Stripping everything would remove the exported symbols
+                    // other binaries link against, `-x` only removes local ones.
+                    if kind == ArtifactKind::Dylib {
+                        cmd.arg("-x");
+                    }
+                } else if kind == ArtifactKind::Dylib {
+                    cmd.arg("--strip-unneeded");
+                } else {
+                    cmd.arg("--strip-all");
+                }
+                cmd.arg(src).arg("-o").arg(dst);
+                cmd
+            }
+            StripTool::LlvmObjcopy(path) => {
+                let mut cmd = Command::new(path);
+                cmd.arg("--strip-debug").arg(src).arg(dst);
+                cmd
+            }
+        }
+    }
+
+    /// Returns the `objcopy` that goes with this tool, running on `build`, to
+    /// extract debug info with.
+    fn objcopy(&self, build: TargetSelection) -> Option<PathBuf> {
+        match self {
+            StripTool::Strip(_) => Finder::new().maybe_have("objcopy"),
+            StripTool::LlvmStrip(path) => Some(exe(path.with_file_name("llvm-objcopy"), build)),
+            StripTool::LlvmObjcopy(path) => Some(path.clone()),
+        }
+    }
+}
+
+static STRIP_TOOLS: Lazy<Mutex<HashMap<TargetSelection, Option<StripTool>>>> =
+    Lazy::new(Default::default);
+
+/// Finds a tool on the `PATH` that can strip binaries built for `target` on
+/// `build`. The system's `strip` only understands its own platform's
+/// binaries, so other targets need `llvm-strip`, or `llvm-objcopy` to at
+/// least remove debug info. MSVC binaries keep their debug info in `.pdb`
+/// files and are never stripped. The `PATH` is only searched once per target.
+pub fn strip_tool(build: TargetSelection, target: TargetSelection) -> Option<StripTool> {
+    let mut tools = t!(STRIP_TOOLS.lock());
+    tools
+        .entry(target)
+        .or_insert_with(|| {
+            if target.contains("msvc") {
+                return None;
+            }
+            let mut finder = Finder::new();
+            let native = target == build || (target.contains("apple") && build.contains("apple"));
+            let strip =
+                if native { finder.maybe_have("strip").map(StripTool::Strip) } else { None };
+            strip
+                .or_else(|| finder.maybe_have("llvm-strip").map(StripTool::LlvmStrip))
+                .or_else(|| finder.maybe_have("llvm-objcopy").map(StripTool::LlvmObjcopy))
+        })
+        .clone()
+}
+
+/// Strips the symbols and debug info from `path`, a `kind` artifact built for
+/// `target`, in place. Only executables and dylibs are touched: rlibs, rmeta
+/// files and static libraries need their symbols to be linked against, and
+/// scripts have nothing to strip. Nothing happens if no strip tool for
+/// `target` is installed, or under `--dry-run`.
+///
+/// With `rust.debuginfo-packages` the debug info of ELF binaries is first
+/// extracted into a `.debug` file next to `path`, which debuggers find
+/// through the `.gnu_debuglink` section added to the stripped binary.
+///
+/// The stripped binary is renamed over `path`, so other hard links to the
+/// original, like the ones dist makes into the sysroot, keep their symbols.
+pub fn strip_artifact(
+    builder: &Builder<'_>,
+    path: &Path,
+    kind: ArtifactKind,
+    target: TargetSelection,
+) {
+    if strip_file(&builder.config, path, kind, target) {
+        builder.verbose(&format!("stripped {}", path.display()));
+    }
+}
+
+fn strip_file(config: &Config, path: &Path, kind: ArtifactKind, target: TargetSelection) -> bool {
+    if config.dry_run || !matches!(kind, ArtifactKind::Exe | ArtifactKind::Dylib) {
+        return false;
+    }
+    let mut magic = [0; 2];
+    if t!(File::open(path)).read_exact(&mut magic).is_err() || &magic == b"#!" {
+        return false;
+    }
+    let tool = match strip_tool(config.build, target) {
+        Some(tool) => tool,
+        None => return false,
+    };
+
+    let name = path.file_name().unwrap().to_str().unwrap();
+    let stripped = path.with_file_name(format!("{}.stripped", name));
+    let elf = !target.contains("apple") && !target.contains("windows");
+    let objcopy =
+        if config.rust_debuginfo_packages && elf { tool.objcopy(config.build) } else { None };
+    let debug = path.with_file_name(format!("{}.debug", name));
+    if let Some(objcopy) = &objcopy {
+        run(Command::new(objcopy).arg("--only-keep-debug").arg(path).arg(&debug), true);
+    }
+    run(&mut tool.command(path, &stripped, kind, target), true);
+    if let Some(objcopy) = &objcopy {
+        let mut debuglink = OsString::from("--add-gnu-debuglink=");
+        debuglink.push(&debug);
+        run(Command::new(objcopy).arg(debuglink).arg(&stripped), true);
+    }
+    t!(fs::set_permissions(&stripped, t!(fs::metadata(path)).permissions()));
+    t!(fs::rename(&stripped, path));
+    true
+}
+
 #[track_caller]
 pub fn output(cmd: &mut Command) -> String {
     let output = match cmd.stderr(Stdio::inherit()).output() {
@@ -400,45 +2453,807 @@ pub fn output(cmd: &mut Command) -> String {
     String::from_utf8(output.stdout).unwrap()
 }
 
+/// Writes `contents` to `path` without ever leaving it half-written, even if
+/// the build is killed: the contents are written to a temporary file next to
+/// `path`, synced to disk, and then renamed over `path`.
+pub fn write_file_atomic(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    let tmp = path.with_extension(format!("tmp.{}", std::process::id()));
+    let written = File::create(&tmp).and_then(|mut file| {
+        file.write_all(contents.as_ref())?;
+        file.sync_all()
+    });
+    let mut result = written.and_then(|()| fs::rename(&tmp, path));
+    // Windows can't replace a file while another process, like a virus
+    // scanner or the indexer, has it open. They don't hold on to it for long.
+    let mut backoff = Duration::from_millis(10);
+    while cfg!(windows) && backoff < Duration::from_secs(1) {
+        match result {
+            Err(ref e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                std::thread::sleep(backoff);
+                backoff *= 2;
+                result = fs::rename(&tmp, path);
+            }
+            _ => break,
+        }
+    }
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// Like `write_file_atomic`, but leaves `path` untouched, including its
+/// modification time, if it already has these contents, so nothing that
+/// depends on it is rebuilt. Returns whether the file was written.
+///
+/// Don't use this for stamps whose modification time records when something
+/// was last built.
+pub fn write_if_changed(path: &Path, contents: impl AsRef<[u8]>) -> io::Result<bool> {
+    if fs::read(path).ok().as_deref() == Some(contents.as_ref()) {
+        return Ok(false);
+    }
+    write_file_atomic(path, contents)?;
+    Ok(true)
+}
+
+/// Removes the directory `path` and everything in it, succeeding if it
+/// doesn't exist.
+///
+/// On Windows, files can't be removed for a moment after a test binary using
+/// them exits, or while a virus scanner has them open. Each removal is retried
+/// with backoff for a few seconds, read-only files are made writable first,
+/// and errors name the file that couldn't be removed rather than `path`. On
+/// other platforms this is `fs::remove_dir_all`.
+pub fn remove_dir_all_retrying(path: &Path) -> io::Result<()> {
+    let result = if cfg!(windows) { remove_tree_retrying(path) } else { fs::remove_dir_all(path) };
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+fn remove_tree_retrying(path: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(path)?;
+    // Links, including junctions, are removed without touching their target.
+    let result = if metadata.is_dir() && !metadata.file_type().is_symlink() {
+        for entry in fs::read_dir(path)? {
+            remove_tree_retrying(&entry?.path())?;
+        }
+        remove_retrying(path, |p| fs::remove_dir(p))
+    } else {
+        remove_retrying(path, |p| fs::remove_file(p).or_else(|_| fs::remove_dir(p)))
+    };
+    result.map_err(|e| {
+        io::Error::new(e.kind(), format!("failed to remove `{}`: {}", path.display(), e))
+    })
+}
+
+/// Calls `remove` on `path`, treating a missing `path` as removed. On Windows,
+/// where a file can't be removed while a virus scanner or the indexer has it
+/// open, this is retried until it succeeds, backing off for up to a few
+/// seconds, and read-only files are made writable after the first attempt, as
+/// git checks some out that way and Windows refuses to remove them.
+pub fn remove_retrying(
+    path: &Path,
+    mut remove: impl FnMut(&Path) -> io::Result<()>,
+) -> io::Result<()> {
+    let mut backoff = Duration::from_millis(10);
+    loop {
+        let err = match remove(path) {
+            Ok(()) => return Ok(()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => e,
+        };
+        if !cfg!(windows) || backoff > Duration::from_secs(2) {
+            return Err(err);
+        }
+        if err.kind() == io::ErrorKind::PermissionDenied {
+            make_writable(path);
+        }
+        std::thread::sleep(backoff);
+        backoff *= 2;
+    }
+}
+
+/// Makes `path` writable by its owner if it is read-only.
+fn make_writable(path: &Path) {
+    let mut permissions = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata.permissions(),
+        Err(_) => return,
+    };
+    if !permissions.readonly() {
+        return;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        permissions.set_mode(permissions.mode() | 0o200);
+    }
+    #[cfg(windows)]
+    permissions.set_readonly(false);
+    let _ = fs::set_permissions(path, permissions);
+}
+
 /// Returns the last-modified time for `path`, or zero if it doesn't exist.
 pub fn mtime(path: &Path) -> SystemTime {
     fs::metadata(path).and_then(|f| f.modified()).unwrap_or(UNIX_EPOCH)
 }
 
+/// Like `mtime`, but if `path` is a symlink returns the last-modified time of
+/// the link itself rather than of its target.
+pub fn mtime_nofollow(path: &Path) -> SystemTime {
+    fs::symlink_metadata(path).and_then(|f| f.modified()).unwrap_or(UNIX_EPOCH)
+}
+
+/// Whether `up_to_date` resolves a symlinked source to its target.
+///
+/// Symlinks found while walking a directory source are never followed: they
+/// are compared by their own modification time either way.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum FollowLinks {
+    /// Use the target of the link, walking it if it is a directory. A broken
+    /// link counts as very old.
+    Yes,
+    /// Use the modification time of the link itself, e.g. for a vendored
+    /// source tree symlinked from somewhere that is touched all the time.
+    No,
+}
+
 /// Returns `true` if `dst` is up to date given that the file or files in `src`
 /// are used to generate it.
 ///
-/// Uses last-modified time checks to verify this.
-pub fn up_to_date(src: &Path, dst: &Path) -> bool {
+/// Uses last-modified time checks to verify this. Directory sources are walked
+/// on up to `builder.jobs()` threads. In verbose mode the (serial) walk from
+/// `find_stale` is used instead so the offending file can be reported.
+///
+/// A source file with exactly the same timestamp as `dst` is handled according
+/// to `build.equal-mtime`; files found in a directory source are always stale
+/// in that case.
+///
+/// Panics if `src` doesn't exist; prefer `up_to_date_checked` where a source
+/// may legitimately be missing, such as an optional submodule.
+pub fn up_to_date(
+    builder: &Builder<'_>,
+    src: &Path,
+    dst: &Path,
+    follow_links: FollowLinks,
+) -> bool {
+    match up_to_date_checked(builder, src, dst, follow_links) {
+        Ok(fresh) => fresh,
+        Err(e) => panic!("{}", e),
+    }
+}
+
+/// Like `up_to_date`, but returns an error instead of panicking when `src`
+/// doesn't exist, so that the caller can decide what that means.
+pub fn up_to_date_checked(
+    builder: &Builder<'_>,
+    src: &Path,
+    dst: &Path,
+    follow_links: FollowLinks,
+) -> Result<bool, FreshnessError> {
+    if fs::symlink_metadata(src).is_err() {
+        return Err(FreshnessError::MissingSource {
+            path: src.to_path_buf(),
+            submodule: containing_submodule(&builder.src, src),
+        });
+    }
+    // Nothing is written in dry-run mode, so a cached answer could never be
+    // invalidated by the step that would have produced `dst`.
+    if builder.config.dry_run {
+        return Ok(check_up_to_date(builder, src, dst, follow_links));
+    }
+    if let Some(fresh) = builder.freshness.get(src, dst, follow_links) {
+        return Ok(fresh);
+    }
+    let fresh = check_up_to_date(builder, src, dst, follow_links);
+    builder.freshness.insert(src, dst, follow_links, fresh);
+    Ok(fresh)
+}
+
+/// Why `up_to_date_checked` couldn't compare a source with its destination.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FreshnessError {
+    /// The source doesn't exist. `submodule` is the submodule it lives in,
+    /// relative to the source root, if any: the usual reason is that the
+    /// submodule isn't checked out.
+    MissingSource { path: PathBuf, submodule: Option<PathBuf> },
+}
+
+impl fmt::Display for FreshnessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FreshnessError::MissingSource { path, submodule: None } => {
+                write!(f, "source {} does not exist", path.display())
+            }
+            FreshnessError::MissingSource { path, submodule: Some(submodule) } => write!(
+                f,
+                "source {} does not exist; the `{}` submodule may not be checked out, \
+                 try `git submodule update --init {}`",
+                path.display(),
+                submodule.display(),
+                submodule.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FreshnessError {}
+
+/// Returns the submodule listed in `src/.gitmodules` that `path` is inside
+/// of, relative to `src`.
+fn containing_submodule(src: &Path, path: &Path) -> Option<PathBuf> {
+    let relative = path.strip_prefix(src).unwrap_or(path);
+    let gitmodules = fs::read_to_string(src.join(".gitmodules")).ok()?;
+    gitmodules
+        .lines()
+        .filter_map(|line| {
+            let (key, value) = line.trim().split_once('=')?;
+            if key.trim() == "path" { Some(PathBuf::from(value.trim())) } else { None }
+        })
+        .find(|submodule| relative.starts_with(submodule))
+}
+
+fn check_up_to_date(
+    builder: &Builder<'_>,
+    src: &Path,
+    dst: &Path,
+    follow_links: FollowLinks,
+) -> bool {
+    let equal_mtime = equal_mtime_policy(&builder.config);
+    if builder.is_verbose() {
+        return match find_stale(src, dst, equal_mtime, follow_links, &builder.hash_stamps) {
+            Some(reason) => {
+                builder.verbose(&format!("out of date: {}", reason));
+                false
+            }
+            None => true,
+        };
+    }
     if !dst.exists() {
         return false;
     }
     let threshold = mtime(dst);
-    let meta = match fs::metadata(src) {
-        Ok(meta) => meta,
-        Err(e) => panic!("source {:?} failed to get metadata: {}", src, e),
+    let meta = match source_metadata(src, follow_links) {
+        Ok(Some(meta)) => meta,
+        Ok(None) => return true,
+        // A source that can't be read has to be regenerated from.
+        Err(_) => return false,
     };
     if meta.is_dir() {
-        dir_up_to_date(src, threshold)
+        dir_up_to_date(src, threshold, builder.jobs() as usize)
+    } else {
+        let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+        file_up_to_date(src, modified, dst, threshold, equal_mtime, &builder.hash_stamps)
+    }
+}
+
+/// Returns the metadata of the source of a freshness check, `Ok(None)` if
+/// `src` is a symlink whose target doesn't exist, or an error if `src` itself
+/// can't be read, e.g. because it was removed since it was checked for.
+fn source_metadata(src: &Path, follow_links: FollowLinks) -> io::Result<Option<fs::Metadata>> {
+    let meta = match follow_links {
+        FollowLinks::Yes => fs::metadata(src),
+        FollowLinks::No => fs::symlink_metadata(src),
+    };
+    match meta {
+        Ok(meta) => Ok(Some(meta)),
+        Err(_) if fs::symlink_metadata(src).is_ok() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+fn file_up_to_date(
+    src: &Path,
+    src_mtime: SystemTime,
+    dst: &Path,
+    dst_mtime: SystemTime,
+    equal_mtime: EqualMtime,
+    stamps: &HashStamps,
+) -> bool {
+    match src_mtime.cmp(&dst_mtime) {
+        cmp::Ordering::Less => true,
+        cmp::Ordering::Greater => false,
+        // Reproducible builds clamp every timestamp to `SOURCE_DATE_EPOCH`,
+        // so equal times say nothing about which file was written last.
+        cmp::Ordering::Equal if source_date_epoch() == Some(src_mtime) => stamps.check(src, dst),
+        cmp::Ordering::Equal => match equal_mtime {
+            EqualMtime::Auto | EqualMtime::Fresh => true,
+            EqualMtime::Stale => false,
+            EqualMtime::Content => stamps.check(src, dst),
+        },
+    }
+}
+
+fn hash_file(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let mut hasher = DefaultHasher::new();
+    let mut buf = [0; 64 * 1024];
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(hasher.finish()),
+            n => hasher.write(&buf[..n]),
+        }
+    }
+}
+
+/// The time reproducible builds clamp file timestamps to, taken from
+/// `SOURCE_DATE_EPOCH` (seconds since the Unix epoch).
+pub fn source_date_epoch() -> Option<SystemTime> {
+    let secs = env::var("SOURCE_DATE_EPOCH").ok()?.trim().parse().ok()?;
+    Some(UNIX_EPOCH + Duration::from_secs(secs))
+}
+
+/// Resolves `EqualMtime::Auto` by probing the timestamp granularity of the
+/// build directory.
+fn equal_mtime_policy(config: &Config) -> EqualMtime {
+    match config.equal_mtime {
+        EqualMtime::Auto if !config.dry_run && has_coarse_mtimes(&config.out) => EqualMtime::Stale,
+        policy => policy,
+    }
+}
+
+/// Returns `true` if the filesystem holding `dir` only records modification
+/// times with whole-second (or coarser) granularity.
+///
+/// This is detected by writing two files back-to-back and checking that
+/// neither of their timestamps has a sub-second part, which is vanishingly
+/// unlikely on a filesystem with fine-grained timestamps. The probe is only
+/// done once per directory.
+pub fn has_coarse_mtimes(dir: &Path) -> bool {
+    static PROBED: Lazy<Mutex<HashMap<PathBuf, bool>>> = Lazy::new(Default::default);
+
+    let mut probed = t!(PROBED.lock());
+    if let Some(&coarse) = probed.get(dir) {
+        return coarse;
+    }
+    let probe = || -> io::Result<bool> {
+        fs::create_dir_all(dir)?;
+        let files = [dir.join(".mtime-probe-1"), dir.join(".mtime-probe-2")];
+        let mut whole_seconds = true;
+        for file in &files {
+            fs::write(file, "")?;
+        }
+        for file in &files {
+            let modified = fs::metadata(file)?.modified()?;
+            whole_seconds &=
+                modified.duration_since(UNIX_EPOCH).map_or(true, |d| d.subsec_nanos() == 0);
+            let _ = fs::remove_file(file);
+        }
+        Ok(whole_seconds)
+    };
+    let coarse = probe().unwrap_or(false);
+    probed.insert(dir.to_path_buf(), coarse);
+    coarse
+}
+
+/// Why `up_to_date` considers a destination stale.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StaleReason {
+    /// The destination doesn't exist yet.
+    MissingDst(PathBuf),
+    /// The source doesn't exist, or can't be read.
+    MissingSrc(PathBuf),
+    /// A source file was modified after the destination.
+    Newer { src: PathBuf, src_mtime: SystemTime, dst: PathBuf, dst_mtime: SystemTime },
+}
+
+impl fmt::Display for StaleReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StaleReason::MissingDst(dst) => write!(f, "{} does not exist", dst.display()),
+            StaleReason::MissingSrc(src) => write!(f, "source {} can't be read", src.display()),
+            StaleReason::Newer { src, src_mtime, dst, dst_mtime } => write!(
+                f,
+                "{} ({}) newer than {} ({})",
+                src.display(),
+                format_mtime(*src_mtime),
+                dst.display(),
+                format_mtime(*dst_mtime)
+            ),
+        }
+    }
+}
+
+/// Like `up_to_date`, but returns the reason `dst` is stale, if it is.
+///
+/// For directory sources this reports the first stale file found.
+pub fn find_stale(
+    src: &Path,
+    dst: &Path,
+    equal_mtime: EqualMtime,
+    follow_links: FollowLinks,
+    stamps: &HashStamps,
+) -> Option<StaleReason> {
+    if !dst.exists() {
+        return Some(StaleReason::MissingDst(dst.to_path_buf()));
+    }
+    let threshold = mtime(dst);
+    let meta = match source_metadata(src, follow_links) {
+        Ok(Some(meta)) => meta,
+        Ok(None) => return None,
+        Err(_) => return Some(StaleReason::MissingSrc(src.to_path_buf())),
+    };
+    let (src, src_mtime) = if meta.is_dir() {
+        dir_find_stale(src, threshold)?
     } else {
-        meta.modified().unwrap_or(UNIX_EPOCH) <= threshold
+        let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+        if file_up_to_date(src, modified, dst, threshold, equal_mtime, stamps) {
+            return None;
+        }
+        (src.to_path_buf(), modified)
+    };
+    Some(StaleReason::Newer { src, src_mtime, dst: dst.to_path_buf(), dst_mtime: threshold })
+}
+
+/// Formats `time` as a UTC `YYYY-MM-DD HH:MM` string.
+fn format_mtime(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (days, rem) = (secs / 86400, secs % 86400);
+    // Civil-from-days conversion, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!("{:04}-{:02}-{:02} {:02}:{:02}", year, month, day, rem / 3600, rem % 3600 / 60)
+}
+
+/// Returns `true` if every file under `src` is older than `threshold`.
+///
+/// With more than one job the subdirectories of `src` are handed to a
+/// work-stealing pool of `jobs` threads, and all workers stop as soon as any
+/// of them finds a stale file. With a single job the tree is walked serially
+/// on the current thread so the traversal order stays deterministic.
+fn dir_up_to_date(src: &Path, threshold: SystemTime, jobs: usize) -> bool {
+    if jobs <= 1 {
+        return dir_up_to_date_serial(src, threshold);
+    }
+
+    // Check the top level here so that the walker is only ever handed real
+    // directories: a symlinked `src` is followed, but symlinks found inside
+    // the tree are not, exactly as in the serial walk.
+    let mut subdirs = Vec::new();
+    for e in t!(fs::read_dir(src)) {
+        let e = t!(e);
+        let meta = t!(e.metadata());
+        if meta.is_dir() {
+            subdirs.push(e.path());
+        } else if meta.modified().unwrap_or(UNIX_EPOCH) >= threshold {
+            return false;
+        }
+    }
+    let (first, rest) = match subdirs.split_first() {
+        Some(dirs) => dirs,
+        None => return true,
+    };
+
+    let mut walker = WalkBuilder::new(first);
+    for dir in rest {
+        walker.add(dir);
+    }
+    // Hidden and gitignored files are inputs too, so don't filter anything.
+    let walker = walker.standard_filters(false).threads(jobs).build_parallel();
+
+    let stale = Arc::new(AtomicBool::new(false));
+    walker.run(|| {
+        let stale = Arc::clone(&stale);
+        Box::new(move |entry| {
+            if stale.load(Ordering::Relaxed) {
+                return WalkState::Quit;
+            }
+            let entry = t!(entry);
+            let meta = t!(entry.metadata());
+            if !meta.is_dir() && meta.modified().unwrap_or(UNIX_EPOCH) >= threshold {
+                stale.store(true, Ordering::Relaxed);
+                return WalkState::Quit;
+            }
+            WalkState::Continue
+        })
+    });
+    !stale.load(Ordering::Relaxed)
+}
+
+/// Returns the newest modification time of any file under `path`, or of
+/// `path` itself if it is a file. An empty directory counts as `UNIX_EPOCH`.
+pub fn newest_mtime(path: &Path) -> io::Result<SystemTime> {
+    Ok(newest_file(path)?.map_or(UNIX_EPOCH, |(_, mtime)| mtime))
+}
+
+/// Like `newest_mtime`, but also says which file it was, for diagnostics.
+/// Returns `None` for an empty directory.
+///
+/// As in `dir_up_to_date`, the tree is walked in parallel without any
+/// ignore-file filtering, and symlinks inside it are not followed.
+pub fn newest_file(path: &Path) -> io::Result<Option<(PathBuf, SystemTime)>> {
+    let newest = Arc::new(Mutex::new(None::<(PathBuf, SystemTime)>));
+    let error = Arc::new(Mutex::new(None));
+    WalkBuilder::new(path).standard_filters(false).build_parallel().run(|| {
+        let newest = Arc::clone(&newest);
+        let error = Arc::clone(&error);
+        Box::new(move |entry| {
+            let (entry, meta) = match entry.and_then(|e| e.metadata().map(|m| (e, m))) {
+                Ok(found) => found,
+                Err(e) => {
+                    let kind = e.io_error().map_or(io::ErrorKind::Other, |e| e.kind());
+                    *error.lock().unwrap() = Some(io::Error::new(kind, e));
+                    return WalkState::Quit;
+                }
+            };
+            if !meta.is_dir() {
+                let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+                let mut newest = newest.lock().unwrap();
+                let is_newest = match &*newest {
+                    Some((_, mtime)) => modified > *mtime,
+                    None => true,
+                };
+                if is_newest {
+                    *newest = Some((entry.into_path(), modified));
+                }
+            }
+            WalkState::Continue
+        })
+    });
+    if let Some(e) = error.lock().unwrap().take() {
+        return Err(e);
     }
+    let newest = newest.lock().unwrap().take();
+    Ok(newest)
+}
+
+fn dir_up_to_date_serial(src: &Path, threshold: SystemTime) -> bool {
+    dir_find_stale(src, threshold).is_none()
 }
 
-fn dir_up_to_date(src: &Path, threshold: SystemTime) -> bool {
-    t!(fs::read_dir(src)).map(|e| t!(e)).all(|e| {
+/// Returns the first file under `src` that isn't older than `threshold`,
+/// along with its modification time.
+fn dir_find_stale(src: &Path, threshold: SystemTime) -> Option<(PathBuf, SystemTime)> {
+    for e in t!(fs::read_dir(src)) {
+        let e = t!(e);
         let meta = t!(e.metadata());
         if meta.is_dir() {
-            dir_up_to_date(&e.path(), threshold)
+            if let Some(stale) = dir_find_stale(&e.path(), threshold) {
+                return Some(stale);
+            }
         } else {
-            meta.modified().unwrap_or(UNIX_EPOCH) < threshold
+            let modified = meta.modified().unwrap_or(UNIX_EPOCH);
+            if modified >= threshold {
+                return Some((e.path(), modified));
+            }
         }
-    })
+    }
+    None
 }
 
-fn fail(s: &str) -> ! {
-    println!("\n\n{}\n\n", s);
-    std::process::exit(1);
+/// Results of the `up_to_date` checks made during a single invocation.
+///
+/// Docs, dist and test steps tend to check the same large directories against
+/// the same outputs, and each check is a full walk of the source. Entries are
+/// keyed on the canonicalized `(src, dst)` pair; a step that writes to a path
+/// must call `invalidate` on it so that later checks see the new state.
+#[derive(Debug, Default)]
+pub struct FreshnessCache {
+    results: Mutex<HashMap<(PathBuf, PathBuf, FollowLinks), bool>>,
+    hits: AtomicUsize,
+}
+
+impl FreshnessCache {
+    pub fn new() -> FreshnessCache {
+        FreshnessCache::default()
+    }
+
+    /// Returns the cached result for `src` against `dst`, if there is one.
+    pub fn get(&self, src: &Path, dst: &Path, follow_links: FollowLinks) -> Option<bool> {
+        let key = (canonical(src), canonical(dst), follow_links);
+        let fresh = self.results.lock().unwrap().get(&key).copied();
+        if fresh.is_some() {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+        }
+        fresh
+    }
+
+    /// Records whether `dst` is up to date with `src`. Nothing is recorded for
+    /// a `dst` that doesn't exist yet, as the step that checked it goes on to
+    /// create it.
+    pub fn insert(&self, src: &Path, dst: &Path, follow_links: FollowLinks, fresh: bool) {
+        if fs::symlink_metadata(dst).is_err() {
+            return;
+        }
+        let key = (canonical(src), canonical(dst), follow_links);
+        self.results.lock().unwrap().insert(key, fresh);
+    }
+
+    /// Forgets every result that `path` being written could have changed:
+    /// those whose source or destination is `path`, lies beneath it, or
+    /// contains it.
+    pub fn invalidate(&self, path: &Path) {
+        let paths = [path.to_path_buf(), canonical(path)];
+        let affects = |p: &PathBuf| paths.iter().any(|w| p.starts_with(w) || w.starts_with(p));
+        self.results.lock().unwrap().retain(|(src, dst, _), _| !affects(src) && !affects(dst));
+    }
+
+    /// The number of directory walks and timestamp comparisons that were
+    /// answered from the cache.
+    pub fn hits(&self) -> usize {
+        self.hits.load(Ordering::Relaxed)
+    }
+}
+
+/// Hashes of the sources that destinations were last generated from, for the
+/// freshness checks where equal timestamps settle nothing: under
+/// `SOURCE_DATE_EPOCH`, and with `build.equal-mtime = "content"`.
+///
+/// A generated file rarely has the contents of its source, so the source is
+/// compared with the hash recorded in a stamp for `dst` instead. A check that
+/// finds `dst` stale queues the current hash of `src`; it is written once the
+/// step that made the check (and so regenerated `dst`) has finished. A step
+/// that fails writes nothing, leaving `dst` stale for the next build.
+#[derive(Debug)]
+pub struct HashStamps {
+    dir: PathBuf,
+    dry_run: bool,
+    /// The number of steps currently running.
+    depth: AtomicUsize,
+    /// `(depth, dst, src, hash)` for each check whose step hasn't finished.
+    pending: Mutex<Vec<(usize, PathBuf, PathBuf, u64)>>,
+}
+
+impl HashStamps {
+    pub fn new(dir: PathBuf, dry_run: bool) -> HashStamps {
+        HashStamps { dir, dry_run, depth: AtomicUsize::new(0), pending: Mutex::new(Vec::new()) }
+    }
+
+    /// The stamp for `dst`, named after a hash of its canonical path.
+    fn stamp(&self, dst: &Path) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        canonical(dst).hash(&mut hasher);
+        self.dir.join(format!("{:016x}", hasher.finish()))
+    }
+
+    /// Returns `true` if `dst` was generated from `src` as it is now. If it
+    /// wasn't, the hash of `src` is recorded when the running step finishes.
+    fn check(&self, src: &Path, dst: &Path) -> bool {
+        let hash = match hash_file(src) {
+            Ok(hash) => hash,
+            Err(_) => return false,
+        };
+        let src = canonical(src);
+        let entry = format!("{:016x} {}", hash, src.display());
+        let recorded = fs::read_to_string(self.stamp(dst)).unwrap_or_default();
+        if recorded.lines().any(|line| line == entry) {
+            return true;
+        }
+        let depth = self.depth.load(Ordering::SeqCst);
+        self.pending.lock().unwrap().push((depth, dst.to_path_buf(), src, hash));
+        false
+    }
+
+    /// Called by `Builder::ensure` before running a step.
+    pub fn enter_step(&self) {
+        self.depth.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// Called by `Builder::ensure` once a step has run successfully: records
+    /// the hashes queued by its checks and by those of the steps it ran.
+    pub fn exit_step(&self) {
+        let depth = self.depth.fetch_sub(1, Ordering::SeqCst) - 1;
+        let finished: Vec<_> = {
+            let mut pending = self.pending.lock().unwrap();
+            let (finished, rest) = pending.drain(..).partition(|entry| entry.0 > depth);
+            *pending = rest;
+            finished
+        };
+        if self.dry_run {
+            return;
+        }
+        for (_, dst, src, hash) in finished {
+            self.record(&dst, &src, hash);
+        }
+    }
+
+    fn record(&self, dst: &Path, src: &Path, hash: u64) {
+        let stamp = self.stamp(dst);
+        let src = src.display().to_string();
+        let mut lines: Vec<String> = fs::read_to_string(&stamp)
+            .unwrap_or_default()
+            .lines()
+            .filter(|line| matches!(line.split_once(' '), Some((_, path)) if path != src))
+            .map(String::from)
+            .collect();
+        lines.push(format!("{:016x} {}", hash, src));
+        // A stamp that can't be written only costs a rebuild next time.
+        let _ = fs::create_dir_all(&self.dir)
+            .and_then(|()| write_file_atomic(&stamp, lines.join("\n") + "\n"));
+    }
+}
+
+/// `path` with symlinks resolved, or as given if it doesn't exist (yet).
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// A stamp file marking that some piece of work has been done.
+///
+/// A stamp is either plain, in which case it is compared by modification time
+/// against the inputs of the work, or it records a string (a commit hash, a
+/// tool version, a set of flags) and is only valid for exactly that string.
+/// Everything that writes to disk is a no-op in dry-run mode.
+#[derive(Clone, Debug)]
+pub struct Stamp {
+    pub path: PathBuf,
+}
+
+impl Stamp {
+    pub fn new(path: impl Into<PathBuf>) -> Stamp {
+        Stamp { path: path.into() }
+    }
+
+    /// Returns `true` if the stamp exists and isn't older than any of
+    /// `inputs`, which may be files or directories. An input file with the
+    /// stamp's exact timestamp is handled according to `build.equal-mtime`,
+    /// like in `up_to_date`; files found in a directory input are always
+    /// stale in that case.
+    pub fn is_up_to_date(&self, config: &Config, stamps: &HashStamps, inputs: &[&Path]) -> bool {
+        if !self.path.exists() {
+            return false;
+        }
+        let threshold = mtime(&self.path);
+        let equal_mtime = equal_mtime_policy(config);
+        inputs.iter().all(|input| {
+            if input.is_dir() {
+                dir_up_to_date_serial(input, threshold)
+            } else {
+                file_up_to_date(input, mtime(input), &self.path, threshold, equal_mtime, stamps)
+            }
+        })
+    }
+
+    /// Returns `true` if the stamp exists and records exactly `content`.
+    pub fn is_recorded(&self, content: &str) -> bool {
+        match fs::read(&self.path) {
+            Ok(recorded) => recorded == content.as_bytes(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => false,
+            Err(e) => panic!("failed to read stamp file `{}`: {}", self.path.display(), e),
+        }
+    }
+
+    /// Creates or updates an empty stamp file.
+    pub fn touch(&self, config: &Config) -> io::Result<()> {
+        self.record(config, "")
+    }
+
+    /// Writes `content` to the stamp file, creating its parent directory if
+    /// needed.
+    pub fn record(&self, config: &Config, content: &str) -> io::Result<()> {
+        if config.dry_run {
+            return Ok(());
+        }
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        write_file_atomic(&self.path, content)
+    }
+
+    /// Removes the stamp file, if it exists.
+    pub fn remove(&self, config: &Config) -> io::Result<()> {
+        if config.dry_run {
+            return Ok(());
+        }
+        match fs::remove_file(&self.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+pub fn fail(s: &str) -> ! {
+    log_line(&format!("\n\n{}\n\n", CiEnv::current().annotation(Level::Error, s)));
+    exit(1);
 }
 
 /// Copied from `std::path::absolute` until it stabilizes.
@@ -549,3 +3364,6 @@ fn GetFullPathNameW(
         }
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,2008 @@
+use super::*;
+use filetime::FileTime;
+use std::thread;
+use std::time::Duration;
+
+/// Returns a fresh, empty scratch directory unique to the calling test.
+fn tmpdir(name: &str) -> PathBuf {
+    let dir = env::temp_dir()
+        .join("rustbuild-util-tests")
+        .join(thread::current().name().unwrap_or("unknown").replace(":", "-"))
+        .join(name);
+    let _ = fs::remove_dir_all(&dir);
+    t!(fs::create_dir_all(&dir));
+    dir
+}
+
+fn set_mtime(path: &Path, time: SystemTime) {
+    t!(filetime::set_file_mtime(path, FileTime::from_system_time(time)));
+}
+
+/// Generates `depth` levels of `width` subdirectories, each holding a few
+/// files, all stamped with `time`.
+fn deep_tree(root: &Path, depth: usize, width: usize, time: SystemTime) {
+    for i in 0..3 {
+        let file = root.join(format!("file{}.rs", i));
+        t!(fs::write(&file, ""));
+        set_mtime(&file, time);
+    }
+    if depth == 0 {
+        return;
+    }
+    for i in 0..width {
+        let dir = root.join(format!("dir{}", i));
+        t!(fs::create_dir(&dir));
+        deep_tree(&dir, depth - 1, width, time);
+    }
+}
+
+/// Stamps that are never written, for checks that don't rely on them.
+fn no_stamps() -> HashStamps {
+    HashStamps::new(PathBuf::from("/nonexistent"), true)
+}
+
+#[test]
+fn dir_up_to_date_parallel_matches_serial() {
+    let root = tmpdir("tree");
+    let old = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let threshold = old + Duration::from_secs(60);
+    // 4^5 leaf directories, ~4000 files.
+    deep_tree(&root, 5, 4, old);
+
+    for &jobs in &[1, 2, 8] {
+        let start = Instant::now();
+        assert!(dir_up_to_date(&root, threshold, jobs));
+        println!("fresh walk with {} jobs: {:?}", jobs, start.elapsed());
+    }
+    assert!(dir_up_to_date_serial(&root, threshold));
+
+    // A single stale file deep in the tree and one at the top level must both
+    // be found regardless of the job count.
+    for stale in &[root.join("dir3/dir2/dir1/dir0/dir3/file2.rs"), root.join("file0.rs")] {
+        set_mtime(stale, threshold);
+        for &jobs in &[1, 2, 8] {
+            let start = Instant::now();
+            assert!(!dir_up_to_date(&root, threshold, jobs));
+            println!("stale walk with {} jobs: {:?}", jobs, start.elapsed());
+        }
+        assert!(!dir_up_to_date_serial(&root, threshold));
+        set_mtime(stale, old);
+    }
+
+    // Hidden files are inputs like any other.
+    let hidden = root.join("dir1/.hidden");
+    t!(fs::write(&hidden, ""));
+    set_mtime(&hidden, threshold);
+    assert!(!dir_up_to_date(&root, threshold, 8));
+    assert!(!dir_up_to_date_serial(&root, threshold));
+}
+
+#[test]
+fn dir_up_to_date_empty_dir() {
+    let root = tmpdir("empty");
+    assert!(dir_up_to_date(&root, UNIX_EPOCH, 1));
+    assert!(dir_up_to_date(&root, UNIX_EPOCH, 4));
+}
+
+#[test]
+fn find_stale_file_vs_file() {
+    let root = tmpdir("file-vs-file");
+    let (src, dst) = (root.join("src.md"), root.join("dst.html"));
+    t!(fs::write(&src, ""));
+    t!(fs::write(&dst, ""));
+    let old = UNIX_EPOCH + Duration::from_secs(1_704_103_200); // 2024-01-01 10:00
+    let new = old + Duration::from_secs(86400 + 660); // 2024-01-02 10:11
+    set_mtime(&src, old);
+    set_mtime(&dst, old);
+    assert_eq!(find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps()), None);
+
+    set_mtime(&src, new);
+    let reason = find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps()).unwrap();
+    assert_eq!(
+        reason,
+        StaleReason::Newer { src: src.clone(), src_mtime: new, dst: dst.clone(), dst_mtime: old }
+    );
+    assert_eq!(
+        reason.to_string(),
+        format!(
+            "{} (2024-01-02 10:11) newer than {} (2024-01-01 10:00)",
+            src.display(),
+            dst.display()
+        )
+    );
+}
+
+#[test]
+fn find_stale_dir_vs_file() {
+    let root = tmpdir("dir-vs-file");
+    let old = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let src = root.join("src");
+    t!(fs::create_dir(&src));
+    deep_tree(&src, 2, 2, old);
+    let dst = root.join("index.html");
+    t!(fs::write(&dst, ""));
+    set_mtime(&dst, old + Duration::from_secs(1));
+    assert_eq!(find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps()), None);
+
+    let stale = src.join("dir1/dir0/file1.rs");
+    let new = old + Duration::from_secs(10);
+    set_mtime(&stale, new);
+    assert_eq!(
+        find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps()),
+        Some(StaleReason::Newer {
+            src: stale,
+            src_mtime: new,
+            dst: dst.clone(),
+            dst_mtime: old + Duration::from_secs(1),
+        })
+    );
+}
+
+#[test]
+fn find_stale_missing_dst() {
+    let root = tmpdir("missing-dst");
+    let (src, dst) = (root.join("src.md"), root.join("dst.html"));
+    t!(fs::write(&src, ""));
+    assert_eq!(
+        find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps()),
+        Some(StaleReason::MissingDst(dst.clone()))
+    );
+    assert_eq!(
+        find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps())
+            .unwrap()
+            .to_string(),
+        format!("{} does not exist", dst.display())
+    );
+}
+
+#[test]
+fn find_stale_missing_src() {
+    let root = tmpdir("missing-src");
+    let (src, dst) = (root.join("src.md"), root.join("dst.html"));
+    t!(fs::write(&dst, ""));
+    assert_eq!(
+        find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps()),
+        Some(StaleReason::MissingSrc(src.clone()))
+    );
+}
+
+#[test]
+fn stamp_records_content() {
+    let root = tmpdir("stamp-content");
+    let config = Config::default_opts();
+    let stamp = Stamp::new(root.join("nested/llvm-finished-building"));
+    assert!(!stamp.is_recorded(""));
+
+    t!(stamp.record(&config, "abc123\nLLVM_ENABLE_ASSERTIONS=ON"));
+    assert!(stamp.is_recorded("abc123\nLLVM_ENABLE_ASSERTIONS=ON"));
+    assert!(!stamp.is_recorded("abc123"));
+    assert!(!stamp.is_recorded("abc123\nLLVM_ENABLE_ASSERTIONS=OFF"));
+
+    t!(stamp.remove(&config));
+    assert!(!stamp.path.exists());
+    // Removing a missing stamp is fine.
+    t!(stamp.remove(&config));
+}
+
+#[test]
+fn stamp_is_up_to_date() {
+    let root = tmpdir("stamp-mtime");
+    let config = Config::default_opts();
+    let old = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let input = root.join("input.c");
+    t!(fs::write(&input, ""));
+    set_mtime(&input, old);
+    let dir = root.join("inputs");
+    t!(fs::create_dir(&dir));
+    deep_tree(&dir, 1, 2, old);
+
+    let stamp = Stamp::new(root.join(".stamp"));
+    let is_up_to_date =
+        |config: &Config, inputs: &[&Path]| stamp.is_up_to_date(config, &no_stamps(), inputs);
+    assert!(!is_up_to_date(&config, &[&input]));
+    t!(stamp.touch(&config));
+    set_mtime(&stamp.path, old + Duration::from_secs(1));
+    assert!(is_up_to_date(&config, &[&input, &dir]));
+
+    set_mtime(&dir.join("dir1/file0.rs"), old + Duration::from_secs(2));
+    assert!(!is_up_to_date(&config, &[&input, &dir]));
+    assert!(is_up_to_date(&config, &[&input]));
+
+    // An input as old as the stamp follows `build.equal-mtime`.
+    set_mtime(&input, old + Duration::from_secs(1));
+    let mut policy = Config::default_opts();
+    policy.equal_mtime = EqualMtime::Stale;
+    assert!(!is_up_to_date(&policy, &[&input]));
+    policy.equal_mtime = EqualMtime::Fresh;
+    assert!(is_up_to_date(&policy, &[&input]));
+}
+
+#[test]
+fn stamp_dry_run() {
+    let root = tmpdir("stamp-dry-run");
+    let mut config = Config::default_opts();
+    config.dry_run = true;
+    let stamp = Stamp::new(root.join("missing/.stamp"));
+    t!(stamp.touch(&config));
+    t!(stamp.record(&config, "hash"));
+    assert!(!stamp.path.exists());
+
+    config.dry_run = false;
+    t!(stamp.record(&config, "hash"));
+    config.dry_run = true;
+    t!(stamp.remove(&config));
+    assert!(stamp.is_recorded("hash"));
+}
+
+#[test]
+fn equal_mtime_policies() {
+    let root = tmpdir("equal-mtime");
+    let (src, dst) = (root.join("src.txt"), root.join("dst.txt"));
+    t!(fs::write(&src, "same"));
+    t!(fs::write(&dst, "same"));
+    let time = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    set_mtime(&src, time);
+    set_mtime(&dst, time);
+
+    assert_eq!(find_stale(&src, &dst, EqualMtime::Auto, FollowLinks::Yes, &no_stamps()), None);
+    assert_eq!(find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps()), None);
+    assert!(find_stale(&src, &dst, EqualMtime::Stale, FollowLinks::Yes, &no_stamps()).is_some());
+
+    // "content" goes by the source's hash when `dst` was last generated.
+    let stamps = HashStamps::new(root.join("stamps"), false);
+    stamps.enter_step();
+    assert!(find_stale(&src, &dst, EqualMtime::Content, FollowLinks::Yes, &stamps).is_some());
+    stamps.exit_step();
+    assert_eq!(find_stale(&src, &dst, EqualMtime::Content, FollowLinks::Yes, &stamps), None);
+
+    t!(fs::write(&src, "edited"));
+    set_mtime(&src, time);
+    assert_eq!(find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps()), None);
+    assert!(find_stale(&src, &dst, EqualMtime::Content, FollowLinks::Yes, &stamps).is_some());
+
+    // Files inside a directory source with the same timestamp as the
+    // destination were always considered stale.
+    let dir = root.join("dir");
+    t!(fs::create_dir(&dir));
+    t!(fs::write(dir.join("file"), ""));
+    set_mtime(&dir.join("file"), time);
+    assert!(find_stale(&dir, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps()).is_some());
+}
+
+#[test]
+fn coarse_mtime_probe_is_cached() {
+    let root = tmpdir("mtime-probe");
+    let coarse = has_coarse_mtimes(&root);
+    assert!(!root.join(".mtime-probe-1").exists());
+    t!(fs::remove_dir_all(&root));
+    assert_eq!(has_coarse_mtimes(&root), coarse);
+    assert!(!root.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn find_stale_symlinked_source() {
+    use std::os::unix::fs::symlink;
+
+    let root = tmpdir("symlinks");
+    let old = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    let external = root.join("external.c");
+    t!(fs::write(&external, ""));
+    set_mtime(&external, old + Duration::from_secs(10));
+    let link = root.join("link.c");
+    t!(symlink(&external, &link));
+    t!(filetime::set_symlink_file_times(
+        &link,
+        FileTime::from_system_time(old),
+        FileTime::from_system_time(old)
+    ));
+    let dst = root.join("out.o");
+    t!(fs::write(&dst, ""));
+    set_mtime(&dst, old + Duration::from_secs(5));
+
+    assert_eq!(mtime(&link), old + Duration::from_secs(10));
+    assert_eq!(mtime_nofollow(&link), old);
+    assert!(find_stale(&link, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps()).is_some());
+    assert_eq!(find_stale(&link, &dst, EqualMtime::Fresh, FollowLinks::No, &no_stamps()), None);
+
+    // A symlinked directory is walked only when following links.
+    let external_dir = root.join("external");
+    t!(fs::create_dir(&external_dir));
+    t!(fs::write(external_dir.join("new.c"), ""));
+    set_mtime(&external_dir.join("new.c"), old + Duration::from_secs(10));
+    let dir_link = root.join("vendor");
+    t!(symlink(&external_dir, &dir_link));
+    t!(filetime::set_symlink_file_times(
+        &dir_link,
+        FileTime::from_system_time(old),
+        FileTime::from_system_time(old)
+    ));
+    assert!(
+        find_stale(&dir_link, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps()).is_some()
+    );
+    assert_eq!(find_stale(&dir_link, &dst, EqualMtime::Fresh, FollowLinks::No, &no_stamps()), None);
+
+    // Broken links are very old rather than an error, both as the source and
+    // inside a directory being walked.
+    let broken = root.join("broken.c");
+    t!(symlink(root.join("missing.c"), &broken));
+    assert_eq!(mtime(&broken), UNIX_EPOCH);
+    assert_eq!(find_stale(&broken, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps()), None);
+    let src_dir = root.join("src");
+    t!(fs::create_dir(&src_dir));
+    let inner_broken = src_dir.join("broken.c");
+    t!(symlink(root.join("missing.c"), &inner_broken));
+    t!(filetime::set_symlink_file_times(
+        &inner_broken,
+        FileTime::from_system_time(old),
+        FileTime::from_system_time(old)
+    ));
+    assert_eq!(find_stale(&src_dir, &dst, EqualMtime::Fresh, FollowLinks::Yes, &no_stamps()), None);
+    assert!(dir_up_to_date(&src_dir, mtime(&dst), 4));
+}
+
+#[test]
+fn freshness_cache_invalidate() {
+    let dir = tmpdir("freshness_cache_invalidate");
+    let src = dir.join("src");
+    let out = dir.join("out");
+    t!(fs::create_dir_all(&src));
+    t!(fs::create_dir_all(&out));
+    let dst = out.join("index.html");
+
+    let cache = FreshnessCache::new();
+    // a missing destination is about to be generated, so it isn't cached
+    cache.insert(&src, &dst, FollowLinks::Yes, false);
+    assert_eq!(cache.get(&src, &dst, FollowLinks::Yes), None);
+    t!(fs::write(&dst, ""));
+    cache.insert(&src, &dst, FollowLinks::Yes, false);
+    assert_eq!(cache.get(&src, &dst, FollowLinks::Yes), Some(false));
+    assert_eq!(cache.get(&src, &dst, FollowLinks::No), None);
+    assert_eq!(cache.hits(), 1);
+
+    // writing anything inside the source or destination forgets the result
+    cache.invalidate(&src.join("a.md"));
+    assert_eq!(cache.get(&src, &dst, FollowLinks::Yes), None);
+    cache.insert(&src, &dst, FollowLinks::Yes, true);
+    cache.invalidate(&out);
+    assert_eq!(cache.get(&src, &dst, FollowLinks::Yes), None);
+
+    // unrelated writes don't
+    cache.insert(&src, &dst, FollowLinks::Yes, true);
+    cache.invalidate(&dir.join("elsewhere"));
+    assert_eq!(cache.get(&src, &dst, FollowLinks::Yes), Some(true));
+}
+
+#[test]
+fn source_date_epoch_compares_hash_stamps() {
+    let root = tmpdir("source-date-epoch");
+    let (src, dst) = (root.join("src.txt"), root.join("dst.txt"));
+    let stamps = HashStamps::new(root.join("stamps"), false);
+    t!(fs::write(&src, "input"));
+    t!(fs::write(&dst, "generated from input"));
+    let clamp = UNIX_EPOCH + Duration::from_secs(1_700_000_123);
+    set_mtime(&src, clamp);
+    set_mtime(&dst, clamp);
+
+    // Without the clamp, equal timestamps count as fresh.
+    env::remove_var("SOURCE_DATE_EPOCH");
+    assert_eq!(source_date_epoch(), None);
+    assert_eq!(find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &stamps), None);
+
+    env::set_var("SOURCE_DATE_EPOCH", "1700000123");
+    assert_eq!(source_date_epoch(), Some(clamp));
+    // Nothing is recorded until the step that found `dst` stale succeeds.
+    stamps.enter_step();
+    assert!(find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &stamps).is_some());
+    assert!(find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &stamps).is_some());
+    stamps.exit_step();
+    // `dst` differs from `src`, but was generated from it as it is now.
+    assert_eq!(find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &stamps), None);
+
+    t!(fs::write(&src, "patched"));
+    set_mtime(&src, clamp);
+    assert!(find_stale(&src, &dst, EqualMtime::Fresh, FollowLinks::Yes, &stamps).is_some());
+    env::remove_var("SOURCE_DATE_EPOCH");
+}
+
+#[test]
+fn newest_file_in_tree() {
+    let root = tmpdir("newest-file");
+    let old = UNIX_EPOCH + Duration::from_secs(1_000_000);
+    deep_tree(&root, 3, 3, old);
+    assert_eq!(newest_mtime(&root).unwrap(), old);
+
+    let newest = root.join("dir0").join("dir1").join("newest");
+    t!(fs::write(&newest, ""));
+    let new = old + Duration::from_secs(60);
+    set_mtime(&newest, new);
+    assert_eq!(newest_file(&root).unwrap(), Some((newest.clone(), new)));
+    assert_eq!(newest_mtime(&newest).unwrap(), new);
+
+    let empty = tmpdir("newest-file-empty");
+    assert_eq!(newest_file(&empty).unwrap(), None);
+    assert_eq!(newest_mtime(&empty).unwrap(), UNIX_EPOCH);
+
+    assert_eq!(newest_mtime(&root.join("missing")).unwrap_err().kind(), io::ErrorKind::NotFound);
+}
+
+#[test]
+fn freshness_error_names_submodule() {
+    let src = tmpdir("freshness-error");
+    t!(fs::write(
+        src.join(".gitmodules"),
+        "[submodule \"src/doc/book\"]\n\tpath = src/doc/book\n\turl = https://example.com/book.git\n"
+    ));
+    let book = src.join("src/doc/book");
+    assert_eq!(containing_submodule(&src, &book.join("src")), Some(PathBuf::from("src/doc/book")));
+    assert_eq!(containing_submodule(&src, &src.join("src/doc/index.md")), None);
+
+    let err = FreshnessError::MissingSource {
+        path: book.clone(),
+        submodule: containing_submodule(&src, &book),
+    };
+    assert!(err.to_string().contains("git submodule update --init src/doc/book"));
+    let missing = src.join("missing");
+    let err = FreshnessError::MissingSource { path: missing.clone(), submodule: None };
+    assert_eq!(err.to_string(), format!("source {} does not exist", missing.display()));
+}
+
+/// Serializes tests that change the CI detection variables, since the
+/// environment is shared by the whole process.
+static CI_ENV_LOCK: Lazy<Mutex<()>> = Lazy::new(|| Mutex::new(()));
+
+const CI_ENV_VARS: &[&str] =
+    &["TF_BUILD", "GITHUB_ACTIONS", "GITLAB_CI", "CIRCLECI", "BUILDKITE", "TEAMCITY_VERSION", "CI"];
+
+/// Runs `f` with only `vars` set out of the CI detection variables, restoring
+/// the original environment afterwards.
+fn with_ci_env(vars: &[(&str, &str)], f: impl FnOnce()) {
+    let _lock = CI_ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let saved: Vec<_> = CI_ENV_VARS.iter().map(|var| (*var, env::var_os(var))).collect();
+    for var in CI_ENV_VARS {
+        env::remove_var(var);
+    }
+    for (var, value) in vars {
+        env::set_var(var, value);
+    }
+    f();
+    for (var, value) in saved {
+        match value {
+            Some(value) => env::set_var(var, value),
+            None => env::remove_var(var),
+        }
+    }
+}
+
+#[test]
+fn ci_env_detection() {
+    let cases: &[(&[(&str, &str)], CiEnv)] = &[
+        (&[], CiEnv::None),
+        (&[("TF_BUILD", "True")], CiEnv::AzurePipelines),
+        (&[("GITHUB_ACTIONS", "true"), ("CI", "true")], CiEnv::GitHubActions),
+        (&[("GITLAB_CI", "true"), ("CI", "true")], CiEnv::GitLab),
+        (&[("CIRCLECI", "true"), ("CI", "true")], CiEnv::CircleCi),
+        (&[("BUILDKITE", "true"), ("CI", "true")], CiEnv::Buildkite),
+        (&[("TEAMCITY_VERSION", "2022.04")], CiEnv::TeamCity),
+        (&[("CI", "true")], CiEnv::Generic),
+        (&[("CI", "false")], CiEnv::None),
+    ];
+    for (vars, expected) in cases {
+        with_ci_env(vars, || assert_eq!(CiEnv::detect(), *expected, "{:?}", vars));
+    }
+}
+
+#[test]
+fn ci_env_forces_coloring() {
+    with_ci_env(&[("BUILDKITE", "true")], || {
+        let mut cmd = Command::new("true");
+        CiEnv::detect().force_coloring_in_ci(&mut cmd);
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["--color", "always"]);
+    });
+    with_ci_env(&[], || {
+        let mut cmd = Command::new("true");
+        CiEnv::detect().force_coloring_in_ci(&mut cmd);
+        assert_eq!(cmd.get_args().count(), 0);
+    });
+}
+
+#[test]
+fn ci_env_override_precedence() {
+    with_ci_env(&[("GITHUB_ACTIONS", "true")], || {
+        // Detection is the fallback when nothing overrides it.
+        assert_eq!(CiEnv::resolve(None, None), CiEnv::GitHubActions);
+        // `build.ci-env` beats detection...
+        assert_eq!(CiEnv::resolve(None, Some("azure")), CiEnv::AzurePipelines);
+        assert_eq!(CiEnv::resolve(None, Some("none")), CiEnv::None);
+        // ...and `RUSTBUILD_CI_ENV` beats `build.ci-env`.
+        assert_eq!(CiEnv::resolve(Some("none"), Some("azure")), CiEnv::None);
+        assert_eq!(CiEnv::resolve(Some("GitHub"), None), CiEnv::GitHubActions);
+    });
+}
+
+#[test]
+#[should_panic(expected = "Unrecognized option 'githib' set in RUSTBUILD_CI_ENV, expected one of")]
+fn ci_env_override_typo() {
+    CiEnv::resolve(Some("githib"), None);
+}
+
+#[test]
+fn ci_group_markers() {
+    let mut out = Vec::new();
+    {
+        let _outer = CiEnv::GitHubActions.group_to("outer", &mut out);
+        // GitHub can't nest groups, so this one prints nothing.
+        let _inner = CiEnv::GitHubActions.group_to("inner", io::sink());
+    }
+    assert_eq!(String::from_utf8(out).unwrap(), "::group::outer\n::endgroup::\n");
+
+    let mut out = Vec::new();
+    drop(CiEnv::AzurePipelines.group_to("step", &mut out));
+    assert_eq!(String::from_utf8(out).unwrap(), "##[group]step\n##[endgroup]\n");
+
+    let mut out = Vec::new();
+    let mut inner = Vec::new();
+    {
+        let _outer = CiEnv::TeamCity.group_to("outer", &mut out);
+        // TeamCity nests blocks.
+        drop(CiEnv::TeamCity.group_to("inner", &mut inner));
+    }
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "##teamcity[blockOpened name='outer']\n##teamcity[blockClosed name='outer']\n"
+    );
+    assert_eq!(
+        String::from_utf8(inner).unwrap(),
+        "##teamcity[blockOpened name='inner']\n##teamcity[blockClosed name='inner']\n"
+    );
+
+    let mut out = Vec::new();
+    drop(CiEnv::None.group_to("step", &mut out));
+    drop(CiEnv::GitLab.group_to("step", &mut out));
+    assert!(out.is_empty());
+}
+
+#[test]
+fn ci_group_closed_on_panic() {
+    let mut out = Vec::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _group = CiEnv::GitHubActions.group_to("failing step", &mut out);
+        panic!("step failed");
+    }));
+    assert!(result.is_err());
+    assert_eq!(String::from_utf8(out).unwrap(), "::group::failing step\n::endgroup::\n");
+
+    // The depth was restored, so the next group is printed again.
+    let mut out = Vec::new();
+    drop(CiEnv::GitHubActions.group_to("next", &mut out));
+    assert_eq!(String::from_utf8(out).unwrap(), "::group::next\n::endgroup::\n");
+}
+
+#[test]
+fn timeit_nested_lines() {
+    let mut config = Config::default_opts();
+    config.timeit_threshold = Duration::ZERO;
+    let mut outer = Vec::new();
+    let mut inner = Vec::new();
+    {
+        let _outer = TimeIt::new(&config, Some("llvm"), &mut outer);
+        drop(TimeIt::new(&config, Some("llvm cmake configure"), &mut inner));
+    }
+    let outer = String::from_utf8(outer).unwrap();
+    let inner = String::from_utf8(inner).unwrap();
+    assert!(outer.starts_with("  llvm: ") && outer.ends_with("s\n"), "{:?}", outer);
+    assert!(
+        inner.starts_with("    llvm cmake configure: ") && inner.ends_with("s\n"),
+        "{:?}",
+        inner
+    );
+
+    config.dry_run = true;
+    let mut out = Vec::new();
+    drop(TimeIt::new(&config, Some("dry run"), &mut out));
+    assert!(out.is_empty());
+
+    assert_eq!(timeit_line(Some("step"), 1, Duration::from_millis(312_440)), "  step: 312.44s");
+    assert_eq!(timeit_line(None, 2, Duration::from_millis(1_500)), "    finished in 1.500 seconds");
+}
+
+#[test]
+fn timeit_threshold_hides_quick_scopes() {
+    let mut config = Config::default_opts();
+    config.timeit_threshold = Duration::from_secs(3600);
+    let mut out = Vec::new();
+    drop(TimeIt::new(&config, Some("quick"), &mut out));
+    assert!(out.is_empty());
+
+    config.verbose = 2;
+    drop(TimeIt::new(&config, Some("quick"), &mut out));
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("  quick: "), "{:?}", out);
+}
+
+#[test]
+fn resource_usage_since() {
+    let start = ResourceUsage { cpu: Duration::from_millis(1_500), max_rss_kb: Some(4096) };
+    let end = ResourceUsage { cpu: Duration::from_millis(4_000), max_rss_kb: Some(8192) };
+    assert_eq!(
+        end.since(&start),
+        ResourceUsage { cpu: Duration::from_millis(2_500), max_rss_kb: Some(8192) }
+    );
+    // An earlier process had the higher peak.
+    let end = ResourceUsage { cpu: Duration::from_millis(4_000), max_rss_kb: Some(4096) };
+    assert_eq!(end.since(&start).max_rss_kb, None);
+    assert_eq!(end.since(&start).to_string(), "cpu 2.50s");
+    assert_eq!(start.to_string(), "cpu 1.50s, max rss 4 MB");
+}
+
+#[cfg(unix)]
+#[test]
+fn children_usage_counts_finished_children() {
+    let start = children_usage().unwrap();
+    // Spins for about half a second of CPU time.
+    let status = Command::new("sh")
+        .args(["-c", "i=0; while [ $i -lt 1000000 ]; do i=$((i + 1)); done"])
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let usage = children_usage().unwrap().since(&start);
+    // How much CPU time the loop gets depends on the load, and other tests'
+    // children may finish in the meantime, so this can only check that some
+    // time was counted.
+    assert!(usage.cpu > Duration::ZERO, "{:?} of cpu", usage.cpu);
+}
+
+#[test]
+fn timeit_depth_restored_on_panic() {
+    let mut config = Config::default_opts();
+    config.timeit_threshold = Duration::ZERO;
+    let mut out = Vec::new();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _time = TimeIt::new(&config, Some("failing step"), io::sink());
+        panic!("step failed");
+    }));
+    assert!(result.is_err());
+
+    drop(TimeIt::new(&config, Some("next"), &mut out));
+    let out = String::from_utf8(out).unwrap();
+    assert!(out.starts_with("  next: "), "{:?}", out);
+}
+
+#[test]
+fn step_summary_appends_within_limit() {
+    let dir = tmpdir("step-summary");
+    let summary = dir.join("summary.md");
+    assert!(append_step_summary(&summary, "### first\n"));
+    assert!(append_step_summary(&summary, "### second\n"));
+    assert_eq!(t!(fs::read_to_string(&summary)), "### first\n### second\n");
+
+    // Nothing is added past the limit, not even part of the section.
+    t!(fs::write(&summary, "x".repeat(STEP_SUMMARY_LIMIT as usize - 4)));
+    assert!(!append_step_summary(&summary, "### too long\n"));
+    assert_eq!(t!(fs::metadata(&summary)).len(), STEP_SUMMARY_LIMIT - 4);
+
+    // I/O errors only warn.
+    assert!(!append_step_summary(&dir, "### not a file\n"));
+}
+
+#[test]
+fn failure_summary_names_command() {
+    assert_eq!(
+        failure_summary(Some(r#""cargo" "build""#)),
+        "### Build failed\n\nThe last command that failed was:\n\n```text\n\"cargo\" \"build\"\n```\n"
+    );
+    assert_eq!(failure_summary(None), "### Build failed\n\nSee the log for the error.\n");
+    let long = failure_summary(Some(&"é".repeat(1500)));
+    assert!(long.contains(&format!("{} ...\n```", "é".repeat(1000))), "{}", long);
+}
+
+#[test]
+fn ci_annotation_escaping() {
+    let msg = "100% broken; see [log]\r\nnext line";
+    assert_eq!(
+        CiEnv::AzurePipelines.annotation(Level::Error, msg),
+        "##vso[task.logissue type=error]100%AZP25 broken%3B see [log%5D%0D%0Anext line"
+    );
+    assert_eq!(
+        CiEnv::GitHubActions.annotation(Level::Warning, msg),
+        "::warning::100%25 broken; see [log]%0D%0Anext line"
+    );
+    assert_eq!(
+        CiEnv::TeamCity.annotation(Level::Error, msg),
+        "##teamcity[buildProblem description='100% broken; see |[log|]|r|nnext line']"
+    );
+    assert_eq!(
+        CiEnv::TeamCity.annotation(Level::Warning, "plain"),
+        "##teamcity[message text='plain' status='WARNING']"
+    );
+    assert_eq!(CiEnv::None.annotation(Level::Error, "plain"), "error: plain");
+    assert_eq!(CiEnv::GitLab.annotation(Level::Warning, "plain"), "warning: plain");
+}
+
+#[test]
+fn teamcity_escaping() {
+    // `|` is escaped first, so the escapes added afterwards aren't doubled.
+    assert_eq!(escape_teamcity("a|b"), "a||b");
+    assert_eq!(escape_teamcity("it's [x]"), "it|'s |[x|]");
+    assert_eq!(escape_teamcity("|[\n"), "|||[|n");
+    assert_eq!(escape_teamcity("line\r\n"), "line|r|n");
+    assert_eq!(
+        CiEnv::block_open("Std { target: 'x86_64' }"),
+        "##teamcity[blockOpened name='Std { target: |'x86_64|' }']"
+    );
+    assert_eq!(CiEnv::block_close("a]b"), "##teamcity[blockClosed name='a|]b']");
+    assert_eq!(CiEnv::build_problem("x|y"), "##teamcity[buildProblem description='x||y']");
+}
+
+#[test]
+fn timestamp_prefixes() {
+    let elapsed = Duration::from_secs(2 * 3600 + 5 * 60 + 7);
+    assert_eq!(timestamp_lines("Building stage1", elapsed), "[02:05:07] Building stage1");
+    assert_eq!(
+        timestamp_lines("\n\ncommand failed\nexpected success\n\n", elapsed),
+        "\n\n[02:05:07] command failed\n[02:05:07] expected success\n\n"
+    );
+    assert_eq!(
+        timestamp_lines("::error::failed%0Abadly\n##vso[task.logissue type=error]x", elapsed),
+        "::error::failed%0Abadly\n##vso[task.logissue type=error]x"
+    );
+    assert_eq!(timestamp_lines("\tfinished", Duration::from_secs(59)), "[00:00:59] \tfinished");
+}
+
+#[test]
+fn make_on_linux() {
+    assert_eq!(make("x86_64-unknown-linux-gnu"), Path::new("make"));
+}
+
+#[cfg(unix)]
+#[test]
+fn copy_recursive_preserves_metadata() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tmpdir("copy");
+    let src = dir.join("src");
+    t!(fs::create_dir_all(src.join("sub")));
+    t!(fs::write(src.join("sub/tool.sh"), "#!/bin/sh\n"));
+    t!(fs::set_permissions(src.join("sub/tool.sh"), fs::Permissions::from_mode(0o755)));
+    let old = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+    set_mtime(&src.join("sub/tool.sh"), old);
+    set_mtime(&src.join("sub"), old);
+
+    let dest = dir.join("dest");
+    let config = Config::default_opts();
+    assert_eq!(t!(copy_recursive(&config, &src, &dest, &CopyOpts::default())), 1);
+    let copied = t!(fs::metadata(dest.join("sub/tool.sh")));
+    assert_eq!(copied.permissions().mode() & 0o777, 0o755);
+    assert_eq!(t!(copied.modified()), old);
+    assert_eq!(t!(t!(fs::metadata(dest.join("sub"))).modified()), old);
+    assert_eq!(t!(fs::read_to_string(dest.join("sub/tool.sh"))), "#!/bin/sh\n");
+
+    // A destination inside the source isn't copied into itself.
+    let nested = src.join("sub/nested");
+    t!(copy_recursive(&config, &src, &nested, &CopyOpts::default()));
+    assert!(nested.join("sub/tool.sh").exists());
+    assert!(!nested.join("sub/nested").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_copy_fallback_refreshes() {
+    fn fail(_: &Path, _: &Path) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+    fn link(src: &Path, dest: &Path) -> io::Result<()> {
+        std::os::unix::fs::symlink(src, dest)
+    }
+
+    let dir = tmpdir("fallback");
+    let src = dir.join("src");
+    let dest = dir.join("dest");
+    let marker = dir.join("dest.copy");
+    t!(fs::create_dir_all(&src));
+    t!(fs::write(src.join("lib.rs"), "old"));
+
+    assert!(symlink_dir_or_copy(&src, &src, &dest, false, false, &fail).is_err());
+    assert!(!marker.exists());
+
+    t!(symlink_dir_or_copy(&src, &src, &dest, true, false, &fail));
+    assert!(marker.exists());
+    assert!(!t!(fs::symlink_metadata(&dest)).file_type().is_symlink());
+    assert_eq!(t!(fs::read_to_string(dest.join("lib.rs"))), "old");
+
+    // The marker makes the next build replace the stale copy.
+    t!(fs::write(src.join("lib.rs"), "new"));
+    t!(fs::write(dest.join("stale.rs"), ""));
+    t!(symlink_dir_or_copy(&src, &src, &dest, true, false, &fail));
+    assert_eq!(t!(fs::read_to_string(dest.join("lib.rs"))), "new");
+    assert!(!dest.join("stale.rs").exists());
+
+    // Once linking works again, the copy is replaced by a link.
+    t!(symlink_dir_or_copy(&src, &src, &dest, true, false, &link));
+    assert!(!marker.exists());
+    assert!(t!(fs::symlink_metadata(&dest)).file_type().is_symlink());
+}
+
+#[cfg(unix)]
+#[test]
+fn symlink_dir_existing_dest() {
+    fn fail(_: &Path, _: &Path) -> io::Result<()> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+    fn link(src: &Path, dest: &Path) -> io::Result<()> {
+        std::os::unix::fs::symlink(src, dest)
+    }
+
+    let dir = tmpdir("existing");
+    let src = dir.join("src");
+    let other = dir.join("other");
+    let dest = dir.join("dest");
+    t!(fs::create_dir_all(&src));
+    t!(fs::create_dir_all(&other));
+    let reset = || {
+        if let Ok(metadata) = fs::symlink_metadata(&dest) {
+            if metadata.is_dir() {
+                t!(fs::remove_dir_all(&dest));
+            } else {
+                t!(fs::remove_file(&dest));
+            }
+        }
+    };
+    let linked = || t!(fs::read_link(&dest)) == src;
+
+    // Missing.
+    t!(symlink_dir_or_copy(&src, &src, &dest, false, false, &link));
+    assert!(linked());
+
+    // Already the right link: left alone, so linking isn't even attempted.
+    t!(symlink_dir_or_copy(&src, &src, &dest, false, false, &fail));
+    assert!(linked());
+
+    // A link elsewhere or a dangling link is replaced.
+    for old in [&other, &dir.join("missing")] {
+        reset();
+        t!(link(old, &dest));
+        t!(symlink_dir_or_copy(&src, &src, &dest, false, false, &link));
+        assert!(linked());
+        assert!(other.exists());
+    }
+
+    // An empty directory is replaced.
+    reset();
+    t!(fs::create_dir(&dest));
+    t!(symlink_dir_or_copy(&src, &src, &dest, false, false, &link));
+    assert!(linked());
+
+    // A non-empty directory or a file is only replaced with `overwrite`.
+    for make in [
+        |dest: &Path| t!(fs::write(dest, "")),
+        |dest: &Path| {
+            t!(fs::create_dir(dest));
+            t!(fs::write(dest.join("leftover"), ""));
+        },
+    ] {
+        reset();
+        make(&dest);
+        let err = symlink_dir_or_copy(&src, &src, &dest, true, false, &link).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AlreadyExists);
+        assert!(err.to_string().contains(&*dest.to_string_lossy()));
+        assert!(!t!(fs::symlink_metadata(&dest)).file_type().is_symlink());
+        t!(symlink_dir_or_copy(&src, &src, &dest, false, true, &link));
+        assert!(linked());
+    }
+}
+
+#[test]
+fn relative_paths() {
+    let rel = |base: &str, target: &str| path_relative_from(Path::new(base), Path::new(target));
+    assert_eq!(rel("/build/x/doc", "/build/x/compiler-doc"), Some("../compiler-doc".into()));
+    assert_eq!(rel("/build/host/stage1/x", "/build/x/doc"), Some("../../../x/doc".into()));
+    assert_eq!(rel("/build", "/build/a/b"), Some("a/b".into()));
+    assert_eq!(rel("/build/a", "/build/a"), Some(".".into()));
+    assert_eq!(rel("a/b", "a/./b"), Some(".".into()));
+    assert_eq!(rel("a/./b", "a/c"), Some("../c".into()));
+    // `..` in the base can't be resolved without the filesystem.
+    assert_eq!(rel("/build/a/..", "/build/b"), None);
+    assert_eq!(rel("/build", "relative"), None);
+}
+
+#[cfg(unix)]
+#[test]
+fn relative_symlink_survives_move() {
+    let dir = tmpdir("relative");
+    let root = dir.join("before");
+    t!(fs::create_dir_all(root.join("x/compiler-doc")));
+    t!(fs::write(root.join("x/compiler-doc/index.html"), "docs"));
+    t!(fs::create_dir_all(root.join("host/stage1-rustc/x")));
+
+    let link = root.join("host/stage1-rustc/x/doc");
+    t!(symlink_dir_relative(&Config::default_opts(), &root.join("x/compiler-doc"), &link, false));
+    assert!(t!(fs::read_link(&link)).is_relative());
+
+    let moved = dir.join("after");
+    t!(fs::rename(&root, &moved));
+    let link = moved.join("host/stage1-rustc/x/doc");
+    assert_eq!(t!(fs::read_to_string(link.join("index.html"))), "docs");
+}
+
+#[test]
+fn link_file_fallback_chain() {
+    fn fail(_: &Path, _: &Path) -> io::Result<()> {
+        Err(io::ErrorKind::PermissionDenied.into())
+    }
+    fn hard_link(src: &Path, dest: &Path) -> io::Result<()> {
+        fs::hard_link(src, dest)
+    }
+
+    let dir = tmpdir("link-file");
+    let src = dir.join("LICENSE-MIT");
+    let dest = dir.join("share/LICENSE-MIT");
+    t!(fs::create_dir_all(dir.join("share")));
+    t!(fs::write(&src, "license"));
+
+    // Symlinks need Developer Mode on Windows; without it the hard link is used.
+    let kind = t!(link_file_with(
+        &src,
+        &dest,
+        &[
+            (LinkKind::Symlink, &fail),
+            (LinkKind::HardLink, &hard_link),
+            (LinkKind::Copy, &copy_file)
+        ],
+    ));
+    assert_eq!(kind, LinkKind::HardLink);
+    assert_eq!(t!(fs::read_to_string(&dest)), "license");
+
+    // Hard links fail across filesystems, leaving only a copy.
+    let kind = t!(link_file_with(
+        &src,
+        &dest,
+        &[(LinkKind::Symlink, &fail), (LinkKind::HardLink, &fail), (LinkKind::Copy, &copy_file)],
+    ));
+    assert_eq!(kind, LinkKind::Copy);
+    assert_eq!(t!(fs::read_to_string(&dest)), "license");
+
+    let err = link_file_with(&src, &dest, &[(LinkKind::Symlink, &fail)]).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+    assert!(!dest.exists());
+}
+
+#[test]
+fn symlink_file_replaces_stale_link() {
+    let dir = tmpdir("symlink-file");
+    let src = dir.join("rustc");
+    let dest = dir.join("bin-rustc");
+    t!(fs::write(&src, "new"));
+    let config = Config::default_opts();
+
+    // A dangling link left behind by an earlier build.
+    #[cfg(unix)]
+    t!(std::os::unix::fs::symlink(dir.join("gone"), &dest));
+    let kind = t!(symlink_file(&config, &src, &dest));
+    if cfg!(unix) {
+        assert_eq!(kind, LinkKind::Symlink);
+    }
+    assert_eq!(t!(fs::read_to_string(&dest)), "new");
+
+    assert_eq!(t!(hardlink_file(&config, &src, &dest)), LinkKind::HardLink);
+    assert!(!t!(fs::symlink_metadata(&dest)).file_type().is_symlink());
+    assert_eq!(t!(fs::read_to_string(&dest)), "new");
+}
+
+#[cfg(unix)]
+#[test]
+fn copy_recursive_hard_links() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let dir = tmpdir("copy-hard-links");
+    let src = dir.join("src");
+    let dest = dir.join("dest");
+    t!(fs::create_dir_all(src.join("lib/rustlib")));
+    t!(fs::write(src.join("rustc"), "rustc"));
+    t!(fs::set_permissions(src.join("rustc"), fs::Permissions::from_mode(0o755)));
+    t!(fs::write(src.join("lib/rustlib/libstd.rlib"), "std"));
+    t!(std::os::unix::fs::symlink("rustlib/libstd.rlib", src.join("lib/libstd.rlib")));
+    t!(fs::write(src.join("manifest.in"), "file:bin/rustc"));
+
+    let modified = |path: &Path| path == Path::new("manifest.in");
+    let opts =
+        CopyOpts { links: FollowLinks::No, hard_link: Some(&modified), ..Default::default() };
+    assert_eq!(t!(copy_recursive(&Config::default_opts(), &src, &dest, &opts)), 4);
+    // Linking again replaces the existing files.
+    assert_eq!(t!(copy_recursive(&Config::default_opts(), &src, &dest, &opts)), 4);
+
+    let ino = |path: &Path| t!(fs::metadata(path)).ino();
+    assert_eq!(ino(&src.join("rustc")), ino(&dest.join("rustc")));
+    let rlib = "lib/rustlib/libstd.rlib";
+    assert_eq!(ino(&src.join(rlib)), ino(&dest.join(rlib)));
+    assert_eq!(t!(fs::metadata(src.join(rlib))).nlink(), 2);
+    assert_eq!(t!(fs::metadata(dest.join("rustc"))).permissions().mode() & 0o777, 0o755);
+    // Files that will be modified are copied, so writing to them leaves the
+    // originals alone.
+    assert_ne!(ino(&src.join("manifest.in")), ino(&dest.join("manifest.in")));
+    assert_eq!(t!(fs::metadata(src.join("manifest.in"))).nlink(), 1);
+    t!(fs::write(dest.join("manifest.in"), "file:bin/cargo"));
+    assert_eq!(t!(fs::read_to_string(src.join("manifest.in"))), "file:bin/rustc");
+    // Symlinks stay symlinks.
+    let link = dest.join("lib/libstd.rlib");
+    assert_eq!(t!(fs::read_link(&link)), Path::new("rustlib/libstd.rlib"));
+}
+
+#[test]
+fn dylib_audit_is_prepended() {
+    let (first, second) =
+        (PathBuf::from("/opt/trace/libaudit.so"), PathBuf::from("/opt/libcount.so"));
+    let existing: Vec<_> =
+        env::var_os("LD_AUDIT").map_or(vec![], |v| env::split_paths(&v).collect());
+    let mut cmd = Command::new("true");
+    add_dylib_audit(first.clone(), &mut cmd);
+    let list = lookup_path(&cmd, "LD_AUDIT");
+    assert_eq!(list[0], first);
+    assert_eq!(list[1..], existing[..]);
+
+    // A second library keeps the first, and adding it again doesn't repeat it.
+    add_dylib_audit(second.clone(), &mut cmd);
+    add_dylib_audit(second.clone(), &mut cmd);
+    let list = lookup_path(&cmd, "LD_AUDIT");
+    assert_eq!(list[..2], [second, first]);
+    assert_eq!(list[2..], existing[..]);
+}
+
+/// The lookup path `var` as set on `cmd`.
+fn lookup_path(cmd: &Command, var: &str) -> Vec<PathBuf> {
+    let (_, value) = cmd.get_envs().find(|(key, _)| *key == var).unwrap();
+    env::split_paths(value.unwrap()).collect()
+}
+
+#[test]
+fn add_dylib_path_merges_repeated_calls() {
+    let var = dylib_path_var();
+    let paths = |list: &[&str]| list.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+    let mut cmd = Command::new("true");
+    cmd.env(var, t!(env::join_paths(["/usr/lib", "/opt/a"])));
+    add_dylib_path(paths(&["/opt/b", "/opt/a"]), PathPlacement::Prepend, &mut cmd);
+    add_dylib_path(paths(&["/opt/c", "/opt/b"]), PathPlacement::Prepend, &mut cmd);
+    assert_eq!(lookup_path(&cmd, var), paths(&["/opt/c", "/opt/b", "/opt/a", "/usr/lib"]));
+
+    let mut cmd = Command::new("true");
+    cmd.env(var, t!(env::join_paths(["/usr/lib", "/opt/a"])));
+    add_dylib_path(paths(&["/opt/b", "/usr/lib"]), PathPlacement::Append, &mut cmd);
+    add_dylib_path(paths(&["/opt/c", "/opt/a"]), PathPlacement::Append, &mut cmd);
+    assert_eq!(lookup_path(&cmd, var), paths(&["/usr/lib", "/opt/a", "/opt/b", "/opt/c"]));
+
+    // A variable removed from the command starts out empty rather than
+    // picking up our environment again.
+    let mut cmd = Command::new("true");
+    cmd.env_remove(var);
+    add_dylib_path(paths(&["/opt/a"]), PathPlacement::Append, &mut cmd);
+    add_dylib_path(paths(&["/opt/b", "/opt/a"]), PathPlacement::Prepend, &mut cmd);
+    assert_eq!(lookup_path(&cmd, var), paths(&["/opt/b", "/opt/a"]));
+}
+
+#[test]
+fn record_lookup_paths_labels_added_directories() {
+    let var = dylib_path_var();
+    let mut cmd = Command::new("true");
+    add_dylib_path(vec![PathBuf::from("/opt/a")], PathPlacement::Prepend, &mut cmd);
+    let entries = record_lookup_paths(|| {
+        label_lookup_paths("step", || {
+            add_dylib_path(vec![PathBuf::from("/opt/b")], PathPlacement::Prepend, &mut cmd);
+        });
+        add_dylib_path(vec![PathBuf::from("/opt/c")], PathPlacement::Prepend, &mut cmd);
+    });
+    let entries: Vec<_> = entries.into_iter().filter(|entry| entry.var == var).collect();
+    let entry = |dir: &str, label: &str| LookupPathEntry {
+        var: var.to_string(),
+        dir: PathBuf::from(dir),
+        label: label.into(),
+    };
+    assert_eq!(entries, [entry("/opt/b", "step"), entry("/opt/c", "bootstrap")]);
+
+    // Recording stops with the closure, even if it panics.
+    add_dylib_path(vec![PathBuf::from("/opt/d")], PathPlacement::Prepend, &mut cmd);
+    assert!(record_lookup_paths(|| {}).is_empty());
+    let panicked = std::panic::catch_unwind(|| record_lookup_paths(|| panic!("step failed")));
+    assert!(panicked.is_err());
+    assert!(LOOKUP_PATH_LOG.with(|log| log.borrow().is_none()));
+}
+
+#[test]
+#[cfg(windows)]
+fn dll_load_failure_hint_lists_path() {
+    // A copy of rustc away from its sysroot can't find the rustc_driver DLL.
+    let sysroot = output(Command::new("rustc").args(&["--print", "sysroot"]));
+    let dir = tmpdir("dll-load-failure");
+    let rustc = dir.join("rustc.exe");
+    t!(fs::copy(Path::new(sysroot.trim()).join("bin").join("rustc.exe"), &rustc));
+
+    let mut cmd = Command::new(&rustc);
+    cmd.arg("--version").env("PATH", &dir);
+    let status = t!(cmd.status());
+    let hint = dll_load_failure_hint(&cmd, status).expect("no hint for a missing DLL");
+    assert!(hint.contains("failed to locate a DLL"), "{}", hint);
+    assert!(hint.contains(&dir.display().to_string()), "{}", hint);
+    assert!(!try_run(&mut cmd, false));
+}
+
+#[test]
+#[cfg(unix)]
+fn run_with_env_override_restores_variables() {
+    // Set only for the run, overriding what `cmd` set, and unset by `cmd`.
+    let (set, overridden, unset) =
+        ("RUSTBUILD_TEST_SET", "RUSTBUILD_TEST_OVERRIDE", "RUSTBUILD_TEST_UNSET");
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(format!(
+        r#"test "${}" = 1 && test "${}" = 2 && test "${}" = 3"#,
+        set, overridden, unset
+    ));
+    cmd.env(overridden, "original");
+    cmd.env_remove(unset);
+    let overrides = [(set, "1"), (overridden, "2"), (unset, "3")];
+    assert!(run_with_env_override(&mut cmd, &overrides, false));
+
+    let env: HashMap<_, _> = cmd.get_envs().collect();
+    assert_eq!(env[OsStr::new(set)], None);
+    assert_eq!(env[OsStr::new(overridden)], Some(OsStr::new("original")));
+    assert_eq!(env[OsStr::new(unset)], None);
+
+    // Restored after a failed run as well.
+    assert!(!run_with_env_override(&mut cmd, &[(set, "1")], false));
+    assert_eq!(cmd.get_envs().find(|&(k, _)| k == set), Some((OsStr::new(set), None)));
+}
+
+#[test]
+#[cfg(unix)]
+fn dll_load_failure_hint_ignores_other_failures() {
+    let mut cmd = Command::new("false");
+    let status = t!(cmd.status());
+    assert_eq!(dll_load_failure_hint(&cmd, status), None);
+}
+
+#[test]
+fn add_link_lib_path_merges_repeated_calls() {
+    let var = link_lib_path_var();
+    let paths = |list: &[&str]| list.iter().map(PathBuf::from).collect::<Vec<_>>();
+
+    // Without a value on the command, the first call starts from ours.
+    let mut cmd = Command::new("true");
+    add_link_lib_path(paths(&["/opt/llvm/lib"]), PathPlacement::Prepend, &mut cmd);
+    add_link_lib_path(paths(&["/opt/extra", "/opt/llvm/lib"]), PathPlacement::Append, &mut cmd);
+    let mut expected = paths(&["/opt/llvm/lib"]);
+    if let Some(existing) = env::var_os(var) {
+        for path in env::split_paths(&existing) {
+            if !expected.contains(&path) {
+                expected.push(path);
+            }
+        }
+    }
+    if !expected.contains(&PathBuf::from("/opt/extra")) {
+        expected.push(PathBuf::from("/opt/extra"));
+    }
+    assert_eq!(lookup_path(&cmd, var), expected);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn dylib_path_vars_linux() {
+    assert_eq!(dylib_path_var(), "LD_LIBRARY_PATH");
+    assert_eq!(dylib_fallback_path_var(), None);
+}
+
+#[cfg(target_os = "aix")]
+#[test]
+fn dylib_path_vars_aix() {
+    assert_eq!(dylib_path_var(), "LIBPATH");
+    assert_eq!(dylib_fallback_path_var(), None);
+}
+
+#[cfg(target_os = "haiku")]
+#[test]
+fn dylib_path_vars_haiku() {
+    assert_eq!(dylib_path_var(), "LIBRARY_PATH");
+    assert_eq!(dylib_fallback_path_var(), None);
+}
+
+#[cfg(target_os = "macos")]
+#[test]
+fn add_dylib_path_sets_fallback_on_macos() {
+    let libdir = PathBuf::from("/build/stage1/lib");
+    let mut cmd = Command::new("true");
+    add_dylib_path(vec![libdir.clone()], PathPlacement::Prepend, &mut cmd);
+    assert_eq!(lookup_path(&cmd, "DYLD_LIBRARY_PATH")[0], libdir);
+    let fallback = lookup_path(&cmd, "DYLD_FALLBACK_LIBRARY_PATH");
+    assert_eq!(fallback[0], libdir);
+    if env::var_os("DYLD_FALLBACK_LIBRARY_PATH").is_none() {
+        assert!(fallback.contains(&PathBuf::from("/usr/lib")), "{:?}", fallback);
+    }
+}
+
+#[test]
+fn dylib_path_var_for_cross_targets() {
+    let cases = [
+        ("linux", "x86_64-unknown-linux-gnu", "LD_LIBRARY_PATH"),
+        ("linux", "x86_64-pc-windows-gnu", "WINEPATH"),
+        ("linux", "aarch64-unknown-linux-gnu", "LD_LIBRARY_PATH"),
+        ("macos", "x86_64-pc-windows-gnu", "WINEPATH"),
+        ("macos", "aarch64-apple-darwin", "DYLD_LIBRARY_PATH"),
+        ("windows", "x86_64-pc-windows-msvc", "PATH"),
+        ("windows", "i686-pc-windows-gnu", "PATH"),
+        ("linux", "x86_64-unknown-haiku", "LIBRARY_PATH"),
+        ("linux", "powerpc64-ibm-aix", "LIBPATH"),
+    ];
+    for (host_os, target, var) in cases.iter() {
+        let target = TargetSelection::from_user(target);
+        assert_eq!(dylib_path_var_on(host_os, target), *var, "{} on {}", target, host_os);
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn add_dylib_path_for_target_uses_winepath() {
+    let windows = TargetSelection::from_user("x86_64-pc-windows-gnu");
+    let mut cmd = Command::new("true");
+    cmd.env("WINEPATH", "C:\\windows;/opt/a");
+    add_dylib_path_for_target(vec!["/opt/b".into(), "/opt/a".into()], &mut cmd, windows);
+    let (_, value) = cmd.get_envs().find(|(key, _)| *key == "WINEPATH").unwrap();
+    assert_eq!(value.unwrap(), "/opt/b;/opt/a;C:\\windows");
+
+    // A target the host can run natively keeps the host's variable.
+    let linux = TargetSelection::from_user("aarch64-unknown-linux-gnu");
+    let mut cmd = Command::new("true");
+    cmd.env("LD_LIBRARY_PATH", "/usr/lib");
+    add_dylib_path_for_target(vec!["/opt/b".into()], &mut cmd, linux);
+    assert_eq!(lookup_path(&cmd, "LD_LIBRARY_PATH"), [Path::new("/opt/b"), Path::new("/usr/lib")]);
+}
+
+#[test]
+fn default_lookup_path_keeps_dyld_fallbacks() {
+    let list = default_lookup_path("DYLD_FALLBACK_LIBRARY_PATH");
+    assert!(list.ends_with(&[
+        PathBuf::from("/usr/local/lib"),
+        PathBuf::from("/lib"),
+        PathBuf::from("/usr/lib")
+    ]));
+    assert!(default_lookup_path("LD_LIBRARY_PATH").is_empty());
+}
+
+#[test]
+fn lookup_paths_dedup_ignores_trailing_separators() {
+    let mut list: Vec<PathBuf> =
+        ["/opt/a", "/opt/b/", "/opt/a/", "/", "/opt/b", "//"].iter().map(PathBuf::from).collect();
+    dedup_lookup_paths(&mut list);
+    assert_eq!(list, [Path::new("/opt/a"), Path::new("/opt/b/"), Path::new("/")]);
+}
+
+#[test]
+fn fit_lookup_path_drops_missing_directories() {
+    let dir = tmpdir("fit-lookup-path");
+    let existing = dir.join("lib");
+    t!(fs::create_dir_all(&existing));
+    let missing = dir.join("a-directory-that-was-never-created");
+    let list = vec![missing.clone(), existing.clone(), missing.join("nested")];
+
+    // Under the limit, nothing is dropped.
+    let len = env_var_len("PATH", &list);
+    assert_eq!(fit_lookup_path("PATH", list.clone(), len).unwrap(), list);
+
+    // Over it, the directories that don't exist go first.
+    let fitted = fit_lookup_path("PATH", list.clone(), len - 1).unwrap();
+    assert_eq!(fitted, [existing]);
+    assert!(env_var_len("PATH", &fitted) < len);
+
+    // When even that isn't enough, the error says what and by how much.
+    let err = fit_lookup_path("PATH", list, 8).unwrap_err();
+    assert!(err.contains("PATH"), "{}", err);
+    assert!(err.contains(&len.to_string()), "{}", err);
+    assert!(err.contains("limit of 8"), "{}", err);
+}
+
+#[test]
+fn write_if_changed_preserves_mtime() {
+    let dir = tmpdir("write-if-changed");
+    let path = dir.join("version");
+    t!(write_file_atomic(&path, "1.61.0"));
+    let old = SystemTime::now() - Duration::from_secs(3600);
+    set_mtime(&path, old);
+
+    assert!(!t!(write_if_changed(&path, "1.61.0")));
+    assert_eq!(mtime(&path), old);
+
+    assert!(t!(write_if_changed(&path, "1.62.0")));
+    assert_eq!(t!(fs::read_to_string(&path)), "1.62.0");
+    assert!(mtime(&path) > old);
+    // The temporary file was renamed into place.
+    assert_eq!(t!(fs::read_dir(&dir)).count(), 1);
+}
+
+#[test]
+fn remove_tree_keeps_link_targets() {
+    let dir = tmpdir("remove-tree");
+    let outside = dir.join("outside");
+    let tree = dir.join("tree");
+    t!(fs::create_dir_all(&outside));
+    t!(fs::write(outside.join("keep"), ""));
+    t!(fs::create_dir_all(tree.join("a/b")));
+    t!(fs::write(tree.join("a/b/file"), ""));
+    #[cfg(unix)]
+    t!(std::os::unix::fs::symlink(&outside, tree.join("a/link")));
+
+    // The walk used on Windows, which also works elsewhere.
+    t!(remove_tree_retrying(&tree));
+    assert!(!tree.exists());
+    assert!(outside.join("keep").exists());
+    t!(remove_dir_all_retrying(&tree));
+}
+
+#[cfg(windows)]
+#[test]
+fn remove_dir_all_retries_locked_files() {
+    use std::os::windows::fs::OpenOptionsExt;
+    const FILE_SHARE_READ: u32 = 1;
+
+    let dir = tmpdir("remove-locked");
+    let locked = dir.join("out/test.exe");
+    t!(fs::create_dir_all(locked.parent().unwrap()));
+    t!(fs::write(&locked, ""));
+    let open = || t!(fs::OpenOptions::new().read(true).share_mode(FILE_SHARE_READ).open(&locked));
+
+    // Released while the removal is being retried.
+    let file = open();
+    let release = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(200));
+        drop(file);
+    });
+    t!(remove_dir_all_retrying(&dir.join("out")));
+    release.join().unwrap();
+    assert!(!dir.join("out").exists());
+
+    // Never released: the error names the locked file.
+    t!(fs::create_dir_all(locked.parent().unwrap()));
+    t!(fs::write(&locked, ""));
+    let file = open();
+    let err = remove_dir_all_retrying(&dir.join("out")).unwrap_err();
+    assert!(err.to_string().contains(&*locked.to_string_lossy()), "{}", err);
+    drop(file);
+}
+
+#[cfg(unix)]
+#[test]
+fn copy_recursive_links_and_filter() {
+    use std::os::unix::fs::symlink;
+
+    let dir = tmpdir("copy-opts");
+    let src = dir.join("src");
+    t!(fs::create_dir_all(src.join("lib/.git")));
+    t!(fs::write(src.join("lib/lib.rs"), "lib"));
+    t!(fs::write(src.join("lib/.git/HEAD"), ""));
+    t!(symlink("lib/lib.rs", src.join("link.rs")));
+    let config = Config::default_opts();
+
+    let follow = dir.join("follow");
+    let not_git = |path: &Path| !path.ends_with(".git");
+    let opts = CopyOpts { filter: Some(&not_git), ..Default::default() };
+    assert_eq!(t!(copy_recursive(&config, &src, &follow, &opts)), 2);
+    assert!(!t!(fs::symlink_metadata(follow.join("link.rs"))).file_type().is_symlink());
+    assert_eq!(t!(fs::read_to_string(follow.join("link.rs"))), "lib");
+    assert!(!follow.join("lib/.git").exists());
+
+    let links = dir.join("links");
+    let opts = CopyOpts { links: FollowLinks::No, ..Default::default() };
+    assert_eq!(t!(copy_recursive(&config, &src, &links, &opts)), 3);
+    assert_eq!(t!(fs::read_link(links.join("link.rs"))), Path::new("lib/lib.rs"));
+    assert!(links.join("lib/.git/HEAD").exists());
+    // Copying again replaces the link rather than writing through it.
+    assert_eq!(t!(copy_recursive(&config, &src, &links, &opts)), 3);
+    // What the filter now excludes is removed from an earlier copy.
+    let opts = CopyOpts { links: FollowLinks::No, filter: Some(&not_git), ..Default::default() };
+    assert_eq!(t!(copy_recursive(&config, &src, &links, &opts)), 2);
+    assert!(!links.join("lib/.git").exists());
+    assert!(links.join("lib/lib.rs").exists());
+    // Pruning also removes what was deleted from the source since.
+    t!(fs::write(links.join("lib/stale.rs"), ""));
+    t!(fs::create_dir_all(links.join("stale")));
+    let opts = CopyOpts { prune: true, ..opts };
+    assert_eq!(t!(copy_recursive(&config, &src, &links, &opts)), 2);
+    assert!(!links.join("lib/stale.rs").exists());
+    assert!(!links.join("stale").exists());
+    assert!(links.join("lib/lib.rs").exists());
+
+    let mut dry_run = Config::default_opts();
+    dry_run.dry_run = true;
+    assert_eq!(t!(copy_recursive(&dry_run, &src, &dir.join("dry"), &opts)), 0);
+    assert!(!dir.join("dry").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn prune_dangling_links_removes_broken_links() {
+    use std::os::unix::fs::symlink;
+
+    let dir = tmpdir("dangling");
+    let sysroot = dir.join("stage1");
+    let bin = sysroot.join("bin");
+    let lib = sysroot.join("lib/rustlib");
+    t!(fs::create_dir_all(&bin));
+    t!(fs::create_dir_all(&lib));
+    t!(fs::write(bin.join("rustc"), ""));
+    t!(fs::create_dir_all(dir.join("outside")));
+    t!(symlink("rustc", bin.join("rustc-link")));
+    t!(symlink("rustdoc", bin.join("rustdoc")));
+    t!(symlink(dir.join("stage0/lib/librustc_driver.so"), lib.join("librustc_driver.so")));
+    t!(symlink(dir.join("outside"), lib.join("src")));
+    // Links to directories are kept but not walked into.
+    t!(symlink("missing", dir.join("outside/dangling")));
+
+    let mut removed = t!(prune_dangling_links(&sysroot));
+    removed.sort();
+    assert_eq!(removed, [bin.join("rustdoc"), lib.join("librustc_driver.so")]);
+    assert!(bin.join("rustc-link").exists());
+    assert!(lib.join("src").exists());
+    assert!(t!(fs::symlink_metadata(dir.join("outside/dangling"))).file_type().is_symlink());
+
+    assert!(t!(prune_dangling_links(&sysroot)).is_empty());
+    assert!(t!(prune_dangling_links(&dir.join("stage2"))).is_empty());
+}
+
+#[test]
+fn exe_suffix_per_target() {
+    let exe = |target| exe_name("rustc", TargetSelection::from_user(target));
+    assert_eq!(exe("x86_64-unknown-linux-gnu"), "rustc");
+    assert_eq!(exe("aarch64-apple-darwin"), "rustc");
+    assert_eq!(exe("x86_64-pc-windows-msvc"), "rustc.exe");
+    assert_eq!(exe("i686-pc-windows-gnu"), "rustc.exe");
+    assert_eq!(exe("wasm32-wasi"), "rustc.wasm");
+    assert_eq!(exe("wasm32-unknown-unknown"), "rustc.wasm");
+    assert_eq!(exe("wasm64-unknown-unknown"), "rustc.wasm");
+    assert_eq!(exe("wasm32-unknown-emscripten"), "rustc.js");
+    assert_eq!(exe("x86_64-unknown-uefi"), "rustc.efi");
+    assert_eq!(exe("aarch64-unknown-uefi"), "rustc.efi");
+}
+
+#[test]
+fn exe_preserves_paths() {
+    let windows = TargetSelection::from_user("x86_64-pc-windows-msvc");
+    let linux = TargetSelection::from_user("x86_64-unknown-linux-gnu");
+    assert_eq!(exe("rustc", windows), Path::new("rustc.exe"));
+    assert_eq!(exe("stage1/bin/rustc", windows), Path::new("stage1/bin/rustc.exe"));
+    assert_eq!(exe("build.v2/bin/rustc", windows), Path::new("build.v2/bin/rustc.exe"));
+    assert_eq!(
+        exe(Path::new("/opt/llvm/bin").join("FileCheck"), linux),
+        Path::new("/opt/llvm/bin/FileCheck")
+    );
+    assert_eq!(exe("stage1/bin/rustc", linux), Path::new("stage1/bin/rustc"));
+
+    // The suffix isn't doubled.
+    assert_eq!(exe("rustc.exe", windows), Path::new("rustc.exe"));
+    assert_eq!(exe("bin/rustc.exe", windows), Path::new("bin/rustc.exe"));
+    assert_eq!(exe_name("rustc.exe", windows), "rustc.exe");
+}
+
+/// A custom target in the style of a hobby OS, which uses Windows-style
+/// file names but isn't recognizable as Windows from its name.
+const CUSTOM_TARGET_SPEC: &str = r#"{
+  "arch": "x86_64",
+  "data-layout": "e-m:w-p270:32:32-p271:32:32-p272:64:64-i64:64-f80:128-n8:16:32:64-S128",
+  "dll-prefix": "",
+  "dll-suffix": ".dll",
+  "exe-suffix": ".exe",
+  "is-builtin": false,
+  "linker-flavor": "ld.lld",
+  "llvm-target": "x86_64-unknown-none",
+  "os": "windows",
+  "target-pointer-width": "64"
+}"#;
+
+#[test]
+fn target_spec_parse() {
+    let spec = TargetSpec::parse(CUSTOM_TARGET_SPEC).unwrap();
+    assert_eq!(spec.os, "windows");
+    assert_eq!(spec.dll_prefix, "");
+    assert_eq!(spec.dll_suffix, ".dll");
+    assert_eq!(spec.exe_suffix, ".exe");
+    assert!(!spec.uses_special_linker());
+
+    // Defaults are omitted by rustc.
+    let spec = TargetSpec::parse(r#"{"arch": "riscv32"}"#).unwrap();
+    assert_eq!(spec.os, "none");
+    assert_eq!(spec.dll_prefix, "lib");
+    assert_eq!(spec.dll_suffix, ".so");
+    assert_eq!(spec.exe_suffix, "");
+    assert!(!spec.uses_special_linker());
+
+    assert_eq!(TargetSpec::parse("error: unknown target"), None);
+}
+
+#[test]
+fn target_spec_linker_flavors() {
+    let special = |flavor: &str| {
+        TargetSpec { linker_flavor: Some(flavor.to_string()), ..TargetSpec::parse("{}").unwrap() }
+            .uses_special_linker()
+    };
+    for flavor in ["gcc", "ld", "msvc", "ld.lld", "lld-link", "gnu-cc", "gnu-lld", "darwin-cc"] {
+        assert!(!special(flavor), "{}", flavor);
+    }
+    for flavor in ["em", "em-cc", "wasm-ld", "wasm-lld", "ptx-linker", "ptx", "bpf-linker"] {
+        assert!(special(flavor), "{}", flavor);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn target_spec_custom_target_file() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let dir = tmpdir("target-spec");
+    let target_file = dir.join("my-os.json");
+    t!(fs::write(&target_file, CUSTOM_TARGET_SPEC));
+    let target = TargetSelection::from_user(target_file.to_str().unwrap());
+    assert_eq!(target.triple, "my-os");
+
+    // A stand-in for rustc which logs its arguments and prints the spec.
+    let rustc = dir.join("rustc");
+    let log = dir.join("log");
+    t!(fs::write(
+        &rustc,
+        format!("#!/bin/sh\necho \"$@\" >> {}\ncat {}\n", log.display(), target_file.display()),
+    ));
+    t!(fs::set_permissions(&rustc, fs::Permissions::from_mode(0o755)));
+
+    let spec = target_spec_from(&rustc, target).unwrap();
+    assert_eq!(spec, TargetSpec::parse(CUSTOM_TARGET_SPEC).unwrap());
+    assert_eq!(target_spec_from(&rustc, target), Some(spec));
+    let log = t!(fs::read_to_string(&log));
+    assert_eq!(
+        log.lines().collect::<Vec<_>>(),
+        [format!(
+            "--print target-spec-json -Z unstable-options --target {}",
+            target_file.display()
+        )]
+    );
+
+    assert_eq!(target_spec_from(&dir.join("missing-rustc"), target), None);
+}
+
+#[test]
+fn is_dylib_versioned_names() {
+    let cases = [
+        ("libLLVM-14.so", true),
+        ("libLLVM-14.so.1", true),
+        ("libLLVM-14-rust-1.61.0-stable.so", true),
+        ("libLLVM.dylib", true),
+        ("libLLVM-14.dylib", true),
+        ("libstdc++.so.6", true),
+        ("libstdc++.so.6.0.30", true),
+        ("libgcc_s.so.1", true),
+        ("liblldb.so.14.0.6", true),
+        ("libc++.1.dylib", true),
+        ("libc++.1.0.dylib", true),
+        ("LLVM-C.dll", true),
+        ("libLLVMSupport.a", false),
+        ("libstdc++.so.6.0.30-gdb.py", false),
+        ("readme.so.txt", false),
+        ("libfoo.so.", false),
+        ("libfoo.so.1.", false),
+        ("libfoo.so.1a", false),
+        ("libfoo.sox", false),
+    ];
+    for (name, expected) in cases {
+        assert_eq!(is_dylib(name), expected, "{}", name);
+    }
+}
+
+#[test]
+fn dylib_variants_strip_versions() {
+    let cases: &[(&str, &[&str])] = &[
+        ("libLLVM-14.so", &["libLLVM-14.so"]),
+        ("libLLVM-14.so.1", &["libLLVM-14.so.1", "libLLVM-14.so"]),
+        (
+            "libstdc++.so.6.0.30",
+            &["libstdc++.so.6.0.30", "libstdc++.so.6.0", "libstdc++.so.6", "libstdc++.so"],
+        ),
+        ("libc++.1.0.dylib", &["libc++.1.0.dylib", "libc++.1.dylib", "libc++.dylib"]),
+        ("libLLVM-14.dylib", &["libLLVM-14.dylib"]),
+        ("LLVM-C.dll", &["LLVM-C.dll"]),
+    ];
+    for (name, expected) in cases {
+        assert_eq!(dylib_variants(name), *expected, "{}", name);
+    }
+}
+
+#[test]
+fn is_debug_info_split_debuginfo() {
+    let dir = tmpdir("is_debug_info");
+    let bundle = dir.join("librustc_driver.dylib.dSYM");
+    t!(fs::create_dir_all(&bundle));
+    let not_a_bundle = dir.join("notes.dSYM");
+    t!(fs::write(&not_a_bundle, ""));
+
+    let debug_info = [
+        PathBuf::from("rustc_driver-1234.pdb"),
+        PathBuf::from("librustc_driver-1234.so.dwp"),
+        PathBuf::from("rustc_driver-1234.rustc_driver.abcd-cgu.0.rcgu.dwo"),
+        PathBuf::from("libstd-1234.so.debug"),
+        bundle,
+    ];
+    for path in debug_info.iter() {
+        assert!(is_debug_info(path), "{}", path.display());
+        assert!(!is_dylib(path.file_name().unwrap().to_str().unwrap()), "{}", path.display());
+    }
+    for name in ["librustc_driver-1234.so", "libstd-1234.rlib", "rustc.exe", "foo.pdb.txt"] {
+        assert!(!is_debug_info(Path::new(name)), "{}", name);
+    }
+    assert!(!is_debug_info(&not_a_bundle));
+}
+
+#[test]
+fn debuginfo_companions_per_platform() {
+    let dir = tmpdir("debuginfo_companions");
+    let mut config = Config::default_opts();
+    let companions = |config: &Config, artifact: &str, target: &str| {
+        debuginfo_companions(config, &dir.join(artifact), TargetSelection::from_user(target), &[])
+    };
+
+    // MSVC always writes `.pdb` files, named after the crate for executables.
+    let msvc = "x86_64-pc-windows-msvc";
+    assert_eq!(
+        companions(&config, "rustc_driver-1234.dll", msvc),
+        [dir.join("rustc_driver-1234.pdb")]
+    );
+    assert_eq!(
+        companions(&config, "rustc-main.exe", msvc),
+        [dir.join("rustc-main.pdb"), dir.join("rustc_main.pdb")]
+    );
+
+    // Apple targets only pack debug info into `.dSYM` bundles with dsymutil.
+    let apple = "aarch64-apple-darwin";
+    assert_eq!(companions(&config, "librustc_driver-1234.dylib", apple), Vec::<PathBuf>::new());
+    config.rust_run_dsymutil = true;
+    assert_eq!(
+        companions(&config, "librustc_driver-1234.dylib", apple),
+        [dir.join("librustc_driver-1234.dylib.dSYM")]
+    );
+
+    // Linux keeps debug info in the binaries unless configured otherwise.
+    let linux = "x86_64-unknown-linux-gnu";
+    assert_eq!(companions(&config, "librustc_driver-1234.so", linux), Vec::<PathBuf>::new());
+    config.rust_split_debuginfo = Some(SplitDebuginfo::Packed);
+    assert_eq!(
+        companions(&config, "librustc_driver-1234.so", linux),
+        [dir.join("librustc_driver-1234.so.dwp")]
+    );
+
+    config.rust_split_debuginfo = Some(SplitDebuginfo::Unpacked);
+    for name in [
+        "librustc_driver-1234.so",
+        "rustc_driver-1234.rustc_driver.a1-cgu.0.rcgu.dwo",
+        "rustc_driver-1234.rustc_driver.a1-cgu.1.rcgu.dwo",
+        "rustc_driver-5678.rustc_driver.b2-cgu.0.rcgu.dwo",
+        "rustc_driver_impl-1234.rustc_driver_impl.c3-cgu.0.rcgu.dwo",
+        "rustc-main",
+        "rustc_main-9abc.rustc_main.d4-cgu.0.rcgu.dwo",
+    ] {
+        t!(fs::write(dir.join(name), ""));
+    }
+    assert_eq!(
+        companions(&config, "librustc_driver-1234.so", linux),
+        [
+            dir.join("rustc_driver-1234.rustc_driver.a1-cgu.0.rcgu.dwo"),
+            dir.join("rustc_driver-1234.rustc_driver.a1-cgu.1.rcgu.dwo"),
+        ]
+    );
+    assert_eq!(
+        companions(&config, "rustc-main", linux),
+        [dir.join("rustc_main-9abc.rustc_main.d4-cgu.0.rcgu.dwo")]
+    );
+    // Unpacked debug info stays in the object files on Apple platforms.
+    assert_eq!(companions(&config, "librustc_driver-1234.dylib", apple), Vec::<PathBuf>::new());
+}
+
+#[test]
+fn copy_debuginfo_from_build_dir() {
+    let dir = tmpdir("copy_debuginfo");
+    let (sysroot, out, image) = (dir.join("sysroot"), dir.join("out"), dir.join("image"));
+    let deps = out.join("deps");
+    for d in [&sysroot, &deps, &image] {
+        t!(fs::create_dir_all(d));
+    }
+    // The sysroot holds a copy of the dylib, while its debug info was left
+    // where cargo built it.
+    let dylib = sysroot.join("librustc_driver-1234.so");
+    t!(fs::write(&dylib, ""));
+    t!(fs::write(deps.join("librustc_driver-1234.so"), ""));
+    let dwo = "rustc_driver-1234.rustc_driver.a1-cgu.0.rcgu.dwo";
+    t!(fs::write(deps.join(dwo), "debug info"));
+    t!(fs::write(deps.join("std-5678.std.b2-cgu.0.rcgu.dwo"), ""));
+
+    let mut config = Config::default_opts();
+    config.rust_split_debuginfo = Some(SplitDebuginfo::Unpacked);
+    let linux = TargetSelection::from_user("x86_64-unknown-linux-gnu");
+    let build_dirs = [deps.clone(), out.clone()];
+    assert_eq!(t!(copy_debuginfo(&config, &dylib, linux, &[], &image)), 0);
+    assert_eq!(t!(copy_debuginfo(&config, &dylib, linux, &build_dirs, &image)), 1);
+    assert_eq!(t!(fs::read_to_string(image.join(dwo))), "debug info");
+    assert!(!image.join("std-5678.std.b2-cgu.0.rcgu.dwo").exists());
+
+    // The same goes for packed debug info, uplifted to the output directory.
+    config.rust_split_debuginfo = Some(SplitDebuginfo::Packed);
+    t!(fs::write(out.join("librustc_driver-1234.so.dwp"), "packed"));
+    assert_eq!(t!(copy_debuginfo(&config, &dylib, linux, &build_dirs, &image)), 1);
+    assert_eq!(t!(fs::read_to_string(image.join("librustc_driver-1234.so.dwp"))), "packed");
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn strip_file_keeps_binaries_working() {
+    let mut config = Config::default_opts();
+    let target = config.build;
+    // Machines without binutils or LLVM can't strip anything.
+    if strip_tool(config.build, target).is_none() {
+        return;
+    }
+    let dir = tmpdir("strip_file");
+    // The test binary itself is a handy executable with plenty to strip.
+    let original = dir.join("original");
+    t!(fs::copy(t!(env::current_exe()), &original));
+    let runs = |exe: &Path| {
+        let output = t!(Command::new(exe).arg("--list").output());
+        output.status.success() && !output.stdout.is_empty()
+    };
+
+    let contents = t!(fs::read(&original));
+    let bin = dir.join("bin");
+    t!(fs::hard_link(&original, &bin));
+    assert!(strip_file(&config, &bin, ArtifactKind::Exe, target));
+    assert!(runs(&bin));
+    assert!(t!(fs::metadata(&bin)).len() < t!(fs::metadata(&original)).len());
+    // The other hard link to the binary keeps its symbols.
+    assert_eq!(t!(fs::read(&original)), contents);
+    assert!(!dir.join("bin.debug").exists());
+
+    config.rust_debuginfo_packages = true;
+    let packaged = dir.join("packaged");
+    t!(fs::copy(&original, &packaged));
+    assert!(strip_file(&config, &packaged, ArtifactKind::Exe, target));
+    assert!(runs(&packaged));
+    assert!(dir.join("packaged.debug").is_file());
+
+    // Libraries that are linked against and scripts are left alone, and so
+    // is everything in a dry run.
+    let script = dir.join("rust-gdb");
+    t!(fs::write(&script, "#!/bin/sh\nexec gdb \"$@\"\n"));
+    assert!(!strip_file(&config, &script, ArtifactKind::Exe, target));
+    for (name, kind) in [
+        ("libstd-1234.rlib", ArtifactKind::Rlib),
+        ("libstd-1234.rmeta", ArtifactKind::Rmeta),
+        ("libstd-1234.a", ArtifactKind::StaticLib),
+    ] {
+        let path = dir.join(name);
+        t!(fs::copy(&original, &path));
+        assert!(!strip_file(&config, &path, kind, target), "{}", name);
+        assert_eq!(t!(fs::read(&path)), contents, "{}", name);
+    }
+    config.dry_run = true;
+    let dry = dir.join("dry");
+    t!(fs::copy(&original, &dry));
+    assert!(!strip_file(&config, &dry, ArtifactKind::Exe, target));
+}
+
+#[test]
+fn library_names_per_target() {
+    let staticlib = |target| staticlib("unwind", TargetSelection::from_user(target));
+    assert_eq!(staticlib("x86_64-pc-windows-msvc"), "unwind.lib");
+    assert_eq!(staticlib("aarch64-pc-windows-msvc"), "unwind.lib");
+    assert_eq!(staticlib("x86_64-pc-windows-gnu"), "libunwind.a");
+    assert_eq!(staticlib("x86_64-unknown-linux-gnu"), "libunwind.a");
+    assert_eq!(staticlib("aarch64-apple-darwin"), "libunwind.a");
+    assert_eq!(staticlib("wasm32-wasi"), "libunwind.a");
+
+    let dylib = |target| dylib("LLVM", TargetSelection::from_user(target));
+    assert_eq!(dylib("x86_64-pc-windows-msvc"), "LLVM.dll");
+    assert_eq!(dylib("x86_64-pc-windows-gnu"), "LLVM.dll");
+    assert_eq!(dylib("x86_64-unknown-linux-gnu"), "libLLVM.so");
+    assert_eq!(dylib("x86_64-apple-darwin"), "libLLVM.dylib");
+    assert_eq!(dylib("aarch64-apple-ios"), "libLLVM.dylib");
+}
+
+#[test]
+fn artifact_kind_per_target_family() {
+    use ArtifactKind::*;
+
+    let cases: &[(&str, &[(&str, ArtifactKind)])] = &[
+        (
+            "x86_64-unknown-linux-gnu",
+            &[
+                ("rustc-main", Exe),
+                ("rustc_driver-1234.d", Other),
+                ("librustc_driver-1234.so", Dylib),
+                ("libLLVM-14.so.1", Dylib),
+                ("libstd-1234.rlib", Rlib),
+                ("libstd-1234.rmeta", Rmeta),
+                ("libunwind.a", StaticLib),
+                ("libstd-1234.so.dwp", DebugInfo),
+                ("std-1234.std.abcd-cgu.0.rcgu.dwo", DebugInfo),
+                ("libstd-1234.so.debug", DebugInfo),
+                ("build-script-build.exe", Other),
+            ],
+        ),
+        (
+            "x86_64-apple-darwin",
+            &[
+                ("rustc-main", Exe),
+                ("librustc_driver-1234.dylib", Dylib),
+                ("libc++.1.dylib", Dylib),
+                ("libstd-1234.rlib", Rlib),
+                ("libclang_rt.asan_osx_dynamic.dylib", Dylib),
+                ("libunwind.a", StaticLib),
+            ],
+        ),
+        (
+            "x86_64-pc-windows-msvc",
+            &[
+                ("rustc-main.exe", Exe),
+                ("rustc_driver-1234.dll", Dylib),
+                ("rustc_driver-1234.dll.lib", ImportLib),
+                ("rustc_driver-1234.pdb", DebugInfo),
+                ("std-1234.lib", StaticLib),
+                ("libstd-1234.rlib", Rlib),
+                ("libstd-1234.rmeta", Rmeta),
+                ("rustc-main", Other),
+                (".exe", Other),
+            ],
+        ),
+        (
+            "x86_64-pc-windows-gnu",
+            &[
+                ("rustc-main.exe", Exe),
+                ("rustc_driver-1234.dll", Dylib),
+                ("librustc_driver-1234.dll.a", ImportLib),
+                ("libstd-1234.a", StaticLib),
+                ("libstd-1234.rlib", Rlib),
+            ],
+        ),
+        (
+            "wasm32-wasi",
+            &[
+                ("hello.wasm", Exe),
+                ("libstd-1234.rlib", Rlib),
+                ("libc.a", StaticLib),
+                ("hello", Other),
+            ],
+        ),
+    ];
+    for (target, names) in cases {
+        let target = TargetSelection::from_user(target);
+        for (name, kind) in names.iter() {
+            assert_eq!(
+                ArtifactKind::classify(Path::new(name), target),
+                *kind,
+                "{} on {}",
+                name,
+                target
+            );
+        }
+    }
+
+    let dir = tmpdir("artifact_kind");
+    let bundle = dir.join("librustc_driver-1234.dylib.dSYM");
+    t!(fs::create_dir_all(&bundle));
+    let apple = TargetSelection::from_user("aarch64-apple-darwin");
+    assert_eq!(ArtifactKind::classify(&bundle, apple), DebugInfo);
+    assert_eq!(ArtifactKind::classify(&dir.join("missing.dSYM"), apple), Other);
+}
+
+#[test]
+fn is_import_lib_per_toolchain() {
+    let cases: &[(&str, &[(&str, bool)])] = &[
+        (
+            "x86_64-pc-windows-msvc",
+            &[
+                ("rustc_driver-1234.dll.lib", true),
+                ("STD-1234.DLL.LIB", true),
+                ("std-1234.lib", false),
+                ("librustc_driver-1234.dll.a", false),
+                ("rustc_driver-1234.dll", false),
+                (".dll.lib", false),
+            ],
+        ),
+        (
+            "x86_64-pc-windows-gnu",
+            &[
+                ("librustc_driver-1234.dll.a", true),
+                ("std-1234.dll.a", true),
+                ("libfoo.a", false),
+                ("libfoo.dll.lib", false),
+                ("libdll.a", false),
+                (".dll.a", false),
+            ],
+        ),
+        (
+            "x86_64-unknown-linux-gnu",
+            &[("libfoo.a", false), ("libfoo.dll.a", false), ("foo.dll.lib", false)],
+        ),
+    ];
+    for (target, names) in cases {
+        let target = TargetSelection::from_user(target);
+        for (name, expected) in names.iter() {
+            assert_eq!(is_import_lib(name, target), *expected, "{} on {}", name, target);
+        }
+    }
+    let mingw = TargetSelection::from_user("x86_64-pc-windows-gnu");
+    assert_eq!(ArtifactKind::classify(Path::new("libfoo.a"), mingw), ArtifactKind::StaticLib);
+}
+
+// Just enough of an ELF header for `is_dylib_file`: the identification bytes
+// and e_type.
+fn elf_header(e_type: u16, big_endian: bool) -> Vec<u8> {
+    let mut header = vec![0x7f, b'E', b'L', b'F', 2, if big_endian { 2 } else { 1 }, 1];
+    header.resize(16, 0);
+    header.extend_from_slice(&if big_endian { e_type.to_be_bytes() } else { e_type.to_le_bytes() });
+    header.resize(64, 0);
+    header
+}
+
+// A 64-bit Mach-O header: magic, cputype, cpusubtype and filetype.
+fn macho_header(filetype: u32) -> Vec<u8> {
+    let mut header = Vec::new();
+    for field in [0xfeedfacf, 0x0100000c, 0, filetype] {
+        header.extend_from_slice(&u32::to_le_bytes(field));
+    }
+    header.resize(32, 0);
+    header
+}
+
+// A DOS stub pointing at a PE signature and COFF header with the given
+// Characteristics.
+fn pe_header(characteristics: u16) -> Vec<u8> {
+    let mut header = b"MZ".to_vec();
+    header.resize(0x3c, 0);
+    header.extend_from_slice(&0x80u32.to_le_bytes());
+    header.resize(0x80, 0);
+    header.extend_from_slice(b"PE\0\0");
+    header.extend_from_slice(&0x8664u16.to_le_bytes());
+    header.resize(0x80 + 4 + 18, 0);
+    header.extend_from_slice(&characteristics.to_le_bytes());
+    header.resize(0x100, 0);
+    header
+}
+
+#[test]
+fn is_dylib_file_reads_headers() {
+    let dir = tmpdir("is_dylib_file");
+    let cases: &[(&str, Vec<u8>, bool)] = &[
+        ("libfoo.so", elf_header(3, false), true),
+        ("plugin", elf_header(3, false), true),
+        ("libfoo-be.so", elf_header(3, true), true),
+        ("libfoo.so.exec", elf_header(2, false), false),
+        ("libobj.so", elf_header(1, false), false),
+        ("libfoo.dylib", macho_header(6), true),
+        ("macho-plugin", macho_header(6), true),
+        ("tool", macho_header(2), false),
+        ("foo.dll", pe_header(0x2022), true),
+        ("foo.plugin", pe_header(0x2022), true),
+        ("foo.exe", pe_header(0x0022), false),
+        ("fixture.so", b"not a library\n".to_vec(), false),
+        ("empty.so", Vec::new(), false),
+    ];
+    for (name, contents, expected) in cases {
+        let path = dir.join(name);
+        t!(fs::write(&path, contents));
+        assert_eq!(t!(is_dylib_file(&path)), *expected, "{}", name);
+    }
+
+    // Universal binaries aren't looked into, so the name decides.
+    let fat = 0xcafebabeu32.to_be_bytes();
+    t!(fs::write(dir.join("libfat.dylib"), fat));
+    t!(fs::write(dir.join("fat"), fat));
+    assert!(t!(is_dylib_file(&dir.join("libfat.dylib"))));
+    assert!(!t!(is_dylib_file(&dir.join("fat"))));
+
+    assert!(is_dylib_file(&dir.join("missing.so")).is_err());
+
+    // Directories can be opened but not read on Unix, which falls back to the
+    // name too.
+    if cfg!(unix) {
+        t!(fs::create_dir_all(dir.join("libdir.so")));
+        assert!(t!(is_dylib_file(&dir.join("libdir.so"))));
+    }
+}
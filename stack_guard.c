@@ -0,0 +1,68 @@
+// Stack overflow detection for C benchmarks
+//
+// run.py builds this into a shared library and preloads it into C benchmarks
+// when --stack-size is given. Its constructor installs a SIGSEGV handler
+// running on an alternate stack, so that it still runs once the main thread's
+// stack is exhausted. A fault just below the end of that stack is reported as
+// a stack overflow on stderr and the process exits with STACK_OVERFLOW_EXIT;
+// any other fault is left to kill the process as it would have without the
+// handler. Threads the benchmark spawns have no alternate stack, so their
+// overflows are still reported as crashes.
+
+#define _GNU_SOURCE
+#include <pthread.h>
+#include <signal.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+#include <unistd.h>
+
+#ifndef STACK_OVERFLOW_EXIT
+#define STACK_OVERFLOW_EXIT 86
+#endif
+
+// The lowest address of the main thread's stack, and its size.
+static uintptr_t stack_Low;
+static size_t stack_Size;
+
+
+static void on_Segv(int signal_Number, siginfo_t * const info, void * const context){
+  (void)context;
+  const uintptr_t address=(uintptr_t)info->si_addr;
+  const uintptr_t page=sysconf(_SC_PAGESIZE);
+  // A frame can reach past the guard gap below the stack, so faults up to a
+  // stack's size below it count.
+  if(stack_Low && address<stack_Low+page && address+stack_Size>=stack_Low){
+    static const char message[]="stack_guard: the main thread has overflowed its stack\n";
+    write(STDERR_FILENO, message, sizeof(message)-1);
+    _exit(STACK_OVERFLOW_EXIT);
+  }
+  // Let the fault happen again without the handler.
+  signal(signal_Number, SIG_DFL);
+}
+
+
+__attribute__((constructor))
+static void install(){
+  pthread_attr_t attr;
+  void *low;
+  if(pthread_getattr_np(pthread_self(), &attr)!=0)
+    return;
+  if(pthread_attr_getstack(&attr, &low, &stack_Size)==0)
+    stack_Low=(uintptr_t)low;
+  pthread_attr_destroy(&attr);
+
+  stack_t alternate;
+  alternate.ss_size=SIGSTKSZ>65536 ? SIGSTKSZ : 65536;
+  alternate.ss_sp=malloc(alternate.ss_size);
+  alternate.ss_flags=0;
+  if(!alternate.ss_sp || sigaltstack(&alternate, NULL)!=0)
+    return;
+
+  struct sigaction action;
+  memset(&action, 0, sizeof(action));
+  action.sa_sigaction=on_Segv;
+  action.sa_flags=SA_SIGINFO|SA_ONSTACK;
+  sigemptyset(&action.sa_mask);
+  sigaction(SIGSEGV, &action, NULL);
+}
@@ -0,0 +1,130 @@
+import json
+import os
+import pathlib
+import shutil
+import subprocess
+import tempfile
+import unittest
+from unittest import mock
+
+import run
+
+# A cargo benchmark with dependencies, so that cargo builds some crates (build
+# scripts) with -C embed-bitcode=no
+CARGO_BENCHMARK = 'Benchmarks/Performance_Benchmarks/Rust/sieve'
+
+class CargoEnvTest(unittest.TestCase):
+  def test_moves_lto_into_the_profile(self):
+    env = run.cargo_env(['-C', 'opt-level=3', '-C', 'lto=fat', '-Ccodegen-units=1'])
+    self.assertEqual(env, {
+      'RUSTFLAGS': '-A warnings -C opt-level=3',
+      'CARGO_PROFILE_RELEASE_LTO': 'fat',
+      'CARGO_PROFILE_RELEASE_CODEGEN_UNITS': '1',
+    })
+
+  def test_bare_lto_is_fat(self):
+    self.assertEqual(run.cargo_env(['-C', 'lto'])['CARGO_PROFILE_RELEASE_LTO'], 'true')
+
+class BenchMainTimeTest(unittest.TestCase):
+  def test_reads_the_mean(self):
+    output = 'warming up\n{"name":"sort","iterations":2,"warmup":1,"seed":0,"mean_ns":1500000000,"min_ns":1,"max_ns":2,"times_ns":[1, 2]}\n'
+    self.assertEqual(run.bench_main_time(output), 1.5)
+
+  def test_ignores_other_output(self):
+    self.assertIsNone(run.bench_main_time('{not json}\n{"primes": 3}\n'))
+
+# Recurses until the stack overflows, or dereferences a null pointer when
+# given any input
+CRASHING_C = '''
+#include <stdio.h>
+int recurse(int n){ volatile char frame[256]; frame[0] = n; return recurse(n + 1) + frame[0]; }
+int main(){ int *p = NULL; if (getchar() != EOF) return *p; return recurse(0); }
+'''
+
+@unittest.skipIf(shutil.which('gcc') is None, "gcc is not installed")
+class CrashOutcomeTest(unittest.TestCase):
+  def setUp(self):
+    work_dir = tempfile.TemporaryDirectory()
+    self.addCleanup(work_dir.cleanup)
+    self.work_dir = work_dir.name
+    self.exe = f'{self.work_dir}/crash.elf'
+    subprocess.run(['gcc', '-O0', '-xc', '-', '-o', self.exe], input=CRASHING_C, check=True, text=True)
+    self.guard = run.build_stack_guard(self.work_dir)
+    self.assertIsNotNone(self.guard)
+
+  def outcome(self, input_data):
+    input_file = f'{self.work_dir}/input'
+    pathlib.Path(input_file).write_text(input_data)
+    launcher = run.stack_size_launcher(1024 * 1024)
+    with self.assertRaises(run.BenchmarkCrashed) as crash:
+      run.run_c_benchmark(self.exe, input_file, launcher, 60, run.c_benchmark_env('system', self.guard))
+    return crash.exception.outcome
+
+  def test_stack_overflow(self):
+    self.assertEqual(self.outcome(''), 'stack_overflow')
+
+  def test_other_crash(self):
+    self.assertEqual(self.outcome('x'), 'crash')
+
+  def test_crash_is_written_with_empty_times(self):
+    results_file = f'{self.work_dir}/results.csv'
+    metadata = {'profile': 'default', 'allocator': 'system', 'allocator_version': '', 'aslr': 'enabled'}
+    run.write_results(results_file, 'recurse', None, 1.0, 'csv', metadata, outcome='stack_overflow')
+    [row] = run.read_results(results_file)
+    self.assertEqual((row['c_time'], row['rust_time'], row['speedup'], row['outcome']), (None, 1.0, '', 'stack_overflow'))
+
+METADATA = {'profile': 'default', 'allocator': 'system', 'allocator_version': 'glibc 2.36', 'aslr': 'enabled'}
+
+class ReadResultsTest(unittest.TestCase):
+  def setUp(self):
+    work_dir = tempfile.TemporaryDirectory()
+    self.addCleanup(work_dir.cleanup)
+    self.work_dir = work_dir.name
+
+  def test_reads_every_format(self):
+    for result_format in run.RESULT_FORMATS:
+      with self.subTest(result_format=result_format):
+        results_file = f'{self.work_dir}/results.{result_format}'
+        run.write_results(results_file, 'sieve', 0.5, 0.25, result_format, METADATA)
+        run.write_results(results_file, 'hash-map', 2.0, 1.0, result_format, METADATA, instructions=(10, 20))
+        rows = run.read_results(results_file)
+        self.assertEqual([(row['algorithm'], row['c_time'], row['rust_time']) for row in rows],
+                         [('sieve', 0.5, 0.25), ('hash-map', 2.0, 1.0)])
+        self.assertEqual(rows[1]['rust_instructions'], 20)
+        self.assertTrue(run.evaluated(results_file, 'hash-map', METADATA))
+        self.assertFalse(run.evaluated(results_file, 'hash-map', {**METADATA, 'profile': 'lto'}))
+
+  def test_empty_file_has_no_rows(self):
+    results_file = f'{self.work_dir}/results.csv'
+    pathlib.Path(results_file).write_text('')
+    self.assertEqual(run.read_results(results_file), [])
+    self.assertFalse(run.evaluated(results_file, 'sieve', METADATA))
+
+  def test_rejects_other_files(self):
+    results_file = f'{self.work_dir}/notes.txt'
+    pathlib.Path(results_file).write_text('some notes\n')
+    with self.assertRaisesRegex(ValueError, 'not a results file'):
+      run.read_results(results_file)
+    with self.assertRaisesRegex(ValueError, 'does not exist'):
+      run.read_results(f'{self.work_dir}/missing.csv')
+
+class EnvSnapshotTest(unittest.TestCase):
+  def test_reads_snapshots_of_other_versions(self):
+    with tempfile.TemporaryDirectory() as work_dir:
+      snapshot_file = f'{work_dir}/results.csv.env.json'
+      pathlib.Path(snapshot_file).write_text(json.dumps({'cpu_model': 'Ryzen', 'cpu_features': ['avx2'], 'kernel': '6.1'}))
+      self.assertEqual(run.read_env_snapshot(snapshot_file),
+                       run.EnvSnapshot('Ryzen', ['avx2'], None, None, None))
+      self.assertIsNone(run.read_env_snapshot(f'{work_dir}/missing.json'))
+
+@unittest.skipIf(shutil.which('cargo') is None, "cargo is not installed")
+class ProfileBuildTest(unittest.TestCase):
+  def test_builds_a_cargo_benchmark_under_each_profile(self):
+    for profile in run.PROFILES:
+      with self.subTest(profile=profile), tempfile.TemporaryDirectory() as work_dir, mock.patch.dict(os.environ):
+        rust_dir = shutil.copytree(CARGO_BENCHMARK, f'{work_dir}/benchmark', ignore=shutil.ignore_patterns('target'))
+        _, rust_flags = run.profile_flags(profile)
+        self.assertTrue(run.compile_rust(f'{work_dir}/missing.rs', rust_dir, None, ['-C', 'opt-level=3', *rust_flags]))
+
+if __name__ == '__main__':
+  unittest.main()